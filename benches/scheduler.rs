@@ -0,0 +1,76 @@
+// Timing of the event queue with thousands of pending events.
+//
+// Run with `cargo bench --bench scheduler`. Uses only std so it builds on
+// stable without extra dependencies.
+
+use phasorsyncrs::midi_output::MidiMessage;
+use phasorsyncrs::scheduler::{EventHandle, EventQueue};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const PENDING: [u64; 3] = [1_000, 10_000, 100_000];
+const ROUNDS: u32 = 20;
+
+fn note_off(index: u64) -> MidiMessage {
+    MidiMessage::NoteOff {
+        channel: (index % 16) as u8,
+        note: (index % 128) as u8,
+    }
+}
+
+// Spread events over a bar at 960 PPQN, several per tick
+fn filled_queue(pending: u64) -> (EventQueue<MidiMessage>, Vec<EventHandle>) {
+    let mut queue = EventQueue::new();
+    let handles = (0..pending)
+        .map(|index| queue.schedule((index * 7919) % 3_840, note_off(index)))
+        .collect();
+    (queue, handles)
+}
+
+fn time(name: &str, pending: u64, mut run: impl FnMut() -> Duration) {
+    let total: Duration = (0..ROUNDS).map(|_| run()).sum();
+    let per_round = total / ROUNDS;
+    println!(
+        "{:<10} {:>7} events  {:>10.3?} per round  {:>8.1} ns/event",
+        name,
+        pending,
+        per_round,
+        per_round.as_nanos() as f64 / pending as f64
+    );
+}
+
+fn main() {
+    for pending in PENDING {
+        time("schedule", pending, || {
+            let start = Instant::now();
+            black_box(filled_queue(pending));
+            start.elapsed()
+        });
+
+        time("drain", pending, || {
+            let (mut queue, _) = filled_queue(pending);
+            let start = Instant::now();
+            for tick in 0..3_840 {
+                black_box(queue.drain_until(tick));
+            }
+            start.elapsed()
+        });
+
+        time("cancel", pending, || {
+            let (mut queue, handles) = filled_queue(pending);
+            let start = Instant::now();
+            for handle in handles.into_iter().step_by(2) {
+                black_box(queue.cancel(handle));
+            }
+            start.elapsed()
+        });
+
+        time("shift", pending, || {
+            let (mut queue, _) = filled_queue(pending);
+            let start = Instant::now();
+            queue.shift(-1_920);
+            black_box(&queue);
+            start.elapsed()
+        });
+    }
+}
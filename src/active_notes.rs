@@ -0,0 +1,131 @@
+// active_notes.rs
+
+use crate::midi_output::MidiMessage;
+use std::collections::BTreeMap;
+
+/// The notes sounding on each output port, worked out from what was
+/// actually sent, so they can all be ended on demand.
+///
+/// A note started twice before it is ended is counted twice, and only
+/// stops sounding once both NoteOffs have gone out.
+#[derive(Debug, Default)]
+pub struct ActiveNotes {
+    // (port, channel, note) to the number of NoteOns not yet ended
+    sounding: BTreeMap<(usize, u8, u8), u32>,
+}
+
+impl ActiveNotes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the sounding notes for a message sent on `port`.
+    pub fn observe(&mut self, port: usize, message: &MidiMessage) {
+        match *message {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+                ..
+            } if velocity > 0 => {
+                *self
+                    .sounding
+                    .entry((port, channel & 0x0F, note))
+                    .or_default() += 1;
+            }
+            // A NoteOn with zero velocity is a NoteOff by another name
+            MidiMessage::NoteOn { channel, note, .. } | MidiMessage::NoteOff { channel, note } => {
+                self.end((port, channel & 0x0F, note));
+            }
+            MidiMessage::AllNotesOff { channel } | MidiMessage::AllSoundOff { channel } => {
+                self.sounding
+                    .retain(|&(p, c, _), _| p != port || c != channel & 0x0F);
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, key: (usize, u8, u8)) {
+        if let Some(count) = self.sounding.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                self.sounding.remove(&key);
+            }
+        }
+    }
+
+    /// Number of distinct notes sounding across every port.
+    pub fn len(&self) -> usize {
+        self.sounding.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sounding.is_empty()
+    }
+
+    /// Forgets every sounding note and returns a NoteOff for each, with the
+    /// port to send it on.
+    pub fn release(&mut self) -> Vec<(usize, MidiMessage)> {
+        std::mem::take(&mut self.sounding)
+            .into_keys()
+            .map(|(port, channel, note)| (port, MidiMessage::NoteOff { channel, note }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(channel: u8, note: u8, velocity: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel,
+            note,
+            velocity,
+            duration_ticks: 0,
+        }
+    }
+
+    fn note_off(channel: u8, note: u8) -> MidiMessage {
+        MidiMessage::NoteOff { channel, note }
+    }
+
+    #[test]
+    fn test_tracks_notes_per_port_and_channel() {
+        let mut notes = ActiveNotes::new();
+        notes.observe(0, &note_on(1, 60, 100));
+        notes.observe(0, &note_on(2, 60, 100));
+        notes.observe(1, &note_on(1, 60, 100));
+        notes.observe(0, &note_off(2, 60));
+        notes.observe(1, &note_on(1, 60, 0));
+        assert_eq!(notes.len(), 1);
+
+        assert_eq!(notes.release(), vec![(0, note_off(1, 60))]);
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn test_retriggered_note_needs_both_note_offs() {
+        let mut notes = ActiveNotes::new();
+        notes.observe(0, &note_on(0, 36, 100));
+        notes.observe(0, &note_on(0, 36, 100));
+        notes.observe(0, &note_off(0, 36));
+        assert_eq!(notes.len(), 1);
+        notes.observe(0, &note_off(0, 36));
+        assert!(notes.is_empty());
+
+        // Ending a note that never started is harmless
+        notes.observe(0, &note_off(0, 36));
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn test_all_notes_off_clears_only_its_channel() {
+        let mut notes = ActiveNotes::new();
+        notes.observe(0, &note_on(3, 60, 100));
+        notes.observe(0, &note_on(3, 64, 100));
+        notes.observe(0, &note_on(4, 60, 100));
+        notes.observe(0, &MidiMessage::AllNotesOff { channel: 3 });
+        assert_eq!(notes.release(), vec![(0, note_off(4, 60))]);
+    }
+}
@@ -1,58 +1,507 @@
 // clock.rs
 
+use crate::config::{MIDI_CLOCK_PPQN, TICKS_PER_CLOCK};
+use crate::error::MidiError;
 use crate::event_loop::EngineMessage;
-use log::{info, trace};
+use log::{error, info, trace};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::Sender;
-use std::thread;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
-#[allow(dead_code)]
-pub trait ClockSource {
-    fn start(&self);
+pub trait ClockSource: Send {
+    /// Starts the clock's threads. Starting a running clock does nothing.
+    fn start(&self) -> Result<(), MidiError>;
+
+    /// Stops the clock and waits for its threads to finish, so nothing it
+    /// sends reaches the engine after this returns. A stopped clock can be
+    /// started again.
+    fn stop(&self);
+}
+
+/// Cleared when a clock is stopped; its threads poll this and wind down.
+#[derive(Clone, Debug)]
+pub struct RunFlag(Arc<AtomicBool>);
+
+impl RunFlag {
+    pub fn is_running(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// The threads behind a clock source and the flag that keeps them going.
+pub struct ClockThreads {
+    running: Arc<AtomicBool>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for ClockThreads {
+    fn default() -> Self {
+        ClockThreads {
+            running: Arc::new(AtomicBool::new(false)),
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ClockThreads {
+    /// Marks the clock as running and returns the flag its threads poll,
+    /// or `None` if it is running already.
+    pub fn begin(&self) -> Option<RunFlag> {
+        let was_running = self.running.swap(true, Ordering::AcqRel);
+        (!was_running).then(|| RunFlag(self.running.clone()))
+    }
+
+    pub fn spawn(&self, body: impl FnOnce() + Send + 'static) {
+        self.handles.lock().unwrap().push(thread::spawn(body));
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Clears the run flag and joins every thread spawned since `begin`.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+        let handles: Vec<_> = self.handles.lock().unwrap().drain(..).collect();
+        for handle in handles {
+            if handle.join().is_err() {
+                error!("A clock thread panicked");
+            }
+        }
+    }
+}
+
+/// Lateness statistics for ticks emitted by the internal clock, measured
+/// against each tick's absolute deadline.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct JitterStats {
+    pub ticks: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+impl JitterStats {
+    pub fn record(&mut self, lateness: Duration) {
+        if self.ticks == 0 {
+            self.min = lateness;
+            self.max = lateness;
+        } else {
+            self.min = self.min.min(lateness);
+            self.max = self.max.max(lateness);
+        }
+        self.ticks += 1;
+
+        // Incremental mean keeps the figure stable over arbitrarily long runs
+        let mean_ns = self.mean.as_nanos() as f64;
+        let delta_ns = lateness.as_nanos() as f64 - mean_ns;
+        let updated_ns = mean_ns + delta_ns / self.ticks as f64;
+        self.mean = Duration::from_nanos(updated_ns.max(0.0).round() as u64);
+    }
+}
+
+/// Cloneable handle onto the jitter statistics of a running clock.
+#[derive(Clone, Debug, Default)]
+pub struct JitterMonitor {
+    stats: Arc<Mutex<JitterStats>>,
+}
+
+impl JitterMonitor {
+    pub fn snapshot(&self) -> JitterStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Adds how late one tick was emitted.
+    pub fn record(&self, lateness: Duration) {
+        self.stats.lock().unwrap().record(lateness);
+    }
+}
+
+pub const MIN_BPM: f64 = 20.0;
+pub const MAX_BPM: f64 = 300.0;
+
+/// Shared, lock-free tempo target read by the internal clock on every tick.
+#[derive(Clone)]
+pub struct TempoHandle {
+    bits: Arc<AtomicU64>,
+}
+
+impl TempoHandle {
+    pub fn new(bpm: f64) -> Self {
+        TempoHandle {
+            bits: Arc::new(AtomicU64::new(clamp_bpm(bpm).to_bits())),
+        }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+
+    /// Sets the tempo, clamped to the supported range, and returns the value applied.
+    pub fn set_bpm(&self, bpm: f64) -> f64 {
+        let bpm = clamp_bpm(bpm);
+        self.bits.store(bpm.to_bits(), Ordering::Relaxed);
+        bpm
+    }
+}
+
+fn clamp_bpm(bpm: f64) -> f64 {
+    if bpm.is_finite() {
+        bpm.clamp(MIN_BPM, MAX_BPM)
+    } else {
+        MIN_BPM
+    }
+}
+
+/// Computes absolute tick deadlines from an anchor instant so that sleep
+/// overshoot never accumulates into drift. A tempo change re-anchors the
+/// schedule at the deadline of the tick it takes effect from.
+pub struct TickDeadlines {
+    anchor: Instant,
+    anchor_tick: u64,
+    bpm: f64,
+    tick_interval_ns: f64,
+}
+
+impl TickDeadlines {
+    pub fn new(start: Instant, bpm: f64) -> Self {
+        TickDeadlines {
+            anchor: start,
+            anchor_tick: 0,
+            bpm,
+            tick_interval_ns: tick_interval_ns(bpm),
+        }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    /// Deadline of the `tick`th tick after start (tick 0 is the start itself).
+    pub fn deadline(&self, tick: u64) -> Instant {
+        let ticks_since_anchor = tick.saturating_sub(self.anchor_tick) as f64;
+        let offset_ns = (ticks_since_anchor * self.tick_interval_ns).round() as u64;
+        self.anchor + Duration::from_nanos(offset_ns)
+    }
+
+    /// Switches tempo so that ticks after `from_tick` use the new interval.
+    pub fn set_bpm(&mut self, from_tick: u64, bpm: f64) {
+        self.anchor = self.deadline(from_tick);
+        self.anchor_tick = from_tick;
+        self.bpm = bpm;
+        self.tick_interval_ns = tick_interval_ns(bpm);
+    }
+}
+
+/// Nanoseconds between 24 PPQN ticks, kept fractional to avoid truncation.
+pub fn tick_interval_ns(bpm: f64) -> f64 {
+    60_000_000_000.0 / (bpm * MIDI_CLOCK_PPQN as f64)
+}
+
+/// Spreads the engine ticks between MIDI clock pulses over time, so the
+/// engine runs finer than 24 PPQN whatever the clock source. Each pulse
+/// lands on a multiple of `TICKS_PER_CLOCK`; the ticks after it are timed
+/// from the last pulse interval, and any still owed when the next pulse
+/// arrives are played with that pulse.
+pub struct PulseInterpolator {
+    last_pulse: Option<Instant>,
+    interval: Option<Duration>,
+    // Engine ticks still to play before the next pulse
+    owed: u64,
+}
+
+impl Default for PulseInterpolator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PulseInterpolator {
+    pub fn new() -> Self {
+        PulseInterpolator {
+            last_pulse: None,
+            interval: None,
+            owed: TICKS_PER_CLOCK - 1,
+        }
+    }
+
+    /// Registers a pulse at `now` and returns how many engine ticks it
+    /// plays: those still owed from the previous pulse plus its own.
+    pub fn pulse(&mut self, now: Instant) -> u64 {
+        if let Some(last) = self.last_pulse {
+            let gap = now.saturating_duration_since(last);
+            // Anything slower than the slowest tempo is a dropout, not an interval
+            if gap.as_nanos() as f64 <= tick_interval_ns(MIN_BPM) {
+                self.interval = Some(gap);
+            }
+        }
+        self.last_pulse = Some(now);
+
+        let ticks = self.owed + 1;
+        self.owed = TICKS_PER_CLOCK - 1;
+        ticks
+    }
+
+    /// When the next interpolated tick falls due. `None` until two pulses
+    /// have given an interval, or once every tick before the next pulse
+    /// has been played.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.owed == 0 {
+            return None;
+        }
+        let (last_pulse, interval) = (self.last_pulse?, self.interval?);
+        let slot = (TICKS_PER_CLOCK - self.owed) as u32;
+        Some(last_pulse + interval * slot / TICKS_PER_CLOCK as u32)
+    }
+
+    /// Number of interpolated ticks that have fallen due by `now`.
+    pub fn due(&mut self, now: Instant) -> u64 {
+        let mut ticks = 0;
+        while self.next_deadline().is_some_and(|deadline| deadline <= now) {
+            self.owed -= 1;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// Realigns after the transport jumps to `tick`, so that the next pulse
+    /// lands on a multiple of `TICKS_PER_CLOCK` again.
+    pub fn realign(&mut self, tick: u64) {
+        self.owed = TICKS_PER_CLOCK - 1 - tick % TICKS_PER_CLOCK;
+    }
 }
 
 pub struct InternalClock {
-    bpm: u32,
+    tempo: TempoHandle,
     tick_tx: Sender<EngineMessage>,
+    jitter: JitterMonitor,
+    threads: ClockThreads,
 }
 
 impl InternalClock {
-    pub fn new(tick_tx: Sender<EngineMessage>) -> Self {
-        info!("Creating new InternalClock with default BPM: 122");
-        InternalClock { bpm: 122, tick_tx }
+    pub fn new(tempo: TempoHandle, tick_tx: Sender<EngineMessage>) -> Self {
+        info!("Creating new InternalClock with BPM: {}", tempo.bpm());
+        InternalClock {
+            tempo,
+            tick_tx,
+            jitter: JitterMonitor::default(),
+            threads: ClockThreads::default(),
+        }
     }
 }
 
 impl ClockSource for InternalClock {
-    fn start(&self) {
-        info!("Starting InternalClock with BPM: {}", self.bpm);
-        let beat_duration_us = 60_000_000 / self.bpm; // total microseconds per beat
-        let tick_interval_us = beat_duration_us / 24; // microseconds per tick
-        trace!("Calculated tick interval: {} µs", tick_interval_us);
-        let tick_tx = self.tick_tx.clone();
-
-        let start_time = Instant::now();
+    fn start(&self) -> Result<(), MidiError> {
+        let Some(running) = self.threads.begin() else {
+            return Ok(());
+        };
+        info!("Starting InternalClock with BPM: {}", self.tempo.bpm());
+        // Lets the engine publish how late the ticks run
+        let _ = self
+            .tick_tx
+            .send(EngineMessage::ClockJitter(self.jitter.clone()));
+        let mut ticker = InternalTicker {
+            deadlines: TickDeadlines::new(Instant::now(), self.tempo.bpm()),
+            tempo: self.tempo.clone(),
+            tick_tx: self.tick_tx.clone(),
+            jitter: self.jitter.clone(),
+            tick_count: 0,
+        };
+        trace!(
+            "Calculated tick interval: {:.3} µs",
+            ticker.deadlines.tick_interval_ns / 1_000.0
+        );
 
-        thread::spawn(move || {
+        self.threads.spawn(move || {
             info!("Internal clock thread started");
-            let mut tick_count = 0;
-            loop {
-                for _ in 0..24 {
-                    let start = Instant::now();
-                    thread::sleep(Duration::from_micros(tick_interval_us as u64));
-                    let end = Instant::now();
-                    let sleep_duration = end.duration_since(start);
-                    trace!("Sleep duration: {:?}", sleep_duration);
-
-                    let now = Instant::now();
-                    let elapsed = now.duration_since(start_time).as_millis();
-                    trace!("InternalClock tick at {} ms", elapsed);
-
-                    tick_tx.send(EngineMessage::Tick).unwrap();
-                    tick_count += 1;
-                }
-                trace!("Internal clock beat: {}", tick_count / 24);
-            }
+            while running.is_running() && ticker.tick() {}
+            info!("Internal clock thread stopped");
         });
+        Ok(())
+    }
+
+    fn stop(&self) {
+        info!("Stopping InternalClock");
+        self.threads.stop();
+    }
+}
+
+/// State owned by the internal clock thread.
+struct InternalTicker {
+    deadlines: TickDeadlines,
+    tempo: TempoHandle,
+    tick_tx: Sender<EngineMessage>,
+    jitter: JitterMonitor,
+    tick_count: u64,
+}
+
+impl InternalTicker {
+    /// Waits for the next deadline and emits a tick. Returns false once the
+    /// engine has gone away.
+    fn tick(&mut self) -> bool {
+        let bpm = self.tempo.bpm();
+        if bpm != self.deadlines.bpm() {
+            info!("Internal clock tempo changed to {} BPM", bpm);
+            self.deadlines.set_bpm(self.tick_count, bpm);
+        }
+
+        self.tick_count += 1;
+        let deadline = self.deadlines.deadline(self.tick_count);
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        }
+
+        let lateness = Instant::now().saturating_duration_since(deadline);
+        self.jitter.record(lateness);
+        trace!(
+            "InternalClock tick {} late by {:?}",
+            self.tick_count,
+            lateness
+        );
+
+        if let Err(e) = self.tick_tx.send(EngineMessage::Tick) {
+            error!("Internal clock lost the engine channel: {}", e);
+            return false;
+        }
+
+        if self.tick_count.is_multiple_of(MIDI_CLOCK_PPQN) {
+            trace!(
+                "Internal clock beat: {}, jitter: {:?}",
+                self.tick_count / MIDI_CLOCK_PPQN,
+                self.jitter.snapshot()
+            );
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_interval_keeps_fractional_microseconds() {
+        // 122 BPM at 24 PPQN is 20491.803... µs per tick; integer maths gave 20491
+        let interval_us = tick_interval_ns(122.0) / 1_000.0;
+        assert!((interval_us - 20_491.803).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_deadlines_do_not_accumulate_rounding_error() {
+        let start = Instant::now();
+        let deadlines = TickDeadlines::new(start, 122.0);
+
+        // Ten minutes of ticks should land within a nanosecond of the ideal time
+        let ticks = 122 * 24 * 10;
+        let elapsed = deadlines.deadline(ticks).duration_since(start);
+        assert_eq!(elapsed, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_tempo_change_reanchors_at_current_tick() {
+        let start = Instant::now();
+        let mut deadlines = TickDeadlines::new(start, 120.0);
+        let before = deadlines.deadline(24);
+
+        deadlines.set_bpm(24, 60.0);
+
+        // The tick the change takes effect from keeps its deadline...
+        assert_eq!(deadlines.deadline(24), before);
+        // ...and the following beat takes twice as long
+        assert_eq!(
+            deadlines.deadline(48).duration_since(before),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn test_interpolator_spreads_ticks_between_pulses() {
+        let start = Instant::now();
+        // 125 BPM, so the pulse interval splits evenly
+        let period = Duration::from_millis(20);
+        let mut interpolator = PulseInterpolator::new();
+
+        // Nothing to interpolate from on the first pulse, so the ticks
+        // leading up to it play with it
+        assert_eq!(interpolator.pulse(start), TICKS_PER_CLOCK);
+        assert_eq!(interpolator.next_deadline(), None);
+
+        // With an interval known the ticks after the next pulse are spaced
+        // evenly across it
+        assert_eq!(interpolator.pulse(start + period), TICKS_PER_CLOCK);
+        let slot = period / TICKS_PER_CLOCK as u32;
+        assert_eq!(interpolator.next_deadline(), Some(start + period + slot));
+        assert_eq!(interpolator.due(start + period + slot * 10), 10);
+
+        // A pulse that comes early plays the rest straight away
+        assert_eq!(
+            interpolator.pulse(start + period * 3 / 2),
+            TICKS_PER_CLOCK - 10
+        );
+    }
+
+    #[test]
+    fn test_interpolator_realigns_to_the_pulse_grid() {
+        let mut interpolator = PulseInterpolator::new();
+        interpolator.realign(TICKS_PER_CLOCK * 3 + 5);
+        assert_eq!(interpolator.pulse(Instant::now()), TICKS_PER_CLOCK - 5);
+    }
+
+    #[test]
+    fn test_tempo_handle_clamps_and_shares_value() {
+        let handle = TempoHandle::new(120.0);
+        let clock_view = handle.clone();
+
+        assert_eq!(handle.set_bpm(133.5), 133.5);
+        assert_eq!(clock_view.bpm(), 133.5);
+        assert_eq!(handle.set_bpm(1_000.0), MAX_BPM);
+        assert_eq!(handle.set_bpm(f64::NAN), MIN_BPM);
+    }
+
+    #[test]
+    fn test_jitter_stats_track_min_max_mean() {
+        let mut stats = JitterStats::default();
+        stats.record(Duration::from_micros(100));
+        stats.record(Duration::from_micros(300));
+        stats.record(Duration::from_micros(200));
+
+        assert_eq!(stats.ticks, 3);
+        assert_eq!(stats.min, Duration::from_micros(100));
+        assert_eq!(stats.max, Duration::from_micros(300));
+        assert_eq!(stats.mean, Duration::from_micros(200));
+    }
+
+    #[test]
+    fn test_jitter_monitor_shares_stats() {
+        let monitor = JitterMonitor::default();
+        let handle = monitor.clone();
+        monitor.record(Duration::from_micros(50));
+
+        assert_eq!(handle.snapshot().ticks, 1);
+        assert_eq!(handle.snapshot().max, Duration::from_micros(50));
+    }
+
+    #[test]
+    fn test_internal_clock_stops_and_restarts() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clock = InternalClock::new(TempoHandle::new(MAX_BPM), tx);
+
+        clock.start().unwrap();
+        clock.start().unwrap(); // already running, so no second ticker
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        clock.stop();
+
+        // Once stop returns the thread is gone and nothing more arrives
+        while rx.try_recv().is_ok() {}
+        thread::sleep(Duration::from_millis(30));
+        assert!(rx.try_recv().is_err());
+        assert!(!clock.threads.is_running());
+
+        clock.start().unwrap();
+        assert!(rx.recv_timeout(Duration::from_secs(1)).is_ok());
+        clock.stop();
     }
 }
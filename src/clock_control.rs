@@ -0,0 +1,521 @@
+// clock_control.rs
+
+use crate::clock::{ClockSource, ClockThreads, InternalClock, RunFlag, TempoHandle};
+use crate::clock_failover::FailoverClock;
+use crate::error::{MidiError, Recovery, RecoveryPolicy};
+use crate::event_loop::EngineMessage;
+use crate::external_clock::ExternalClock;
+use crate::link::{LinkClock, LinkConfig};
+use crate::reconnect::{ConnectionState, ReconnectPolicy, Reconnector};
+use crate::state::ClockStatus;
+use log::{error, info, warn};
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often a retrying start checks whether it has been stopped
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Which clock drives the engine, and for an external clock which device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClockSelection {
+    Internal,
+    External(String),
+    Link,
+}
+
+impl ClockSelection {
+    pub fn status(&self) -> ClockStatus {
+        match self {
+            ClockSelection::Internal => ClockStatus::Internal,
+            ClockSelection::External(_) => ClockStatus::External,
+            ClockSelection::Link => ClockStatus::Link,
+        }
+    }
+
+    pub fn device(&self) -> Option<&str> {
+        match self {
+            ClockSelection::External(device) => Some(device),
+            ClockSelection::Internal | ClockSelection::Link => None,
+        }
+    }
+}
+
+impl fmt::Display for ClockSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClockSelection::Internal => write!(f, "internal"),
+            ClockSelection::External(device) => write!(f, "external ({})", device),
+            ClockSelection::Link => write!(f, "link"),
+        }
+    }
+}
+
+/// Everything needed to build any of the real clock sources.
+pub struct ClockFactory {
+    pub engine_tx: Sender<EngineMessage>,
+    pub tempo: TempoHandle,
+    pub link: LinkConfig,
+    /// Route external clocks through a failover relay with this timeout
+    pub clock_timeout: Option<Duration>,
+    /// How external clock devices are found again after being unplugged
+    pub reconnect: ReconnectPolicy,
+    /// Whether an external clock device missing at start is waited for
+    pub recovery: RecoveryPolicy,
+}
+
+impl ClockFactory {
+    pub fn build(&self, selection: &ClockSelection) -> Box<dyn ClockSource> {
+        let engine_tx = self.engine_tx.clone();
+        match (selection, self.clock_timeout) {
+            (ClockSelection::Internal, _) => {
+                Box::new(InternalClock::new(self.tempo.clone(), engine_tx))
+            }
+            (ClockSelection::Link, _) => Box::new(LinkClock::new(
+                self.link.clone(),
+                self.tempo.clone(),
+                engine_tx,
+            )),
+            (ClockSelection::External(device), None) => Box::new(
+                ExternalClock::new(device.clone(), engine_tx)
+                    .with_reconnect_policy(self.reconnect)
+                    .with_recovery_policy(self.recovery),
+            ),
+            (ClockSelection::External(device), Some(timeout)) => Box::new(
+                FailoverClock::new(device.clone(), timeout, engine_tx)
+                    .with_reconnect_policy(self.reconnect)
+                    .with_recovery_policy(self.recovery),
+            ),
+        }
+    }
+}
+
+type BuildClock = Box<dyn Fn(&ClockSelection) -> Box<dyn ClockSource> + Send>;
+
+struct Controller {
+    build: BuildClock,
+    engine_tx: Sender<EngineMessage>,
+    selection: ClockSelection,
+    last_device: Option<String>,
+    source: Option<Box<dyn ClockSource>>,
+    recovery: RecoveryPolicy,
+    reconnect: ReconnectPolicy,
+}
+
+/// Owns the running clock source and swaps it at runtime. Clones share the
+/// same clock, so the TUI and web UI can both drive it.
+///
+/// A switch stops the old source and waits for its threads before telling
+/// the engine and starting the new one. Every tick from the old clock is
+/// therefore queued ahead of the `ClockSwitched` notice, and every tick from
+/// the new one after it.
+///
+/// A source that fails to start with an error retrying can fix is started
+/// again on the reconnect interval under the retry policy, staying
+/// selected meanwhile. Otherwise it is replaced by the internal clock,
+/// unless the recovery policy is to abort, which leaves no clock running.
+#[derive(Clone)]
+pub struct ClockController {
+    inner: Arc<Mutex<Controller>>,
+}
+
+impl ClockController {
+    pub fn new(
+        engine_tx: Sender<EngineMessage>,
+        selection: ClockSelection,
+        build: impl Fn(&ClockSelection) -> Box<dyn ClockSource> + Send + 'static,
+    ) -> Self {
+        ClockController {
+            inner: Arc::new(Mutex::new(Controller {
+                build: Box::new(build),
+                engine_tx,
+                last_device: selection.device().map(str::to_string),
+                selection,
+                source: None,
+                recovery: RecoveryPolicy::default(),
+                reconnect: ReconnectPolicy::default(),
+            })),
+        }
+    }
+
+    pub fn with_recovery_policy(self, policy: RecoveryPolicy) -> Self {
+        self.inner.lock().unwrap().recovery = policy;
+        self
+    }
+
+    /// How often, and how many times, a failed start is tried again.
+    pub fn with_reconnect_policy(self, policy: ReconnectPolicy) -> Self {
+        self.inner.lock().unwrap().reconnect = policy;
+        self
+    }
+
+    pub fn selection(&self) -> ClockSelection {
+        self.inner.lock().unwrap().selection.clone()
+    }
+
+    /// The most recently bound external device, kept while on another source.
+    pub fn last_device(&self) -> Option<String> {
+        self.inner.lock().unwrap().last_device.clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.inner.lock().unwrap().source.is_some()
+    }
+
+    /// Starts the selected clock if nothing is running.
+    pub fn start(&self) -> Result<(), MidiError> {
+        let mut controller = self.inner.lock().unwrap();
+        if controller.source.is_some() {
+            return Ok(());
+        }
+        let selection = controller.selection.clone();
+        controller.launch(selection)
+    }
+
+    /// Replaces the running clock, or the one that will run on `start`.
+    pub fn switch(&self, selection: ClockSelection) -> Result<(), MidiError> {
+        let mut controller = self.inner.lock().unwrap();
+        if controller.source.is_none() {
+            controller.remember(selection);
+            return Ok(());
+        }
+        info!(
+            "Switching clock from {} to {}",
+            controller.selection, selection
+        );
+        controller.halt();
+        controller.launch(selection)
+    }
+
+    /// Stops the running clock and waits for its threads.
+    pub fn stop(&self) {
+        self.inner.lock().unwrap().halt();
+    }
+}
+
+impl Controller {
+    fn remember(&mut self, selection: ClockSelection) {
+        if let Some(device) = selection.device() {
+            self.last_device = Some(device.to_string());
+        }
+        self.selection = selection;
+    }
+
+    fn halt(&mut self) {
+        if let Some(source) = self.source.take() {
+            info!("Stopping {} clock", self.selection);
+            source.stop();
+        }
+    }
+
+    // Starts `selection`, retrying it or falling back to the internal
+    // clock if it fails, as the recovery policy picks. Any failure is
+    // returned.
+    fn launch(&mut self, selection: ClockSelection) -> Result<(), MidiError> {
+        self.remember(selection.clone());
+        if let Err(e) = self
+            .engine_tx
+            .send(EngineMessage::ClockSwitched(selection.clone()))
+        {
+            error!("Failed to announce clock switch: {}", e);
+        }
+        let source = (self.build)(&selection);
+        info!("Starting {} clock", selection);
+        if let Err(e) = source.start() {
+            error!("Failed to start {} clock: {}", selection, e);
+            match self.recovery.recover(&e) {
+                Recovery::Retry => {
+                    warn!("Trying the {} clock again", selection);
+                    let retrying = RetryingStart::new(source, self.reconnect);
+                    // Starting only spawns the retry thread, which cannot fail
+                    let _ = retrying.start();
+                    self.source = Some(Box::new(retrying));
+                }
+                Recovery::Degrade if selection != ClockSelection::Internal => {
+                    warn!("Falling back to the internal clock");
+                    // A failure of the internal clock is logged as it happens
+                    let _ = self.launch(ClockSelection::Internal);
+                }
+                Recovery::Degrade | Recovery::Abort => {}
+            }
+            return Err(e);
+        }
+        self.source = Some(source);
+        Ok(())
+    }
+}
+
+/// A clock source that failed to start, started again on the reconnect
+/// interval until it succeeds, the policy gives up, or it is stopped.
+struct RetryingStart {
+    source: Arc<Mutex<Box<dyn ClockSource>>>,
+    reconnect: ReconnectPolicy,
+    threads: ClockThreads,
+}
+
+impl RetryingStart {
+    fn new(source: Box<dyn ClockSource>, reconnect: ReconnectPolicy) -> Self {
+        RetryingStart {
+            source: Arc::new(Mutex::new(source)),
+            reconnect,
+            threads: ClockThreads::default(),
+        }
+    }
+}
+
+impl ClockSource for RetryingStart {
+    fn start(&self) -> Result<(), MidiError> {
+        let Some(running) = self.threads.begin() else {
+            return Ok(());
+        };
+        let source = self.source.clone();
+        let mut reconnector = Reconnector::new(self.reconnect, Instant::now());
+        // The attempt that brought us here was the first
+        reconnector.failed(Instant::now());
+        self.threads
+            .spawn(move || retry_start(&source, &mut reconnector, &running));
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.threads.stop();
+        self.source.lock().unwrap().stop();
+    }
+}
+
+fn retry_start(
+    source: &Mutex<Box<dyn ClockSource>>,
+    reconnector: &mut Reconnector,
+    running: &RunFlag,
+) {
+    while running.is_running() {
+        if reconnector.due(Instant::now()) {
+            match source.lock().unwrap().start() {
+                Ok(()) => {
+                    info!("Clock started after retrying");
+                    return;
+                }
+                Err(e) => {
+                    warn!("Clock still failing to start: {}", e);
+                    reconnector.failed(Instant::now());
+                }
+            }
+        }
+        if matches!(reconnector.state(), ConnectionState::GaveUp { .. }) {
+            error!("Giving up on starting the clock");
+            return;
+        }
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{SimulatedClock, SimulationMode, VirtualTime};
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::mpsc;
+
+    /// Stands in for an external clock whose device is missing.
+    struct MissingDevice;
+
+    impl ClockSource for MissingDevice {
+        fn start(&self) -> Result<(), MidiError> {
+            Err(MidiError::PortNotFound {
+                device: "OXI ONE".to_string(),
+                available: Vec::new(),
+            })
+        }
+
+        fn stop(&self) {}
+    }
+
+    /// Internal ticks arrive as `Tick` and the stand-in Link clock's as
+    /// `TickAt`, so the order they reach the engine in can be checked.
+    fn controller(engine_tx: Sender<EngineMessage>) -> ClockController {
+        let tempo = TempoHandle::new(crate::clock::MAX_BPM);
+        let tick_tx = engine_tx.clone();
+        ClockController::new(
+            engine_tx,
+            ClockSelection::Internal,
+            move |selection| match selection {
+                ClockSelection::Link => Box::new(SimulatedClock::new(
+                    VirtualTime::new(),
+                    tempo.clone(),
+                    tick_tx.clone(),
+                    SimulationMode::FreeRunning { ticks: 1_000 },
+                )),
+                ClockSelection::External(_) => Box::new(MissingDevice),
+                _ => Box::new(InternalClock::new(tempo.clone(), tick_tx.clone())),
+            },
+        )
+    }
+
+    #[test]
+    fn test_switch_keeps_ticks_in_source_order() {
+        let (engine_tx, engine_rx) = mpsc::channel();
+        let clocks = controller(engine_tx);
+
+        clocks.start().unwrap();
+        thread::sleep(Duration::from_millis(30));
+        clocks.switch(ClockSelection::Link).unwrap();
+        clocks.stop();
+
+        let messages: Vec<EngineMessage> = engine_rx.try_iter().collect();
+        let switched = messages
+            .iter()
+            .rposition(|message| {
+                matches!(message, EngineMessage::ClockSwitched(ClockSelection::Link))
+            })
+            .expect("switch announced");
+        let (before, after) = messages.split_at(switched);
+
+        assert!(matches!(
+            before.first(),
+            Some(EngineMessage::ClockSwitched(ClockSelection::Internal))
+        ));
+        assert!(before.iter().any(|m| matches!(m, EngineMessage::Tick)));
+        assert!(!before.iter().any(|m| matches!(m, EngineMessage::TickAt(_))));
+        assert!(!after.iter().any(|m| matches!(m, EngineMessage::Tick)));
+    }
+
+    #[test]
+    fn test_stop_silences_the_clock_until_started_again() {
+        let (engine_tx, engine_rx) = mpsc::channel();
+        let clocks = controller(engine_tx);
+
+        clocks.start().unwrap();
+        engine_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        clocks.stop();
+        assert!(!clocks.is_running());
+
+        while engine_rx.try_recv().is_ok() {}
+        thread::sleep(Duration::from_millis(30));
+        assert!(engine_rx.try_recv().is_err());
+
+        clocks.start().unwrap();
+        assert!(clocks.is_running());
+        clocks.stop();
+    }
+
+    #[test]
+    fn test_switch_while_stopped_only_changes_the_selection() {
+        let (engine_tx, engine_rx) = mpsc::channel();
+        let clocks = controller(engine_tx);
+
+        clocks
+            .switch(ClockSelection::External("OXI ONE".to_string()))
+            .unwrap();
+        clocks.switch(ClockSelection::Internal).unwrap();
+        assert!(!clocks.is_running());
+        assert!(engine_rx.try_recv().is_err());
+
+        // The device is remembered for switching back to external later
+        assert_eq!(clocks.selection(), ClockSelection::Internal);
+        assert_eq!(clocks.last_device().as_deref(), Some("OXI ONE"));
+        assert_eq!(
+            ClockSelection::External("OXI ONE".to_string()).to_string(),
+            "external (OXI ONE)"
+        );
+    }
+
+    #[test]
+    fn test_failed_start_falls_back_to_the_internal_clock() {
+        let (engine_tx, engine_rx) = mpsc::channel();
+        let clocks = controller(engine_tx).with_recovery_policy(RecoveryPolicy::Degrade);
+
+        let device = ClockSelection::External("OXI ONE".to_string());
+        clocks.switch(device.clone()).unwrap();
+        assert!(matches!(
+            clocks.start(),
+            Err(MidiError::PortNotFound { .. })
+        ));
+        assert!(clocks.is_running());
+        assert_eq!(clocks.selection(), ClockSelection::Internal);
+        assert_eq!(clocks.last_device().as_deref(), Some("OXI ONE"));
+        clocks.stop();
+
+        let switches: Vec<ClockSelection> = engine_rx
+            .try_iter()
+            .filter_map(|message| match message {
+                EngineMessage::ClockSwitched(selection) => Some(selection),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(switches, vec![device, ClockSelection::Internal]);
+    }
+
+    /// A device that is missing for its first few starts.
+    struct LateDevice {
+        missing_starts: Arc<AtomicU32>,
+        started: Arc<AtomicBool>,
+    }
+
+    impl ClockSource for LateDevice {
+        fn start(&self) -> Result<(), MidiError> {
+            let missing = self.missing_starts.load(Ordering::Acquire);
+            if missing > 0 {
+                self.missing_starts.store(missing - 1, Ordering::Release);
+                return MissingDevice.start();
+            }
+            self.started.store(true, Ordering::Release);
+            Ok(())
+        }
+
+        fn stop(&self) {
+            self.started.store(false, Ordering::Release);
+        }
+    }
+
+    #[test]
+    fn test_failed_start_under_retry_keeps_the_device_and_tries_again() {
+        let (engine_tx, _engine_rx) = mpsc::channel();
+        let missing_starts = Arc::new(AtomicU32::new(3));
+        let started = Arc::new(AtomicBool::new(false));
+        let device = LateDevice {
+            missing_starts: missing_starts.clone(),
+            started: started.clone(),
+        };
+        let selection = ClockSelection::External("OXI ONE".to_string());
+        let device = Mutex::new(Some(device));
+        let clocks = ClockController::new(engine_tx, selection.clone(), move |_| {
+            Box::new(device.lock().unwrap().take().expect("built once")) as Box<dyn ClockSource>
+        })
+        .with_recovery_policy(RecoveryPolicy::Retry)
+        .with_reconnect_policy(ReconnectPolicy {
+            interval: Duration::from_millis(1),
+            max_attempts: None,
+        });
+
+        assert!(matches!(
+            clocks.start(),
+            Err(MidiError::PortNotFound { .. })
+        ));
+        assert_eq!(clocks.selection(), selection);
+        assert!(clocks.is_running());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !started.load(Ordering::Acquire) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(started.load(Ordering::Acquire));
+        assert_eq!(missing_starts.load(Ordering::Acquire), 0);
+
+        clocks.stop();
+        assert!(!started.load(Ordering::Acquire));
+        assert!(!clocks.is_running());
+    }
+
+    #[test]
+    fn test_failed_start_under_abort_leaves_no_clock_running() {
+        let (engine_tx, _engine_rx) = mpsc::channel();
+        let clocks = controller(engine_tx).with_recovery_policy(RecoveryPolicy::Abort);
+
+        clocks.start().unwrap();
+        let switched = clocks.switch(ClockSelection::External("OXI ONE".to_string()));
+        assert!(switched.is_err());
+        assert!(!clocks.is_running());
+    }
+}
@@ -0,0 +1,410 @@
+// clock_failover.rs
+
+use crate::clock::{tick_interval_ns, ClockSource, ClockThreads, TickDeadlines};
+use crate::error::{MidiError, RecoveryPolicy};
+use crate::event_loop::EngineMessage;
+use crate::external_clock::ExternalClock;
+use crate::reconnect::ReconnectPolicy;
+use crate::state::ClockStatus;
+use crate::tempo_estimator::PllEstimator;
+use log::{info, warn};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sits between the external clock and the engine. It forwards everything
+/// the device sends, and if ticks stop arriving for longer than the timeout
+/// it keeps the engine running from a flywheel at the last measured tempo.
+/// When the device comes back the tick count is realigned to its phase.
+pub struct FailoverRelay {
+    timeout: Duration,
+    tempo: PllEstimator,
+    last_external_tick: Option<Instant>,
+    flywheel: Option<Flywheel>,
+    // Ticks to swallow after the flywheel ran ahead of the returning device
+    skip_ticks: u64,
+}
+
+struct Flywheel {
+    deadlines: TickDeadlines,
+    emitted: u64,
+}
+
+impl FailoverRelay {
+    pub fn new(timeout: Duration) -> Self {
+        FailoverRelay {
+            timeout,
+            tempo: PllEstimator::default(),
+            last_external_tick: None,
+            flywheel: None,
+            skip_ticks: 0,
+        }
+    }
+
+    /// When `on_timeout` next needs to run, or `None` until the first tick.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match &self.flywheel {
+            Some(flywheel) => Some(flywheel.deadlines.deadline(flywheel.emitted + 1)),
+            None => self.last_external_tick.map(|last| last + self.timeout),
+        }
+    }
+
+    /// Handles a message from the external clock, returning what to pass on.
+    pub fn on_message(&mut self, message: EngineMessage, now: Instant) -> Vec<EngineMessage> {
+        if !matches!(message, EngineMessage::Tick) {
+            return vec![message];
+        }
+
+        let mut messages = Vec::new();
+        match self.flywheel.take() {
+            Some(flywheel) => messages.extend(self.resume_external(flywheel, now)),
+            None if self.last_external_tick.is_none() => {
+                info!("External clock detected");
+                messages.push(EngineMessage::ClockStatus(ClockStatus::External));
+                messages.push(EngineMessage::Tick);
+            }
+            None => self.forward_tick(&mut messages),
+        }
+
+        self.tempo.observe(now);
+        self.last_external_tick = Some(now);
+        messages
+    }
+
+    /// Handles the passing of `next_deadline`, returning flywheel ticks.
+    pub fn on_timeout(&mut self, now: Instant) -> Vec<EngineMessage> {
+        let mut messages = Vec::new();
+        if self.flywheel.is_none() {
+            let Some(flywheel) = self.start_flywheel(now) else {
+                return messages;
+            };
+            self.flywheel = Some(flywheel);
+            messages.push(EngineMessage::ClockStatus(ClockStatus::Flywheel));
+        }
+
+        let flywheel = self.flywheel.as_mut().unwrap();
+        while flywheel.deadlines.deadline(flywheel.emitted + 1) <= now {
+            flywheel.emitted += 1;
+            messages.push(EngineMessage::Tick);
+        }
+        messages
+    }
+
+    fn forward_tick(&mut self, messages: &mut Vec<EngineMessage>) {
+        if self.skip_ticks > 0 {
+            self.skip_ticks -= 1;
+        } else {
+            messages.push(EngineMessage::Tick);
+        }
+    }
+
+    fn start_flywheel(&self, now: Instant) -> Option<Flywheel> {
+        let last_tick = self.last_external_tick?;
+        if now < last_tick + self.timeout {
+            return None;
+        }
+        let bpm = self.tempo.bpm()?;
+        warn!(
+            "No external clock for {} ms, switching to internal flywheel at {:.2} BPM",
+            self.timeout.as_millis(),
+            bpm
+        );
+        // The flywheel continues the device's timeline, so the ticks missed
+        // while waiting out the timeout are emitted straight away
+        Some(Flywheel {
+            deadlines: TickDeadlines::new(last_tick, bpm),
+            emitted: 0,
+        })
+    }
+
+    fn resume_external(&mut self, flywheel: Flywheel, now: Instant) -> Vec<EngineMessage> {
+        let last_tick = self.last_external_tick.unwrap_or(now);
+        let interval_ns = tick_interval_ns(flywheel.deadlines.bpm());
+        // Which tick of the device's timeline this is, counting from the
+        // last one seen before the dropout
+        let expected = (now.duration_since(last_tick).as_nanos() as f64 / interval_ns)
+            .round()
+            .max(1.0) as u64;
+        info!(
+            "External clock resumed after {} flywheel ticks, realigning to {}",
+            flywheel.emitted, expected
+        );
+
+        let mut messages = vec![EngineMessage::ClockStatus(ClockStatus::External)];
+        if expected > flywheel.emitted {
+            let catch_up = expected - flywheel.emitted;
+            messages.extend((0..catch_up).map(|_| EngineMessage::Tick));
+        } else {
+            self.skip_ticks = flywheel.emitted - expected;
+        }
+        messages
+    }
+}
+
+// Reads from the external clock and writes to the engine until either
+// side hangs up
+fn run_relay(
+    mut relay: FailoverRelay,
+    external_rx: Receiver<EngineMessage>,
+    engine_tx: Sender<EngineMessage>,
+) {
+    loop {
+        let received = match relay.next_deadline() {
+            Some(deadline) => {
+                external_rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => external_rx
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        let now = Instant::now();
+        let messages = match received {
+            Ok(message) => relay.on_message(message, now),
+            Err(RecvTimeoutError::Timeout) => relay.on_timeout(now),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        for message in messages {
+            if engine_tx.send(message).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// An external clock routed through a failover relay, started and stopped
+/// as one source. Stopping closes the device first, then lets the relay
+/// forward what is already queued and exit.
+pub struct FailoverClock {
+    device_name: String,
+    timeout: Duration,
+    engine_tx: Sender<EngineMessage>,
+    reconnect: ReconnectPolicy,
+    recovery: RecoveryPolicy,
+    external: Mutex<Option<ExternalClock>>,
+    threads: ClockThreads,
+}
+
+impl FailoverClock {
+    pub fn new(device_name: String, timeout: Duration, engine_tx: Sender<EngineMessage>) -> Self {
+        FailoverClock {
+            device_name,
+            timeout,
+            engine_tx,
+            reconnect: ReconnectPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            external: Mutex::new(None),
+            threads: ClockThreads::default(),
+        }
+    }
+
+    /// Sets how the device is looked for again after it is unplugged; the
+    /// flywheel keeps time meanwhile.
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Sets whether a device missing at start is looked for, or fails the
+    /// start.
+    pub fn with_recovery_policy(mut self, policy: RecoveryPolicy) -> Self {
+        self.recovery = policy;
+        self
+    }
+}
+
+impl ClockSource for FailoverClock {
+    fn start(&self) -> Result<(), MidiError> {
+        if self.threads.begin().is_none() {
+            return Ok(());
+        }
+        let (external_tx, external_rx) = mpsc::channel();
+        let relay = FailoverRelay::new(self.timeout);
+        let engine_tx = self.engine_tx.clone();
+        self.threads
+            .spawn(move || run_relay(relay, external_rx, engine_tx));
+
+        let external = ExternalClock::new(self.device_name.clone(), external_tx)
+            .with_reconnect_policy(self.reconnect)
+            .with_recovery_policy(self.recovery);
+        if let Err(e) = external.start() {
+            // Dropping the external clock lets the relay finish
+            drop(external);
+            self.threads.stop();
+            return Err(e);
+        }
+        *self.external.lock().unwrap() = Some(external);
+        Ok(())
+    }
+
+    fn stop(&self) {
+        // Dropping the external clock hangs up the relay's input
+        if let Some(external) = self.external.lock().unwrap().take() {
+            external.stop();
+        }
+        self.threads.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_loop::TransportAction;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_millis(250);
+
+    fn tick_period(bpm: f64) -> Duration {
+        Duration::from_nanos(tick_interval_ns(bpm).round() as u64)
+    }
+
+    fn count_ticks(messages: &[EngineMessage]) -> usize {
+        messages
+            .iter()
+            .filter(|message| matches!(message, EngineMessage::Tick))
+            .count()
+    }
+
+    fn has_status(messages: &[EngineMessage], status: ClockStatus) -> bool {
+        messages
+            .iter()
+            .any(|message| matches!(message, EngineMessage::ClockStatus(s) if *s == status))
+    }
+
+    /// Feeds a bar of steady external ticks at 120 BPM and returns the time
+    /// of the last one.
+    fn lock_at_120(relay: &mut FailoverRelay, start: Instant) -> Instant {
+        let period = tick_period(120.0);
+        for n in 0..96 {
+            relay.on_message(EngineMessage::Tick, start + period * n);
+        }
+        start + period * 95
+    }
+
+    #[test]
+    fn test_forwards_ticks_and_reports_external_clock() {
+        let mut relay = FailoverRelay::new(TIMEOUT);
+        let start = Instant::now();
+
+        let first = relay.on_message(EngineMessage::Tick, start);
+        assert!(has_status(&first, ClockStatus::External));
+        assert_eq!(count_ticks(&first), 1);
+
+        let second = relay.on_message(EngineMessage::Tick, start + tick_period(120.0));
+        assert_eq!(count_ticks(&second), 1);
+        assert_eq!(second.len(), 1);
+    }
+
+    #[test]
+    fn test_passes_through_transport_messages() {
+        let mut relay = FailoverRelay::new(TIMEOUT);
+        let messages = relay.on_message(
+            EngineMessage::TransportCommand(TransportAction::Start),
+            Instant::now(),
+        );
+        assert!(matches!(
+            messages.as_slice(),
+            [EngineMessage::TransportCommand(TransportAction::Start)]
+        ));
+    }
+
+    #[test]
+    fn test_no_failover_before_first_tick() {
+        let mut relay = FailoverRelay::new(TIMEOUT);
+        assert_eq!(relay.next_deadline(), None);
+        assert!(relay.on_timeout(Instant::now() + TIMEOUT * 4).is_empty());
+    }
+
+    #[test]
+    fn test_switches_to_flywheel_after_timeout() {
+        let mut relay = FailoverRelay::new(TIMEOUT);
+        let last = lock_at_120(&mut relay, Instant::now());
+        assert_eq!(relay.next_deadline(), Some(last + TIMEOUT));
+
+        // 250 ms at 120 BPM is 12 ticks, which are emitted as the flywheel
+        // takes over so no position is lost
+        let messages = relay.on_timeout(last + TIMEOUT);
+        assert!(has_status(&messages, ClockStatus::Flywheel));
+        assert_eq!(count_ticks(&messages), 12);
+
+        // and it keeps ticking at the measured tempo
+        let next = relay.next_deadline().unwrap();
+        assert_eq!(next, last + tick_period(120.0) * 13);
+        assert_eq!(count_ticks(&relay.on_timeout(next)), 1);
+    }
+
+    #[test]
+    fn test_resume_catches_up_when_flywheel_is_behind() {
+        let mut relay = FailoverRelay::new(TIMEOUT);
+        let last = lock_at_120(&mut relay, Instant::now());
+        relay.on_timeout(last + TIMEOUT);
+
+        // The device returns on its 15th tick after the dropout; the flywheel
+        // has only emitted 12, so this tick brings three
+        let period = tick_period(120.0);
+        let messages = relay.on_message(EngineMessage::Tick, last + period * 15);
+        assert!(has_status(&messages, ClockStatus::External));
+        assert_eq!(count_ticks(&messages), 3);
+
+        let next = relay.on_message(EngineMessage::Tick, last + period * 16);
+        assert_eq!(count_ticks(&next), 1);
+    }
+
+    #[test]
+    fn test_resume_skips_ticks_when_flywheel_is_ahead() {
+        let mut relay = FailoverRelay::new(TIMEOUT);
+        let last = lock_at_120(&mut relay, Instant::now());
+        let period = tick_period(120.0);
+        relay.on_timeout(last + TIMEOUT);
+        relay.on_timeout(last + period * 14);
+
+        // The device returns on its 12th tick but 14 have been emitted
+        let period_after = |n: u32| last + period * n;
+        let messages = relay.on_message(EngineMessage::Tick, period_after(12));
+        assert_eq!(count_ticks(&messages), 0);
+        assert_eq!(
+            count_ticks(&relay.on_message(EngineMessage::Tick, period_after(13))),
+            0
+        );
+        assert_eq!(
+            count_ticks(&relay.on_message(EngineMessage::Tick, period_after(14))),
+            0
+        );
+        assert_eq!(
+            count_ticks(&relay.on_message(EngineMessage::Tick, period_after(15))),
+            1
+        );
+    }
+
+    #[test]
+    fn test_relay_thread_keeps_engine_ticking() {
+        let (external_tx, external_rx) = std::sync::mpsc::channel();
+        let (engine_tx, engine_rx) = std::sync::mpsc::channel();
+        let relay = FailoverRelay::new(Duration::from_millis(20));
+        thread::spawn(move || run_relay(relay, external_rx, engine_tx));
+
+        for _ in 0..4 {
+            external_tx.send(EngineMessage::Tick).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let flywheel_started = engine_rx
+            .iter()
+            .take(50)
+            .any(|message| matches!(message, EngineMessage::ClockStatus(ClockStatus::Flywheel)));
+        assert!(flywheel_started);
+    }
+
+    #[test]
+    fn test_failover_clock_stop_shuts_down_the_relay() {
+        let (engine_tx, _engine_rx) = std::sync::mpsc::channel();
+        let clock = FailoverClock::new("NonExistentDevice12345".to_string(), TIMEOUT, engine_tx);
+
+        // The device is missing, so only the relay is left waiting; stop
+        // must still return with every thread joined
+        clock.start().unwrap();
+        clock.stop();
+        assert!(!clock.threads.is_running());
+        assert!(clock.external.lock().unwrap().is_none());
+    }
+}
@@ -1,7 +1,22 @@
 // config.rs
 
+use crate::error::RecoveryPolicy;
+use crate::latency::PortLatency;
+use crate::link::LinkConfig;
+use crate::meter::MeterMap;
+use crate::mtc::FrameRate;
+use crate::output_worker::{OverflowPolicy, DEFAULT_QUEUE_CAPACITY};
+use crate::reconnect::ReconnectPolicy;
+use crate::routing::{OutputPortSpec, Route, RoutingTable};
+use crate::swing::{Swing, SwingGrid};
+use crate::tempo_estimator::TempoEstimatorKind;
+use crate::tempo_map::TempoMap;
+use crate::thru::{InputPortSpec, ThruRoute, ThruTable};
 use clap::{Arg, Command};
-use log::{debug, info};
+use log::{debug, error, info};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
 
 pub struct Config {
     pub bpm: u32,
@@ -13,64 +28,257 @@ pub struct Config {
     pub midi_output_device: Option<String>, // New field for MIDI output
     pub send_test_note: bool,               // For testing MIDI output
     pub direct_test: bool,                  // For direct MIDI output test
+    pub tempo_map: TempoMap,
+    pub clock_master: bool,                // Transmit MIDI clock and transport
+    pub clock_output_devices: Vec<String>, // Extra ports that only receive clock
+    pub output_latencies: Vec<PortLatency>, // How late each output port's device sounds
+    pub output_ports: Vec<OutputPortSpec>, // Named outputs that routes send to
+    pub routing: RoutingTable,             // Which outputs each track and channel goes to
+    pub input_ports: Vec<InputPortSpec>,   // Named inputs played through to the outputs
+    pub thru: ThruTable, // Which outputs each input's messages are played through to
+    pub output_queue_capacity: usize, // Messages each output queues for its sender thread
+    pub output_overflow: OverflowPolicy, // What gives when an output's queue is full
+    pub midi_panic: bool, // Full panic, not just NoteOffs, when releasing notes
+    pub reconnect: ReconnectPolicy, // Finding unplugged MIDI devices again
+    pub recovery: RecoveryPolicy, // Devices missing at startup: retry, degrade or abort
+    pub resume_recording: ResumeRecording,
+    pub tempo_estimator: TempoEstimatorKind,
+    pub clock_timeout: Option<Duration>, // Fail over to a flywheel after this long without external ticks
+    pub link: LinkConfig,
+    pub mtc_rate: FrameRate,
+    pub mtc_output: bool,                 // Generate MTC on the MIDI output
+    pub mtc_input_device: Option<String>, // Follow MTC from this device
+    pub swing: Swing,
+    pub meter: MeterMap,
+}
+
+/// What happens to a recording paused along with the transport when it resumes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResumeRecording {
+    /// Suspend the take on pause and carry on writing it on resume
+    ContinueTake,
+    /// Close the take on pause and start a new one on resume
+    #[default]
+    NewTake,
 }
 
 #[derive(PartialEq)]
 pub enum ClockSource {
     Internal,
     External,
+    Link,
 }
 
 impl Config {
     fn parse_arguments() -> clap::ArgMatches {
         Command::new("Phasorsyncrs")
+            .args(Self::clock_arguments())
+            .args(Self::musical_arguments())
+            .args(Self::output_arguments())
+            .args(Self::midi_arguments())
+            .args(Self::thru_arguments())
+            .args(Self::recovery_arguments())
             .arg(
-                Arg::new("bpm")
-                    .short('b')
-                    .long("bpm")
-                    .value_name("BPM")
-                    .help("Sets the beats per minute")
-                    .required(false),
-            )
-            .arg(
-                Arg::new("clock-source")
-                    .short('c')
-                    .long("clock-source")
-                    .value_name("SOURCE")
-                    .help("Sets the clock source (internal/external)")
-                    .required(false),
-            )
-            .arg(
-                Arg::new("bind-to-device")
-                    .long("bind-to-device")
-                    .value_name("DEVICE")
-                    .help("Sets the external MIDI device to bind to")
-                    .required(false),
-            )
-            .arg(
-                Arg::new("midi-output")
-                    .long("midi-output")
-                    .value_name("DEVICE")
-                    .help("Sets the MIDI output device")
-                    .required(false),
-            )
-            .arg(
-                Arg::new("test-note")
-                    .long("test-note")
-                    .help("Send a test MIDI note on startup")
-                    .action(clap::ArgAction::SetTrue)
-                    .required(false),
-            )
-            .arg(
-                Arg::new("direct-test")
-                    .long("direct-test")
-                    .help("Run a direct MIDI output test")
-                    .action(clap::ArgAction::SetTrue)
+                Arg::new("resume-recording")
+                    .long("resume-recording")
+                    .value_name("MODE")
+                    .help("On resume after a pause, continue the take or start a new one (continue/new-take)")
                     .required(false),
             )
             .get_matches()
     }
 
+    fn clock_arguments() -> Vec<Arg> {
+        vec![
+            Arg::new("bpm")
+                .short('b')
+                .long("bpm")
+                .value_name("BPM")
+                .help("Sets the beats per minute")
+                .required(false),
+            Arg::new("clock-source")
+                .short('c')
+                .long("clock-source")
+                .value_name("SOURCE")
+                .help("Sets the clock source (internal/external/link)")
+                .required(false),
+            Arg::new("tempo-estimator")
+                .long("tempo-estimator")
+                .value_name("ESTIMATOR")
+                .help("How BPM is measured from incoming ticks (average/pll)")
+                .required(false),
+            Arg::new("link-port")
+                .long("link-port")
+                .value_name("PORT")
+                .help("UDP port for Link discovery (default 20808)")
+                .required(false),
+            Arg::new("link-peer")
+                .long("link-peer")
+                .value_name("ADDR")
+                .help("Announce to this Link peer directly instead of by multicast")
+                .action(clap::ArgAction::Append)
+                .required(false),
+            Arg::new("clock-timeout-ms")
+                .long("clock-timeout-ms")
+                .value_name("MS")
+                .help("Run on from the last measured tempo when external clock stops for this long")
+                .required(false),
+        ]
+    }
+
+    fn musical_arguments() -> Vec<Arg> {
+        vec![
+            Arg::new("tempo-map")
+                .long("tempo-map")
+                .value_name("MAP")
+                .help("Bar-indexed tempo changes, e.g. 1:120,17:140:linear:4,33:90:exp:2")
+                .required(false),
+            Arg::new("meter")
+                .long("meter")
+                .value_name("MAP")
+                .help("Time signature, or bar-indexed changes, e.g. 7/8 or 1:4/4,9:7/8")
+                .required(false),
+            Arg::new("swing")
+                .long("swing")
+                .value_name("PERCENT")
+                .help("Swing amount from 50 (straight) to 75")
+                .required(false),
+            Arg::new("swing-grid")
+                .long("swing-grid")
+                .value_name("GRID")
+                .help("Subdivision to swing (8/16)")
+                .required(false),
+        ]
+    }
+
+    fn output_arguments() -> Vec<Arg> {
+        vec![
+            Arg::new("midi-output")
+                .long("midi-output")
+                .value_name("DEVICE")
+                .help("Sets the MIDI output device")
+                .required(false),
+            Arg::new("clock-master")
+                .long("clock-master")
+                .help("Transmit MIDI clock, Start, Continue and Stop to the outputs")
+                .action(clap::ArgAction::SetTrue)
+                .required(false),
+            Arg::new("clock-output")
+                .long("clock-output")
+                .value_name("DEVICE")
+                .help("Additional MIDI output that only receives clock (implies --clock-master)")
+                .action(clap::ArgAction::Append)
+                .required(false),
+            Arg::new("output-latency")
+                .long("output-latency")
+                .value_name("PORT=LATENCY")
+                .help("Latency of an output port's device in ms or ticks, e.g. \"OXI ONE=12ms\" or TR-8=-24t")
+                .action(clap::ArgAction::Append)
+                .required(false),
+            Arg::new("output-queue")
+                .long("output-queue")
+                .value_name("MESSAGES")
+                .help("Messages each MIDI output queues for its sender thread (default 1024)")
+                .required(false),
+            Arg::new("output-overflow")
+                .long("output-overflow")
+                .value_name("POLICY")
+                .help("When an output's queue is full: drop-oldest, drop-newest or block (default drop-newest)")
+                .required(false),
+            Arg::new("output")
+                .long("output")
+                .value_name("NAME=DEVICE")
+                .help("Named MIDI output for routes to send to, e.g. bass=Minilogue")
+                .action(clap::ArgAction::Append)
+                .required(false),
+            Arg::new("route")
+                .long("route")
+                .value_name("SOURCE=PORT[:CHANNEL]")
+                .help("Send a track or channel to a named output, e.g. track:1=bass:2 or ch:10=drums (default output: main)")
+                .action(clap::ArgAction::Append)
+                .required(false),
+        ]
+    }
+
+    // Inputs played through to the outputs alongside what is generated
+    fn thru_arguments() -> Vec<Arg> {
+        vec![
+            Arg::new("input")
+                .long("input")
+                .value_name("NAME=DEVICE")
+                .help("Named MIDI input for thru routes to play from, e.g. keys=KeyStep")
+                .action(clap::ArgAction::Append)
+                .required(false),
+            Arg::new("thru")
+                .long("thru")
+                .value_name("INPUT=PORT[:CHANNEL][,FILTER]")
+                .help("Play an input through to a named output, e.g. keys=bass:2,ch=1,type=note+bend,notes=24-59; inputs clock and timecode are the --bind-to-device and --mtc-in devices, and type=clock or transport forwards their sync (default: channel messages)")
+                .action(clap::ArgAction::Append)
+                .required(false),
+        ]
+    }
+
+    // How missing and unplugged devices are dealt with
+    fn recovery_arguments() -> Vec<Arg> {
+        vec![
+            Arg::new("reconnect-interval")
+                .long("reconnect-interval")
+                .value_name("SECONDS")
+                .help("How often to look for a missing or unplugged MIDI device (default 2)")
+                .required(false),
+            Arg::new("reconnect-attempts")
+                .long("reconnect-attempts")
+                .value_name("COUNT")
+                .help("Attempts to find a missing MIDI device before giving up; 0 never reconnects (default: keep trying)")
+                .required(false),
+            Arg::new("on-error")
+                .long("on-error")
+                .value_name("POLICY")
+                .help("When a MIDI device or the clock fails to start: retry, degrade (carry on without it) or abort (default retry)")
+                .required(false),
+        ]
+    }
+
+    fn midi_arguments() -> Vec<Arg> {
+        vec![
+            Arg::new("bind-to-device")
+                .long("bind-to-device")
+                .value_name("DEVICE")
+                .help("Sets the external MIDI device to bind to")
+                .required(false),
+            Arg::new("midi-panic")
+                .long("midi-panic")
+                .help("Send All Notes Off and All Sound Off on every channel on stop, clock loss and exit")
+                .action(clap::ArgAction::SetTrue)
+                .required(false),
+            Arg::new("mtc-in")
+                .long("mtc-in")
+                .value_name("DEVICE")
+                .help("Follow MIDI Time Code from this device")
+                .required(false),
+            Arg::new("mtc-out")
+                .long("mtc-out")
+                .help("Generate MIDI Time Code on the MIDI output")
+                .action(clap::ArgAction::SetTrue)
+                .required(false),
+            Arg::new("mtc-rate")
+                .long("mtc-rate")
+                .value_name("FPS")
+                .help("MIDI Time Code frame rate (24/25/29.97df/30)")
+                .required(false),
+            Arg::new("test-note")
+                .long("test-note")
+                .help("Send a test MIDI note on startup")
+                .action(clap::ArgAction::SetTrue)
+                .required(false),
+            Arg::new("direct-test")
+                .long("direct-test")
+                .help("Run a direct MIDI output test")
+                .action(clap::ArgAction::SetTrue)
+                .required(false),
+        ]
+    }
+
     // Parse BPM from command line arguments
     fn parse_bpm(matches: &clap::ArgMatches) -> u32 {
         let bpm = matches
@@ -84,6 +292,278 @@ impl Config {
         bpm
     }
 
+    // Parse the tempo map, falling back to an empty map if it is malformed
+    fn parse_tempo_map(matches: &clap::ArgMatches) -> TempoMap {
+        let Some(spec) = matches.get_one::<String>("tempo-map") else {
+            return TempoMap::default();
+        };
+
+        match TempoMap::parse(spec) {
+            Ok(map) => {
+                info!("Loaded tempo map with {} changes", map.changes().len());
+                map
+            }
+            Err(e) => {
+                error!("Ignoring tempo map: {}", e);
+                TempoMap::default()
+            }
+        }
+    }
+
+    // Parse the meter, falling back to 4/4 throughout if it is malformed
+    fn parse_meter(matches: &clap::ArgMatches) -> MeterMap {
+        let Some(spec) = matches.get_one::<String>("meter") else {
+            return MeterMap::default();
+        };
+
+        match MeterMap::parse(spec) {
+            Ok(map) => {
+                info!("Loaded meter map with {} changes", map.changes().len());
+                map
+            }
+            Err(e) => {
+                error!("Ignoring meter: {}", e);
+                MeterMap::default()
+            }
+        }
+    }
+
+    // Test note and direct MIDI output test flags
+    fn parse_test_flags(matches: &clap::ArgMatches) -> (bool, bool) {
+        let send_test_note = matches.get_flag("test-note");
+        if send_test_note {
+            info!("Test note flag enabled - will send a test note on startup");
+        }
+
+        let direct_test = matches.get_flag("direct-test");
+        if direct_test {
+            info!("Direct MIDI test flag enabled - will run direct MIDI output test");
+        }
+        (send_test_note, direct_test)
+    }
+
+    // Parse per-port output latencies, skipping malformed entries
+    fn parse_output_latencies(matches: &clap::ArgMatches) -> Vec<PortLatency> {
+        Self::parse_specs(
+            matches,
+            "output-latency",
+            "output latency",
+            PortLatency::parse,
+        )
+    }
+
+    // Parse the named outputs and the routes to them, skipping malformed entries
+    fn parse_routing(matches: &clap::ArgMatches) -> (Vec<OutputPortSpec>, RoutingTable) {
+        let ports = Self::parse_specs(matches, "output", "output", OutputPortSpec::parse);
+        let routes = Self::parse_specs(matches, "route", "route", Route::parse);
+        (ports, RoutingTable::new(routes))
+    }
+
+    // Parse the named inputs and the thru routes from them, skipping
+    // malformed entries
+    fn parse_thru(matches: &clap::ArgMatches) -> (Vec<InputPortSpec>, ThruTable) {
+        let inputs = Self::parse_specs(matches, "input", "input", InputPortSpec::parse);
+        let routes = Self::parse_specs(matches, "thru", "thru route", ThruRoute::parse);
+        (inputs, ThruTable::new(routes))
+    }
+
+    // Parse every value given for `id`, logging and skipping the ones
+    // `parse` rejects
+    fn parse_specs<T, E: fmt::Display>(
+        matches: &clap::ArgMatches,
+        id: &str,
+        what: &str,
+        parse: impl Fn(&str) -> Result<T, E>,
+    ) -> Vec<T> {
+        matches
+            .get_many::<String>(id)
+            .into_iter()
+            .flatten()
+            .filter_map(|spec| {
+                parse(spec)
+                    .map_err(|e| error!("Ignoring {}: {}", what, e))
+                    .ok()
+            })
+            .collect()
+    }
+
+    // Parse the output queue size and overflow policy, keeping the
+    // defaults for anything malformed
+    fn parse_output_queue(matches: &clap::ArgMatches) -> (usize, OverflowPolicy) {
+        let capacity = match matches.get_one::<String>("output-queue") {
+            Some(value) => value.parse().ok().filter(|&n| n > 0).unwrap_or_else(|| {
+                error!("Ignoring output queue size '{}'", value);
+                DEFAULT_QUEUE_CAPACITY
+            }),
+            None => DEFAULT_QUEUE_CAPACITY,
+        };
+        let overflow = match matches.get_one::<String>("output-overflow") {
+            Some(value) => OverflowPolicy::parse(value).unwrap_or_else(|| {
+                error!("Ignoring output overflow policy '{}'", value);
+                OverflowPolicy::default()
+            }),
+            None => OverflowPolicy::default(),
+        };
+        (capacity, overflow)
+    }
+
+    fn parse_recovery(matches: &clap::ArgMatches) -> RecoveryPolicy {
+        let policy = match matches.get_one::<String>("on-error") {
+            Some(value) => RecoveryPolicy::parse(value).unwrap_or_else(|| {
+                error!("Ignoring recovery policy '{}'", value);
+                RecoveryPolicy::default()
+            }),
+            None => RecoveryPolicy::default(),
+        };
+        debug!("Recovery policy: {}", policy);
+        policy
+    }
+
+    // Parse the device reconnect policy, keeping the defaults for anything malformed
+    fn parse_reconnect(matches: &clap::ArgMatches) -> ReconnectPolicy {
+        let mut policy = ReconnectPolicy::default();
+        if let Some(value) = matches.get_one::<String>("reconnect-interval") {
+            match value.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                    policy.interval = Duration::from_secs_f64(seconds)
+                }
+                _ => error!("Ignoring reconnect interval '{}'", value),
+            }
+        }
+        if let Some(value) = matches.get_one::<String>("reconnect-attempts") {
+            match value.parse::<u32>() {
+                Ok(attempts) => policy.max_attempts = Some(attempts),
+                Err(_) => error!("Ignoring reconnect attempts '{}'", value),
+            }
+        }
+        debug!("Reconnect policy: {:?}", policy);
+        policy
+    }
+
+    // Clock-only outputs imply clock master mode
+    fn parse_clock_master(matches: &clap::ArgMatches) -> (bool, Vec<String>) {
+        let clock_output_devices: Vec<String> = matches
+            .get_many::<String>("clock-output")
+            .map(|devices| devices.cloned().collect())
+            .unwrap_or_default();
+        let clock_master = matches.get_flag("clock-master") || !clock_output_devices.is_empty();
+        if clock_master {
+            info!(
+                "MIDI clock master enabled, extra clock outputs: {:?}",
+                clock_output_devices
+            );
+        }
+        (clock_master, clock_output_devices)
+    }
+
+    // Parse the external clock failover timeout; failover is off unless given
+    fn parse_clock_timeout(matches: &clap::ArgMatches) -> Option<Duration> {
+        let value = matches.get_one::<String>("clock-timeout-ms")?;
+        match value.parse::<u64>() {
+            Ok(ms) if ms > 0 => {
+                info!("External clock failover after {} ms", ms);
+                Some(Duration::from_millis(ms))
+            }
+            _ => {
+                error!("Ignoring invalid clock timeout '{}'", value);
+                None
+            }
+        }
+    }
+
+    // Parse the Link peer settings, skipping peers that are not addresses
+    fn parse_link(matches: &clap::ArgMatches) -> LinkConfig {
+        let mut link = LinkConfig::default();
+        if let Some(port) = matches.get_one::<String>("link-port") {
+            match port.parse::<u16>() {
+                Ok(port) => link.port = port,
+                Err(_) => error!("Ignoring invalid Link port '{}'", port),
+            }
+        }
+        for peer in matches
+            .get_many::<String>("link-peer")
+            .into_iter()
+            .flatten()
+        {
+            match peer.parse::<SocketAddr>() {
+                Ok(addr) => link.peers.push(addr),
+                Err(_) => error!("Ignoring invalid Link peer '{}'", peer),
+            }
+        }
+        link
+    }
+
+    // Parse the MTC frame rate (default 30 fps), output flag and input device
+    fn parse_mtc(matches: &clap::ArgMatches) -> (FrameRate, bool, Option<String>) {
+        let rate = match matches.get_one::<String>("mtc-rate") {
+            Some(value) => FrameRate::parse(value).unwrap_or_else(|| {
+                error!("Unknown MTC frame rate '{}', using 30", value);
+                FrameRate::default()
+            }),
+            None => FrameRate::default(),
+        };
+        let output = matches.get_flag("mtc-out");
+        let input_device = matches.get_one::<String>("mtc-in").cloned();
+        if output || input_device.is_some() {
+            info!(
+                "MTC at {} fps, output: {}, input: {:?}",
+                rate, output, input_device
+            );
+        }
+        (rate, output, input_device)
+    }
+
+    // Parse swing amount and grid, defaulting to straight sixteenths
+    fn parse_swing(matches: &clap::ArgMatches) -> Swing {
+        let percent = match matches.get_one::<String>("swing") {
+            Some(value) => value.parse::<f64>().unwrap_or_else(|_| {
+                error!("Invalid swing '{}', playing straight", value);
+                50.0
+            }),
+            None => 50.0,
+        };
+        let grid = match matches.get_one::<String>("swing-grid") {
+            Some(value) => SwingGrid::parse(value).unwrap_or_else(|| {
+                error!("Unknown swing grid '{}', using 16th", value);
+                SwingGrid::Sixteenth
+            }),
+            None => SwingGrid::Sixteenth,
+        };
+        let swing = Swing::new(percent, grid);
+        debug!("Swing: {}", swing);
+        swing
+    }
+
+    // Parse the tempo estimator, defaulting to the moving average
+    fn parse_tempo_estimator(matches: &clap::ArgMatches) -> TempoEstimatorKind {
+        match matches
+            .get_one::<String>("tempo-estimator")
+            .map(|s| s.as_str())
+        {
+            Some("pll") => TempoEstimatorKind::PhaseLocked,
+            Some("average") | None => TempoEstimatorKind::MovingAverage,
+            Some(other) => {
+                error!("Unknown tempo estimator '{}', using average", other);
+                TempoEstimatorKind::MovingAverage
+            }
+        }
+    }
+
+    // Parse the recording resume policy, defaulting to a new take
+    fn parse_resume_recording(matches: &clap::ArgMatches) -> ResumeRecording {
+        match matches
+            .get_one::<String>("resume-recording")
+            .map(|s| s.as_str())
+        {
+            Some("continue") => ResumeRecording::ContinueTake,
+            Some("new-take") | None => ResumeRecording::NewTake,
+            Some(other) => {
+                error!("Unknown resume-recording mode '{}', using new-take", other);
+                ResumeRecording::NewTake
+            }
+        }
+    }
+
     // Determine clock source based on arguments
     fn determine_clock_source(matches: &clap::ArgMatches) -> ClockSource {
         let clock_source_arg = matches
@@ -101,6 +581,9 @@ impl Config {
         } else if clock_source_arg == "external" {
             info!("External clock mode selected via --clock-source");
             ClockSource::External
+        } else if clock_source_arg == "link" {
+            info!("Link clock mode selected via --clock-source");
+            ClockSource::Link
         } else {
             info!("Using internal clock mode");
             ClockSource::Internal
@@ -124,17 +607,24 @@ impl Config {
         let midi_output_device = matches.get_one::<String>("midi-output").cloned();
         debug!("MIDI output device argument: {:?}", midi_output_device);
 
-        // Test note flag
-        let send_test_note = matches.get_flag("test-note");
-        if send_test_note {
-            info!("Test note flag enabled - will send a test note on startup");
-        }
+        let (send_test_note, direct_test) = Self::parse_test_flags(&matches);
 
-        // Direct test flag
-        let direct_test = matches.get_flag("direct-test");
-        if direct_test {
-            info!("Direct MIDI test flag enabled - will run direct MIDI output test");
-        }
+        let tempo_map = Self::parse_tempo_map(&matches);
+
+        // Clock master mode, optionally with clock-only output ports
+        let (clock_master, clock_output_devices) = Self::parse_clock_master(&matches);
+        let output_latencies = Self::parse_output_latencies(&matches);
+        let (output_ports, routing) = Self::parse_routing(&matches);
+        let (input_ports, thru) = Self::parse_thru(&matches);
+        let (output_queue_capacity, output_overflow) = Self::parse_output_queue(&matches);
+
+        let resume_recording = Self::parse_resume_recording(&matches);
+        debug!("Resume recording mode: {:?}", resume_recording);
+
+        let tempo_estimator = Self::parse_tempo_estimator(&matches);
+        debug!("Tempo estimator: {:?}", tempo_estimator);
+
+        let (mtc_rate, mtc_output, mtc_input_device) = Self::parse_mtc(&matches);
 
         Config {
             bpm,
@@ -144,6 +634,28 @@ impl Config {
             midi_output_device,
             send_test_note,
             direct_test,
+            tempo_map,
+            clock_master,
+            clock_output_devices,
+            output_latencies,
+            output_ports,
+            routing,
+            input_ports,
+            thru,
+            output_queue_capacity,
+            output_overflow,
+            midi_panic: matches.get_flag("midi-panic"),
+            reconnect: Self::parse_reconnect(&matches),
+            recovery: Self::parse_recovery(&matches),
+            resume_recording,
+            tempo_estimator,
+            clock_timeout: Self::parse_clock_timeout(&matches),
+            link: Self::parse_link(&matches),
+            mtc_rate,
+            mtc_output,
+            mtc_input_device,
+            swing: Self::parse_swing(&matches),
+            meter: Self::parse_meter(&matches),
         }
     }
 }
@@ -154,6 +666,12 @@ impl Default for Config {
     }
 }
 
-pub const TICKS_PER_BEAT: u64 = 24;
-pub const BEATS_PER_BAR: u64 = 4;
+// Engine resolution. Positions, note lengths and the musical graph all run
+// at this rate; MIDI clock pulses are interpolated up to it.
+pub const TICKS_PER_BEAT: u64 = 960;
+// MIDI clock always runs at 24 pulses per quarter note
+pub const MIDI_CLOCK_PPQN: u64 = 24;
+pub const TICKS_PER_CLOCK: u64 = TICKS_PER_BEAT / MIDI_CLOCK_PPQN;
+// Song Position Pointer counts "MIDI beats", which are sixteenth notes
+pub const TICKS_PER_SONG_POSITION_BEAT: u64 = TICKS_PER_BEAT / 4;
 pub const BARS_PER_PHRASE: u64 = 4;
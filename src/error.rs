@@ -0,0 +1,179 @@
+// error.rs
+
+use std::fmt;
+
+/// What can go wrong setting up or running the clock, MIDI inputs and
+/// MIDI outputs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiError {
+    /// The MIDI system itself could not be opened
+    Backend(String),
+    /// No port's name contains the device name, which is empty when any
+    /// port would have done
+    PortNotFound {
+        device: String,
+        available: Vec<String>,
+    },
+    /// The device's port was found but would not connect
+    Connect { device: String, reason: String },
+    /// A connected device went away
+    PortDisconnected { device: String },
+    /// A message was sent to an output that is not connected
+    PortClosed { port: String },
+    /// An output queue was full, so messages were dropped
+    BufferOverrun { dropped_events: usize },
+    /// The Link session could not be joined
+    Network(String),
+    /// The configuration asks for something that cannot be set up
+    Config(String),
+    /// The engine has shut down and takes no more messages
+    EngineStopped,
+}
+
+impl MidiError {
+    /// True for errors that may clear up by themselves, such as a device
+    /// that is not plugged in yet, so trying again is worthwhile.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            MidiError::Backend(_)
+                | MidiError::PortNotFound { .. }
+                | MidiError::Connect { .. }
+                | MidiError::PortDisconnected { .. }
+                | MidiError::PortClosed { .. }
+                | MidiError::BufferOverrun { .. }
+        )
+    }
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiError::Backend(reason) => write!(f, "MIDI unavailable: {}", reason),
+            MidiError::PortNotFound { device, .. } if device.is_empty() => {
+                write!(f, "no MIDI ports available")
+            }
+            MidiError::PortNotFound { device, available } if available.is_empty() => {
+                write!(f, "MIDI device '{}' not found (no ports available)", device)
+            }
+            MidiError::PortNotFound { device, available } => write!(
+                f,
+                "MIDI device '{}' not found (available: {})",
+                device,
+                available.join(", ")
+            ),
+            MidiError::Connect { device, reason } => {
+                write!(f, "failed to connect to '{}': {}", device, reason)
+            }
+            MidiError::PortDisconnected { device } => write!(f, "'{}' is disconnected", device),
+            MidiError::PortClosed { port } => write!(f, "MIDI output '{}' not connected", port),
+            MidiError::BufferOverrun { dropped_events } => {
+                write!(f, "output queue full, {} messages dropped", dropped_events)
+            }
+            MidiError::Network(reason) => write!(f, "Link unavailable: {}", reason),
+            MidiError::Config(reason) => write!(f, "invalid configuration: {}", reason),
+            MidiError::EngineStopped => write!(f, "engine has stopped"),
+        }
+    }
+}
+
+impl std::error::Error for MidiError {}
+
+/// What to do about a clock, input or output that fails to set up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Keep looking for missing devices as the reconnect policy allows
+    #[default]
+    Retry,
+    /// Carry on without the failed device, on the internal clock if it
+    /// was the clock
+    Degrade,
+    /// Refuse to start
+    Abort,
+}
+
+impl RecoveryPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "retry" => Some(RecoveryPolicy::Retry),
+            "degrade" => Some(RecoveryPolicy::Degrade),
+            "abort" => Some(RecoveryPolicy::Abort),
+            _ => None,
+        }
+    }
+
+    /// What this policy does about `error`. Errors that retrying cannot
+    /// fix are degraded instead, and a stopped engine always aborts.
+    pub fn recover(self, error: &MidiError) -> Recovery {
+        match (self, error) {
+            (_, MidiError::EngineStopped) | (RecoveryPolicy::Abort, _) => Recovery::Abort,
+            (RecoveryPolicy::Retry, error) if error.is_retryable() => Recovery::Retry,
+            _ => Recovery::Degrade,
+        }
+    }
+}
+
+impl fmt::Display for RecoveryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecoveryPolicy::Retry => write!(f, "retry"),
+            RecoveryPolicy::Degrade => write!(f, "degrade"),
+            RecoveryPolicy::Abort => write!(f, "abort"),
+        }
+    }
+}
+
+/// The action a recovery policy picks for one error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Recovery {
+    Retry,
+    Degrade,
+    Abort,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn not_found() -> MidiError {
+        MidiError::PortNotFound {
+            device: "OXI".to_string(),
+            available: vec!["Midi Through".to_string(), "TR-8".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_policies_pick_their_recovery_for_device_errors() {
+        assert_eq!(RecoveryPolicy::Retry.recover(&not_found()), Recovery::Retry);
+        assert_eq!(
+            RecoveryPolicy::Degrade.recover(&not_found()),
+            Recovery::Degrade
+        );
+        assert_eq!(RecoveryPolicy::Abort.recover(&not_found()), Recovery::Abort);
+    }
+
+    #[test]
+    fn test_errors_retrying_cannot_fix_are_degraded() {
+        let link = MidiError::Network("address in use".to_string());
+        assert_eq!(RecoveryPolicy::Retry.recover(&link), Recovery::Degrade);
+        assert_eq!(RecoveryPolicy::Abort.recover(&link), Recovery::Abort);
+        assert_eq!(
+            RecoveryPolicy::Degrade.recover(&MidiError::EngineStopped),
+            Recovery::Abort
+        );
+    }
+
+    #[test]
+    fn test_parses_and_describes() {
+        assert_eq!(
+            RecoveryPolicy::parse(" Degrade"),
+            Some(RecoveryPolicy::Degrade)
+        );
+        assert_eq!(RecoveryPolicy::parse("panic"), None);
+        assert_eq!(RecoveryPolicy::Abort.to_string(), "abort");
+        assert_eq!(
+            not_found().to_string(),
+            "MIDI device 'OXI' not found (available: Midi Through, TR-8)"
+        );
+    }
+}
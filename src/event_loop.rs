@@ -1,30 +1,119 @@
 // event_loop.rs
 
+use crate::clock::{JitterMonitor, PulseInterpolator, TempoHandle};
+use crate::clock_control::ClockSelection;
+use crate::config::{
+    ResumeRecording, MIDI_CLOCK_PPQN, TICKS_PER_BEAT, TICKS_PER_SONG_POSITION_BEAT,
+};
+use crate::meter::TimeSignature;
+use crate::midi_input::MessageKind;
 use crate::midi_output::{MidiMessage, MidiOutput, MidiOutputManager};
+use crate::mtc::{FrameRate, MtcGenerator, Timecode};
+use crate::reconnect::DeviceStatus;
 use crate::state;
+use crate::swing::Swing;
+use crate::tempo_estimator::{PllEstimator, TempoEstimatorKind};
 use log::{debug, error, info, trace, warn};
 use std::collections::VecDeque;
 use std::env;
 use std::fs;
 use std::io;
 use std::process::{Child, Command, ExitStatus, Stdio};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-const TICK_HISTORY_SIZE: usize = 24 * 4; // Store last 4 beats (1 bar)
+const TICK_HISTORY_SIZE: usize = MIDI_CLOCK_PPQN as usize * 4; // Store last 4 beats (1 bar)
+
+// Incoming MTC only relocates the transport when it is further off than a sixteenth
+const MTC_CHASE_TOLERANCE_TICKS: u64 = TICKS_PER_SONG_POSITION_BEAT;
+const MIDDLE_C_DURATION_TICKS: u64 = 2 * TICKS_PER_BEAT;
+// With no pulse for this long the clock is taken as lost and sounding notes
+// are released, since their NoteOffs are waiting on ticks
+const CLOCK_LOSS_TIMEOUT: Duration = Duration::from_secs(1);
+// How often the output counts, device connections and clock jitter are
+// published. Taking them locks every port's queue, so it stays off the tick
+// path.
+const STATUS_INTERVAL: Duration = Duration::from_millis(250);
+/// How long `shut_down` waits for the engine to release notes.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+// How long shutdown waits for the output ports to write the final NoteOffs
+const OUTPUT_FLUSH_TIMEOUT: Duration = Duration::from_millis(200);
 
 #[derive(Debug)]
 pub enum EngineMessage {
+    /// One MIDI clock pulse (24 PPQN). The engine interpolates its own
+    /// finer ticks between pulses.
     Tick,
+    /// A pulse stamped with when it happened, for clocks running on virtual time.
+    TickAt(Instant),
     TransportCommand(TransportAction),
+    /// Set the internal clock tempo in BPM; takes effect on the next tick.
+    SetTempo(f64),
+    /// Adjust the internal clock tempo by a relative amount in BPM.
+    NudgeTempo(f64),
+    /// Replace the swing applied to the musical graph.
+    SetSwing(Swing),
+    /// Adjust the swing amount by a relative number of percent.
+    NudgeSwing(f64),
+    /// Change the time signature from the next bar line on.
+    SetTimeSignature(TimeSignature),
+    /// Move the transport to an absolute tick position.
+    Locate(u64),
+    /// The clock layer switched between external and flywheel ticks.
+    ClockStatus(state::ClockStatus),
+    /// A different clock source is taking over; sent after the old one has
+    /// stopped and before the new one starts.
+    ClockSwitched(ClockSelection),
+    /// The internal clock started; its tick lateness is read through this.
+    ClockJitter(JitterMonitor),
+    /// Running MIDI Time Code from a completed quarter-frame sequence.
+    Timecode(Timecode),
+    /// MIDI Time Code full-frame locate.
+    LocateTimecode(Timecode),
+    /// End every sounding note and send All Notes Off and All Sound Off on
+    /// every channel.
+    Panic,
+    /// A MIDI device connected, went away or is being looked for again.
+    DeviceStatus(DeviceStatus),
+    /// A message from the named MIDI input, to play through to the
+    /// outputs. Clock, transport and timecode from the clock and timecode
+    /// inputs also arrive as their own engine messages.
+    MidiInput {
+        input: String,
+        message: MidiMessage,
+    },
+    /// Release notes and stop recording before the process exits; the
+    /// engine replies on the sender once it is safe to exit.
+    Shutdown(Sender<()>),
+}
+
+/// Asks the engine to release sounding notes and waits for it, within
+/// reason, so exiting does not leave notes hanging. Returns false if the
+/// engine did not confirm, e.g. because it has already stopped.
+pub fn shut_down(engine_tx: &Sender<EngineMessage>) -> bool {
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    if engine_tx.send(EngineMessage::Shutdown(done_tx)).is_err() {
+        return false;
+    }
+    let confirmed = done_rx.recv_timeout(SHUTDOWN_TIMEOUT).is_ok();
+    if !confirmed {
+        warn!("Engine did not confirm shutdown in time");
+    }
+    confirmed
 }
 
 #[derive(Debug)]
 pub enum TransportAction {
+    /// Play from the top
     Start,
+    /// Halt and rewind to the top
     Stop,
+    /// Halt but hold the current position
+    Pause,
+    /// Play from the held position
+    Continue,
 }
 
 pub struct EventLoop {
@@ -34,6 +123,20 @@ pub struct EventLoop {
     tick_history: Mutex<VecDeque<Duration>>,
     midi_output: Option<MidiOutputManager>,
     recording_manager: ArecordManager,
+    tempo: Option<TempoHandle>,
+    last_mapped_bpm: Option<f64>,
+    resume_recording: ResumeRecording,
+    tempo_estimator: TempoEstimatorKind,
+    pll: PllEstimator,
+    mtc_generator: Option<MtcGenerator>,
+    recording_enabled: bool,
+    interpolator: PulseInterpolator,
+    midi_panic: bool,
+    // Lateness of the internal clock's ticks, while it is driving
+    jitter: Option<JitterMonitor>,
+    // When the status is next published
+    next_status: Instant,
+    running: bool,
 }
 
 impl EventLoop {
@@ -63,46 +166,303 @@ impl EventLoop {
             tick_history: Mutex::new(VecDeque::with_capacity(TICK_HISTORY_SIZE)),
             midi_output,
             recording_manager: ArecordManager::new(spawner),
+            tempo: None,
+            last_mapped_bpm: None,
+            resume_recording: ResumeRecording::default(),
+            tempo_estimator: TempoEstimatorKind::default(),
+            pll: PllEstimator::default(),
+            mtc_generator: None,
+            recording_enabled: true,
+            interpolator: PulseInterpolator::new(),
+            midi_panic: false,
+            jitter: None,
+            next_status: Instant::now(),
+            running: true,
         }
     }
 
+    /// Gives the engine control over the internal clock tempo. Without a
+    /// handle, or while an external clock is driving, tempo change requests
+    /// are ignored.
+    pub fn set_tempo_handle(&mut self, tempo: TempoHandle) {
+        self.tempo = Some(tempo);
+    }
+
+    /// Chooses whether resuming from a pause continues the paused take or
+    /// starts a new one.
+    pub fn set_resume_recording(&mut self, resume_recording: ResumeRecording) {
+        self.resume_recording = resume_recording;
+    }
+
+    /// Chooses which estimator publishes BPM to the shared state. The
+    /// phase-locked loop always runs so its phase error stays available.
+    pub fn set_tempo_estimator(&mut self, tempo_estimator: TempoEstimatorKind) {
+        self.tempo_estimator = tempo_estimator;
+    }
+
+    /// Generates MIDI Time Code at `rate` on the primary output, following
+    /// the transport.
+    pub fn set_mtc_output(&mut self, rate: FrameRate) {
+        self.mtc_generator = Some(MtcGenerator::new(rate));
+    }
+
+    /// Turns recording on transport start on or off; offline renders have
+    /// nothing to record.
+    pub fn set_recording_enabled(&mut self, enabled: bool) {
+        self.recording_enabled = enabled;
+    }
+
+    /// Sends a full MIDI panic, not just the NoteOffs of sounding notes,
+    /// whenever notes are released.
+    pub fn set_midi_panic(&mut self, enabled: bool) {
+        self.midi_panic = enabled;
+    }
+
+    /// Drains what a capturing MIDI output collected since the last call.
+    pub fn take_captured_midi(&mut self) -> Vec<MidiMessage> {
+        self.midi_output
+            .as_mut()
+            .map(MidiOutputManager::take_captured)
+            .unwrap_or_default()
+    }
+
     pub fn run(&mut self) {
-        let start_time = Instant::now();
-        loop {
-            match self.engine_rx.recv() {
-                Ok(EngineMessage::Tick) => self.handle_tick(start_time),
-                Ok(EngineMessage::TransportCommand(action)) => {
-                    self.handle_transport_command(action)
+        while self.running {
+            // Wake for the next interpolated tick unless a message comes
+            // first, or after long enough without one to call the clock lost
+            let interpolating = self.interpolator.next_deadline();
+            let wait = interpolating.map_or(CLOCK_LOSS_TIMEOUT, |deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+            match self.engine_rx.recv_timeout(wait) {
+                Ok(message) => self.handle_message(message),
+                Err(RecvTimeoutError::Timeout) if interpolating.is_some() => {
+                    self.handle_interpolated_ticks(Instant::now())
                 }
+                Err(RecvTimeoutError::Timeout) => self.clock_lost(),
                 Err(e) => {
                     error!("Tick channel error: {}", e);
                     break;
                 }
             }
+            self.publish_status(Instant::now());
         }
     }
 
-    fn handle_tick(&mut self, start_time: Instant) {
-        let now = Instant::now();
-        let elapsed = now.duration_since(start_time).as_millis();
-        trace!("EventLoop received tick at {} ms", elapsed);
+    /// Publishes the output counts, device connections and internal clock
+    /// jitter for the TUI and `/status`, at most once every
+    /// `STATUS_INTERVAL`.
+    pub fn publish_status(&mut self, now: Instant) {
+        if now < self.next_status {
+            return;
+        }
+        self.next_status = now + STATUS_INTERVAL;
+        let mut state = self.shared_state.lock().unwrap();
+        if let Some(midi_output) = &self.midi_output {
+            publish_output_status(&mut state, midi_output);
+        }
+        state.jitter = self.jitter.as_ref().map(JitterMonitor::snapshot);
+    }
+
+    /// Plays the engine ticks interpolated between clock pulses that have
+    /// fallen due by `now`.
+    pub fn handle_interpolated_ticks(&mut self, now: Instant) {
+        for _ in 0..self.interpolator.due(now) {
+            self.engine_tick();
+        }
+    }
+
+    /// When the next interpolated tick falls due, if one is pending.
+    pub fn next_interpolated_tick(&self) -> Option<Instant> {
+        self.interpolator.next_deadline()
+    }
+
+    /// Applies every message already queued without waiting for more.
+    pub fn handle_pending(&mut self) {
+        while let Ok(message) = self.engine_rx.try_recv() {
+            self.handle_message(message);
+        }
+    }
+
+    /// Applies a single engine message. `run` calls this for everything it
+    /// receives; simulations call it directly to drive the engine in step.
+    pub fn handle_message(&mut self, message: EngineMessage) {
+        match message {
+            EngineMessage::Tick => self.handle_tick(Instant::now()),
+            EngineMessage::TickAt(now) => self.handle_tick(now),
+            EngineMessage::TransportCommand(action) => self.handle_transport_command(action),
+            EngineMessage::SetTempo(bpm) => self.set_tempo(bpm),
+            EngineMessage::NudgeTempo(delta) => self.nudge_tempo(delta),
+            EngineMessage::SetSwing(swing) => self.set_swing(swing),
+            EngineMessage::NudgeSwing(delta) => self.nudge_swing(delta),
+            EngineMessage::SetTimeSignature(signature) => self.set_time_signature(signature),
+            EngineMessage::Locate(tick) => self.locate(tick),
+            EngineMessage::ClockStatus(status) => self.set_clock_status(status),
+            EngineMessage::ClockSwitched(selection) => self.clock_switched(selection),
+            EngineMessage::ClockJitter(jitter) => self.jitter = Some(jitter),
+            EngineMessage::Timecode(timecode) => self.chase_timecode(timecode),
+            EngineMessage::LocateTimecode(timecode) => self.locate_to_timecode(timecode),
+            EngineMessage::Panic => self.panic(),
+            EngineMessage::DeviceStatus(status) => {
+                self.shared_state.lock().unwrap().update_device(status)
+            }
+            EngineMessage::MidiInput { input, message } => self.midi_input(&input, message),
+            EngineMessage::Shutdown(done) => self.shutdown(done),
+        }
+    }
+
+    fn midi_input(&mut self, input: &str, message: MidiMessage) {
+        trace!("MIDI input from '{}': {}", input, message);
+        if let Some(midi_output) = &mut self.midi_output {
+            midi_output.forward(input, &message);
+        }
+        if !MessageKind::of(&message).is_sync() {
+            self.shared_state.lock().unwrap().midi_input.record(message);
+        }
+    }
+
+    fn handle_tick(&mut self, now: Instant) {
+        trace!("EventLoop received tick at {:?}", now);
+        let ticks = self.interpolator.pulse(now);
+
+        // Ticks still owed from the previous pulse come first
+        for _ in 1..ticks {
+            self.engine_tick();
+        }
+
+        // Forward the pulse to clock followers before any notes for it
+        self.send_clock(MidiMessage::TimingClock);
 
         // Update tick history and BPM
         self.update_tick_history(now);
+        self.engine_tick();
+    }
 
+    /// Advances the engine by one tick at `TICKS_PER_BEAT` resolution.
+    fn engine_tick(&mut self) {
         // Update shared state
-        {
-            let mut state = self.shared_state.lock().unwrap();
-            state.tick_update();
+        self.shared_state.lock().unwrap().tick_update();
+        self.apply_tempo_map();
+        self.generate_timecode();
+        let (current_tick, swing) = {
+            let state = self.shared_state.lock().unwrap();
+            (state.get_tick_count(), state.swing)
+        };
+
+        // Get new musical events from the musical graph, which runs on
+        // swung time: off-beats are held back and caught up later
+        let mut events = Vec::new();
+        for _ in 0..swing.musical_ticks_due(current_tick) {
+            events.extend(self.get_midi_events_from_musical_graph());
         }
-        let current_tick = self.shared_state.lock().unwrap().get_tick_count();
 
-        // Get new musical events from the musical graph
-        let events = self.get_midi_events_from_musical_graph();
+        // Delegate both sending and scheduling to the unified MIDI method;
+        // the graph's notes follow the routes for its track
+        let bpm = self.current_bpm();
+        if let Some(midi_output) = &mut self.midi_output {
+            midi_output.set_bpm(bpm);
+            midi_output.process_tick_events(current_tick, Vec::new());
+            midi_output.process_track_events(
+                current_tick,
+                crate::musical_graph::MIDDLE_C_TRACK,
+                events,
+            );
+        }
+    }
 
-        // Delegate both sending and scheduling to the unified MIDI method
+    fn send_clock(&mut self, message: MidiMessage) {
         if let Some(midi_output) = &mut self.midi_output {
-            midi_output.process_tick_events(current_tick, events);
+            midi_output.send_clock(message);
+        }
+    }
+
+    fn send_timecode(&mut self, message: MidiMessage) {
+        if let Some(midi_output) = &mut self.midi_output {
+            if let Err(e) = midi_output.send(message) {
+                warn!("Failed to send MIDI Time Code: {}", e);
+            }
+        }
+    }
+
+    /// Tempo used to convert between ticks and real time.
+    fn current_bpm(&self) -> f64 {
+        if let Some(tempo) = &self.tempo {
+            return tempo.bpm();
+        }
+        let state = self.shared_state.lock().unwrap();
+        if state.precise_bpm > 0.0 {
+            state.precise_bpm
+        } else {
+            state.bpm as f64
+        }
+    }
+
+    /// Sends the quarter frames that fall due during this tick. They go out
+    /// with the tick, so carry up to a tick of jitter.
+    fn generate_timecode(&mut self) {
+        let bpm = self.current_bpm();
+        let Some(generator) = &mut self.mtc_generator else {
+            return;
+        };
+        let mut state = self.shared_state.lock().unwrap();
+        if state.transport_state != state::TransportState::Playing || bpm <= 0.0 {
+            return;
+        }
+
+        let due = generator.advance(ticks_to_seconds(1, bpm));
+        state.timecode = Some(generator.timecode());
+        drop(state);
+        for data in due {
+            self.send_timecode(MidiMessage::MtcQuarterFrame(data));
+        }
+    }
+
+    /// Tells MTC followers where the transport now is.
+    fn announce_timecode(&mut self, tick: u64) {
+        let bpm = self.current_bpm();
+        let Some(generator) = &mut self.mtc_generator else {
+            return;
+        };
+        let seconds = if bpm > 0.0 {
+            ticks_to_seconds(tick, bpm)
+        } else {
+            0.0
+        };
+        let timecode = generator.locate(seconds);
+        self.shared_state.lock().unwrap().timecode = Some(timecode);
+        self.send_timecode(MidiMessage::MtcFullFrame(timecode));
+    }
+
+    fn timecode_to_tick(&self, timecode: &Timecode) -> Option<u64> {
+        let bpm = self.current_bpm();
+        (bpm > 0.0)
+            .then(|| (timecode.to_seconds() * bpm / 60.0 * TICKS_PER_BEAT as f64).round() as u64)
+    }
+
+    fn locate_to_timecode(&mut self, timecode: Timecode) {
+        self.shared_state.lock().unwrap().timecode = Some(timecode);
+        match self.timecode_to_tick(&timecode) {
+            Some(tick) => {
+                info!("MTC locate to {}", timecode);
+                self.locate(tick);
+            }
+            None => warn!("Ignoring MTC locate to {} - tempo not known yet", timecode),
+        }
+    }
+
+    /// Follows running MTC, relocating only when the transport has drifted.
+    fn chase_timecode(&mut self, timecode: Timecode) {
+        self.shared_state.lock().unwrap().timecode = Some(timecode);
+        let Some(target) = self.timecode_to_tick(&timecode) else {
+            return;
+        };
+        let current = self.shared_state.lock().unwrap().get_tick_count();
+        if current.abs_diff(target) > MTC_CHASE_TOLERANCE_TICKS {
+            debug!(
+                "Chasing MTC {} from tick {} to {}",
+                timecode, current, target
+            );
+            self.locate(target);
         }
     }
 
@@ -117,23 +477,20 @@ impl EventLoop {
                 channel: 1,
                 note: 60, // Middle C
                 velocity: 100,
-                duration_ticks: 48, // MIDDLE_C_DURATION_TICKS
+                duration_ticks: MIDDLE_C_DURATION_TICKS,
             });
         }
         events
     }
 
     fn update_tick_history(&mut self, now: Instant) {
+        self.pll.observe(now);
         let mut last_tick_time = self.last_tick_time.lock().unwrap();
 
         if let Some(last_time) = *last_tick_time {
             let duration = now.duration_since(last_time);
             update_tick_history(&self.tick_history, duration);
-
-            let bpm = calculate_bpm(&self.tick_history.lock().unwrap());
-            let mut state = self.shared_state.lock().unwrap();
-            state.bpm = bpm;
-            debug!("Calculated BPM: {}", bpm);
+            self.publish_tempo();
         } else {
             info!("First tick received, initializing last_tick_time");
         }
@@ -149,39 +506,296 @@ impl EventLoop {
         );
     }
 
+    fn publish_tempo(&self) {
+        let precise_bpm = match self.tempo_estimator {
+            TempoEstimatorKind::MovingAverage => {
+                calculate_bpm(&self.tick_history.lock().unwrap()) as f64
+            }
+            TempoEstimatorKind::PhaseLocked => match self.pll.bpm() {
+                Some(bpm) => bpm,
+                None => return,
+            },
+        };
+
+        let mut state = self.shared_state.lock().unwrap();
+        state.bpm = precise_bpm.round() as u32;
+        state.precise_bpm = precise_bpm;
+        state.phase_error_ms = self.pll.phase_error_ms();
+        debug!("Calculated BPM: {:.2}", precise_bpm);
+    }
+
+    /// Steers the internal clock from the tempo map. The map is only applied
+    /// when its value changes, so live nudges hold until the next map event.
+    fn apply_tempo_map(&mut self) {
+        let Some(tempo) = self.clock_tempo().cloned() else {
+            return;
+        };
+
+        let mapped = {
+            let state = self.shared_state.lock().unwrap();
+            state
+                .tempo_map
+                .bpm_at(state.position(), state.time_signature().numerator)
+        };
+
+        if let Some(bpm) = mapped {
+            if self.last_mapped_bpm != Some(bpm) {
+                trace!("Tempo map sets {} BPM", bpm);
+                tempo.set_bpm(bpm);
+                self.last_mapped_bpm = Some(bpm);
+            }
+        }
+    }
+
+    /// The tempo handle, while the running clock follows it. External clocks
+    /// bring their own tempo.
+    fn clock_tempo(&self) -> Option<&TempoHandle> {
+        match self.shared_state.lock().unwrap().clock_status {
+            state::ClockStatus::External | state::ClockStatus::Flywheel => None,
+            state::ClockStatus::Internal | state::ClockStatus::Link => self.tempo.as_ref(),
+        }
+    }
+
+    fn set_tempo(&mut self, bpm: f64) {
+        match self.clock_tempo() {
+            Some(tempo) => {
+                let applied = tempo.set_bpm(bpm);
+                info!("Tempo set to {} BPM", applied);
+            }
+            None => warn!("Tempo change to {} BPM ignored - clock is external", bpm),
+        }
+    }
+
+    fn nudge_tempo(&mut self, delta: f64) {
+        let Some(current) = self.clock_tempo().map(TempoHandle::bpm) else {
+            warn!("Tempo nudge of {} BPM ignored - clock is external", delta);
+            return;
+        };
+        self.set_tempo(current + delta);
+    }
+
+    fn set_swing(&mut self, swing: Swing) {
+        info!("Swing set to {}", swing);
+        let mut state = self.shared_state.lock().unwrap();
+        state.swing = swing;
+        // Keep the musical graph where the new swing puts it
+        crate::musical_graph::set_musical_tick_count(
+            swing.musical_ticks_at(state.get_tick_count()),
+        );
+    }
+
+    fn nudge_swing(&mut self, delta: f64) {
+        let current = self.shared_state.lock().unwrap().swing;
+        self.set_swing(current.with_percent(current.percent() + delta));
+    }
+
+    /// Meter changes take effect on the next bar line so the bar in progress
+    /// keeps its length. Before the song starts (bar 0) it applies from bar 1.
+    fn set_time_signature(&mut self, signature: TimeSignature) {
+        let mut state = self.shared_state.lock().unwrap();
+        let bar = state.get_current_bar() + 1;
+        info!("Time signature set to {} from bar {}", signature, bar);
+        state.meter.set_from(bar, signature);
+    }
+
+    fn set_clock_status(&mut self, status: state::ClockStatus) {
+        info!("Clock status: {:?}", status);
+        self.shared_state.lock().unwrap().clock_status = status;
+    }
+
+    /// The first pulse from a new source has nothing to be timed against, so
+    /// tempo measurement and interpolation start afresh from the current
+    /// tick. Leaving an external clock for the internal one carries on at
+    /// the tempo last measured.
+    fn clock_switched(&mut self, selection: ClockSelection) {
+        info!("Clock switched to {}", selection);
+        let (tick, measured_bpm, was_external) = {
+            let mut state = self.shared_state.lock().unwrap();
+            let was_external = matches!(
+                state.clock_status,
+                state::ClockStatus::External | state::ClockStatus::Flywheel
+            );
+            state.clock_status = selection.status();
+            state.clock_device = selection.device().map(str::to_string);
+            state.jitter = None;
+            (state.get_tick_count(), state.precise_bpm, was_external)
+        };
+
+        // The new clock may not pick up where the old one left off
+        self.release_notes();
+        self.jitter = None;
+
+        *self.last_tick_time.lock().unwrap() = None;
+        self.tick_history.lock().unwrap().clear();
+        self.pll = PllEstimator::default();
+        self.interpolator = PulseInterpolator::new();
+        self.interpolator.realign(tick);
+
+        if selection == ClockSelection::Internal && was_external && measured_bpm > 0.0 {
+            self.set_tempo(measured_bpm);
+        }
+    }
+
+    fn locate(&mut self, tick: u64) {
+        info!("Locating transport to tick {}", tick);
+        let (previous, swing) = {
+            let mut state = self.shared_state.lock().unwrap();
+            let previous = state.get_tick_count();
+            state.locate(tick);
+            (previous, state.swing)
+        };
+        if let Some(midi_output) = &mut self.midi_output {
+            midi_output.shift_scheduled(tick as i64 - previous as i64);
+        }
+        crate::musical_graph::set_musical_tick_count(swing.musical_ticks_at(tick));
+        self.interpolator.realign(tick);
+
+        // Song Position Pointer can only express whole sixteenth notes
+        let midi_beats = (tick / TICKS_PER_SONG_POSITION_BEAT).min(0x3FFF) as u16;
+        self.send_clock(MidiMessage::SongPositionPointer(midi_beats));
+        self.announce_timecode(tick);
+    }
+
     fn handle_transport_command(&mut self, action: TransportAction) {
         let current_state = self.shared_state.lock().unwrap().transport_state;
 
         match (current_state, action) {
-            (state::TransportState::Stopped, TransportAction::Start) => {
-                {
-                    let mut state = self.shared_state.lock().unwrap();
-                    state.transport_state = state::TransportState::Playing;
-                }
-                self.start_recording();
+            (state::TransportState::Stopped, TransportAction::Start)
+            | (state::TransportState::Paused, TransportAction::Start) => self.start_transport(),
+            (state::TransportState::Playing, TransportAction::Stop)
+            | (state::TransportState::Paused, TransportAction::Stop) => self.stop_transport(),
+            (state::TransportState::Playing, TransportAction::Pause) => self.pause_transport(),
+            (state::TransportState::Stopped, TransportAction::Continue)
+            | (state::TransportState::Paused, TransportAction::Continue) => {
+                self.continue_transport()
             }
-            (state::TransportState::Playing, TransportAction::Stop) => {
-                {
-                    let mut state = self.shared_state.lock().unwrap();
-                    state.transport_state = state::TransportState::Stopped;
-                    state.tick_count = 0;
-                    state.current_beat = 0;
-                    state.current_bar = 0;
-                }
-
-                crate::musical_graph::reset_musical_tick_count();
-                self.stop_recording();
+            (state::TransportState::Playing, action) => {
+                warn!(
+                    "{:?} command received while already playing - ignoring",
+                    action
+                );
             }
-            (state::TransportState::Playing, TransportAction::Start) => {
-                warn!("Start command received while already playing - ignoring");
+            (current_state, action) => {
+                debug!(
+                    "{:?} command received while {:?} - ignoring",
+                    action, current_state
+                );
             }
-            (state::TransportState::Stopped, TransportAction::Stop) => {
-                debug!("Stop command received while already stopped - ignoring");
+        }
+    }
+
+    fn set_transport_state(&mut self, transport_state: state::TransportState) {
+        self.shared_state.lock().unwrap().transport_state = transport_state;
+    }
+
+    /// Plays from the top. A take held open by a pause is closed first.
+    fn start_transport(&mut self) {
+        self.rewind();
+        self.set_transport_state(state::TransportState::Playing);
+        self.send_clock(MidiMessage::Start);
+        if self.recording_manager.is_paused() {
+            self.stop_recording();
+        }
+        self.start_recording();
+    }
+
+    fn stop_transport(&mut self) {
+        let was_playing =
+            self.shared_state.lock().unwrap().transport_state == state::TransportState::Playing;
+        self.set_transport_state(state::TransportState::Stopped);
+        self.release_notes();
+        self.rewind();
+
+        if was_playing {
+            self.send_clock(MidiMessage::Stop);
+        }
+        self.send_clock(MidiMessage::SongPositionPointer(0));
+        self.stop_recording();
+    }
+
+    /// Halts playback but holds the position so that Continue can resume it.
+    fn pause_transport(&mut self) {
+        self.set_transport_state(state::TransportState::Paused);
+        self.release_notes();
+        self.send_clock(MidiMessage::Stop);
+
+        match self.resume_recording {
+            ResumeRecording::ContinueTake => {
+                if let Err(err) = self.recording_manager.pause() {
+                    warn!("Failed to pause arecord: {}", err);
+                }
+            }
+            ResumeRecording::NewTake => self.stop_recording(),
+        }
+    }
+
+    fn continue_transport(&mut self) {
+        let tick = self.shared_state.lock().unwrap().get_tick_count();
+        self.interpolator.realign(tick);
+        self.set_transport_state(state::TransportState::Playing);
+        self.send_clock(MidiMessage::Continue);
+
+        if !self.recording_manager.is_paused() {
+            self.start_recording();
+        } else if let Err(err) = self.recording_manager.resume() {
+            warn!("Failed to resume arecord, starting a new take: {}", err);
+            self.stop_recording();
+            self.start_recording();
+        }
+    }
+
+    /// Ends sounding notes now rather than leaving them hanging, with a
+    /// full panic if configured.
+    fn release_notes(&mut self) {
+        if self.midi_panic {
+            self.panic();
+        } else if let Some(midi_output) = &mut self.midi_output {
+            midi_output.release_notes();
+        }
+    }
+
+    fn panic(&mut self) {
+        if let Some(midi_output) = &mut self.midi_output {
+            midi_output.panic();
+        }
+    }
+
+    fn clock_lost(&mut self) {
+        let sounding = self
+            .midi_output
+            .as_ref()
+            .map_or(0, MidiOutputManager::sounding_notes);
+        if sounding > 0 {
+            warn!("No clock for {:?} with notes sounding", CLOCK_LOSS_TIMEOUT);
+            self.release_notes();
+        }
+    }
+
+    fn shutdown(&mut self, done: Sender<()>) {
+        info!("Engine shutting down");
+        self.release_notes();
+        if let Some(midi_output) = &self.midi_output {
+            if !midi_output.flush(OUTPUT_FLUSH_TIMEOUT) {
+                warn!("MIDI output did not finish sending before shutdown");
             }
         }
+        self.stop_recording();
+        self.running = false;
+        // The requester may have given up waiting
+        let _ = done.send(());
+    }
+
+    fn rewind(&mut self) {
+        self.shared_state.lock().unwrap().locate(0);
+        crate::musical_graph::reset_musical_tick_count();
+        self.interpolator.realign(0);
+        self.announce_timecode(0);
     }
 
     fn start_recording(&mut self) {
+        if !self.recording_enabled {
+            return;
+        }
         match self.recording_manager.start() {
             Ok(target) => {
                 let mut state = self.shared_state.lock().unwrap();
@@ -215,6 +829,10 @@ fn update_tick_history(tick_history: &Mutex<VecDeque<Duration>>, duration: Durat
         tick_history_lock.pop_front();
     }
 }
+fn ticks_to_seconds(ticks: u64, bpm: f64) -> f64 {
+    ticks as f64 * 60.0 / (bpm * TICKS_PER_BEAT as f64)
+}
+
 fn calculate_bpm(tick_history: &VecDeque<Duration>) -> u32 {
     if tick_history.is_empty() {
         return 60;
@@ -226,7 +844,7 @@ fn calculate_bpm(tick_history: &VecDeque<Duration>) -> u32 {
     let average_duration = total_duration / tick_history.len() as u32;
     trace!("calculate_bpm: average_duration={:?}", average_duration);
 
-    // 60 seconds / (duration in seconds * 24 ticks per beat)
+    // 60 seconds / (duration in seconds * 24 pulses per beat)
     let seconds = average_duration.as_secs_f64();
     trace!("calculate_bpm: seconds={}", seconds);
 
@@ -234,7 +852,7 @@ fn calculate_bpm(tick_history: &VecDeque<Duration>) -> u32 {
         // Avoid division by zero
         return 60;
     }
-    let bpm = 60.0 / (seconds * 24.0);
+    let bpm = 60.0 / (seconds * MIDI_CLOCK_PPQN as f64);
     trace!("calculate_bpm: bpm={}", bpm);
 
     let rounded_bpm = bpm.round() as u32;
@@ -242,6 +860,14 @@ fn calculate_bpm(tick_history: &VecDeque<Duration>) -> u32 {
     rounded_bpm
 }
 
+// Counts and device connections of the MIDI outputs, for the TUI and /status
+fn publish_output_status(state: &mut state::SharedState, midi_output: &MidiOutputManager) {
+    state.midi_output = midi_output.stats();
+    for device in midi_output.device_statuses() {
+        state.update_device(device);
+    }
+}
+
 const ARECORD_FILENAME_TEMPLATE: &str = "wav_files/take_%Y%m%d_%H%M%S_pair1.wav";
 const ARECORD_SAMPLE_RATE: &str = "48000";
 const ARECORD_CHANNELS: &str = "2";
@@ -252,6 +878,7 @@ const ARECORD_WAIT_STEP: Duration = Duration::from_millis(50);
 struct ArecordManager {
     spawner: Box<dyn RecordingSpawner>,
     child: Option<Box<dyn ManagedChild>>,
+    paused: bool,
 }
 
 impl ArecordManager {
@@ -259,9 +886,35 @@ impl ArecordManager {
         Self {
             spawner,
             child: None,
+            paused: false,
         }
     }
 
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Suspends the running arecord so the take can be continued later.
+    fn pause(&mut self) -> io::Result<()> {
+        let Some(child) = &self.child else {
+            debug!("Pause requested but arecord was not running");
+            return Ok(());
+        };
+        self.spawner.send_signal(child.id(), Signal::Pause)?;
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> io::Result<()> {
+        let Some(child) = &self.child else {
+            self.paused = false;
+            return Ok(());
+        };
+        self.spawner.send_signal(child.id(), Signal::Resume)?;
+        self.paused = false;
+        Ok(())
+    }
+
     fn start(&mut self) -> io::Result<String> {
         if self.child.is_some() {
             return Err(io::Error::new(
@@ -299,22 +952,16 @@ impl ArecordManager {
         };
 
         let pid = child.id();
+        if self.paused {
+            // A stopped process would not act on SIGTERM until continued
+            self.paused = false;
+            self.signal_or_warn(pid, Signal::Resume);
+        }
         if let Err(err) = self.spawner.send_signal(pid, Signal::Term) {
             warn!("Failed to send SIGTERM to arecord (pid {pid}): {err}");
         }
 
-        let mut exited = false;
-        for _ in 0..=ARECORD_WAIT_ATTEMPTS {
-            match child.try_wait()? {
-                Some(_status) => {
-                    exited = true;
-                    break;
-                }
-                None => thread::sleep(ARECORD_WAIT_STEP),
-            }
-        }
-
-        if !exited {
+        if !wait_for_exit(child.as_mut())? {
             warn!(
                 "arecord (pid {}) did not exit after SIGTERM - sending SIGKILL (WAV may be damaged)",
                 pid
@@ -327,6 +974,25 @@ impl ArecordManager {
 
         Ok(())
     }
+
+    fn signal_or_warn(&self, pid: u32, signal: Signal) {
+        if let Err(err) = self.spawner.send_signal(pid, signal) {
+            warn!(
+                "Failed to send {:?} to arecord (pid {}): {}",
+                signal, pid, err
+            );
+        }
+    }
+}
+
+fn wait_for_exit(child: &mut dyn ManagedChild) -> io::Result<bool> {
+    for _ in 0..=ARECORD_WAIT_ATTEMPTS {
+        match child.try_wait()? {
+            Some(_status) => return Ok(true),
+            None => thread::sleep(ARECORD_WAIT_STEP),
+        }
+    }
+    Ok(false)
 }
 
 impl Drop for ArecordManager {
@@ -359,10 +1025,12 @@ impl ManagedChild for RealChild {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Signal {
     Term,
     Kill,
+    Pause,
+    Resume,
 }
 
 trait RecordingSpawner: Send {
@@ -411,6 +1079,8 @@ impl RecordingSpawner for SystemRecordingSpawner {
         let flag = match signal {
             Signal::Term => "-TERM",
             Signal::Kill => "-KILL",
+            Signal::Pause => "-STOP",
+            Signal::Resume => "-CONT",
         };
 
         let status = Command::new("kill")
@@ -483,6 +1153,8 @@ fn ensure_pair_supported() -> io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::JitterStats;
+    use crate::simulation::VirtualTime;
     use std::collections::HashMap;
     use std::os::unix::process::ExitStatusExt;
     use std::sync::atomic::{AtomicBool, AtomicU32, Ordering as AtomicOrdering};
@@ -563,6 +1235,9 @@ mod tests {
 
         fn send_signal(&self, pid: u32, signal: Signal) -> io::Result<()> {
             self.signals.lock().unwrap().push((pid, signal));
+            if !matches!(signal, Signal::Term | Signal::Kill) {
+                return Ok(());
+            }
             if let Some(flag) = self.children.lock().unwrap().get(&pid) {
                 flag.store(true, AtomicOrdering::SeqCst);
             }
@@ -650,6 +1325,102 @@ mod tests {
         assert!(signal_calls.lock().unwrap().is_empty());
     }
 
+    fn play_one_bar(event_loop: &mut EventLoop) {
+        for _ in 0..(TimeSignature::default().ticks_per_bar() / crate::config::TICKS_PER_CLOCK) {
+            event_loop.handle_tick(Instant::now());
+        }
+    }
+
+    #[test]
+    fn test_pause_holds_position_and_continue_resumes() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+
+        event_loop.handle_transport_command(TransportAction::Start);
+        play_one_bar(&mut event_loop);
+        event_loop.handle_transport_command(TransportAction::Pause);
+        let held_tick = shared_state.lock().unwrap().tick_count;
+        assert_eq!(
+            shared_state.lock().unwrap().transport_state,
+            state::TransportState::Paused
+        );
+
+        // Ticks while paused do not move the position
+        event_loop.handle_tick(Instant::now());
+        assert_eq!(shared_state.lock().unwrap().tick_count, held_tick);
+
+        event_loop.handle_transport_command(TransportAction::Continue);
+        event_loop.handle_tick(Instant::now());
+        assert_eq!(
+            shared_state.lock().unwrap().tick_count,
+            held_tick + crate::config::TICKS_PER_CLOCK
+        );
+        assert_eq!(
+            shared_state.lock().unwrap().transport_state,
+            state::TransportState::Playing
+        );
+    }
+
+    #[test]
+    fn test_start_and_stop_from_pause_rewind() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+
+        event_loop.handle_transport_command(TransportAction::Start);
+        play_one_bar(&mut event_loop);
+        event_loop.handle_transport_command(TransportAction::Pause);
+        event_loop.handle_transport_command(TransportAction::Start);
+        assert_eq!(shared_state.lock().unwrap().tick_count, 0);
+
+        play_one_bar(&mut event_loop);
+        event_loop.handle_transport_command(TransportAction::Pause);
+        event_loop.handle_transport_command(TransportAction::Stop);
+        assert_eq!(shared_state.lock().unwrap().tick_count, 0);
+        assert_eq!(
+            shared_state.lock().unwrap().transport_state,
+            state::TransportState::Stopped
+        );
+    }
+
+    #[test]
+    fn test_resume_continues_take_when_configured() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let spawner = MockSpawner::new();
+        let start_calls = spawner.starts.clone();
+        let signal_calls = spawner.signals.clone();
+        let mut event_loop = build_event_loop(shared_state.clone(), spawner);
+        event_loop.set_resume_recording(ResumeRecording::ContinueTake);
+
+        event_loop.handle_transport_command(TransportAction::Start);
+        event_loop.handle_transport_command(TransportAction::Pause);
+        event_loop.handle_transport_command(TransportAction::Continue);
+
+        assert_eq!(start_calls.lock().unwrap().len(), 1);
+        let signals: Vec<Signal> = signal_calls.lock().unwrap().iter().map(|s| s.1).collect();
+        assert_eq!(signals, vec![Signal::Pause, Signal::Resume]);
+        assert!(shared_state.lock().unwrap().recording);
+    }
+
+    #[test]
+    fn test_resume_starts_new_take_when_configured() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let spawner = MockSpawner::new();
+        let start_calls = spawner.starts.clone();
+        let signal_calls = spawner.signals.clone();
+        let mut event_loop = build_event_loop(shared_state.clone(), spawner);
+        event_loop.set_resume_recording(ResumeRecording::NewTake);
+
+        event_loop.handle_transport_command(TransportAction::Start);
+        event_loop.handle_transport_command(TransportAction::Pause);
+        assert!(!shared_state.lock().unwrap().recording);
+        event_loop.handle_transport_command(TransportAction::Continue);
+
+        assert_eq!(start_calls.lock().unwrap().len(), 2);
+        let signals: Vec<Signal> = signal_calls.lock().unwrap().iter().map(|s| s.1).collect();
+        assert_eq!(signals, vec![Signal::Term]);
+        assert!(shared_state.lock().unwrap().recording);
+    }
+
     #[test]
     fn test_arecord_immediate_exit_surfaces_error() {
         let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
@@ -664,6 +1435,422 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tempo_messages_update_handle() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state, MockSpawner::new());
+        let tempo = TempoHandle::new(120.0);
+        event_loop.set_tempo_handle(tempo.clone());
+
+        event_loop.set_tempo(128.0);
+        assert_eq!(tempo.bpm(), 128.0);
+
+        event_loop.nudge_tempo(-2.5);
+        assert_eq!(tempo.bpm(), 125.5);
+    }
+
+    #[test]
+    fn test_tempo_map_steers_clock_without_overriding_nudges() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        {
+            let mut state = shared_state.lock().unwrap();
+            state.transport_state = state::TransportState::Playing;
+            state.tempo_map = crate::tempo_map::TempoMap::parse("1:100,2:140").unwrap();
+        }
+        let mut event_loop = build_event_loop(shared_state, MockSpawner::new());
+        let tempo = TempoHandle::new(120.0);
+        event_loop.set_tempo_handle(tempo.clone());
+        event_loop.handle_tick(Instant::now());
+        assert_eq!(tempo.bpm(), 100.0);
+
+        event_loop.nudge_tempo(1.0);
+        event_loop.handle_tick(Instant::now());
+        assert_eq!(tempo.bpm(), 101.0);
+
+        // Bar 2 starts after one bar of pulses
+        for _ in 0..(TimeSignature::default().ticks_per_bar() / crate::config::TICKS_PER_CLOCK) {
+            event_loop.handle_tick(Instant::now());
+        }
+        assert_eq!(tempo.bpm(), 140.0);
+    }
+
+    #[test]
+    fn test_locate_moves_transport_position() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        shared_state.lock().unwrap().transport_state = state::TransportState::Playing;
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+
+        let bar = TimeSignature::default().ticks_per_bar();
+        event_loop.locate(bar);
+        event_loop.handle_tick(Instant::now());
+
+        let state = shared_state.lock().unwrap();
+        assert_eq!(state.get_tick_count(), bar + crate::config::TICKS_PER_CLOCK);
+        assert_eq!(state.get_current_bar(), 2);
+        assert_eq!(state.get_current_beat(), 1);
+    }
+
+    #[test]
+    fn test_stop_ends_scheduled_notes_and_locate_carries_them() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let (_tx, rx) = mpsc::channel();
+        let mut event_loop = EventLoop::with_recorder_spawner(
+            shared_state,
+            rx,
+            Some(MidiOutputManager::capturing()),
+            Box::new(MockSpawner::new()),
+        );
+        let note_on = MidiMessage::NoteOn {
+            channel: 1,
+            note: 60,
+            velocity: 100,
+            duration_ticks: 100,
+        };
+        let note_off = MidiMessage::NoteOff {
+            channel: 1,
+            note: 60,
+        };
+        event_loop.handle_transport_command(TransportAction::Start);
+
+        // A locate keeps the note's remaining length
+        let output = event_loop.midi_output.as_mut().unwrap();
+        output.process_tick_events(0, vec![note_on]);
+        let handle = output.schedule(100, note_off.clone());
+        event_loop.locate(1_000);
+        let output = event_loop.midi_output.as_mut().unwrap();
+        assert_eq!(output.scheduled_tick(handle), Some(1_100));
+
+        event_loop.handle_transport_command(TransportAction::Stop);
+        let output = event_loop.midi_output.as_mut().unwrap();
+        assert!(!output.cancel(handle));
+        assert_eq!(output.scheduled_count(), 0);
+        assert_eq!(output.sounding_notes(), 0);
+        let captured = event_loop.take_captured_midi();
+        assert_eq!(captured.iter().filter(|m| **m == note_off).count(), 1);
+    }
+
+    #[test]
+    fn test_output_status_is_published_on_its_interval_not_per_tick() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let (_tx, rx) = mpsc::channel();
+        let mut event_loop = EventLoop::with_recorder_spawner(
+            shared_state.clone(),
+            rx,
+            Some(MidiOutputManager::capturing()),
+            Box::new(MockSpawner::new()),
+        );
+        event_loop.set_midi_panic(true);
+        let start = Instant::now();
+        event_loop.publish_status(start);
+
+        // Ticks and sends leave the published counts alone
+        event_loop.handle_transport_command(TransportAction::Start);
+        event_loop.handle_tick(start);
+        event_loop.handle_message(EngineMessage::Panic);
+        assert_eq!(shared_state.lock().unwrap().midi_output.sent, 0);
+
+        event_loop.publish_status(start + STATUS_INTERVAL / 2);
+        assert_eq!(shared_state.lock().unwrap().midi_output.sent, 0);
+        event_loop.publish_status(start + STATUS_INTERVAL);
+        assert!(shared_state.lock().unwrap().midi_output.sent > 0);
+    }
+
+    #[test]
+    fn test_shutdown_releases_notes_and_ends_the_loop() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let (engine_tx, rx) = mpsc::channel();
+        let mut event_loop = EventLoop::with_recorder_spawner(
+            shared_state,
+            rx,
+            Some(MidiOutputManager::capturing()),
+            Box::new(MockSpawner::new()),
+        );
+        event_loop.set_midi_panic(true);
+        let output = event_loop.midi_output.as_mut().unwrap();
+        output.process_tick_events(
+            0,
+            vec![MidiMessage::NoteOn {
+                channel: 0,
+                note: 36,
+                velocity: 100,
+                duration_ticks: TICKS_PER_BEAT,
+            }],
+        );
+
+        let (done_tx, done_rx) = mpsc::channel();
+        engine_tx.send(EngineMessage::Shutdown(done_tx)).unwrap();
+        event_loop.run();
+
+        done_rx.try_recv().expect("shutdown acknowledged");
+        let captured = event_loop.take_captured_midi();
+        assert!(captured.contains(&MidiMessage::NoteOff {
+            channel: 0,
+            note: 36
+        }));
+        assert!(captured.contains(&MidiMessage::AllNotesOff { channel: 15 }));
+    }
+
+    #[test]
+    fn test_shut_down_waits_for_the_engine_and_gives_up_once_it_is_gone() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let (engine_tx, rx) = mpsc::channel();
+        let engine = thread::spawn(move || {
+            let mut event_loop = EventLoop::with_recorder_spawner(
+                shared_state,
+                rx,
+                Some(MidiOutputManager::capturing()),
+                Box::new(MockSpawner::new()),
+            );
+            event_loop.run();
+        });
+
+        assert!(shut_down(&engine_tx));
+        engine.join().unwrap();
+        assert!(!shut_down(&engine_tx));
+    }
+
+    #[test]
+    fn test_swing_holds_back_off_beats_for_the_musical_graph() {
+        use crate::config::TICKS_PER_CLOCK;
+        use crate::musical_graph::musical_tick_count;
+        use crate::swing::SwingGrid;
+
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        shared_state.lock().unwrap().transport_state = state::TransportState::Playing;
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+        crate::musical_graph::reset_musical_tick_count();
+        event_loop.set_swing(Swing::new(75.0, SwingGrid::Eighth));
+
+        // The off-beat 8th reaches the graph on pulse 18 instead of 12
+        for _ in 0..12 {
+            event_loop.handle_tick(Instant::now());
+        }
+        assert_eq!(musical_tick_count(), 8 * TICKS_PER_CLOCK);
+        for _ in 12..18 {
+            event_loop.handle_tick(Instant::now());
+        }
+        assert_eq!(musical_tick_count(), 12 * TICKS_PER_CLOCK);
+
+        // and the graph is back in step with the clock on the next beat
+        for _ in 18..24 {
+            event_loop.handle_tick(Instant::now());
+        }
+        assert_eq!(musical_tick_count(), TICKS_PER_BEAT);
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            TICKS_PER_BEAT
+        );
+    }
+
+    #[test]
+    fn test_nudge_swing_is_published_and_clamped() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+
+        event_loop.handle_message(EngineMessage::NudgeSwing(8.0));
+        assert_eq!(shared_state.lock().unwrap().swing.percent(), 58.0);
+
+        event_loop.handle_message(EngineMessage::NudgeSwing(40.0));
+        assert_eq!(shared_state.lock().unwrap().swing.percent(), 75.0);
+    }
+
+    #[test]
+    fn test_internal_clock_jitter_is_published_until_the_clock_switches() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let (_tx, rx) = mpsc::channel();
+        let mut event_loop = EventLoop::with_recorder_spawner(
+            shared_state.clone(),
+            rx,
+            None,
+            Box::new(MockSpawner::new()),
+        );
+        let monitor = JitterMonitor::default();
+        event_loop.handle_message(EngineMessage::ClockJitter(monitor.clone()));
+        monitor.record(Duration::from_micros(100));
+        monitor.record(Duration::from_micros(300));
+
+        // The engine reads the clock's figures as they stand when it publishes
+        let start = Instant::now();
+        event_loop.publish_status(start);
+        assert_eq!(
+            shared_state.lock().unwrap().jitter,
+            Some(JitterStats {
+                ticks: 2,
+                min: Duration::from_micros(100),
+                max: Duration::from_micros(300),
+                mean: Duration::from_micros(200),
+            })
+        );
+
+        event_loop.handle_message(EngineMessage::ClockSwitched(ClockSelection::Link));
+        event_loop.publish_status(start + STATUS_INTERVAL);
+        assert_eq!(shared_state.lock().unwrap().jitter, None);
+    }
+
+    #[test]
+    fn test_clock_switch_hands_measured_tempo_to_internal_clock() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        {
+            let mut state = shared_state.lock().unwrap();
+            state.clock_status = state::ClockStatus::External;
+            state.precise_bpm = 133.0;
+        }
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+        let tempo = TempoHandle::new(120.0);
+        event_loop.set_tempo_handle(tempo.clone());
+
+        // The external clock owns the tempo
+        event_loop.handle_message(EngineMessage::NudgeTempo(1.0));
+        assert_eq!(tempo.bpm(), 120.0);
+
+        event_loop.handle_message(EngineMessage::ClockSwitched(ClockSelection::Internal));
+        assert_eq!(tempo.bpm(), 133.0);
+        assert_eq!(
+            shared_state.lock().unwrap().clock_status,
+            state::ClockStatus::Internal
+        );
+
+        let device = ClockSelection::External("OXI ONE".to_string());
+        event_loop.handle_message(EngineMessage::ClockSwitched(device));
+        let state = shared_state.lock().unwrap();
+        assert_eq!(state.clock_status, state::ClockStatus::External);
+        assert_eq!(state.clock_device.as_deref(), Some("OXI ONE"));
+    }
+
+    #[test]
+    fn test_clock_switch_keeps_pulses_on_the_grid() {
+        use crate::config::TICKS_PER_CLOCK;
+
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        shared_state.lock().unwrap().transport_state = state::TransportState::Playing;
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+
+        // The old clock stops half way between two pulses
+        let start = Instant::now();
+        event_loop.handle_tick(start);
+        event_loop.handle_tick(start + Duration::from_millis(20));
+        event_loop.handle_interpolated_ticks(start + Duration::from_millis(30));
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            2 * TICKS_PER_CLOCK + TICKS_PER_CLOCK / 2
+        );
+
+        // The new clock's first pulse completes that pulse, and the gap
+        // between the two clocks is not taken as a pulse interval
+        event_loop.handle_message(EngineMessage::ClockSwitched(ClockSelection::Internal));
+        event_loop.handle_tick(start + Duration::from_secs(1));
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            3 * TICKS_PER_CLOCK
+        );
+        assert!(event_loop.next_interpolated_tick().is_none());
+    }
+
+    #[test]
+    fn test_time_signature_changes_at_the_next_bar() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        shared_state.lock().unwrap().transport_state = state::TransportState::Playing;
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+        let seven_eight = TimeSignature::new(7, 8).unwrap();
+
+        // Half way through bar 1 the change waits for bar 2
+        for _ in 0..(2 * MIDI_CLOCK_PPQN) {
+            event_loop.handle_tick(Instant::now());
+        }
+        event_loop.handle_message(EngineMessage::SetTimeSignature(seven_eight));
+        assert_eq!(
+            shared_state.lock().unwrap().time_signature().to_string(),
+            "4/4"
+        );
+
+        for _ in 0..(2 * MIDI_CLOCK_PPQN) {
+            event_loop.handle_tick(Instant::now());
+        }
+        {
+            let state = shared_state.lock().unwrap();
+            assert_eq!(state.get_current_bar(), 2);
+            assert_eq!(state.time_signature(), seven_eight);
+        }
+
+        // Bar 2 is seven eighths long, so bar 3 starts 3.5 beats later
+        for _ in 0..(MIDI_CLOCK_PPQN * 7 / 2) {
+            event_loop.handle_tick(Instant::now());
+        }
+        let state = shared_state.lock().unwrap();
+        assert_eq!(state.get_current_bar(), 3);
+        assert_eq!(state.get_current_beat(), 1);
+    }
+
+    fn timecode(seconds: u8) -> Timecode {
+        Timecode {
+            hours: 0,
+            minutes: 0,
+            seconds,
+            frames: 0,
+            rate: FrameRate::Fps25,
+        }
+    }
+
+    #[test]
+    fn test_mtc_full_frame_locates_at_current_tempo() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+        event_loop.set_tempo_handle(TempoHandle::new(120.0));
+
+        // Two seconds at 120 BPM is four beats
+        event_loop.locate_to_timecode(timecode(2));
+
+        let state = shared_state.lock().unwrap();
+        assert_eq!(state.get_tick_count(), 4 * TICKS_PER_BEAT);
+        assert_eq!(state.timecode, Some(timecode(2)));
+    }
+
+    #[test]
+    fn test_running_mtc_only_relocates_when_drifted() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+        event_loop.set_tempo_handle(TempoHandle::new(120.0));
+        let nearly_two_seconds = 4 * TICKS_PER_BEAT + 2 * crate::config::TICKS_PER_CLOCK;
+        event_loop.locate(nearly_two_seconds);
+
+        event_loop.chase_timecode(timecode(2));
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            nearly_two_seconds
+        );
+
+        event_loop.chase_timecode(timecode(4));
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            8 * TICKS_PER_BEAT
+        );
+    }
+
+    #[test]
+    fn test_mtc_generator_follows_transport() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+        event_loop.set_tempo_handle(TempoHandle::new(120.0));
+        event_loop.set_mtc_output(FrameRate::Fps25);
+
+        event_loop.handle_transport_command(TransportAction::Start);
+        assert_eq!(shared_state.lock().unwrap().timecode, Some(timecode(0)));
+
+        // A bar at 120 BPM is two seconds
+        play_one_bar(&mut event_loop);
+        assert_eq!(shared_state.lock().unwrap().timecode, Some(timecode(2)));
+    }
+
+    #[test]
+    fn test_tempo_messages_without_handle_are_ignored() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state, MockSpawner::new());
+
+        event_loop.set_tempo(128.0);
+        event_loop.nudge_tempo(1.0);
+        assert!(event_loop.tempo.is_none());
+    }
+
     #[test]
     fn test_handle_tick() {
         let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
@@ -676,8 +1863,7 @@ mod tests {
         );
 
         // Call handle_tick
-        let start_time = Instant::now();
-        event_loop.handle_tick(start_time);
+        event_loop.handle_tick(Instant::now());
 
         // Check if last_tick_time is updated
         let last_tick_time = event_loop.last_tick_time.lock().unwrap();
@@ -702,12 +1888,14 @@ mod tests {
         let initial_tick_count = shared_state.lock().unwrap().tick_count;
 
         // Call handle_tick
-        let start_time = Instant::now();
-        event_loop.handle_tick(start_time);
+        event_loop.handle_tick(Instant::now());
 
-        // Check if tick count is incremented
+        // Check if tick count advances by one pulse of engine ticks
         let current_tick_count = shared_state.lock().unwrap().tick_count;
-        assert_eq!(current_tick_count, initial_tick_count + 1);
+        assert_eq!(
+            current_tick_count,
+            initial_tick_count + crate::config::TICKS_PER_CLOCK
+        );
 
         // Check if last_tick_time is updated
         let last_tick_time = event_loop.last_tick_time.lock().unwrap();
@@ -824,14 +2012,15 @@ mod tests {
         shared_state.lock().unwrap().transport_state = state::TransportState::Playing;
 
         // Set last_tick_time to simulate a previous tick
-        let first_time = Instant::now();
+        let time = VirtualTime::new();
+        let first_time = time.now();
         *event_loop.last_tick_time.lock().unwrap() = Some(first_time);
 
-        // Wait a bit to ensure a measurable duration
-        std::thread::sleep(Duration::from_millis(10));
+        // Step virtual time by exactly 10 ms
+        time.advance(Duration::from_millis(10));
 
         // Call update_tick_history
-        let second_time = Instant::now();
+        let second_time = time.now();
         event_loop.update_tick_history(second_time);
 
         // Verify that last_tick_time is updated
@@ -842,8 +2031,38 @@ mod tests {
         let tick_history = event_loop.tick_history.lock().unwrap();
         assert_eq!(tick_history.len(), 1);
 
-        // Verify that the BPM is calculated
-        assert!(shared_state.lock().unwrap().get_bpm() > 0);
+        // A 10 ms tick interval is 250 BPM at 24 PPQN
+        assert_eq!(shared_state.lock().unwrap().get_bpm(), 250);
+    }
+
+    #[test]
+    fn test_phase_locked_estimator_publishes_fractional_bpm() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+        event_loop.set_tempo_estimator(TempoEstimatorKind::PhaseLocked);
+
+        // 121.6 BPM at 24 PPQN
+        let period = Duration::from_nanos(20_559_211);
+        let start = Instant::now();
+        for n in 0..48 {
+            event_loop.update_tick_history(start + period * n);
+        }
+
+        let state = shared_state.lock().unwrap();
+        assert!((state.precise_bpm - 121.6).abs() < 0.01);
+        assert_eq!(state.get_bpm(), 122);
+    }
+
+    #[test]
+    fn test_clock_status_is_published() {
+        let shared_state = Arc::new(Mutex::new(state::SharedState::new(120)));
+        let mut event_loop = build_event_loop(shared_state.clone(), MockSpawner::new());
+
+        event_loop.set_clock_status(state::ClockStatus::Flywheel);
+        assert_eq!(
+            shared_state.lock().unwrap().clock_status,
+            state::ClockStatus::Flywheel
+        );
     }
 
     #[test]
@@ -889,10 +2108,12 @@ mod tests {
         shared_state.lock().unwrap().transport_state = state::TransportState::Playing;
 
         // Call handle_tick
-        let start_time = Instant::now();
-        event_loop.handle_tick(start_time);
+        event_loop.handle_tick(Instant::now());
 
-        // Verify that tick count is incremented
-        assert_eq!(shared_state.lock().unwrap().get_tick_count(), 1);
+        // Verify that tick count advances by one pulse of engine ticks
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            crate::config::TICKS_PER_CLOCK
+        );
     }
 }
@@ -1,54 +1,240 @@
-use crate::clock::ClockSource;
+use crate::clock::{ClockSource, ClockThreads, RunFlag};
+use crate::config::TICKS_PER_SONG_POSITION_BEAT;
+use crate::error::{MidiError, Recovery, RecoveryPolicy};
 use crate::event_loop::{EngineMessage, TransportAction};
-use log::{debug, error, info};
-use midir::{Ignore, MidiInput, MidiInputPort};
+use crate::midi_input::MidiParser;
+use crate::midi_message::MidiMessage;
+use crate::mtc::QuarterFrameDecoder;
+use crate::reconnect::{ConnectionState, DeviceStatus, ReconnectPolicy, Reconnector};
+use crate::thru::{CLOCK_INPUT, TIMECODE_INPUT};
+use log::{debug, error, info, trace, warn};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort};
 use std::sync::mpsc::Sender;
 use std::thread;
+use std::time::{Duration, Instant};
+
+// How often the connection thread checks whether it has been stopped
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct ExternalClock {
     device_name: String,
     engine_tx: Sender<EngineMessage>,
+    role: InputRole,
+    reconnect: ReconnectPolicy,
+    recovery: RecoveryPolicy,
+    threads: ClockThreads,
 }
 
+// A device connection that forwards what it receives to the engine
+type InputConnection = MidiInputConnection<InputDecoder>;
+
 impl ExternalClock {
     pub fn new(device_name: String, engine_tx: Sender<EngineMessage>) -> Self {
         info!("Creating new ExternalClock with device: {}", device_name);
         ExternalClock {
             device_name,
             engine_tx,
+            role: InputRole::Clock,
+            reconnect: ReconnectPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            threads: ClockThreads::default(),
+        }
+    }
+
+    /// Follows MIDI Time Code from a device without taking its beat clock.
+    pub fn timecode_reader(device_name: String, engine_tx: Sender<EngineMessage>) -> Self {
+        info!("Creating MTC reader with device: {}", device_name);
+        ExternalClock {
+            device_name,
+            engine_tx,
+            role: InputRole::Timecode,
+            reconnect: ReconnectPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            threads: ClockThreads::default(),
         }
     }
+
+    /// Passes on what a device plays, for thru routes from input `name`,
+    /// without taking its clock or timecode.
+    pub fn thru_input(name: String, device_name: String, engine_tx: Sender<EngineMessage>) -> Self {
+        info!(
+            "Creating MIDI input '{}' with device: {}",
+            name, device_name
+        );
+        ExternalClock {
+            device_name,
+            engine_tx,
+            role: InputRole::Thru(name),
+            reconnect: ReconnectPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            threads: ClockThreads::default(),
+        }
+    }
+
+    /// Sets how the device is looked for again after it is unplugged.
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Sets whether a device missing at start is looked for, or fails the
+    /// start.
+    pub fn with_recovery_policy(mut self, policy: RecoveryPolicy) -> Self {
+        self.recovery = policy;
+        self
+    }
 }
 
 impl ClockSource for ExternalClock {
-    fn start(&self) {
+    /// Connects to the device straight away. If it is missing, the start
+    /// fails unless the recovery policy is to keep looking for it.
+    fn start(&self) -> Result<(), MidiError> {
+        let Some(running) = self.threads.begin() else {
+            return Ok(());
+        };
         info!("Starting ExternalClock with device: {}", self.device_name);
-        let engine_tx = self.engine_tx.clone();
-        let device_name = self.device_name.clone();
-
-        thread::spawn(move || {
-            run_midi_connection(engine_tx, device_name);
-        });
-    }
-}
-
-fn handle_midi_message(timestamp: u64, message: &[u8], engine_message_tx: &Sender<EngineMessage>) {
-    if message.first() == Some(&0xF8) {
-        debug!("Received MIDI Clock message");
-        engine_message_tx.send(EngineMessage::Tick).unwrap();
-    } else if message.first() == Some(&0xFA) {
-        engine_message_tx
-            .send(EngineMessage::TransportCommand(TransportAction::Start))
-            .unwrap();
-    } else if message.first() == Some(&0xFC) {
-        engine_message_tx
-            .send(EngineMessage::TransportCommand(TransportAction::Stop))
-            .unwrap();
-    } else if let Some(&msg_type) = message.first() {
-        debug!(
-            "Received MIDI message type: {:X} at timestamp: {}",
-            msg_type, timestamp
-        );
+        let connection = MidiConnection {
+            engine_tx: self.engine_tx.clone(),
+            device_name: self.device_name.clone(),
+            role: self.role.clone(),
+            reconnect: self.reconnect,
+        };
+
+        match connection.connect() {
+            Err(e) if self.recovery.recover(&e) != Recovery::Retry => {
+                self.threads.stop();
+                Err(e)
+            }
+            first => {
+                self.threads.spawn(move || connection.run(first, &running));
+                Ok(())
+            }
+        }
+    }
+
+    fn stop(&self) {
+        info!("Stopping ExternalClock on device: {}", self.device_name);
+        self.threads.stop();
+    }
+}
+
+/// Names of the MIDI input ports that can be bound as a clock source.
+pub fn input_device_names() -> Vec<String> {
+    let Ok(midi_in) = MidiInput::new("phasorsyncrs-list") else {
+        return Vec::new();
+    };
+    midi_in
+        .ports()
+        .iter()
+        .filter_map(|port| midi_in.port_name(port).ok())
+        .collect()
+}
+
+/// Decodes the 14-bit Song Position Pointer value (in MIDI beats) from its
+/// LSB and MSB data bytes.
+pub fn decode_song_position(lsb: u8, msb: u8) -> u16 {
+    ((msb as u16 & 0x7F) << 7) | (lsb as u16 & 0x7F)
+}
+
+/// Converts a Song Position Pointer value into engine ticks.
+pub fn song_position_to_ticks(midi_beats: u16) -> u64 {
+    midi_beats as u64 * TICKS_PER_SONG_POSITION_BEAT
+}
+
+/// What a bound input is used for.
+#[derive(Clone, Debug, PartialEq)]
+enum InputRole {
+    // Drives the engine's clock and transport, and its timecode is followed
+    Clock,
+    // Only its MIDI Time Code is followed
+    Timecode,
+    // Drives nothing; what it plays is passed on under this name
+    Thru(String),
+}
+
+impl InputRole {
+    // The input's name in thru routes and device statuses
+    fn name(&self) -> &str {
+        match self {
+            InputRole::Clock => CLOCK_INPUT,
+            InputRole::Timecode => TIMECODE_INPUT,
+            InputRole::Thru(name) => name,
+        }
+    }
+}
+
+/// What a device connection has read so far: the partial message the
+/// parser is part way through and the quarter frames gathered towards the
+/// next timecode.
+#[derive(Debug)]
+struct InputDecoder {
+    parser: MidiParser,
+    quarter_frames: QuarterFrameDecoder,
+    role: InputRole,
+}
+
+impl InputDecoder {
+    fn new(role: InputRole) -> Self {
+        InputDecoder {
+            parser: MidiParser::new(),
+            quarter_frames: QuarterFrameDecoder::default(),
+            role,
+        }
+    }
+
+    /// Parses bytes from the device, however they are split up. What the
+    /// engine acts on is forwarded as its own message, and then every
+    /// message is passed on for the thru routes.
+    fn receive(&mut self, bytes: &[u8], engine_message_tx: &Sender<EngineMessage>) {
+        for message in self.parser.parse(bytes) {
+            if let Some(engine_message) = self.engine_message(&message) {
+                forward(engine_message, engine_message_tx);
+            }
+            let input = self.role.name().to_string();
+            forward(
+                EngineMessage::MidiInput { input, message },
+                engine_message_tx,
+            );
+        }
+    }
+
+    // Timecode goes to the engine from the clock and timecode inputs.
+    // Clock and transport drive it only from the clock input.
+    fn engine_message(&mut self, message: &MidiMessage) -> Option<EngineMessage> {
+        match (&self.role, message) {
+            (InputRole::Thru(_), _) => None,
+            (_, MidiMessage::MtcQuarterFrame(data)) => {
+                self.quarter_frames.push(*data).map(EngineMessage::Timecode)
+            }
+            (_, MidiMessage::MtcFullFrame(timecode)) => {
+                debug!("Received MTC full frame {}", timecode);
+                Some(EngineMessage::LocateTimecode(*timecode))
+            }
+            (InputRole::Timecode, _) => None,
+            (_, message) => transport_message(message),
+        }
+    }
+}
+
+fn transport_message(message: &MidiMessage) -> Option<EngineMessage> {
+    match *message {
+        MidiMessage::TimingClock => Some(EngineMessage::Tick),
+        MidiMessage::Start => Some(EngineMessage::TransportCommand(TransportAction::Start)),
+        MidiMessage::Continue => Some(EngineMessage::TransportCommand(TransportAction::Continue)),
+        // MIDI Stop holds the song position so that Continue can pick it up
+        MidiMessage::Stop => Some(EngineMessage::TransportCommand(TransportAction::Pause)),
+        MidiMessage::SongPositionPointer(position) => {
+            debug!("Received Song Position Pointer: {} MIDI beats", position);
+            Some(EngineMessage::Locate(song_position_to_ticks(position)))
+        }
+        _ => None,
+    }
+}
+
+fn forward(engine_message: EngineMessage, engine_message_tx: &Sender<EngineMessage>) {
+    trace!("Forwarding {:?}", engine_message);
+    if let Err(e) = engine_message_tx.send(engine_message) {
+        error!("Failed to forward MIDI input: {}", e);
     }
 }
 
@@ -56,7 +242,7 @@ fn port_name_matches_device(port_name: &str, device_name: &str) -> bool {
     port_name.contains(device_name)
 }
 
-fn find_midi_port(midi_in: &mut MidiInput, device_name: &str) -> Option<MidiInputPort> {
+fn find_midi_port(midi_in: &mut MidiInput, device_name: &str) -> Result<MidiInputPort, MidiError> {
     let in_ports = midi_in.ports();
     debug!("Available MIDI input ports:");
     for port in &in_ports {
@@ -70,57 +256,271 @@ fn find_midi_port(midi_in: &mut MidiInput, device_name: &str) -> Option<MidiInpu
         debug!("Checking port: {}", port_name);
         port_name_matches_device(&port_name, device_name)
     }) {
-        Some(port) => Some(port.clone()),
-        None => {
-            // Log available devices for troubleshooting
-            let available_devices: Vec<String> = in_ports
+        Some(port) => Ok(port.clone()),
+        // The error lists the available devices for troubleshooting
+        None => Err(MidiError::PortNotFound {
+            device: device_name.to_string(),
+            available: in_ports
                 .iter()
                 .filter_map(|p| midi_in.port_name(p).ok())
-                .collect();
+                .collect(),
+        }),
+    }
+}
 
-            let error_message = format!("External MIDI device '{}' not found!", device_name);
-            error!("{}", error_message);
-            info!("Available MIDI devices: {:?}", available_devices);
-            println!("{}", error_message);
-            error!("Application cannot continue without the specified device");
+/// What the connection thread needs to bind a device and feed the engine.
+struct MidiConnection {
+    engine_tx: Sender<EngineMessage>,
+    device_name: String,
+    role: InputRole,
+    reconnect: ReconnectPolicy,
+}
 
-            // Exit with error code
-            std::process::exit(1);
+impl MidiConnection {
+    /// Holds the device connection open until the clock is stopped,
+    /// starting from the attempt made at start. A missing or unplugged
+    /// device is looked for again as the reconnect policy allows, and leaves
+    /// the clock silent meanwhile, so another source or device can be
+    /// chosen without restarting.
+    fn run(self, first: Result<(String, InputConnection), MidiError>, running: &RunFlag) {
+        let mut reconnector = Reconnector::new(self.reconnect, Instant::now());
+        let mut attempt = Some(first);
+        while running.is_running() {
+            if attempt.is_none() && reconnector.due(Instant::now()) {
+                attempt = Some(self.connect());
+            }
+            if let Some(result) = attempt.take() {
+                self.settle(result, &mut reconnector, running);
+                let gave_up = matches!(reconnector.state(), ConnectionState::GaveUp { .. });
+                if gave_up && running.is_running() {
+                    error!("Giving up on MIDI input '{}'", self.device_name);
+                    return;
+                }
+            }
+            thread::sleep(STOP_POLL_INTERVAL);
         }
     }
-}
 
-fn run_midi_connection(engine_tx: Sender<EngineMessage>, device_name: String) {
-    let mut midi_in =
-        MidiInput::new("phasorsyncrs-external").expect("Failed to initialize MIDI input");
-    midi_in.ignore(Ignore::None);
+    // Holds a connection until it is lost, or counts a failed attempt
+    fn settle(
+        &self,
+        result: Result<(String, InputConnection), MidiError>,
+        reconnector: &mut Reconnector,
+        running: &RunFlag,
+    ) {
+        match result {
+            Ok((port_name, connection)) => {
+                reconnector.connected(port_name.clone());
+                self.report(reconnector.state());
+                self.hold(connection, &port_name, running);
+                reconnector.lost(Instant::now());
+            }
+            Err(e) => {
+                warn!("MIDI input '{}': {}", self.device_name, e);
+                reconnector.failed(Instant::now());
+            }
+        }
+        self.report(reconnector.state());
+    }
 
-    let in_port = find_midi_port(&mut midi_in, &device_name).unwrap();
+    fn connect(&self) -> Result<(String, InputConnection), MidiError> {
+        let mut midi_in = MidiInput::new("phasorsyncrs-external")
+            .map_err(|e| MidiError::Backend(e.to_string()))?;
+        midi_in.ignore(Ignore::None);
 
-    info!("Found matching MIDI device, attempting connection...");
+        let in_port = find_midi_port(&mut midi_in, &self.device_name)?;
+        let connect_error = |reason: String| MidiError::Connect {
+            device: self.device_name.clone(),
+            reason,
+        };
+        let port_name = midi_in
+            .port_name(&in_port)
+            .map_err(|e| connect_error(e.to_string()))?;
+        info!("Found matching MIDI device, attempting connection...");
 
-    let engine_message_tx = engine_tx.clone(); // Shadow the outer tick_tx
+        let engine_message_tx = self.engine_tx.clone();
+        let connection = midi_in
+            .connect(
+                &in_port,
+                "phasorsyncrs-external-conn",
+                move |_timestamp, message, decoder| decoder.receive(message, &engine_message_tx),
+                InputDecoder::new(self.role.clone()),
+            )
+            .map_err(|e| connect_error(e.to_string()))?;
+        info!("Connected to MIDI input {}", port_name);
+        Ok((port_name, connection))
+    }
 
-    let _conn_in = midi_in
-        .connect(
-            &in_port,
-            "phasorsyncrs-external-conn",
-            move |timestamp, message, _| {
-                handle_midi_message(timestamp, message, &engine_message_tx);
-            },
-            (),
-        )
-        .expect("Failed to connect to external MIDI device");
+    // Keeps the connection until the clock is stopped or the port drops
+    // out of the port list because the device was unplugged
+    fn hold(&self, connection: InputConnection, port_name: &str, running: &RunFlag) {
+        let mut last_check = Instant::now();
+        while running.is_running() {
+            thread::sleep(STOP_POLL_INTERVAL);
+            if last_check.elapsed() < self.reconnect.interval {
+                continue;
+            }
+            last_check = Instant::now();
+            if !input_device_names().iter().any(|name| name == port_name) {
+                warn!("MIDI input {} disconnected", port_name);
+                break;
+            }
+        }
 
-    info!("Starting MIDI connection maintenance thread");
-    loop {
-        thread::sleep(std::time::Duration::from_millis(10));
+        // Closing joins the input callback, so no message outlives the stop
+        connection.close();
+        info!("Disconnected from {}", self.device_name);
+    }
+
+    fn report(&self, state: &ConnectionState) {
+        // The engine may already have gone during shutdown
+        let _ = self
+            .engine_tx
+            .send(EngineMessage::DeviceStatus(DeviceStatus {
+                role: self.role.name().to_string(),
+                device: self.device_name.clone(),
+                input: true,
+                state: state.clone(),
+            }));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{TICKS_PER_BEAT, TICKS_PER_CLOCK};
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_decode_song_position_combines_7_bit_halves() {
+        assert_eq!(decode_song_position(0x00, 0x00), 0);
+        assert_eq!(decode_song_position(0x48, 0x01), 200);
+        assert_eq!(decode_song_position(0x7F, 0x7F), 0x3FFF);
+        // The top bit of each data byte is not part of the value
+        assert_eq!(decode_song_position(0xFF, 0x80), 0x7F);
+    }
+
+    #[test]
+    fn test_song_position_is_six_clock_pulses_per_midi_beat() {
+        assert_eq!(TICKS_PER_SONG_POSITION_BEAT, 6 * TICKS_PER_CLOCK);
+        assert_eq!(song_position_to_ticks(1), 6 * TICKS_PER_CLOCK);
+        // 16 MIDI beats is one 4/4 bar of sixteenths
+        assert_eq!(song_position_to_ticks(16), 4 * TICKS_PER_BEAT);
+    }
+
+    // What the engine was told to do, leaving out the messages passed on
+    // for the thru routes
+    fn engine_actions(rx: &mpsc::Receiver<EngineMessage>) -> Vec<EngineMessage> {
+        rx.try_iter()
+            .filter(|message| !matches!(message, EngineMessage::MidiInput { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn test_song_position_message_locates_engine() {
+        let (tx, rx) = mpsc::channel();
+        InputDecoder::new(InputRole::Clock).receive(&[0xF2, 0x10, 0x00], &tx);
+
+        match rx.try_recv() {
+            Ok(EngineMessage::Locate(tick)) => assert_eq!(tick, 4 * TICKS_PER_BEAT),
+            other => panic!("expected a locate message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stop_pauses_and_continue_resumes() {
+        let (tx, rx) = mpsc::channel();
+        let mut decoder = InputDecoder::new(InputRole::Clock);
+        decoder.receive(&[0xFC], &tx);
+        decoder.receive(&[0xFB], &tx);
+
+        assert!(matches!(
+            engine_actions(&rx).as_slice(),
+            [
+                EngineMessage::TransportCommand(TransportAction::Pause),
+                EngineMessage::TransportCommand(TransportAction::Continue),
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_clock_inside_a_running_status_note_still_ticks() {
+        let (tx, rx) = mpsc::channel();
+        let mut decoder = InputDecoder::new(InputRole::Clock);
+        decoder.receive(&[0x91, 60], &tx);
+        decoder.receive(&[0xF8, 100, 62, 0], &tx);
+
+        let received: Vec<_> = rx.try_iter().collect();
+        assert!(matches!(
+            received.as_slice(),
+            [
+                EngineMessage::Tick,
+                EngineMessage::MidiInput {
+                    message: MidiMessage::TimingClock,
+                    ..
+                },
+                EngineMessage::MidiInput {
+                    message: MidiMessage::NoteOn { note: 60, .. },
+                    ..
+                },
+                EngineMessage::MidiInput {
+                    message: MidiMessage::NoteOn {
+                        channel: 1,
+                        note: 62,
+                        velocity: 0,
+                        ..
+                    },
+                    ..
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_thru_inputs_drive_nothing() {
+        let (tx, rx) = mpsc::channel();
+        let mut decoder = InputDecoder::new(InputRole::Thru("keys".to_string()));
+        decoder.receive(&[0xFA, 0xF8, 0x90, 60, 100, 0xFE], &tx);
+
+        // Active sensing is passed on too, for routes that ask for it
+        let received: Vec<_> = rx.try_iter().collect();
+        assert_eq!(received.len(), 4);
+        assert!(received.iter().all(|message| matches!(
+            message,
+            EngineMessage::MidiInput { input, .. } if input == "keys"
+        )));
+    }
+
+    #[test]
+    fn test_timecode_messages_reach_engine() {
+        let (tx, rx) = mpsc::channel();
+        let mut decoder = InputDecoder::new(InputRole::Timecode);
+        let timecode = crate::mtc::Timecode {
+            hours: 1,
+            minutes: 0,
+            seconds: 0,
+            frames: 0,
+            rate: crate::mtc::FrameRate::Fps25,
+        };
+
+        decoder.receive(&crate::mtc::full_frame(&timecode), &tx);
+        for piece in 0..8 {
+            let data = crate::mtc::quarter_frame_data(&timecode, piece);
+            decoder.receive(&[0xF1, data], &tx);
+        }
+        // A timecode reader leaves beat clock and transport to other sources
+        decoder.receive(&[0xF8, 0xFA], &tx);
+
+        let actions = engine_actions(&rx);
+        assert!(matches!(
+            actions.as_slice(),
+            [
+                EngineMessage::LocateTimecode(located),
+                EngineMessage::Timecode(running),
+            ] if *located == timecode && running.frames == 2
+        ));
+    }
 
     #[test]
     fn test_port_name_matches_device_positive() {
@@ -137,4 +537,22 @@ mod tests {
             "NonExistentDevice12345"
         ));
     }
+
+    #[test]
+    fn test_missing_device_fails_the_start_unless_retrying() {
+        let (tx, _rx) = mpsc::channel();
+        let clock = ExternalClock::new("No Such Device Attached".to_string(), tx.clone())
+            .with_recovery_policy(RecoveryPolicy::Degrade);
+        let result = clock.start();
+        assert!(matches!(
+            result,
+            Err(MidiError::PortNotFound { .. } | MidiError::Backend(_))
+        ));
+        // A failed start leaves the clock free to start again
+        assert!(clock.start().is_err());
+
+        let clock = ExternalClock::new("No Such Device Attached".to_string(), tx);
+        assert_eq!(clock.start(), Ok(()));
+        clock.stop();
+    }
 }
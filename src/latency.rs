@@ -0,0 +1,305 @@
+// latency.rs
+
+use crate::config::TICKS_PER_BEAT;
+use crate::midi_output::MidiMessage;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How late a port's device sounds after it is sent a message. Negative
+/// values are for devices that play early relative to the others.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Latency {
+    Millis(f64),
+    Ticks(i64),
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Ticks(0)
+    }
+}
+
+impl Latency {
+    /// Parses `12ms`, `-3.5ms`, `24t` or `24ticks`; a bare number is
+    /// milliseconds.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(ticks) = value
+            .strip_suffix("ticks")
+            .or_else(|| value.strip_suffix('t'))
+        {
+            return ticks.trim().parse().ok().map(Latency::Ticks);
+        }
+        let millis: f64 = value
+            .strip_suffix("ms")
+            .unwrap_or(value)
+            .trim()
+            .parse()
+            .ok()?;
+        millis.is_finite().then_some(Latency::Millis(millis))
+    }
+
+    /// The latency in engine ticks at `bpm`, to the nearest tick.
+    pub fn to_ticks(&self, bpm: f64) -> i64 {
+        match *self {
+            Latency::Ticks(ticks) => ticks,
+            Latency::Millis(_) if bpm <= 0.0 => 0,
+            Latency::Millis(millis) => {
+                (millis / 1000.0 * bpm / 60.0 * TICKS_PER_BEAT as f64).round() as i64
+            }
+        }
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Latency::Millis(millis) => write!(f, "{}ms", millis),
+            Latency::Ticks(ticks) => write!(f, "{}t", ticks),
+        }
+    }
+}
+
+/// A latency for every output port whose name contains `port`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortLatency {
+    pub port: String,
+    pub latency: Latency,
+}
+
+impl PortLatency {
+    /// Parses `PORT=LATENCY`, e.g. `OXI ONE=12ms`.
+    pub fn parse(spec: &str) -> Result<Self, PortLatencyError> {
+        let invalid = || PortLatencyError(spec.to_string());
+        let (port, latency) = spec.rsplit_once('=').ok_or_else(invalid)?;
+        let port = port.trim();
+        if port.is_empty() {
+            return Err(invalid());
+        }
+        Ok(PortLatency {
+            port: port.to_string(),
+            latency: Latency::parse(latency).ok_or_else(invalid)?,
+        })
+    }
+
+    /// The latency of the first entry matching `port_name`, or none.
+    pub fn lookup(latencies: &[PortLatency], port_name: &str) -> Latency {
+        latencies
+            .iter()
+            .find(|entry| port_name.contains(&entry.port))
+            .map_or_else(Latency::default, |entry| entry.latency)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PortLatencyError(String);
+
+impl fmt::Display for PortLatencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid output latency '{}' (expected PORT=MS, PORT=MSms or PORT=TICKSt)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for PortLatencyError {}
+
+/// Holds messages back so every port's device sounds them together.
+///
+/// The lookahead is the largest port latency. A message handed over on a
+/// tick is meant to be heard a lookahead later, so it goes out after
+/// `lookahead - latency` ticks: at once to the slowest port, and last to
+/// the quickest. Time here counts ticks handed to `advance`, not song
+/// position, so a locate never strands a message. A port's delay may
+/// shrink with the tempo, but its messages still go out in the order they
+/// were scheduled.
+#[derive(Debug, Default)]
+pub struct LatencyCompensator {
+    // Ticks each port's messages wait, indexed by port
+    delays: Vec<u64>,
+    lookahead: u64,
+    now: u64,
+    // Latest tick each port has a message due on, indexed by port
+    last_due: Vec<u64>,
+    pending: BTreeMap<u64, Vec<(usize, MidiMessage)>>,
+}
+
+impl LatencyCompensator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the latency of each port in ticks, indexed by port.
+    pub fn set_latencies(&mut self, latencies: &[i64]) {
+        let lookahead = latencies.iter().copied().max().unwrap_or(0).max(0);
+        self.lookahead = lookahead as u64;
+        self.delays = latencies
+            .iter()
+            .map(|latency| (lookahead - latency) as u64)
+            .collect();
+    }
+
+    pub fn lookahead(&self) -> u64 {
+        self.lookahead
+    }
+
+    /// How long `port`'s messages wait. Ports without a latency are
+    /// treated as having none.
+    pub fn delay(&self, port: usize) -> u64 {
+        self.delays.get(port).copied().unwrap_or(self.lookahead)
+    }
+
+    /// Queues `message` for `port`, or hands it back if it is due now.
+    pub fn schedule(&mut self, port: usize, message: MidiMessage) -> Option<MidiMessage> {
+        if self.last_due.len() <= port {
+            self.last_due.resize(port + 1, 0);
+        }
+        // Never ahead of what the port already has waiting
+        let due = (self.now + self.delay(port)).max(self.last_due[port]);
+        if due <= self.now {
+            return Some(message);
+        }
+        self.last_due[port] = due;
+        self.pending.entry(due).or_default().push((port, message));
+        None
+    }
+
+    /// Moves on one tick and returns the messages now due, in the order
+    /// they were scheduled.
+    pub fn advance(&mut self) -> Vec<(usize, MidiMessage)> {
+        self.now += 1;
+        let later = self.pending.split_off(&(self.now + 1));
+        std::mem::replace(&mut self.pending, later)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    /// Removes every message still held back, in the order they were due.
+    pub fn drain_all(&mut self) -> Vec<(usize, MidiMessage)> {
+        self.last_due.clear();
+        std::mem::take(&mut self.pending)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    /// Number of messages still held back.
+    pub fn pending(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(note: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel: 0,
+            note,
+            velocity: 100,
+            duration_ticks: 0,
+        }
+    }
+
+    #[test]
+    fn test_latency_parses_millis_and_ticks() {
+        assert_eq!(Latency::parse("12ms"), Some(Latency::Millis(12.0)));
+        assert_eq!(Latency::parse("-3.5"), Some(Latency::Millis(-3.5)));
+        assert_eq!(Latency::parse("24t"), Some(Latency::Ticks(24)));
+        assert_eq!(Latency::parse("-8ticks"), Some(Latency::Ticks(-8)));
+        assert_eq!(Latency::parse("soon"), None);
+        assert_eq!(Latency::parse("1.5t"), None);
+
+        // 12 ms at 120 BPM is 23.04 ticks of 960 PPQN
+        assert_eq!(Latency::Millis(12.0).to_ticks(120.0), 23);
+        assert_eq!(Latency::Millis(-12.0).to_ticks(120.0), -23);
+        assert_eq!(Latency::Ticks(5).to_ticks(90.0), 5);
+    }
+
+    #[test]
+    fn test_port_latency_parses_and_matches_by_substring() {
+        let entries = vec![
+            PortLatency::parse("OXI ONE=12ms").unwrap(),
+            PortLatency::parse("TR-8=-2t").unwrap(),
+        ];
+        assert_eq!(
+            PortLatency::lookup(&entries, "OXI ONE MIDI 1"),
+            Latency::Millis(12.0)
+        );
+        assert_eq!(
+            PortLatency::lookup(&entries, "TR-8:TR-8 MIDI 1"),
+            Latency::Ticks(-2)
+        );
+        assert_eq!(PortLatency::lookup(&entries, "Other"), Latency::default());
+
+        assert!(PortLatency::parse("OXI ONE").is_err());
+        assert!(PortLatency::parse("=12ms").is_err());
+        assert!(PortLatency::parse("OXI=late").is_err());
+    }
+
+    #[test]
+    fn test_slowest_port_goes_first_and_the_rest_wait() {
+        let mut compensator = LatencyCompensator::new();
+        compensator.set_latencies(&[23, 0]);
+        assert_eq!(compensator.lookahead(), 23);
+
+        assert_eq!(compensator.schedule(0, note_on(60)), Some(note_on(60)));
+        assert_eq!(compensator.schedule(1, note_on(36)), None);
+
+        for _ in 0..22 {
+            assert!(compensator.advance().is_empty());
+        }
+        assert_eq!(compensator.advance(), vec![(1, note_on(36))]);
+        assert_eq!(compensator.pending(), 0);
+    }
+
+    #[test]
+    fn test_a_shorter_delay_never_overtakes_waiting_messages() {
+        let mut compensator = LatencyCompensator::new();
+        compensator.set_latencies(&[23, 0]);
+        compensator.schedule(1, note_on(60));
+        compensator.advance();
+
+        // The tempo halves and the port's delay with it
+        compensator.set_latencies(&[12, 0]);
+        assert_eq!(compensator.schedule(1, note_on(64)), None);
+        for _ in 0..21 {
+            assert!(compensator.advance().is_empty());
+        }
+        assert_eq!(
+            compensator.advance(),
+            vec![(1, note_on(60)), (1, note_on(64))]
+        );
+
+        // Once nothing waits the shorter delay applies
+        compensator.schedule(1, note_on(67));
+        for _ in 0..11 {
+            assert!(compensator.advance().is_empty());
+        }
+        assert_eq!(compensator.advance(), vec![(1, note_on(67))]);
+    }
+
+    #[test]
+    fn test_negative_latency_is_held_back_past_the_others() {
+        let mut compensator = LatencyCompensator::new();
+        compensator.set_latencies(&[0, -4]);
+        assert_eq!(compensator.lookahead(), 0);
+        assert_eq!(compensator.delay(0), 0);
+        assert_eq!(compensator.delay(1), 4);
+
+        compensator.schedule(1, note_on(60));
+        compensator.schedule(1, note_on(64));
+        for _ in 0..3 {
+            compensator.advance();
+        }
+        assert_eq!(compensator.pending(), 2);
+        assert_eq!(
+            compensator.advance(),
+            vec![(1, note_on(60)), (1, note_on(64))]
+        );
+    }
+}
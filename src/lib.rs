@@ -1,9 +1,29 @@
+pub mod active_notes;
 pub mod clock;
+pub mod clock_control;
+pub mod clock_failover;
 pub mod config;
+pub mod error;
 pub mod event_loop;
 pub mod external_clock;
+pub mod latency;
+pub mod link;
+pub mod link_protocol;
 pub mod logging;
+pub mod meter;
+pub mod midi_input;
+pub mod midi_message;
 pub mod midi_output;
+pub mod mtc;
 pub mod musical_graph;
+pub mod output_worker;
+pub mod reconnect;
+pub mod routing;
+pub mod scheduler;
+pub mod simulation;
 pub mod state;
+pub mod swing;
+pub mod tempo_estimator;
+pub mod tempo_map;
+pub mod thru;
 pub mod tui;
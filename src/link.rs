@@ -0,0 +1,825 @@
+// link.rs
+
+use crate::clock::{ClockSource, ClockThreads, RunFlag, TempoHandle};
+use crate::config::MIDI_CLOCK_PPQN;
+use crate::error::MidiError;
+use crate::event_loop::EngineMessage;
+use crate::link_protocol::{
+    decode_discovery, decode_measurement, encode_byebye, encode_discovery, encode_measurement,
+    random_node_id, DiscoveryKind, DiscoveryMessage, MeasurementMessage, NodeId, PeerState,
+    StartStopState, Timeline, DISCOVERY_GROUP, DISCOVERY_PORT,
+};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Seconds a peer stays known without hearing from it again
+const PEER_TTL: u8 = 5;
+// Beats in a Link phase cycle; sessions align their bar lines on this
+const LINK_QUANTUM_BEATS: u64 = 4;
+const BROADCAST_INTERVAL: Duration = Duration::from_millis(250);
+// Pongs gathered before joining a session; the median offset is used
+const MEASUREMENT_SAMPLES: usize = 5;
+// Longest the ticker sleeps, so tempo changes are picked up promptly
+const MAX_TICKER_SLEEP: Duration = Duration::from_millis(5);
+
+/// Where the peer listens and who it talks to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkConfig {
+    /// Port for discovery messages
+    pub port: u16,
+    /// Peers to announce to directly. When set, multicast is not used, which
+    /// lets several instances share one host.
+    pub peers: Vec<SocketAddr>,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            port: DISCOVERY_PORT,
+            peers: Vec::new(),
+        }
+    }
+}
+
+impl LinkConfig {
+    fn uses_multicast(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    fn bind_ip(&self) -> Ipv4Addr {
+        if self.uses_multicast() {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            Ipv4Addr::LOCALHOST
+        }
+    }
+
+    fn announce_targets(&self) -> Vec<SocketAddr> {
+        if self.uses_multicast() {
+            vec![SocketAddr::V4(SocketAddrV4::new(
+                DISCOVERY_GROUP,
+                self.port,
+            ))]
+        } else {
+            self.peers.clone()
+        }
+    }
+}
+
+/// Microseconds on this host's monotonic clock.
+fn host_micros() -> i64 {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_micros() as i64
+}
+
+/// A datagram to send in response to something received.
+#[derive(Debug)]
+pub enum Outgoing {
+    Discovery(SocketAddr, Vec<u8>),
+    Measurement(SocketAddr, Vec<u8>),
+}
+
+// A session we are measuring our clock offset against before joining
+struct Join {
+    session_id: NodeId,
+    timeline: Timeline,
+    offsets: Vec<i64>,
+}
+
+/// Session membership and the shared timeline. Every peer founds a session
+/// of its own; when two sessions meet, the one with the lower id wins and
+/// its members' clock offset is measured so the timeline can be adopted.
+pub struct LinkSession {
+    node_id: NodeId,
+    session_id: NodeId,
+    timeline: Timeline,
+    // Added to host time to get the session's shared ("ghost") time
+    ghost_offset: i64,
+    measurement_endpoint: SocketAddrV4,
+    // Known peers and when they expire
+    peers: HashMap<NodeId, Instant>,
+    join: Option<Join>,
+}
+
+impl LinkSession {
+    pub fn new(bpm: f64, measurement_endpoint: SocketAddrV4) -> Self {
+        let node_id = random_node_id();
+        info!(
+            "Founding Link session {} at {:.2} BPM",
+            String::from_utf8_lossy(&node_id),
+            bpm
+        );
+        LinkSession {
+            node_id,
+            session_id: node_id,
+            timeline: Timeline::new(bpm, host_micros()),
+            ghost_offset: 0,
+            measurement_endpoint,
+            peers: HashMap::new(),
+            join: None,
+        }
+    }
+
+    pub fn session_id(&self) -> NodeId {
+        self.session_id
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.timeline.bpm()
+    }
+
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn ghost_time(&self, host_time: i64) -> i64 {
+        host_time + self.ghost_offset
+    }
+
+    pub fn peer_state(&self) -> PeerState {
+        PeerState {
+            node_id: self.node_id,
+            session_id: self.session_id,
+            timeline: self.timeline,
+            start_stop: StartStopState::default(),
+            measurement_endpoint: self.measurement_endpoint,
+        }
+    }
+
+    /// Changes the session tempo from `host_time`, keeping the beat grid
+    /// continuous.
+    pub fn set_tempo(&mut self, bpm: f64, host_time: i64) {
+        self.timeline = self.timeline.with_tempo(bpm, self.ghost_time(host_time));
+    }
+
+    /// Latest 24 PPQN grid line the session has passed at `host_time`.
+    pub fn session_tick(&self, host_time: i64) -> i64 {
+        let beats = self.timeline.beats_at(self.ghost_time(host_time));
+        (beats * MIDI_CLOCK_PPQN as f64).floor() as i64
+    }
+
+    /// Host time at which the session reaches grid line `tick`.
+    pub fn host_time_of_tick(&self, tick: i64) -> i64 {
+        let beats = tick as f64 / MIDI_CLOCK_PPQN as f64;
+        self.timeline.time_at_beats(beats) - self.ghost_offset
+    }
+
+    pub fn on_discovery(
+        &mut self,
+        message: DiscoveryMessage,
+        source: SocketAddr,
+        now: Instant,
+    ) -> Vec<Outgoing> {
+        if message.node_id == self.node_id {
+            return Vec::new();
+        }
+        let Some(mut state) = message.state else {
+            if self.peers.remove(&message.node_id).is_some() {
+                info!(
+                    "Link peer {} left",
+                    String::from_utf8_lossy(&message.node_id)
+                );
+            }
+            return Vec::new();
+        };
+
+        // Peers bound to every interface cannot name their own address
+        if state.measurement_endpoint.ip().is_unspecified() {
+            if let SocketAddr::V4(source) = source {
+                state.measurement_endpoint =
+                    SocketAddrV4::new(*source.ip(), state.measurement_endpoint.port());
+            }
+        }
+
+        let mut outgoing = Vec::new();
+        if message.kind == DiscoveryKind::Alive {
+            let response = encode_discovery(DiscoveryKind::Response, PEER_TTL, &self.peer_state());
+            outgoing.push(Outgoing::Discovery(source, response));
+        }
+        outgoing.extend(self.track_peer(state, message.ttl, now));
+        outgoing
+    }
+
+    fn track_peer(&mut self, state: PeerState, ttl: u8, now: Instant) -> Vec<Outgoing> {
+        let expires = now + Duration::from_secs(ttl.max(1) as u64);
+        if self.peers.insert(state.node_id, expires).is_none() {
+            info!(
+                "Link peer {} joined",
+                String::from_utf8_lossy(&state.node_id)
+            );
+        }
+
+        if state.session_id == self.session_id {
+            // The most recently re-anchored timeline is the current one
+            if state.timeline.time_origin > self.timeline.time_origin {
+                debug!("Adopting Link timeline at {:.2} BPM", state.timeline.bpm());
+                self.timeline = state.timeline;
+            }
+            return Vec::new();
+        }
+        if state.session_id > self.session_id {
+            return Vec::new();
+        }
+        self.start_join(state)
+    }
+
+    fn start_join(&mut self, state: PeerState) -> Vec<Outgoing> {
+        match &mut self.join {
+            // Already measuring; keep the timeline fresh for when we join
+            Some(join) if join.session_id == state.session_id => {
+                join.timeline = state.timeline;
+                if join.offsets.len() >= MEASUREMENT_SAMPLES {
+                    return Vec::new();
+                }
+            }
+            _ => {
+                self.join = Some(Join {
+                    session_id: state.session_id,
+                    timeline: state.timeline,
+                    offsets: Vec::new(),
+                });
+            }
+        }
+
+        let ping = encode_measurement(&MeasurementMessage::Ping {
+            session_id: self.session_id,
+            host_time: host_micros(),
+        });
+        vec![Outgoing::Measurement(
+            SocketAddr::V4(state.measurement_endpoint),
+            ping,
+        )]
+    }
+
+    pub fn on_measurement(
+        &mut self,
+        message: MeasurementMessage,
+        source: SocketAddr,
+        host_time: i64,
+    ) -> Vec<Outgoing> {
+        match message {
+            MeasurementMessage::Ping {
+                host_time: ping_time,
+                ..
+            } => {
+                let pong = encode_measurement(&MeasurementMessage::Pong {
+                    session_id: self.session_id,
+                    ghost_time: self.ghost_time(host_time),
+                    host_time: ping_time,
+                });
+                vec![Outgoing::Measurement(source, pong)]
+            }
+            MeasurementMessage::Pong {
+                session_id,
+                ghost_time,
+                host_time: ping_time,
+            } => {
+                // The peer read its clock roughly halfway through the round trip
+                let offset = ghost_time - (ping_time + host_time) / 2;
+                self.record_offset(session_id, offset, source)
+            }
+        }
+    }
+
+    fn record_offset(
+        &mut self,
+        session_id: NodeId,
+        offset: i64,
+        source: SocketAddr,
+    ) -> Vec<Outgoing> {
+        let Some(join) = self.join.as_mut().filter(|j| j.session_id == session_id) else {
+            return Vec::new();
+        };
+        join.offsets.push(offset);
+
+        if join.offsets.len() < MEASUREMENT_SAMPLES {
+            let ping = encode_measurement(&MeasurementMessage::Ping {
+                session_id: self.session_id,
+                host_time: host_micros(),
+            });
+            return vec![Outgoing::Measurement(source, ping)];
+        }
+
+        let mut join = self.join.take().unwrap();
+        join.offsets.sort_unstable();
+        self.ghost_offset = join.offsets[join.offsets.len() / 2];
+        self.session_id = join.session_id;
+        self.timeline = join.timeline;
+        info!(
+            "Joined Link session {} at {:.2} BPM",
+            String::from_utf8_lossy(&self.session_id),
+            self.timeline.bpm()
+        );
+        Vec::new()
+    }
+
+    fn expire_peers(&mut self, now: Instant) {
+        self.peers.retain(|node_id, expires| {
+            let alive = *expires > now;
+            if !alive {
+                warn!("Link peer {} timed out", String::from_utf8_lossy(node_id));
+            }
+            alive
+        });
+    }
+}
+
+/// Number of ticks to emit so the engine's tick count follows the session
+/// grid. Counts are compared within a bar: falling behind is made up at
+/// once, while running ahead (e.g. after joining a session) holds ticks
+/// back until the grid catches up.
+pub fn ticks_due(session_tick: i64, emitted: i64) -> i64 {
+    let ticks_per_bar = (MIDI_CLOCK_PPQN * LINK_QUANTUM_BEATS) as i64;
+    let behind = (session_tick + 1 - emitted).rem_euclid(ticks_per_bar);
+    if behind > ticks_per_bar / 2 {
+        0
+    } else {
+        behind
+    }
+}
+
+/// Joins or founds a Link session on the network and ticks the engine
+/// from the session's beat grid. Tempo changes made through the tempo
+/// handle are shared with the session, and vice versa.
+pub struct LinkClock {
+    config: LinkConfig,
+    tempo: TempoHandle,
+    tick_tx: Sender<EngineMessage>,
+    threads: ClockThreads,
+}
+
+impl LinkClock {
+    pub fn new(config: LinkConfig, tempo: TempoHandle, tick_tx: Sender<EngineMessage>) -> Self {
+        LinkClock {
+            config,
+            tempo,
+            tick_tx,
+            threads: ClockThreads::default(),
+        }
+    }
+
+    fn bind(&self) -> io::Result<(UdpSocket, UdpSocket)> {
+        let discovery = UdpSocket::bind((self.config.bind_ip(), self.config.port))?;
+        if self.config.uses_multicast() {
+            discovery.join_multicast_v4(&DISCOVERY_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+            discovery.set_multicast_loop_v4(true)?;
+        }
+        discovery.set_read_timeout(Some(BROADCAST_INTERVAL / 5))?;
+        let measurement = UdpSocket::bind((self.config.bind_ip(), 0))?;
+        // Both sockets wake regularly so their threads notice a stop
+        measurement.set_read_timeout(Some(BROADCAST_INTERVAL / 5))?;
+        Ok((discovery, measurement))
+    }
+
+    // Opens the sockets and starts the session's threads
+    fn join(&self, running: RunFlag) -> io::Result<()> {
+        let (discovery, measurement) = self.bind()?;
+        let SocketAddr::V4(endpoint) = measurement.local_addr()? else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Link needs an IPv4 measurement endpoint",
+            ));
+        };
+        info!(
+            "Starting Link peer on port {}, measurement endpoint {}",
+            self.config.port, endpoint
+        );
+
+        let session = Arc::new(Mutex::new(LinkSession::new(self.tempo.bpm(), endpoint)));
+
+        let discovery_loop = DiscoveryLoop {
+            session: session.clone(),
+            socket: discovery,
+            measurement_socket: measurement.try_clone()?,
+            targets: self.config.announce_targets(),
+            tempo: TempoSync::new(self.tempo.clone()),
+            running: running.clone(),
+        };
+        self.threads.spawn(move || discovery_loop.run());
+
+        let measurement_session = session.clone();
+        let measurement_running = running.clone();
+        self.threads
+            .spawn(move || run_measurement(measurement_session, measurement, &measurement_running));
+
+        let tick_tx = self.tick_tx.clone();
+        self.threads
+            .spawn(move || run_ticker(session, tick_tx, &running));
+        Ok(())
+    }
+}
+
+impl ClockSource for LinkClock {
+    fn start(&self) -> Result<(), MidiError> {
+        let Some(running) = self.threads.begin() else {
+            return Ok(());
+        };
+        self.join(running).map_err(|e| {
+            // Lets a later start try again
+            self.threads.stop();
+            MidiError::Network(e.to_string())
+        })
+    }
+
+    fn stop(&self) {
+        info!("Leaving Link session on port {}", self.config.port);
+        self.threads.stop();
+    }
+}
+
+/// Mirrors tempo between the engine's tempo handle and the session,
+/// whichever changed last.
+struct TempoSync {
+    handle: TempoHandle,
+    handle_bpm: f64,
+    session_bpm: f64,
+}
+
+impl TempoSync {
+    fn new(handle: TempoHandle) -> Self {
+        let bpm = handle.bpm();
+        TempoSync {
+            handle,
+            handle_bpm: bpm,
+            session_bpm: f64::NAN,
+        }
+    }
+
+    /// Returns true when the session tempo was changed locally.
+    fn sync(&mut self, session: &mut LinkSession) -> bool {
+        let handle_bpm = self.handle.bpm();
+        if handle_bpm != self.handle_bpm {
+            info!("Sharing tempo change to {:.2} BPM with Link", handle_bpm);
+            session.set_tempo(handle_bpm, host_micros());
+            self.handle_bpm = handle_bpm;
+            self.session_bpm = session.bpm();
+            return true;
+        }
+        if session.bpm() != self.session_bpm {
+            self.session_bpm = session.bpm();
+            self.handle_bpm = self.handle.set_bpm(self.session_bpm);
+        }
+        false
+    }
+}
+
+struct DiscoveryLoop {
+    session: Arc<Mutex<LinkSession>>,
+    socket: UdpSocket,
+    measurement_socket: UdpSocket,
+    targets: Vec<SocketAddr>,
+    tempo: TempoSync,
+    running: RunFlag,
+}
+
+impl DiscoveryLoop {
+    fn run(mut self) {
+        let mut buffer = [0u8; 512];
+        let mut next_broadcast = Instant::now();
+        while self.running.is_running() {
+            let now = Instant::now();
+            let changed = self.tempo.sync(&mut self.session.lock().unwrap());
+            if changed || now >= next_broadcast {
+                self.session.lock().unwrap().expire_peers(now);
+                self.broadcast();
+                next_broadcast = now + BROADCAST_INTERVAL;
+            }
+
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, source)) => self.receive(&buffer[..len], source),
+                Err(e) if is_timeout(&e) => {}
+                Err(e) => {
+                    error!("Link discovery socket failed: {}", e);
+                    return;
+                }
+            }
+        }
+        self.say_goodbye();
+    }
+
+    /// Tells the other peers we are leaving so they drop us straight away.
+    fn say_goodbye(&self) {
+        let bye = encode_byebye(&self.session.lock().unwrap().node_id);
+        for target in &self.targets {
+            if let Err(e) = self.socket.send_to(&bye, target) {
+                debug!("Failed to say goodbye to {}: {}", target, e);
+            }
+        }
+    }
+
+    fn broadcast(&self) {
+        let state = self.session.lock().unwrap().peer_state();
+        let alive = encode_discovery(DiscoveryKind::Alive, PEER_TTL, &state);
+        for target in &self.targets {
+            if let Err(e) = self.socket.send_to(&alive, target) {
+                debug!("Failed to announce to {}: {}", target, e);
+            }
+        }
+    }
+
+    fn receive(&self, bytes: &[u8], source: SocketAddr) {
+        let Some(message) = decode_discovery(bytes) else {
+            debug!("Ignoring malformed discovery message from {}", source);
+            return;
+        };
+        let outgoing = self
+            .session
+            .lock()
+            .unwrap()
+            .on_discovery(message, source, Instant::now());
+        send_all(&self.socket, &self.measurement_socket, outgoing);
+    }
+}
+
+fn run_measurement(session: Arc<Mutex<LinkSession>>, socket: UdpSocket, running: &RunFlag) {
+    let mut buffer = [0u8; 512];
+    while running.is_running() {
+        let (len, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => {
+                error!("Link measurement socket failed: {}", e);
+                return;
+            }
+        };
+        let Some(message) = decode_measurement(&buffer[..len]) else {
+            continue;
+        };
+        let outgoing = session
+            .lock()
+            .unwrap()
+            .on_measurement(message, source, host_micros());
+        for datagram in outgoing {
+            if let Outgoing::Measurement(target, bytes) = datagram {
+                if let Err(e) = socket.send_to(&bytes, target) {
+                    debug!("Failed to send measurement to {}: {}", target, e);
+                }
+            }
+        }
+    }
+}
+
+fn send_all(discovery: &UdpSocket, measurement: &UdpSocket, outgoing: Vec<Outgoing>) {
+    for datagram in outgoing {
+        let (socket, target, bytes) = match &datagram {
+            Outgoing::Discovery(target, bytes) => (discovery, target, bytes),
+            Outgoing::Measurement(target, bytes) => (measurement, target, bytes),
+        };
+        if let Err(e) = socket.send_to(bytes, target) {
+            debug!("Failed to send Link message to {}: {}", target, e);
+        }
+    }
+}
+
+fn run_ticker(session: Arc<Mutex<LinkSession>>, tick_tx: Sender<EngineMessage>, running: &RunFlag) {
+    let mut emitted = session.lock().unwrap().session_tick(host_micros()) + 1;
+    while running.is_running() {
+        let (due, next_tick_at) = {
+            let session = session.lock().unwrap();
+            let session_tick = session.session_tick(host_micros());
+            let due = ticks_due(session_tick, emitted);
+            (due, session.host_time_of_tick(session_tick + 1))
+        };
+
+        for _ in 0..due {
+            if tick_tx.send(EngineMessage::Tick).is_err() {
+                return;
+            }
+        }
+        emitted += due;
+
+        let wait = Duration::from_micros((next_tick_at - host_micros()).max(0) as u64);
+        thread::sleep(wait.min(MAX_TICKER_SLEEP));
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+
+    fn endpoint(port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)
+    }
+
+    fn alive(session: &LinkSession) -> DiscoveryMessage {
+        decode_discovery(&encode_discovery(
+            DiscoveryKind::Alive,
+            PEER_TTL,
+            &session.peer_state(),
+        ))
+        .unwrap()
+    }
+
+    /// Delivers measurement datagrams between two sessions until neither
+    /// has anything more to say.
+    fn exchange(a: &mut LinkSession, b: &mut LinkSession, mut outgoing: Vec<Outgoing>) {
+        let a_addr = SocketAddr::V4(a.measurement_endpoint);
+        let b_addr = SocketAddr::V4(b.measurement_endpoint);
+        let mut from_a = true;
+        while !outgoing.is_empty() {
+            let (receiver, source) = if from_a {
+                (&mut *b, a_addr)
+            } else {
+                (&mut *a, b_addr)
+            };
+            let mut replies = Vec::new();
+            for datagram in outgoing {
+                if let Outgoing::Measurement(_, bytes) = datagram {
+                    let message = decode_measurement(&bytes).unwrap();
+                    replies.extend(receiver.on_measurement(message, source, host_micros()));
+                }
+            }
+            outgoing = replies;
+            from_a = !from_a;
+        }
+    }
+
+    /// Orders two sessions so the first has the lower, winning id.
+    fn founded_pair() -> (LinkSession, LinkSession) {
+        let a = LinkSession::new(120.0, endpoint(1));
+        let b = LinkSession::new(90.0, endpoint(2));
+        if a.session_id() < b.session_id() {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    #[test]
+    fn test_alive_gets_a_response() {
+        let (mut a, b) = founded_pair();
+        let outgoing = a.on_discovery(alive(&b), "127.0.0.1:2".parse().unwrap(), Instant::now());
+        assert!(matches!(outgoing.first(), Some(Outgoing::Discovery(..))));
+        assert_eq!(a.peer_count(), 1);
+    }
+
+    #[test]
+    fn test_higher_session_joins_lower_after_measuring() {
+        let (mut winner, mut loser) = founded_pair();
+        let winner_bpm = winner.bpm();
+
+        // The winner ignores the other session; the loser measures and joins
+        let ignored = winner.on_discovery(
+            alive(&loser),
+            "127.0.0.1:2".parse().unwrap(),
+            Instant::now(),
+        );
+        assert!(!ignored
+            .iter()
+            .any(|datagram| matches!(datagram, Outgoing::Measurement(..))));
+
+        let pings = loser.on_discovery(
+            alive(&winner),
+            "127.0.0.1:1".parse().unwrap(),
+            Instant::now(),
+        );
+        let pings: Vec<Outgoing> = pings
+            .into_iter()
+            .filter(|datagram| matches!(datagram, Outgoing::Measurement(..)))
+            .collect();
+        assert_eq!(pings.len(), 1);
+        exchange(&mut loser, &mut winner, pings);
+
+        assert_eq!(loser.session_id(), winner.session_id());
+        assert_eq!(loser.bpm(), winner_bpm);
+        // Same host clock, so both sit on the same grid line
+        let now = host_micros();
+        assert!((loser.session_tick(now) - winner.session_tick(now)).abs() <= 1);
+    }
+
+    #[test]
+    fn test_newer_timeline_in_same_session_is_adopted() {
+        let (mut winner, mut loser) = founded_pair();
+        let pings = loser.on_discovery(
+            alive(&winner),
+            "127.0.0.1:1".parse().unwrap(),
+            Instant::now(),
+        );
+        exchange(&mut loser, &mut winner, pings);
+
+        winner.set_tempo(133.0, host_micros() + 1);
+        loser.on_discovery(
+            alive(&winner),
+            "127.0.0.1:1".parse().unwrap(),
+            Instant::now(),
+        );
+        assert!((loser.bpm() - 133.0).abs() < 0.01);
+
+        // An older timeline arriving late does not roll the tempo back
+        let mut stale = alive(&winner);
+        stale.state.as_mut().unwrap().timeline = Timeline::new(60.0, 0);
+        loser.on_discovery(stale, "127.0.0.1:1".parse().unwrap(), Instant::now());
+        assert!((loser.bpm() - 133.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_byebye_forgets_peer() {
+        let (mut a, b) = founded_pair();
+        let source: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        a.on_discovery(alive(&b), source, Instant::now());
+        let bye = decode_discovery(&crate::link_protocol::encode_byebye(&b.node_id)).unwrap();
+        a.on_discovery(bye, source, Instant::now());
+        assert_eq!(a.peer_count(), 0);
+    }
+
+    #[test]
+    fn test_ticks_due_follows_grid_within_a_bar() {
+        assert_eq!(ticks_due(9, 10), 0); // nothing new
+        assert_eq!(ticks_due(10, 10), 1); // next grid line
+        assert_eq!(ticks_due(14, 10), 5); // catch up
+        assert_eq!(ticks_due(8, 10), 0); // ahead, hold
+                                         // A whole bar apart is the same phase
+        assert_eq!(ticks_due(10 + 96, 10), 1);
+    }
+
+    #[test]
+    fn test_tempo_sync_follows_whichever_side_changed() {
+        let handle = TempoHandle::new(120.0);
+        let mut sync = TempoSync::new(handle.clone());
+        let mut session = LinkSession::new(120.0, endpoint(1));
+        sync.sync(&mut session);
+
+        handle.set_bpm(125.0);
+        assert!(sync.sync(&mut session));
+        assert!((session.bpm() - 125.0).abs() < 0.01);
+
+        session.set_tempo(98.0, host_micros());
+        assert!(!sync.sync(&mut session));
+        assert!((handle.bpm() - 98.0).abs() < 0.01);
+        assert!(!sync.sync(&mut session));
+    }
+
+    fn free_port() -> u16 {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn test_two_peers_on_loopback_share_tempo_and_tick() {
+        let (port_a, port_b) = (free_port(), free_port());
+        let peer = |port: u16| SocketAddr::V4(endpoint(port));
+
+        let (tx_a, rx_a) = std::sync::mpsc::channel();
+        let (tx_b, rx_b) = std::sync::mpsc::channel();
+        let tempo_a = TempoHandle::new(120.0);
+        let tempo_b = TempoHandle::new(90.0);
+        let clock_a = LinkClock::new(
+            LinkConfig {
+                port: port_a,
+                peers: vec![peer(port_b)],
+            },
+            tempo_a.clone(),
+            tx_a,
+        );
+        let clock_b = LinkClock::new(
+            LinkConfig {
+                port: port_b,
+                peers: vec![peer(port_a)],
+            },
+            tempo_b.clone(),
+            tx_b,
+        );
+        clock_a.start().unwrap();
+        clock_b.start().unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while tempo_a.bpm() != tempo_b.bpm() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!((tempo_a.bpm() - tempo_b.bpm()).abs() < 0.01);
+
+        for rx in [&rx_a, &rx_b] {
+            assert!(matches!(
+                rx.recv_timeout(Duration::from_secs(1)),
+                Ok(EngineMessage::Tick)
+            ));
+        }
+
+        // Leaving the session joins every thread, after which both go quiet
+        clock_a.stop();
+        clock_b.stop();
+        for rx in [&rx_a, &rx_b] {
+            while rx.try_recv().is_ok() {}
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert!(rx_a.try_recv().is_err());
+        assert!(rx_b.try_recv().is_err());
+    }
+}
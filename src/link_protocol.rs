@@ -0,0 +1,420 @@
+// link_protocol.rs
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+pub const DISCOVERY_GROUP: Ipv4Addr = Ipv4Addr::new(224, 76, 78, 75);
+pub const DISCOVERY_PORT: u16 = 20808;
+
+const DISCOVERY_HEADER: &[u8; 8] = b"_asdp_v\x01";
+const MEASUREMENT_HEADER: &[u8; 8] = b"_link_v\x01";
+
+// Payload entry keys, sent as big-endian four character codes
+const TIMELINE_KEY: u32 = u32::from_be_bytes(*b"tmln");
+const SESSION_KEY: u32 = u32::from_be_bytes(*b"sess");
+const START_STOP_KEY: u32 = u32::from_be_bytes(*b"stst");
+const ENDPOINT_KEY: u32 = u32::from_be_bytes(*b"mep4");
+const HOST_TIME_KEY: u32 = u32::from_be_bytes(*b"__ht");
+const GHOST_TIME_KEY: u32 = u32::from_be_bytes(*b"__gt");
+
+const PING: u8 = 1;
+const PONG: u8 = 2;
+
+/// Identifies a peer, and a session by the id of the peer that founded it.
+pub type NodeId = [u8; 8];
+
+/// A random alphanumeric id, as Link peers use.
+pub fn random_node_id() -> NodeId {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let mut seed = RandomState::new().build_hasher().finish();
+    let mut id = [0u8; 8];
+    for byte in &mut id {
+        *byte = ALPHABET[(seed % ALPHABET.len() as u64) as usize];
+        seed /= ALPHABET.len() as u64;
+    }
+    id
+}
+
+/// Maps the session's beat grid onto its shared clock: at `time_origin`
+/// microseconds the session was at `beat_origin` micro-beats, and beats
+/// advance every `micros_per_beat` microseconds from there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeline {
+    pub micros_per_beat: i64,
+    pub beat_origin: i64,
+    pub time_origin: i64,
+}
+
+impl Timeline {
+    pub fn new(bpm: f64, at_micros: i64) -> Self {
+        Timeline {
+            micros_per_beat: (60_000_000.0 / bpm).round() as i64,
+            beat_origin: 0,
+            time_origin: at_micros,
+        }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        60_000_000.0 / self.micros_per_beat as f64
+    }
+
+    pub fn beats_at(&self, micros: i64) -> f64 {
+        self.beat_origin as f64 / 1_000_000.0
+            + (micros - self.time_origin) as f64 / self.micros_per_beat as f64
+    }
+
+    pub fn time_at_beats(&self, beats: f64) -> i64 {
+        let since_origin = beats - self.beat_origin as f64 / 1_000_000.0;
+        self.time_origin + (since_origin * self.micros_per_beat as f64).round() as i64
+    }
+
+    /// The same beat grid continued at a new tempo from `at_micros`.
+    pub fn with_tempo(&self, bpm: f64, at_micros: i64) -> Self {
+        let beats = self.beats_at(at_micros);
+        Timeline {
+            micros_per_beat: (60_000_000.0 / bpm).round() as i64,
+            beat_origin: (beats * 1_000_000.0).round() as i64,
+            time_origin: at_micros,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StartStopState {
+    pub is_playing: bool,
+    pub beats: i64,
+    pub timestamp: i64,
+}
+
+/// Everything a peer advertises about itself in discovery messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerState {
+    pub node_id: NodeId,
+    pub session_id: NodeId,
+    pub timeline: Timeline,
+    pub start_stop: StartStopState,
+    pub measurement_endpoint: SocketAddrV4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiscoveryKind {
+    Alive = 1,
+    Response = 2,
+    ByeBye = 3,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DiscoveryMessage {
+    pub kind: DiscoveryKind,
+    pub ttl: u8,
+    pub node_id: NodeId,
+    /// Absent for ByeBye, which carries no payload
+    pub state: Option<PeerState>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MeasurementMessage {
+    Ping {
+        session_id: NodeId,
+        host_time: i64,
+    },
+    /// Echoes the ping's host time alongside the responder's session time
+    Pong {
+        session_id: NodeId,
+        ghost_time: i64,
+        host_time: i64,
+    },
+}
+
+pub fn encode_discovery(kind: DiscoveryKind, ttl: u8, state: &PeerState) -> Vec<u8> {
+    let mut bytes = discovery_header(kind, ttl, &state.node_id);
+
+    let timeline = state.timeline;
+    let mut timeline_bytes = Vec::with_capacity(24);
+    timeline_bytes.extend_from_slice(&timeline.micros_per_beat.to_be_bytes());
+    timeline_bytes.extend_from_slice(&timeline.beat_origin.to_be_bytes());
+    timeline_bytes.extend_from_slice(&timeline.time_origin.to_be_bytes());
+    push_entry(&mut bytes, TIMELINE_KEY, &timeline_bytes);
+
+    push_entry(&mut bytes, SESSION_KEY, &state.session_id);
+
+    let start_stop = state.start_stop;
+    let mut start_stop_bytes = vec![start_stop.is_playing as u8];
+    start_stop_bytes.extend_from_slice(&start_stop.beats.to_be_bytes());
+    start_stop_bytes.extend_from_slice(&start_stop.timestamp.to_be_bytes());
+    push_entry(&mut bytes, START_STOP_KEY, &start_stop_bytes);
+
+    let endpoint = state.measurement_endpoint;
+    let mut endpoint_bytes = endpoint.ip().octets().to_vec();
+    endpoint_bytes.extend_from_slice(&endpoint.port().to_be_bytes());
+    push_entry(&mut bytes, ENDPOINT_KEY, &endpoint_bytes);
+
+    bytes
+}
+
+pub fn encode_byebye(node_id: &NodeId) -> Vec<u8> {
+    discovery_header(DiscoveryKind::ByeBye, 0, node_id)
+}
+
+pub fn decode_discovery(bytes: &[u8]) -> Option<DiscoveryMessage> {
+    let rest = bytes.strip_prefix(DISCOVERY_HEADER)?;
+    let (header, payload) = split_at_checked(rest, 12)?;
+    let kind = match header[0] {
+        1 => DiscoveryKind::Alive,
+        2 => DiscoveryKind::Response,
+        3 => DiscoveryKind::ByeBye,
+        _ => return None,
+    };
+    let ttl = header[1];
+    let node_id: NodeId = header[4..12].try_into().ok()?;
+
+    let state = match kind {
+        DiscoveryKind::ByeBye => None,
+        DiscoveryKind::Alive | DiscoveryKind::Response => {
+            Some(decode_peer_state(node_id, payload)?)
+        }
+    };
+    Some(DiscoveryMessage {
+        kind,
+        ttl,
+        node_id,
+        state,
+    })
+}
+
+pub fn encode_measurement(message: &MeasurementMessage) -> Vec<u8> {
+    let mut bytes = MEASUREMENT_HEADER.to_vec();
+    match message {
+        MeasurementMessage::Ping {
+            session_id,
+            host_time,
+        } => {
+            bytes.push(PING);
+            push_entry(&mut bytes, SESSION_KEY, session_id);
+            push_entry(&mut bytes, HOST_TIME_KEY, &host_time.to_be_bytes());
+        }
+        MeasurementMessage::Pong {
+            session_id,
+            ghost_time,
+            host_time,
+        } => {
+            bytes.push(PONG);
+            push_entry(&mut bytes, SESSION_KEY, session_id);
+            push_entry(&mut bytes, GHOST_TIME_KEY, &ghost_time.to_be_bytes());
+            push_entry(&mut bytes, HOST_TIME_KEY, &host_time.to_be_bytes());
+        }
+    }
+    bytes
+}
+
+pub fn decode_measurement(bytes: &[u8]) -> Option<MeasurementMessage> {
+    let rest = bytes.strip_prefix(MEASUREMENT_HEADER)?;
+    let (&message_type, payload) = rest.split_first()?;
+    let entries = parse_entries(payload)?;
+    let session_id = entry(&entries, SESSION_KEY)?.try_into().ok()?;
+    let host_time = read_i64(entry(&entries, HOST_TIME_KEY)?)?;
+
+    match message_type {
+        PING => Some(MeasurementMessage::Ping {
+            session_id,
+            host_time,
+        }),
+        PONG => Some(MeasurementMessage::Pong {
+            session_id,
+            ghost_time: read_i64(entry(&entries, GHOST_TIME_KEY)?)?,
+            host_time,
+        }),
+        _ => None,
+    }
+}
+
+fn discovery_header(kind: DiscoveryKind, ttl: u8, node_id: &NodeId) -> Vec<u8> {
+    let mut bytes = DISCOVERY_HEADER.to_vec();
+    bytes.push(kind as u8);
+    bytes.push(ttl);
+    bytes.extend_from_slice(&0u16.to_be_bytes()); // group id
+    bytes.extend_from_slice(node_id);
+    bytes
+}
+
+fn decode_peer_state(node_id: NodeId, payload: &[u8]) -> Option<PeerState> {
+    let entries = parse_entries(payload)?;
+    Some(PeerState {
+        node_id,
+        session_id: entry(&entries, SESSION_KEY)?.try_into().ok()?,
+        timeline: decode_timeline(entry(&entries, TIMELINE_KEY)?)?,
+        // Older peers do not send start/stop state
+        start_stop: match entry(&entries, START_STOP_KEY) {
+            Some(start_stop) => decode_start_stop(start_stop)?,
+            None => StartStopState::default(),
+        },
+        measurement_endpoint: decode_endpoint(entry(&entries, ENDPOINT_KEY)?)?,
+    })
+}
+
+fn decode_timeline(bytes: &[u8]) -> Option<Timeline> {
+    let timeline = Timeline {
+        micros_per_beat: read_i64(bytes.get(0..8)?)?,
+        beat_origin: read_i64(bytes.get(8..16)?)?,
+        time_origin: read_i64(bytes.get(16..24)?)?,
+    };
+    (timeline.micros_per_beat > 0).then_some(timeline)
+}
+
+fn decode_start_stop(bytes: &[u8]) -> Option<StartStopState> {
+    Some(StartStopState {
+        is_playing: *bytes.first()? != 0,
+        beats: read_i64(bytes.get(1..9)?)?,
+        timestamp: read_i64(bytes.get(9..17)?)?,
+    })
+}
+
+fn decode_endpoint(bytes: &[u8]) -> Option<SocketAddrV4> {
+    let ip: [u8; 4] = bytes.get(0..4)?.try_into().ok()?;
+    let port = u16::from_be_bytes(bytes.get(4..6)?.try_into().ok()?);
+    Some(SocketAddrV4::new(Ipv4Addr::from(ip), port))
+}
+
+fn push_entry(bytes: &mut Vec<u8>, key: u32, value: &[u8]) {
+    bytes.extend_from_slice(&key.to_be_bytes());
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+/// Splits a payload into its key/value entries, keeping unknown keys so
+/// newer peers can add entries without breaking us.
+fn parse_entries(mut payload: &[u8]) -> Option<Vec<(u32, &[u8])>> {
+    let mut entries = Vec::new();
+    while !payload.is_empty() {
+        let (key, rest) = split_at_checked(payload, 4)?;
+        let (size, rest) = split_at_checked(rest, 4)?;
+        let size = u32::from_be_bytes(size.try_into().ok()?) as usize;
+        let (value, rest) = split_at_checked(rest, size)?;
+        entries.push((u32::from_be_bytes(key.try_into().ok()?), value));
+        payload = rest;
+    }
+    Some(entries)
+}
+
+fn entry<'a>(entries: &[(u32, &'a [u8])], key: u32) -> Option<&'a [u8]> {
+    entries
+        .iter()
+        .find(|(entry_key, _)| *entry_key == key)
+        .map(|(_, value)| *value)
+}
+
+fn read_i64(bytes: &[u8]) -> Option<i64> {
+    Some(i64::from_be_bytes(bytes.get(0..8)?.try_into().ok()?))
+}
+
+fn split_at_checked(bytes: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
+    (mid <= bytes.len()).then(|| bytes.split_at(mid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_state() -> PeerState {
+        PeerState {
+            node_id: *b"nodeAAAA",
+            session_id: *b"sessBBBB",
+            timeline: Timeline {
+                micros_per_beat: 500_000,
+                beat_origin: 8_000_000,
+                time_origin: 123_456_789,
+            },
+            start_stop: StartStopState {
+                is_playing: true,
+                beats: 4_000_000,
+                timestamp: 99,
+            },
+            measurement_endpoint: SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 50123),
+        }
+    }
+
+    #[test]
+    fn test_discovery_header_layout() {
+        let bytes = encode_discovery(DiscoveryKind::Alive, 5, &peer_state());
+
+        assert_eq!(&bytes[0..8], b"_asdp_v\x01");
+        assert_eq!(bytes[8], 1); // Alive
+        assert_eq!(bytes[9], 5); // ttl
+        assert_eq!(&bytes[10..12], &[0, 0]); // group
+        assert_eq!(&bytes[12..20], b"nodeAAAA");
+        assert_eq!(&bytes[20..24], b"tmln");
+        assert_eq!(&bytes[24..28], &24u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_discovery_round_trip() {
+        let state = peer_state();
+        let bytes = encode_discovery(DiscoveryKind::Response, 5, &state);
+        let message = decode_discovery(&bytes).unwrap();
+
+        assert_eq!(message.kind, DiscoveryKind::Response);
+        assert_eq!(message.ttl, 5);
+        assert_eq!(message.node_id, state.node_id);
+        assert_eq!(message.state, Some(state));
+    }
+
+    #[test]
+    fn test_byebye_has_no_state() {
+        let message = decode_discovery(&encode_byebye(b"nodeAAAA")).unwrap();
+        assert_eq!(message.kind, DiscoveryKind::ByeBye);
+        assert_eq!(message.state, None);
+    }
+
+    #[test]
+    fn test_unknown_entries_are_skipped() {
+        let mut bytes = encode_discovery(DiscoveryKind::Alive, 5, &peer_state());
+        push_entry(&mut bytes, u32::from_be_bytes(*b"auh_"), &[1, 2, 3]);
+        assert!(decode_discovery(&bytes).unwrap().state.is_some());
+    }
+
+    #[test]
+    fn test_rejects_garbage_and_truncation() {
+        assert_eq!(decode_discovery(b"hello"), None);
+        let bytes = encode_discovery(DiscoveryKind::Alive, 5, &peer_state());
+        assert_eq!(decode_discovery(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(decode_measurement(&bytes), None);
+    }
+
+    #[test]
+    fn test_measurement_round_trip() {
+        let ping = MeasurementMessage::Ping {
+            session_id: *b"sessBBBB",
+            host_time: -42,
+        };
+        assert_eq!(decode_measurement(&encode_measurement(&ping)), Some(ping));
+
+        let pong = MeasurementMessage::Pong {
+            session_id: *b"sessBBBB",
+            ghost_time: 1_000_000,
+            host_time: 500,
+        };
+        let bytes = encode_measurement(&pong);
+        assert_eq!(&bytes[0..9], b"_link_v\x01\x02");
+        assert_eq!(decode_measurement(&bytes), Some(pong));
+    }
+
+    #[test]
+    fn test_timeline_keeps_beat_continuous_across_tempo_change() {
+        let timeline = Timeline::new(120.0, 1_000_000);
+        assert_eq!(timeline.beats_at(2_000_000), 2.0);
+
+        let faster = timeline.with_tempo(150.0, 2_000_000);
+        assert_eq!(faster.beats_at(2_000_000), 2.0);
+        assert_eq!(faster.beats_at(2_400_000), 3.0);
+        assert_eq!(faster.time_at_beats(3.0), 2_400_000);
+        assert!((faster.bpm() - 150.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_random_node_ids_are_alphanumeric() {
+        let id = random_node_id();
+        assert!(id.iter().all(u8::is_ascii_alphanumeric));
+        assert_ne!(id, random_node_id());
+    }
+}
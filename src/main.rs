@@ -1,70 +1,127 @@
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use phasorsyncrs::clock_control::{ClockController, ClockFactory, ClockSelection};
+use phasorsyncrs::error::{MidiError, Recovery};
+use phasorsyncrs::midi_input::InputActivity;
+use phasorsyncrs::reconnect::{ConnectionState, DeviceStatus};
 use phasorsyncrs::{clock, config, event_loop, external_clock, logging, midi_output, state, tui};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::cmp::Reverse;
 use std::fs;
 use std::io::{self, Read, Write};
 use std::net::{TcpListener, TcpStream};
 use std::path::Path;
+use std::process::{self, ExitCode};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::thread::{self, JoinHandle};
 use std::time::UNIX_EPOCH;
 
 use crate::event_loop::EngineMessage;
 
-fn initialize_clock(config: config::Config, engine_tx: Sender<EngineMessage>) {
-    info!("Starting clock thread");
-
-    // Create a new thread for the clock to run independently
-    thread::spawn(move || {
-        // Create the appropriate clock source based on configuration
-        let clock_source: Box<dyn clock::ClockSource> = create_clock_source(&config, engine_tx);
-
-        // Start the clock
-        info!("Starting clock");
-        clock_source.start();
-    });
+/// The clock the configuration asks for at startup.
+fn initial_clock_selection(config: &config::Config) -> Result<ClockSelection, MidiError> {
+    match config.clock_source {
+        config::ClockSource::Internal => Ok(ClockSelection::Internal),
+        config::ClockSource::Link => Ok(ClockSelection::Link),
+        config::ClockSource::External => match &config.bind_to_device {
+            Some(device) => Ok(ClockSelection::External(device.clone())),
+            None => Err(MidiError::Config(
+                "external sync needs --bind-to-device".to_string(),
+            )),
+        },
+    }
 }
 
-/// Creates the appropriate clock source based on configuration
-fn create_clock_source(
+/// Logs a failure to set up `what`, passing it on only when the recovery
+/// policy is to abort.
+fn tolerate(
     config: &config::Config,
-    engine_tx: Sender<EngineMessage>,
-) -> Box<dyn clock::ClockSource> {
-    match config.clock_source {
-        config::ClockSource::Internal => {
-            info!("Initializing internal clock");
-            Box::new(clock::InternalClock::new(engine_tx))
-        }
-        config::ClockSource::External => {
-            info!("Initializing external clock");
-            // Get the device name, panic with helpful message if not provided
-            let device_name = config
-                .bind_to_device
-                .clone()
-                .expect("Device binding required for external sync");
-
-            Box::new(external_clock::ExternalClock::new(device_name, engine_tx))
+    what: &str,
+    result: Result<(), MidiError>,
+) -> Result<(), MidiError> {
+    match result {
+        Err(e) if config.recovery.recover(&e) == Recovery::Abort => Err(e),
+        Err(e) => {
+            warn!("{} unavailable: {}", what, e);
+            Ok(())
         }
+        Ok(()) => Ok(()),
     }
 }
 
-fn start_ui(shared_state: Arc<Mutex<state::SharedState>>, engine_tx: Sender<EngineMessage>) {
+/// Builds the clock controller and starts the configured clock. The
+/// controller can later swap it for another source or device.
+fn initialize_clock(
+    config: &config::Config,
+    engine_tx: Sender<EngineMessage>,
+    tempo: clock::TempoHandle,
+) -> Result<ClockController, MidiError> {
+    info!("Starting clock");
+    let factory = ClockFactory {
+        engine_tx: engine_tx.clone(),
+        tempo,
+        link: config.link.clone(),
+        clock_timeout: config.clock_timeout,
+        reconnect: config.reconnect,
+        recovery: config.recovery,
+    };
+    let clocks = ClockController::new(
+        engine_tx,
+        initial_clock_selection(config)?,
+        move |selection| factory.build(selection),
+    )
+    .with_recovery_policy(config.recovery)
+    .with_reconnect_policy(config.reconnect);
+    tolerate(config, "Clock", clocks.start())?;
+    Ok(clocks)
+}
+
+/// Runs the TUI on its own thread. The thread returns the exit code once
+/// the user quits or the engine stops, or `None` if there is no terminal
+/// to run in, leaving the web UI in charge.
+fn start_ui(
+    shared_state: Arc<Mutex<state::SharedState>>,
+    engine_tx: Sender<EngineMessage>,
+    clocks: ClockController,
+) -> JoinHandle<Option<ExitCode>> {
     thread::spawn(move || {
         info!("Starting TUI");
-        if let Err(e) = tui::run_tui_event_loop(shared_state, engine_tx) {
-            eprintln!("TUI failed: {} (continuing without TUI)", e);
-            error!("TUI failed: {}", e);
+        match tui::run_tui_event_loop(shared_state, engine_tx, clocks) {
+            Ok(()) => Some(ExitCode::SUCCESS),
+            Err(e) if e.is::<MidiError>() => {
+                eprintln!("Stopping: {}", e);
+                error!("Stopping: {}", e);
+                Some(ExitCode::FAILURE)
+            }
+            Err(e) => {
+                eprintln!("TUI failed: {} (continuing without TUI)", e);
+                error!("TUI failed: {}", e);
+                None
+            }
         }
-    });
+    })
 }
 
-fn initialize_logging() {
-    // Initialize logging
-    if let Err(e) = logging::init_logger() {
-        eprintln!("Failed to initialize logger: {}", e);
-        std::process::exit(1);
-    }
+/// Releases sounding notes before the process is ended by SIGINT, SIGTERM
+/// or SIGHUP, with or without the TUI, then exits with the usual code for
+/// the signal.
+fn handle_exit_signals(engine_tx: Sender<EngineMessage>) {
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!("Exit signals will not release notes: {}", e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!("Received signal {}, shutting down", signal);
+            event_loop::shut_down(&engine_tx);
+            tui::restore_terminal();
+            process::exit(128 + signal);
+        }
+    });
 }
 
 // Log configuration details
@@ -74,6 +131,7 @@ fn log_config_details(config: &config::Config) {
         match config.clock_source {
             config::ClockSource::Internal => "Internal",
             config::ClockSource::External => "External",
+            config::ClockSource::Link => "Link",
         }
     );
     if let Some(device) = &config.bind_to_device {
@@ -180,7 +238,23 @@ fn handle_status_request(stream: &mut TcpStream, shared_state: &Arc<Mutex<state:
     let transport = match state.transport_state {
         state::TransportState::Playing => "Playing",
         state::TransportState::Stopped => "Stopped",
+        state::TransportState::Paused => "Paused",
+    };
+    let clock = match state.clock_status {
+        state::ClockStatus::Internal => "Internal",
+        state::ClockStatus::External => "External",
+        state::ClockStatus::Flywheel => "Flywheel",
+        state::ClockStatus::Link => "Link",
     };
+    let timecode = state
+        .timecode
+        .map(|timecode| format!("\"{}\"", timecode))
+        .unwrap_or_else(|| "null".to_string());
+    let clock_device = state
+        .clock_device
+        .as_ref()
+        .map(|device| format!("\"{}\"", escape_json_string(device)))
+        .unwrap_or_else(|| "null".to_string());
     let recording = if state.recording { "true" } else { "false" };
     let recording_target = state
         .recording_target
@@ -188,10 +262,21 @@ fn handle_status_request(stream: &mut TcpStream, shared_state: &Arc<Mutex<state:
         .map(|s| format!("\"{}\"", s))
         .unwrap_or_else(|| "null".to_string());
     let body = format!(
-        "{{\"transport\":\"{transport}\",\"bpm\":{},\"bar\":{},\"beat\":{},\"recording\":{recording},\"recording_target\":{recording_target}}}",
+        "{{\"transport\":\"{transport}\",\"bpm\":{},\"bar\":{},\"beat\":{},\"recording\":{recording},\"recording_target\":{recording_target},\"tempo_map\":{},\"bpm_precise\":{:.3},\"phase_error_ms\":{:.3},\"clock\":\"{clock}\",\"clock_device\":{clock_device},\"timecode\":{timecode},\"swing\":\"{}\",\"time_signature\":\"{}\",\"meter\":{},\"midi_dropped\":{},\"midi_late\":{},\"devices\":{},\"midi_input\":{},\"clock_jitter\":{}}}",
         state.get_bpm(),
         state.get_current_bar(),
         state.get_current_beat(),
+        state.tempo_map.to_json(),
+        state.precise_bpm,
+        state.phase_error_ms,
+        state.swing,
+        state.time_signature(),
+        state.meter.to_json(),
+        state.midi_output.dropped,
+        state.midi_output.late,
+        devices_json(&state.devices),
+        midi_input_json(&state.midi_input),
+        jitter_json(state.jitter),
     );
     send_http_response(
         stream,
@@ -201,6 +286,52 @@ fn handle_status_request(stream: &mut TcpStream, shared_state: &Arc<Mutex<state:
     );
 }
 
+fn devices_json(devices: &[DeviceStatus]) -> String {
+    let entries: Vec<String> = devices
+        .iter()
+        .map(|device| {
+            let port = match &device.state {
+                ConnectionState::Connected(port) => format!("\"{}\"", escape_json_string(port)),
+                _ => "null".to_string(),
+            };
+            format!(
+                "{{\"role\":\"{}\",\"device\":\"{}\",\"direction\":\"{}\",\"connected\":{},\"port\":{},\"state\":\"{}\"}}",
+                escape_json_string(&device.role),
+                escape_json_string(&device.device),
+                if device.input { "in" } else { "out" },
+                device.state.is_connected(),
+                port,
+                escape_json_string(&device.state.to_string()),
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+fn midi_input_json(activity: &InputActivity) -> String {
+    let last = activity
+        .last
+        .as_ref()
+        .map(|event| format!("\"{}\"", escape_json_string(&event.to_string())))
+        .unwrap_or_else(|| "null".to_string());
+    format!("{{\"events\":{},\"last\":{}}}", activity.events, last)
+}
+
+// Internal clock tick lateness in microseconds, or null under other clocks
+fn jitter_json(jitter: Option<clock::JitterStats>) -> String {
+    jitter
+        .map(|stats| {
+            format!(
+                "{{\"ticks\":{},\"min_us\":{},\"max_us\":{},\"mean_us\":{}}}",
+                stats.ticks,
+                stats.min.as_micros(),
+                stats.max.as_micros(),
+                stats.mean.as_micros(),
+            )
+        })
+        .unwrap_or_else(|| "null".to_string())
+}
+
 fn handle_recordings_request(stream: &mut TcpStream) {
     match list_recent_recordings(6) {
         Ok(recordings) => {
@@ -283,11 +414,13 @@ fn handle_toggle_request(
     let command = match current_state {
         state::TransportState::Playing => event_loop::TransportAction::Stop,
         state::TransportState::Stopped => event_loop::TransportAction::Start,
+        state::TransportState::Paused => event_loop::TransportAction::Continue,
     };
 
     let target = match command {
-        event_loop::TransportAction::Start => "Playing",
+        event_loop::TransportAction::Start | event_loop::TransportAction::Continue => "Playing",
         event_loop::TransportAction::Stop => "Stopped",
+        event_loop::TransportAction::Pause => "Paused",
     };
 
     if let Err(e) = engine_tx.send(EngineMessage::TransportCommand(command)) {
@@ -310,10 +443,127 @@ fn handle_toggle_request(
     );
 }
 
+fn handle_panic_request(stream: &mut TcpStream, engine_tx: &Sender<EngineMessage>) {
+    if let Err(e) = engine_tx.send(EngineMessage::Panic) {
+        error!("Failed to send MIDI panic: {}", e);
+        send_http_response(
+            stream,
+            "HTTP/1.1 500 INTERNAL SERVER ERROR",
+            "text/plain; charset=utf-8",
+            "failed to send panic",
+        );
+        return;
+    }
+
+    send_http_response(
+        stream,
+        "HTTP/1.1 200 OK",
+        "application/json; charset=utf-8",
+        "{\"panic\":true}",
+    );
+}
+
+fn handle_tempo_request(stream: &mut TcpStream, engine_tx: &Sender<EngineMessage>, delta: f64) {
+    if let Err(e) = engine_tx.send(EngineMessage::NudgeTempo(delta)) {
+        error!("Failed to send tempo nudge: {}", e);
+        send_http_response(
+            stream,
+            "HTTP/1.1 500 INTERNAL SERVER ERROR",
+            "text/plain; charset=utf-8",
+            "failed to change tempo",
+        );
+        return;
+    }
+
+    let body = format!("{{\"nudged\":{delta}}}");
+    send_http_response(
+        stream,
+        "HTTP/1.1 200 OK",
+        "application/json; charset=utf-8",
+        &body,
+    );
+}
+
+/// Switches the clock for `POST /clock/internal`, `/clock/link`,
+/// `/clock/external` (the last bound device) or `/clock/external/<device>`.
+fn handle_clock_request(stream: &mut TcpStream, clocks: &ClockController, target: &str) {
+    let selection = match target.split_once('/') {
+        Some(("external", device)) if !device.is_empty() => {
+            Some(ClockSelection::External(percent_decode(device)))
+        }
+        _ => match target {
+            "internal" => Some(ClockSelection::Internal),
+            "link" => Some(ClockSelection::Link),
+            "external" => clocks.last_device().map(ClockSelection::External),
+            _ => None,
+        },
+    };
+    let Some(selection) = selection else {
+        send_http_response(
+            stream,
+            "HTTP/1.1 400 BAD REQUEST",
+            "text/plain; charset=utf-8",
+            "unknown clock source or no device bound",
+        );
+        return;
+    };
+
+    // A clock that fails to start leaves the one the recovery policy
+    // picked in its place, which the response reports
+    let (status_line, error) = match clocks.switch(selection) {
+        Ok(()) => ("HTTP/1.1 200 OK", "null".to_string()),
+        Err(e) => (
+            "HTTP/1.1 502 BAD GATEWAY",
+            format!("\"{}\"", escape_json_string(&e.to_string())),
+        ),
+    };
+    let selection = clocks.selection();
+    let device = selection
+        .device()
+        .map(|device| format!("\"{}\"", escape_json_string(device)))
+        .unwrap_or_else(|| "null".to_string());
+    let body = format!(
+        "{{\"clock\":\"{:?}\",\"device\":{device},\"running\":{},\"error\":{error}}}",
+        selection.status(),
+        clocks.is_running(),
+    );
+    send_http_response(
+        stream,
+        status_line,
+        "application/json; charset=utf-8",
+        &body,
+    );
+}
+
+/// Decodes `%XX` escapes in a URL path segment, e.g. `OXI%20ONE`.
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| segment.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 fn handle_web_request(
     mut stream: TcpStream,
     shared_state: &Arc<Mutex<state::SharedState>>,
     engine_tx: &Sender<EngineMessage>,
+    clocks: &ClockController,
 ) {
     let mut buffer = [0; 2048];
     let bytes_read = match stream.read(&mut buffer) {
@@ -336,6 +586,11 @@ fn handle_web_request(
         return;
     }
 
+    if method == "POST" && path.starts_with("/clock/") {
+        handle_clock_request(&mut stream, clocks, path.trim_start_matches("/clock/"));
+        return;
+    }
+
     match (method, path) {
         ("GET", "/") => {
             send_http_response(
@@ -348,6 +603,11 @@ fn handle_web_request(
         ("GET", "/status") => handle_status_request(&mut stream, shared_state),
         ("GET", "/recordings") => handle_recordings_request(&mut stream),
         ("POST", "/toggle") => handle_toggle_request(&mut stream, shared_state, engine_tx),
+        ("POST", "/panic") => handle_panic_request(&mut stream, engine_tx),
+        ("POST", "/tempo/up") => handle_tempo_request(&mut stream, engine_tx, WEB_TEMPO_NUDGE_BPM),
+        ("POST", "/tempo/down") => {
+            handle_tempo_request(&mut stream, engine_tx, -WEB_TEMPO_NUDGE_BPM)
+        }
         _ => send_http_response(
             &mut stream,
             "HTTP/1.1 404 NOT FOUND",
@@ -357,7 +617,11 @@ fn handle_web_request(
     }
 }
 
-fn start_web_ui(shared_state: Arc<Mutex<state::SharedState>>, engine_tx: Sender<EngineMessage>) {
+fn start_web_ui(
+    shared_state: Arc<Mutex<state::SharedState>>,
+    engine_tx: Sender<EngineMessage>,
+    clocks: ClockController,
+) {
     thread::spawn(move || {
         let listener = match TcpListener::bind("0.0.0.0:8080") {
             Ok(listener) => listener,
@@ -370,13 +634,15 @@ fn start_web_ui(shared_state: Arc<Mutex<state::SharedState>>, engine_tx: Sender<
 
         for stream in listener.incoming() {
             match stream {
-                Ok(stream) => handle_web_request(stream, &shared_state, &engine_tx),
+                Ok(stream) => handle_web_request(stream, &shared_state, &engine_tx, &clocks),
                 Err(e) => error!("Web UI connection failed: {}", e),
             }
         }
     });
 }
 
+const WEB_TEMPO_NUDGE_BPM: f64 = 1.0;
+
 const WEB_UI_HTML: &str = r#"<!DOCTYPE html>
 <html lang="en">
 <head>
@@ -407,6 +673,9 @@ const WEB_UI_HTML: &str = r#"<!DOCTYPE html>
         <div id="position">Bar: -- | Beat: --</div>
       </div>
       <button id="toggle">Toggle</button>
+      <button id="tempo-down">Tempo -</button>
+      <button id="tempo-up">Tempo +</button>
+      <button id="panic">Panic</button>
     </div>
     <div class="card">
       <h2>Recent Recordings</h2>
@@ -446,6 +715,23 @@ const WEB_UI_HTML: &str = r#"<!DOCTYPE html>
       }
     }
 
+    async function nudgeTempo(direction) {
+      try {
+        await fetch(`/tempo/${direction}`, { method: 'POST' });
+        await refreshStatus();
+      } catch (_) {
+        transportEl.textContent = 'Tempo change failed';
+      }
+    }
+
+    async function sendPanic() {
+      try {
+        await fetch('/panic', { method: 'POST' });
+      } catch (_) {
+        transportEl.textContent = 'Panic failed';
+      }
+    }
+
     function formatTimestamp(epochSeconds) {
       const date = new Date(epochSeconds * 1000);
       return date.toLocaleString();
@@ -480,6 +766,9 @@ const WEB_UI_HTML: &str = r#"<!DOCTYPE html>
     }
 
     toggleBtn.addEventListener('click', toggleTransport);
+    document.getElementById('tempo-up').addEventListener('click', () => nudgeTempo('up'));
+    document.getElementById('tempo-down').addEventListener('click', () => nudgeTempo('down'));
+    document.getElementById('panic').addEventListener('click', sendPanic);
     refreshStatus();
     refreshRecordings();
     setInterval(refreshStatus, 500);
@@ -489,39 +778,127 @@ const WEB_UI_HTML: &str = r#"<!DOCTYPE html>
 </html>
 "#;
 
-// Initialize application components
-fn initialize_components(
-    config: config::Config,
-) -> (Arc<Mutex<state::SharedState>>, Sender<EngineMessage>) {
-    // Create shared state
-    let shared_state = Arc::new(Mutex::new(state::SharedState::new(config.bpm)));
-    info!("Shared state initialized with BPM: {}", config.bpm);
-
-    // Create engine message channel
-    let (engine_tx, engine_rx): (Sender<EngineMessage>, Receiver<EngineMessage>) = mpsc::channel();
-
-    // Set up MIDI output - always initialize for musical graph
+fn initialize_midi_output(
+    config: &config::Config,
+) -> Result<Option<midi_output::MidiOutputManager>, MidiError> {
     info!("Setting up MIDI output for event loop");
     let mut output_manager = midi_output::MidiOutputManager::new();
+    output_manager.set_port_latencies(config.output_latencies.clone());
+    output_manager.set_output_queue(config.output_queue_capacity, config.output_overflow);
+    output_manager.set_reconnect_policy(config.reconnect);
+    output_manager.set_recovery_policy(config.recovery);
 
+    // With named outputs configured, the primary port is only opened on request
     let result = if let Some(device) = &config.midi_output_device {
         output_manager.connect_to_device(device)
-    } else {
+    } else if config.output_ports.is_empty() {
         output_manager.connect_to_first_available()
+    } else {
+        Ok(())
     };
 
-    let midi_output = if let Err(e) = result {
-        error!("Failed to connect MIDI output: {}", e);
-        None
-    } else {
+    if result.is_ok() {
         info!("MIDI output connected successfully");
-        Some(output_manager)
-    };
+    }
+    tolerate(config, "MIDI output", result)?;
+    connect_named_outputs(&mut output_manager, config)?;
+    configure_clock_master(&mut output_manager, config)?;
+
+    // Ports still looking for their device keep the output alive
+    Ok(output_manager.is_active().then_some(output_manager))
+}
+
+// Each named output connects on its own; one missing synth leaves the rest playing
+fn connect_named_outputs(
+    output_manager: &mut midi_output::MidiOutputManager,
+    config: &config::Config,
+) -> Result<(), MidiError> {
+    for port in &config.output_ports {
+        let result = output_manager.connect_port(&port.name, &port.device);
+        tolerate(config, &format!("MIDI output '{}'", port.name), result)?;
+    }
+    output_manager.set_routing(config.routing.clone());
+    output_manager.set_thru(config.thru.clone());
+    Ok(())
+}
 
-    let midi_output = midi_output;
+// Named inputs only feed the thru routes; each connects on its own
+fn start_named_inputs(
+    config: &config::Config,
+    engine_tx: &Sender<EngineMessage>,
+) -> Result<(), MidiError> {
+    for port in &config.input_ports {
+        let input = external_clock::ExternalClock::thru_input(
+            port.name.clone(),
+            port.device.clone(),
+            engine_tx.clone(),
+        )
+        .with_reconnect_policy(config.reconnect)
+        .with_recovery_policy(config.recovery);
+        let result = clock::ClockSource::start(&input);
+        tolerate(config, &format!("MIDI input '{}'", port.name), result)?;
+    }
+    Ok(())
+}
+
+fn configure_clock_master(
+    output_manager: &mut midi_output::MidiOutputManager,
+    config: &config::Config,
+) -> Result<(), MidiError> {
+    output_manager.set_clock_master(config.clock_master);
+    for device in &config.clock_output_devices {
+        let result = output_manager.add_clock_output(device);
+        tolerate(config, &format!("Clock output {}", device), result)?;
+    }
+    Ok(())
+}
+
+// What the UIs need to drive the running engine
+type Components = (
+    Arc<Mutex<state::SharedState>>,
+    Sender<EngineMessage>,
+    ClockController,
+);
+
+// Initialize application components
+fn initialize_components(config: config::Config) -> Result<Components, MidiError> {
+    // Create shared state
+    let shared_state = Arc::new(Mutex::new(state::SharedState::new(config.bpm)));
+    {
+        let mut state = shared_state.lock().unwrap();
+        state.tempo_map = config.tempo_map.clone();
+        state.swing = config.swing;
+        state.meter = config.meter.clone();
+    }
+    info!("Shared state initialized with BPM: {}", config.bpm);
+
+    // Create engine message channel
+    let (engine_tx, engine_rx): (Sender<EngineMessage>, Receiver<EngineMessage>) = mpsc::channel();
+
+    // Set up MIDI output - always initialize for musical graph
+    let midi_output = initialize_midi_output(&config)?;
+
+    // The internal clock starts from the configured tempo and is steered live by the engine
+    let tempo = clock::TempoHandle::new(config.bpm as f64);
+    let resume_recording = config.resume_recording;
+    let tempo_estimator = config.tempo_estimator;
+    let mtc_output = config.mtc_output.then_some(config.mtc_rate);
+    let midi_panic = config.midi_panic;
+
+    // MTC input runs alongside whichever clock drives the ticks
+    if let Some(device) = config.mtc_input_device.clone() {
+        let reader = external_clock::ExternalClock::timecode_reader(device, engine_tx.clone())
+            .with_reconnect_policy(config.reconnect)
+            .with_recovery_policy(config.recovery);
+        tolerate(&config, "MTC input", clock::ClockSource::start(&reader))?;
+    }
+    start_named_inputs(&config, &engine_tx)?;
+    // The engine steers the internal clock and shares tempo changes with a
+    // Link session; it leaves the handle alone while a device is the clock
+    let engine_tempo = tempo.clone();
 
     // Start the clock thread
-    initialize_clock(config, engine_tx.clone());
+    let clocks = initialize_clock(&config, engine_tx.clone(), tempo)?;
 
     // Start the event loop thread with MIDI output
     let event_loop_shared_state = Arc::clone(&shared_state);
@@ -529,14 +906,24 @@ fn initialize_components(
     thread::spawn(move || {
         let mut event_loop =
             event_loop::EventLoop::new(event_loop_shared_state, engine_rx, midi_output);
+        event_loop.set_tempo_handle(engine_tempo);
+        event_loop.set_resume_recording(resume_recording);
+        event_loop.set_tempo_estimator(tempo_estimator);
+        event_loop.set_midi_panic(midi_panic);
+        if let Some(rate) = mtc_output {
+            event_loop.set_mtc_output(rate);
+        }
         event_loop.run();
     });
 
-    (shared_state, engine_tx)
+    Ok((shared_state, engine_tx, clocks))
 }
 
-fn main() {
-    initialize_logging();
+fn main() -> ExitCode {
+    if let Err(e) = logging::init_logger() {
+        eprintln!("Failed to initialize logger: {}", e);
+        return ExitCode::FAILURE;
+    }
     info!("Starting Phasorsyncrs");
 
     // Load configuration
@@ -552,16 +939,30 @@ fn main() {
     info!("MIDI output setup complete");
 
     // Initialize components
-    let (shared_state, engine_tx) = initialize_components(config);
+    let (shared_state, engine_tx, clocks) = match initialize_components(config) {
+        Ok(components) => components,
+        Err(e) => {
+            eprintln!("Failed to start: {}", e);
+            error!("Failed to start: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    handle_exit_signals(engine_tx.clone());
 
     // Start the web UI thread
-    start_web_ui(Arc::clone(&shared_state), engine_tx.clone());
+    start_web_ui(Arc::clone(&shared_state), engine_tx.clone(), clocks.clone());
 
     // Start the UI thread
-    start_ui(Arc::clone(&shared_state), engine_tx.clone());
+    let ui = start_ui(Arc::clone(&shared_state), engine_tx.clone(), clocks);
 
     info!("All threads started, entering main loop");
-    // Keep the main thread alive to allow other threads to run
+    if let Ok(Some(exit_code)) = ui.join() {
+        event_loop::shut_down(&engine_tx);
+        return exit_code;
+    }
+    // Without the TUI, keep the main thread alive for the web UI until an
+    // exit signal arrives
     loop {
         thread::park();
     }
@@ -0,0 +1,295 @@
+// meter.rs
+
+use crate::config::TICKS_PER_BEAT;
+use std::fmt;
+
+/// A time signature. The beat is the denominator's note value, so 6/8 has
+/// six eighth-note beats to the bar and 5/4 five quarter-note beats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        TimeSignature {
+            numerator: 4,
+            denominator: 4,
+        }
+    }
+}
+
+impl TimeSignature {
+    /// Accepts up to 32 beats of a whole note down to a 32nd note.
+    pub fn new(numerator: u32, denominator: u32) -> Option<Self> {
+        let valid =
+            (1..=32).contains(&numerator) && denominator.is_power_of_two() && denominator <= 32;
+        valid.then_some(TimeSignature {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// Parses `NUM/DEN`, e.g. `7/8`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (numerator, denominator) = value.trim().split_once('/')?;
+        Self::new(numerator.parse().ok()?, denominator.parse().ok()?)
+    }
+
+    /// Engine ticks in one beat of this signature.
+    pub fn ticks_per_beat(&self) -> u64 {
+        TICKS_PER_BEAT * 4 / self.denominator as u64
+    }
+
+    pub fn ticks_per_bar(&self) -> u64 {
+        self.ticks_per_beat() * self.numerator as u64
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// Where a tick falls in the song. Bar and beat are 1-indexed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BarBeat {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick_in_beat: u64,
+    pub signature: TimeSignature,
+}
+
+impl BarBeat {
+    pub fn is_downbeat(&self) -> bool {
+        self.beat == 1 && self.tick_in_beat == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeterChange {
+    pub bar: u32,
+    pub signature: TimeSignature,
+}
+
+/// Time signatures by bar. Meter only changes on a bar line, so every
+/// position can be worked out from the tick count alone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeterMap {
+    // Sorted by bar; the first change is always bar 1
+    changes: Vec<MeterChange>,
+}
+
+impl Default for MeterMap {
+    fn default() -> Self {
+        Self::new(TimeSignature::default())
+    }
+}
+
+impl MeterMap {
+    pub fn new(signature: TimeSignature) -> Self {
+        MeterMap {
+            changes: vec![MeterChange { bar: 1, signature }],
+        }
+    }
+
+    /// Parses a single signature such as `7/8`, or a comma separated list
+    /// of `BAR:NUM/DEN` entries, e.g. `1:4/4,9:7/8,17:6/8`. Bars before the
+    /// first entry are 4/4.
+    pub fn parse(spec: &str) -> Result<Self, MeterMapError> {
+        let mut map = MeterMap::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || MeterMapError(entry.to_string());
+            let (bar, signature) = match entry.split_once(':') {
+                Some((bar, signature)) => (bar.trim().parse().map_err(|_| invalid())?, signature),
+                None => (1, entry),
+            };
+            let signature = TimeSignature::parse(signature).ok_or_else(invalid)?;
+            if bar == 0 {
+                return Err(invalid());
+            }
+            map.insert(bar, signature);
+        }
+        Ok(map)
+    }
+
+    pub fn changes(&self) -> &[MeterChange] {
+        &self.changes
+    }
+
+    /// Switches to `signature` from `bar` on, replacing anything later.
+    pub fn set_from(&mut self, bar: u32, signature: TimeSignature) {
+        self.changes.retain(|change| change.bar < bar.max(1));
+        self.insert(bar, signature);
+    }
+
+    fn insert(&mut self, bar: u32, signature: TimeSignature) {
+        let bar = bar.max(1);
+        self.changes.retain(|change| change.bar != bar);
+        self.changes.push(MeterChange { bar, signature });
+        self.changes.sort_by_key(|change| change.bar);
+    }
+
+    /// Each change with the tick its first bar starts on.
+    fn segments(&self) -> impl Iterator<Item = (u64, &MeterChange)> + '_ {
+        let mut start = 0;
+        let mut previous: Option<&MeterChange> = None;
+        self.changes.iter().map(move |change| {
+            if let Some(previous) = previous {
+                start += (change.bar - previous.bar) as u64 * previous.signature.ticks_per_bar();
+            }
+            previous = Some(change);
+            (start, change)
+        })
+    }
+
+    /// The change in force at `tick` and the tick it took effect on.
+    fn segment_at(&self, tick: u64) -> (u64, &MeterChange) {
+        self.segments()
+            .take_while(|(start, _)| *start <= tick)
+            .last()
+            .expect("meter map always has a change at bar 1")
+    }
+
+    pub fn position(&self, tick: u64) -> BarBeat {
+        let (start, change) = self.segment_at(tick);
+        let signature = change.signature;
+        let into_segment = tick - start;
+        let into_bar = into_segment % signature.ticks_per_bar();
+        BarBeat {
+            bar: change.bar + (into_segment / signature.ticks_per_bar()) as u32,
+            beat: (into_bar / signature.ticks_per_beat()) as u32 + 1,
+            tick_in_beat: into_bar % signature.ticks_per_beat(),
+            signature,
+        }
+    }
+
+    /// The tick on which `bar` starts.
+    pub fn bar_start(&self, bar: u32) -> u64 {
+        let (start, change) = self
+            .segments()
+            .take_while(|(_, change)| change.bar <= bar.max(1))
+            .last()
+            .expect("meter map always has a change at bar 1");
+        start + (bar.max(1) - change.bar) as u64 * change.signature.ticks_per_bar()
+    }
+
+    pub fn signature_at_bar(&self, bar: u32) -> TimeSignature {
+        self.changes
+            .iter()
+            .take_while(|change| change.bar <= bar.max(1))
+            .last()
+            .map_or_else(TimeSignature::default, |change| change.signature)
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .changes
+            .iter()
+            .map(|change| {
+                format!(
+                    "{{\"bar\":{},\"signature\":\"{}\"}}",
+                    change.bar, change.signature
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MeterMapError(String);
+
+impl fmt::Display for MeterMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid meter entry '{}' (expected NUM/DEN or BAR:NUM/DEN)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for MeterMapError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sig(numerator: u32, denominator: u32) -> TimeSignature {
+        TimeSignature::new(numerator, denominator).unwrap()
+    }
+
+    #[test]
+    fn test_signature_parses_and_sizes_beats_by_denominator() {
+        assert_eq!(TimeSignature::parse("7/8"), Some(sig(7, 8)));
+        assert_eq!(TimeSignature::parse("7/6"), None);
+        assert_eq!(TimeSignature::parse("0/4"), None);
+        assert_eq!(TimeSignature::parse("seven"), None);
+
+        assert_eq!(sig(6, 8).ticks_per_beat(), TICKS_PER_BEAT / 2);
+        assert_eq!(sig(6, 8).ticks_per_bar(), TICKS_PER_BEAT * 3);
+        assert_eq!(sig(5, 4).ticks_per_bar(), TICKS_PER_BEAT * 5);
+        assert_eq!(sig(7, 8).to_string(), "7/8");
+    }
+
+    #[test]
+    fn test_position_in_odd_meter() {
+        let map = MeterMap::new(sig(7, 8));
+        let eighth = TICKS_PER_BEAT / 2;
+
+        let position = map.position(eighth * 7);
+        assert_eq!((position.bar, position.beat), (2, 1));
+        assert!(position.is_downbeat());
+
+        let position = map.position(eighth * 13 + 5);
+        assert_eq!(
+            (position.bar, position.beat, position.tick_in_beat),
+            (2, 7, 5)
+        );
+    }
+
+    #[test]
+    fn test_position_follows_meter_changes() {
+        // Two bars of 4/4, then 3/4
+        let map = MeterMap::parse("1:4/4,3:3/4").unwrap();
+        let four_four = TICKS_PER_BEAT * 4;
+
+        assert_eq!(map.bar_start(3), four_four * 2);
+        assert_eq!(map.bar_start(5), four_four * 2 + TICKS_PER_BEAT * 3 * 2);
+
+        let position = map.position(four_four * 2 + TICKS_PER_BEAT * 4);
+        assert_eq!((position.bar, position.beat), (4, 2));
+        assert_eq!(position.signature, sig(3, 4));
+        assert_eq!(map.position(four_four + 1).signature, sig(4, 4));
+    }
+
+    #[test]
+    fn test_set_from_replaces_later_changes() {
+        let mut map = MeterMap::parse("1:4/4,5:7/8,9:6/8").unwrap();
+        map.set_from(7, sig(5, 4));
+
+        assert_eq!(map.signature_at_bar(6), sig(7, 8));
+        assert_eq!(map.signature_at_bar(7), sig(5, 4));
+        assert_eq!(map.signature_at_bar(12), sig(5, 4));
+        assert_eq!(map.changes().len(), 3);
+    }
+
+    #[test]
+    fn test_parse_single_signature_and_errors() {
+        let map = MeterMap::parse("6/8").unwrap();
+        assert_eq!(
+            map.changes(),
+            &[MeterChange {
+                bar: 1,
+                signature: sig(6, 8)
+            }]
+        );
+        assert_eq!(map.to_json(), "[{\"bar\":1,\"signature\":\"6/8\"}]");
+
+        assert!(MeterMap::parse("0:4/4").is_err());
+        assert!(MeterMap::parse("3:4-4").is_err());
+    }
+}
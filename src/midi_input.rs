@@ -0,0 +1,500 @@
+// midi_input.rs
+
+use crate::midi_message::{message_length, MidiMessage};
+
+/// Longest SysEx payload gathered; longer ones are dropped rather than
+/// growing without bound on a stuck or noisy input.
+pub const MAX_SYSEX_BYTES: usize = 64 * 1024;
+
+/// The sorts of message a thru route can pick out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    // NoteOn and NoteOff
+    Note,
+    ControlChange,
+    ProgramChange,
+    PitchBend,
+    // Channel and poly aftertouch
+    Aftertouch,
+    SysEx,
+    // Timing clock pulses alone
+    Clock,
+    // Start, Continue, Stop, Song Position Pointer and Song Select
+    Transport,
+    // MIDI Time Code quarter and full frames
+    Timecode,
+    // Active sensing keep-alives alone
+    Sensing,
+    // Tune request and system reset
+    System,
+}
+
+impl MessageKind {
+    /// What sort of message `message` is, for filtering.
+    pub fn of(message: &MidiMessage) -> Self {
+        match message {
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } => MessageKind::Note,
+            MidiMessage::AllNotesOff { .. }
+            | MidiMessage::AllSoundOff { .. }
+            | MidiMessage::ControlChange { .. }
+            | MidiMessage::ControlChange14 { .. }
+            | MidiMessage::Rpn { .. }
+            | MidiMessage::Nrpn { .. } => MessageKind::ControlChange,
+            MidiMessage::ProgramChange { .. } => MessageKind::ProgramChange,
+            MidiMessage::PitchBend { .. } => MessageKind::PitchBend,
+            MidiMessage::ChannelAftertouch { .. } | MidiMessage::PolyAftertouch { .. } => {
+                MessageKind::Aftertouch
+            }
+            MidiMessage::SysEx(_) => MessageKind::SysEx,
+            MidiMessage::TimingClock => MessageKind::Clock,
+            MidiMessage::Start
+            | MidiMessage::Continue
+            | MidiMessage::Stop
+            | MidiMessage::SongPositionPointer(_)
+            | MidiMessage::SongSelect(_) => MessageKind::Transport,
+            MidiMessage::MtcQuarterFrame(_) | MidiMessage::MtcFullFrame(_) => MessageKind::Timecode,
+            MidiMessage::ActiveSensing => MessageKind::Sensing,
+            MidiMessage::TuneRequest | MidiMessage::Reset => MessageKind::System,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "note" | "notes" => Some(MessageKind::Note),
+            "cc" | "control" => Some(MessageKind::ControlChange),
+            "program" | "pc" => Some(MessageKind::ProgramChange),
+            "bend" | "pitchbend" => Some(MessageKind::PitchBend),
+            "aftertouch" | "at" => Some(MessageKind::Aftertouch),
+            "sysex" => Some(MessageKind::SysEx),
+            "clock" => Some(MessageKind::Clock),
+            "transport" => Some(MessageKind::Transport),
+            "timecode" | "mtc" => Some(MessageKind::Timecode),
+            "sensing" | "activesensing" => Some(MessageKind::Sensing),
+            "system" => Some(MessageKind::System),
+            _ => None,
+        }
+    }
+
+    /// True for the messages carried on a MIDI channel.
+    pub fn is_channel_voice(self) -> bool {
+        matches!(
+            self,
+            MessageKind::Note
+                | MessageKind::ControlChange
+                | MessageKind::ProgramChange
+                | MessageKind::PitchBend
+                | MessageKind::Aftertouch
+        )
+    }
+
+    /// True for the messages that keep time, or the link alive, rather
+    /// than make sound.
+    pub fn is_sync(self) -> bool {
+        matches!(
+            self,
+            MessageKind::Clock
+                | MessageKind::Transport
+                | MessageKind::Timecode
+                | MessageKind::Sensing
+        )
+    }
+}
+
+/// What has come in on the MIDI inputs besides clock, transport, timecode
+/// and active sensing, as shown in the TUI and `/status`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputActivity {
+    pub events: u64,
+    pub last: Option<MidiMessage>,
+}
+
+impl InputActivity {
+    pub fn record(&mut self, message: MidiMessage) {
+        self.events += 1;
+        self.last = Some(message);
+    }
+}
+
+/// Turns a MIDI input byte stream into messages, however it is split up.
+///
+/// Handles running status, where channel messages leave out a repeated
+/// status byte; SysEx, gathered until its F7; and realtime bytes, which
+/// may arrive in the middle of any other message and are returned at once
+/// without disturbing it. Each complete message is read by
+/// `MidiMessage::decode`. Stray data bytes, undefined statuses and messages
+/// cut short by a new status are dropped, so the parser picks up again at
+/// the next status.
+#[derive(Debug, Default)]
+pub struct MidiParser {
+    // Status of the message being gathered. Channel statuses stay on as
+    // running status once their message is complete.
+    status: Option<u8>,
+    data: [u8; 2],
+    received: usize,
+    // Payload of the SysEx being gathered
+    sysex: Option<Vec<u8>>,
+}
+
+impl MidiParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the next byte, returning a message if it completes one.
+    pub fn push(&mut self, byte: u8) -> Option<MidiMessage> {
+        match byte {
+            0xF8..=0xFF => decode(&[byte]),
+            0xF0 => {
+                self.status = None;
+                self.sysex = Some(Vec::new());
+                None
+            }
+            0xF7 => {
+                self.status = None;
+                let payload = self.sysex.take()?;
+                decode(&[&[0xF0], payload.as_slice(), &[0xF7]].concat())
+            }
+            0x80..=0xF6 => self.begin(byte),
+            _ => self.data_byte(byte),
+        }
+    }
+
+    /// Every message completed by `bytes`, in order.
+    pub fn parse(&mut self, bytes: &[u8]) -> Vec<MidiMessage> {
+        bytes.iter().filter_map(|&byte| self.push(byte)).collect()
+    }
+
+    // A status byte other than realtime and SysEx framing. It ends any
+    // SysEx in progress without its F7, which is dropped.
+    fn begin(&mut self, status: u8) -> Option<MidiMessage> {
+        self.sysex = None;
+        self.received = 0;
+        self.status = None;
+        match data_length(status)? {
+            // Tune request has no data bytes to wait for
+            0 => decode(&[status]),
+            _ => {
+                self.status = Some(status);
+                None
+            }
+        }
+    }
+
+    fn data_byte(&mut self, byte: u8) -> Option<MidiMessage> {
+        if let Some(payload) = &mut self.sysex {
+            if payload.len() < MAX_SYSEX_BYTES {
+                payload.push(byte);
+            } else {
+                self.sysex = None;
+            }
+            return None;
+        }
+        let status = self.status?;
+        let length = data_length(status)?;
+        self.data[self.received] = byte;
+        self.received += 1;
+        if self.received < length {
+            return None;
+        }
+        self.received = 0;
+        // System common messages have no running status
+        if status >= 0xF0 {
+            self.status = None;
+        }
+        decode(&[&[status], &self.data[..length]].concat())
+    }
+}
+
+// Data bytes that follow `status`, or `None` for the undefined statuses
+fn data_length(status: u8) -> Option<usize> {
+    message_length(status).map(|length| length - 1)
+}
+
+// One complete message, or `None` for an undefined status
+fn decode(bytes: &[u8]) -> Option<MidiMessage> {
+    MidiMessage::decode(bytes).ok()?.pop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mtc::{self, FrameRate, Timecode};
+
+    fn note_on(channel: u8, note: u8, velocity: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel,
+            note,
+            velocity,
+            duration_ticks: 0,
+        }
+    }
+
+    #[test]
+    fn test_running_status_repeats_the_last_channel_status() {
+        let mut parser = MidiParser::new();
+        assert_eq!(
+            parser.parse(&[0x92, 60, 100, 64, 90, 60, 0, 0xE0, 0x00, 0x40, 0x7F, 0x7F]),
+            vec![
+                note_on(2, 60, 100),
+                note_on(2, 64, 90),
+                note_on(2, 60, 0),
+                MidiMessage::PitchBend {
+                    channel: 0,
+                    value: 0x2000
+                },
+                MidiMessage::PitchBend {
+                    channel: 0,
+                    value: 0x3FFF
+                },
+            ]
+        );
+
+        // System common messages end running status
+        assert_eq!(
+            parser.parse(&[0xF3, 4, 10]),
+            vec![MidiMessage::SongSelect(4)]
+        );
+        assert_eq!(parser.parse(&[20, 30]), vec![]);
+    }
+
+    #[test]
+    fn test_realtime_bytes_do_not_interrupt_other_messages() {
+        let mut parser = MidiParser::new();
+        assert_eq!(
+            parser.parse(&[0xB1, 0xF8, 7, 0xFA, 0xFE, 100, 0xF0, 0x7D, 0xF8, 1, 0xF7]),
+            vec![
+                MidiMessage::TimingClock,
+                MidiMessage::Start,
+                MidiMessage::ActiveSensing,
+                MidiMessage::ControlChange {
+                    channel: 1,
+                    controller: 7,
+                    value: 100
+                },
+                MidiMessage::TimingClock,
+                MidiMessage::SysEx(vec![0x7D, 1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sysex_is_gathered_across_chunks() {
+        let timecode = Timecode {
+            hours: 1,
+            minutes: 2,
+            seconds: 3,
+            frames: 4,
+            rate: FrameRate::Fps25,
+        };
+        let mut parser = MidiParser::new();
+        let mut messages = Vec::new();
+        for chunk in mtc::full_frame(&timecode).chunks(3) {
+            messages.extend(parser.parse(chunk));
+        }
+        assert_eq!(messages, vec![MidiMessage::MtcFullFrame(timecode)]);
+
+        assert_eq!(parser.parse(&[0xF0, 0x43, 0x10]), vec![]);
+        assert_eq!(
+            parser.parse(&[0x4C, 0xF7]),
+            vec![MidiMessage::SysEx(vec![0x43, 0x10, 0x4C])]
+        );
+    }
+
+    #[test]
+    fn test_broken_messages_are_dropped() {
+        let mut parser = MidiParser::new();
+        // An unterminated SysEx, a stray F7, data with no status, a note
+        // cut short, and the undefined statuses
+        assert_eq!(
+            parser.parse(&[0xF0, 1, 2, 0x90, 60, 0xF7, 5, 0x80, 60, 0xF4, 1, 0xF9, 0xFD]),
+            vec![]
+        );
+        assert_eq!(parser.parse(&[0x80, 60, 0]).len(), 1);
+        assert_eq!(parser.parse(&[0xF6]), vec![MidiMessage::TuneRequest]);
+
+        let mut oversized = vec![0xF0];
+        oversized.resize(MAX_SYSEX_BYTES + 10, 0x01);
+        oversized.push(0xF7);
+        assert_eq!(parser.parse(&oversized), vec![]);
+    }
+
+    #[test]
+    fn test_messages_encode_as_they_were_received() {
+        for bytes in [
+            vec![0x9F, 127, 1],
+            vec![0x83, 60, 0],
+            vec![0xA3, 60, 20],
+            vec![0xC9, 5],
+            vec![0xD0, 64],
+            vec![0xE2, 0x01, 0x40],
+            vec![0xF2, 0x10, 0x01],
+            vec![0xF1, 0x23],
+            vec![0xF3, 0x05],
+            vec![0xF6],
+            vec![0xF0, 0x7E, 0x00, 0xF7],
+            vec![0xFE],
+            vec![0xFF],
+        ] {
+            let messages = MidiParser::new().parse(&bytes);
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].to_bytes(), bytes);
+        }
+        assert_eq!(note_on(0, 60, 100).to_string(), "note on 60 vel 100 ch 1");
+    }
+
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn byte(&mut self) -> u8 {
+            self.below(256) as u8
+        }
+
+        fn data(&mut self) -> u8 {
+            self.below(128) as u8
+        }
+    }
+
+    // Every field of a parsed message holds what the wire can carry
+    fn assert_well_formed(message: &MidiMessage) {
+        let bytes = message.to_bytes();
+        assert!(bytes[0] >= 0x80, "{:?}", message);
+        match message {
+            MidiMessage::SysEx(payload) => {
+                assert!(payload.len() <= MAX_SYSEX_BYTES);
+                assert!(payload.iter().all(|&byte| byte < 0x80), "{:?}", message);
+            }
+            MidiMessage::MtcFullFrame(_) => {}
+            _ => assert!(bytes.len() <= 3),
+        }
+        assert!(message.channel().is_none_or(|channel| channel < 16));
+        if let MidiMessage::PitchBend { value, .. } | MidiMessage::SongPositionPointer(value) =
+            message
+        {
+            assert!(*value < 0x4000);
+        }
+        // Re-encoding loses nothing
+        assert_eq!(MidiParser::new().parse(&bytes), vec![message.clone()]);
+    }
+
+    #[test]
+    fn test_fuzzed_byte_streams_never_panic() {
+        let mut rng = Lcg(0x5EED);
+        let mut parser = MidiParser::new();
+        for _ in 0..2_000 {
+            let chunk: Vec<u8> = (0..rng.below(64)).map(|_| rng.byte()).collect();
+            for message in parser.parse(&chunk) {
+                assert_well_formed(&message);
+            }
+        }
+        // Whatever came before, the parser is back in step at the next
+        // status byte
+        parser.parse(&[0xF7]);
+        assert_eq!(parser.parse(&[0x90, 60, 100]), vec![note_on(0, 60, 100)]);
+    }
+
+    fn random_message(rng: &mut Lcg) -> MidiMessage {
+        let channel = rng.below(16) as u8;
+        match rng.below(12) {
+            0 => note_on(channel, rng.data(), rng.data()),
+            1 => MidiMessage::NoteOff {
+                channel,
+                note: rng.data(),
+            },
+            2 => MidiMessage::PolyAftertouch {
+                channel,
+                note: rng.data(),
+                pressure: rng.data(),
+            },
+            // Below the channel mode controllers, which read back as the
+            // messages they stand for
+            3 => MidiMessage::ControlChange {
+                channel,
+                controller: rng.below(120) as u8,
+                value: rng.data(),
+            },
+            4 => MidiMessage::ProgramChange {
+                channel,
+                program: rng.data(),
+            },
+            5 => MidiMessage::ChannelAftertouch {
+                channel,
+                pressure: rng.data(),
+            },
+            6 => MidiMessage::PitchBend {
+                channel,
+                value: rng.below(0x4000) as u16,
+            },
+            // A non-commercial ID, so it can never read as timecode
+            7 => MidiMessage::SysEx(
+                std::iter::once(0x7D)
+                    .chain((0..rng.below(20)).map(|_| rng.data()))
+                    .collect(),
+            ),
+            8 => MidiMessage::MtcQuarterFrame(rng.data()),
+            9 => MidiMessage::SongPositionPointer(rng.below(0x4000) as u16),
+            10 => MidiMessage::SongSelect(rng.data()),
+            _ => MidiMessage::TuneRequest,
+        }
+    }
+
+    // Encodes messages as a sender would, leaving out repeated channel
+    // statuses and dropping realtime bytes in anywhere. Returns the stream
+    // and how many realtime bytes went in.
+    fn encode_stream(messages: &[MidiMessage], rng: &mut Lcg) -> (Vec<u8>, usize) {
+        const REALTIME: [u8; 6] = [0xF8, 0xFA, 0xFB, 0xFC, 0xFE, 0xFF];
+        let mut stream = Vec::new();
+        let mut running_status = None;
+        for message in messages {
+            let bytes = message.to_bytes();
+            let status = bytes[0];
+            let skip = usize::from(status < 0xF0 && running_status == Some(status));
+            running_status = (status < 0xF0).then_some(status);
+            stream.extend_from_slice(&bytes[skip..]);
+        }
+        let mut realtime = 0;
+        let mut interleaved = Vec::with_capacity(stream.len() * 2);
+        for byte in stream {
+            if rng.below(5) == 0 {
+                interleaved.push(REALTIME[rng.below(6) as usize]);
+                realtime += 1;
+            }
+            interleaved.push(byte);
+        }
+        (interleaved, realtime)
+    }
+
+    #[test]
+    fn test_fuzzed_streams_with_running_status_and_realtime_parse_back() {
+        let mut rng = Lcg(0xC10C);
+        for _ in 0..200 {
+            let messages: Vec<MidiMessage> = (0..50).map(|_| random_message(&mut rng)).collect();
+            let (stream, realtime) = encode_stream(&messages, &mut rng);
+
+            let mut parser = MidiParser::new();
+            let mut parsed = Vec::new();
+            let mut rest = stream.as_slice();
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at((rng.below(8) as usize + 1).min(rest.len()));
+                parsed.extend(parser.parse(chunk));
+                rest = tail;
+            }
+
+            let (clock, parsed): (Vec<_>, Vec<_>) =
+                parsed.into_iter().partition(MidiMessage::is_realtime);
+            assert_eq!(clock.len(), realtime);
+            assert_eq!(parsed, messages);
+        }
+    }
+}
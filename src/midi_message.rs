@@ -0,0 +1,818 @@
+// midi_message.rs
+
+use crate::mtc::{self, Timecode};
+use std::fmt;
+
+/// Pitch bend value with the wheel at rest.
+pub const PITCH_BEND_CENTER: u16 = 0x2000;
+
+// Controllers with a fixed meaning in the messages below
+const CC_DATA_ENTRY_MSB: u8 = 6;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const CC_NRPN_LSB: u8 = 98;
+const CC_NRPN_MSB: u8 = 99;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+const CC_ALL_SOUND_OFF: u8 = 120;
+const CC_ALL_NOTES_OFF: u8 = 123;
+// Controllers 0-31 pair with 32-63 for their fine (LSB) half
+const CC_LSB_OFFSET: u8 = 32;
+// Selecting parameter 127/127 deselects, so stray data entry does nothing
+const NULL_PARAMETER: u8 = 0x7F;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+        duration_ticks: u64,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    AllNotesOff {
+        channel: u8,
+    },
+    AllSoundOff {
+        channel: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    // A 14-bit value on controller 0-31 and its fine partner 32-63
+    ControlChange14 {
+        channel: u8,
+        controller: u8,
+        value: u16,
+    },
+    // Registered and non-registered parameters, 14-bit number and value
+    Rpn {
+        channel: u8,
+        parameter: u16,
+        value: u16,
+    },
+    Nrpn {
+        channel: u8,
+        parameter: u16,
+        value: u16,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    // 14 bits with `PITCH_BEND_CENTER` at rest
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+    ChannelAftertouch {
+        channel: u8,
+        pressure: u8,
+    },
+    PolyAftertouch {
+        channel: u8,
+        note: u8,
+        pressure: u8,
+    },
+    // SysEx payload without the F0/F7 framing
+    SysEx(Vec<u8>),
+    // Song position in MIDI beats (sixteenth notes), 14 bits
+    SongPositionPointer(u16),
+    // Song or sequence to cue, 0-127
+    SongSelect(u8),
+    // Asks analog synths to tune their oscillators
+    TuneRequest,
+    // System realtime messages used when acting as clock master
+    TimingClock,
+    Start,
+    Continue,
+    Stop,
+    // Realtime keep-alive and reset, passed on from inputs
+    ActiveSensing,
+    Reset,
+    // MIDI Time Code quarter-frame data byte and full-frame locate
+    MtcQuarterFrame(u8),
+    MtcFullFrame(Timecode),
+}
+
+impl MidiMessage {
+    /// True for NoteOn and NoteOff.
+    pub fn is_note(&self) -> bool {
+        matches!(
+            self,
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. }
+        )
+    }
+
+    /// True for the single-byte messages that may arrive in the middle of
+    /// any other.
+    pub fn is_realtime(&self) -> bool {
+        matches!(
+            self,
+            MidiMessage::TimingClock
+                | MidiMessage::Start
+                | MidiMessage::Continue
+                | MidiMessage::Stop
+                | MidiMessage::ActiveSensing
+                | MidiMessage::Reset
+        )
+    }
+
+    /// The channel of a channel voice or mode message.
+    pub fn channel(&self) -> Option<u8> {
+        match self {
+            MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::AllNotesOff { channel }
+            | MidiMessage::AllSoundOff { channel }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::ControlChange14 { channel, .. }
+            | MidiMessage::Rpn { channel, .. }
+            | MidiMessage::Nrpn { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. }
+            | MidiMessage::PitchBend { channel, .. }
+            | MidiMessage::ChannelAftertouch { channel, .. }
+            | MidiMessage::PolyAftertouch { channel, .. } => Some(*channel),
+            _ => None,
+        }
+    }
+
+    /// The note of a note or poly aftertouch message.
+    pub fn note(&self) -> Option<u8> {
+        match *self {
+            MidiMessage::NoteOn { note, .. }
+            | MidiMessage::NoteOff { note, .. }
+            | MidiMessage::PolyAftertouch { note, .. } => Some(note),
+            _ => None,
+        }
+    }
+
+    /// Moves a channel message to `channel`; other messages are unchanged.
+    pub fn set_channel(&mut self, new_channel: u8) {
+        match self {
+            MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::AllNotesOff { channel }
+            | MidiMessage::AllSoundOff { channel }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::ControlChange14 { channel, .. }
+            | MidiMessage::Rpn { channel, .. }
+            | MidiMessage::Nrpn { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. }
+            | MidiMessage::PitchBend { channel, .. }
+            | MidiMessage::ChannelAftertouch { channel, .. }
+            | MidiMessage::PolyAftertouch { channel, .. } => *channel = new_channel & 0x0F,
+            _ => {}
+        }
+    }
+
+    /// Encodes the message as raw MIDI bytes. 14-bit controllers and
+    /// parameter changes become the run of Control Changes that carries
+    /// them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+                ..
+            } => vec![0x90 | (channel & 0x0F), *note, *velocity],
+            MidiMessage::NoteOff { channel, note } => vec![0x80 | (channel & 0x0F), *note, 0],
+            MidiMessage::AllNotesOff { .. }
+            | MidiMessage::AllSoundOff { .. }
+            | MidiMessage::ControlChange { .. }
+            | MidiMessage::ControlChange14 { .. }
+            | MidiMessage::Rpn { .. }
+            | MidiMessage::Nrpn { .. } => self.controller_bytes(),
+            MidiMessage::ProgramChange { channel, program } => {
+                vec![0xC0 | (channel & 0x0F), program & 0x7F]
+            }
+            MidiMessage::PitchBend { channel, value } => {
+                vec![0xE0 | (channel & 0x0F), lsb(*value), msb(*value)]
+            }
+            MidiMessage::ChannelAftertouch { channel, pressure } => {
+                vec![0xD0 | (channel & 0x0F), pressure & 0x7F]
+            }
+            MidiMessage::PolyAftertouch {
+                channel,
+                note,
+                pressure,
+            } => vec![0xA0 | (channel & 0x0F), note & 0x7F, pressure & 0x7F],
+            MidiMessage::SysEx(data) => {
+                let mut bytes = Vec::with_capacity(data.len() + 2);
+                bytes.push(0xF0);
+                bytes.extend(data.iter().map(|byte| byte & 0x7F));
+                bytes.push(0xF7);
+                bytes
+            }
+            MidiMessage::SongPositionPointer(position) => {
+                vec![0xF2, lsb(*position), msb(*position)]
+            }
+            MidiMessage::SongSelect(song) => vec![0xF3, song & 0x7F],
+            MidiMessage::TuneRequest => vec![0xF6],
+            MidiMessage::TimingClock => vec![0xF8],
+            MidiMessage::Start => vec![0xFA],
+            MidiMessage::Continue => vec![0xFB],
+            MidiMessage::Stop => vec![0xFC],
+            MidiMessage::ActiveSensing => vec![0xFE],
+            MidiMessage::Reset => vec![0xFF],
+            MidiMessage::MtcQuarterFrame(data) => vec![0xF1, data & 0x7F],
+            MidiMessage::MtcFullFrame(timecode) => mtc::full_frame(timecode),
+        }
+    }
+
+    // Control Change messages; 14-bit and parameter changes take several
+    fn controller_bytes(&self) -> Vec<u8> {
+        match self {
+            MidiMessage::AllNotesOff { channel } => control_change(*channel, CC_ALL_NOTES_OFF, 0),
+            MidiMessage::AllSoundOff { channel } => control_change(*channel, CC_ALL_SOUND_OFF, 0),
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => control_change(*channel, *controller, *value),
+            MidiMessage::ControlChange14 {
+                channel,
+                controller,
+                value,
+            } => [
+                control_change(*channel, controller & 0x1F, msb(*value)),
+                control_change(*channel, (controller & 0x1F) + CC_LSB_OFFSET, lsb(*value)),
+            ]
+            .concat(),
+            MidiMessage::Rpn {
+                channel,
+                parameter,
+                value,
+            } => parameter_change(*channel, (CC_RPN_MSB, CC_RPN_LSB), *parameter, *value),
+            MidiMessage::Nrpn {
+                channel,
+                parameter,
+                value,
+            } => parameter_change(*channel, (CC_NRPN_MSB, CC_NRPN_LSB), *parameter, *value),
+            // Only called for the messages above
+            _ => Vec::new(),
+        }
+    }
+
+    /// Decodes a run of complete messages, each with its status byte.
+    ///
+    /// Control Changes are gathered back into the messages `to_bytes`
+    /// spreads over several: a controller 0-31 followed straight away by
+    /// its partner 32-63 is a `ControlChange14`, and a parameter number
+    /// followed by data entry MSB and LSB is an `Rpn` or `Nrpn`, taking the
+    /// deselecting 127/127 after it if present. NoteOns come back with no
+    /// duration.
+    pub fn decode(bytes: &[u8]) -> Result<Vec<MidiMessage>, DecodeError> {
+        let mut messages = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let (message, length) = decode_one(rest)?;
+            messages.push(message);
+            rest = &rest[length..];
+        }
+        Ok(combine_controllers(messages))
+    }
+}
+
+impl fmt::Display for MidiMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Channels are shown 1-16, as on the front panel of a synth
+        match self {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+                ..
+            } => write!(f, "note on {} vel {} ch {}", note, velocity, channel + 1),
+            MidiMessage::NoteOff { channel, note } => {
+                write!(f, "note off {} ch {}", note, channel + 1)
+            }
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => write!(f, "CC {} = {} ch {}", controller, value, channel + 1),
+            MidiMessage::ProgramChange { channel, program } => {
+                write!(f, "program {} ch {}", program, channel + 1)
+            }
+            MidiMessage::PitchBend { channel, value } => {
+                write!(f, "pitch bend {} ch {}", value, channel + 1)
+            }
+            MidiMessage::SysEx(payload) => write!(f, "SysEx ({} bytes)", payload.len()),
+            MidiMessage::MtcFullFrame(timecode) => write!(f, "MTC locate {}", timecode),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Why a byte run could not be decoded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// A data byte where a status byte should be
+    MissingStatus(u8),
+    /// The bytes end, or another status byte starts, part way through a
+    /// message with this status
+    Truncated(u8),
+    /// A status MIDI leaves undefined, or one with no message here
+    UnsupportedStatus(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MissingStatus(byte) => {
+                write!(f, "data byte {:#04X} without a status", byte)
+            }
+            DecodeError::Truncated(status) => write!(f, "message {:#04X} cut short", status),
+            DecodeError::UnsupportedStatus(status) => {
+                write!(f, "unsupported status {:#04X}", status)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn msb(value: u16) -> u8 {
+    ((value >> 7) & 0x7F) as u8
+}
+
+fn lsb(value: u16) -> u8 {
+    (value & 0x7F) as u8
+}
+
+fn fourteen_bit(msb: u8, lsb: u8) -> u16 {
+    ((msb as u16 & 0x7F) << 7) | (lsb as u16 & 0x7F)
+}
+
+fn control_change(channel: u8, controller: u8, value: u8) -> Vec<u8> {
+    vec![0xB0 | (channel & 0x0F), controller & 0x7F, value & 0x7F]
+}
+
+// Select the parameter, enter the value, then deselect
+fn parameter_change(channel: u8, select: (u8, u8), parameter: u16, value: u16) -> Vec<u8> {
+    let (select_msb, select_lsb) = select;
+    [
+        control_change(channel, select_msb, msb(parameter)),
+        control_change(channel, select_lsb, lsb(parameter)),
+        control_change(channel, CC_DATA_ENTRY_MSB, msb(value)),
+        control_change(channel, CC_DATA_ENTRY_LSB, lsb(value)),
+        control_change(channel, select_msb, NULL_PARAMETER),
+        control_change(channel, select_lsb, NULL_PARAMETER),
+    ]
+    .concat()
+}
+
+// Decodes the message at the start of `bytes` and how many bytes it took
+fn decode_one(bytes: &[u8]) -> Result<(MidiMessage, usize), DecodeError> {
+    let status = bytes[0];
+    if status < 0x80 {
+        return Err(DecodeError::MissingStatus(status));
+    }
+    if status == 0xF0 {
+        return decode_sysex(bytes);
+    }
+    let length = message_length(status).ok_or(DecodeError::UnsupportedStatus(status))?;
+    let message = bytes.get(..length).ok_or(DecodeError::Truncated(status))?;
+    if message[1..].iter().any(|byte| *byte >= 0x80) {
+        return Err(DecodeError::Truncated(status));
+    }
+    let decoded = match status {
+        0x80..=0xEF => decode_channel(message),
+        _ => decode_system(message),
+    };
+    Ok((decoded, length))
+}
+
+/// Length of a message including its status byte, for the statuses
+/// `MidiMessage::decode` reads. SysEx runs to its F7 and has none.
+pub fn message_length(status: u8) -> Option<usize> {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF | 0xF2 => Some(3),
+        0xC0..=0xDF | 0xF1 | 0xF3 => Some(2),
+        0xF6 | 0xF8 | 0xFA..=0xFC | 0xFE | 0xFF => Some(1),
+        _ => None,
+    }
+}
+
+fn decode_channel(message: &[u8]) -> MidiMessage {
+    let channel = message[0] & 0x0F;
+    let data = |index: usize| message[index];
+    match message[0] & 0xF0 {
+        0x80 => MidiMessage::NoteOff {
+            channel,
+            note: data(1),
+        },
+        0x90 => MidiMessage::NoteOn {
+            channel,
+            note: data(1),
+            velocity: data(2),
+            duration_ticks: 0,
+        },
+        0xA0 => MidiMessage::PolyAftertouch {
+            channel,
+            note: data(1),
+            pressure: data(2),
+        },
+        0xB0 => decode_control_change(channel, data(1), data(2)),
+        0xC0 => MidiMessage::ProgramChange {
+            channel,
+            program: data(1),
+        },
+        0xD0 => MidiMessage::ChannelAftertouch {
+            channel,
+            pressure: data(1),
+        },
+        _ => MidiMessage::PitchBend {
+            channel,
+            value: fourteen_bit(data(2), data(1)),
+        },
+    }
+}
+
+fn decode_control_change(channel: u8, controller: u8, value: u8) -> MidiMessage {
+    match (controller, value) {
+        (CC_ALL_NOTES_OFF, 0) => MidiMessage::AllNotesOff { channel },
+        (CC_ALL_SOUND_OFF, 0) => MidiMessage::AllSoundOff { channel },
+        _ => MidiMessage::ControlChange {
+            channel,
+            controller,
+            value,
+        },
+    }
+}
+
+// Only called with the system statuses `message_length` accepts
+fn decode_system(message: &[u8]) -> MidiMessage {
+    match message[0] {
+        0xF1 => MidiMessage::MtcQuarterFrame(message[1]),
+        0xF2 => MidiMessage::SongPositionPointer(fourteen_bit(message[2], message[1])),
+        0xF3 => MidiMessage::SongSelect(message[1]),
+        0xF6 => MidiMessage::TuneRequest,
+        0xF8 => MidiMessage::TimingClock,
+        0xFA => MidiMessage::Start,
+        0xFB => MidiMessage::Continue,
+        0xFC => MidiMessage::Stop,
+        0xFE => MidiMessage::ActiveSensing,
+        _ => MidiMessage::Reset,
+    }
+}
+
+fn decode_sysex(bytes: &[u8]) -> Result<(MidiMessage, usize), DecodeError> {
+    let end = bytes[1..]
+        .iter()
+        .position(|byte| *byte >= 0x80)
+        .map(|index| index + 1)
+        .filter(|&index| bytes[index] == 0xF7)
+        .ok_or(DecodeError::Truncated(0xF0))?;
+    let message = &bytes[..=end];
+    let decoded = match mtc::decode_full_frame(message) {
+        Some(timecode) => MidiMessage::MtcFullFrame(timecode),
+        None => MidiMessage::SysEx(message[1..end].to_vec()),
+    };
+    Ok((decoded, end + 1))
+}
+
+fn as_control_change(message: &MidiMessage) -> Option<(u8, u8, u8)> {
+    match *message {
+        MidiMessage::ControlChange {
+            channel,
+            controller,
+            value,
+        } => Some((channel, controller, value)),
+        _ => None,
+    }
+}
+
+// Folds runs of Control Changes back into the messages sent as them
+fn combine_controllers(messages: Vec<MidiMessage>) -> Vec<MidiMessage> {
+    let mut combined = Vec::with_capacity(messages.len());
+    let mut index = 0;
+    while index < messages.len() {
+        let rest = &messages[index..];
+        match parameter_change_at(rest).or_else(|| controller_pair_at(rest)) {
+            Some((message, used)) => {
+                combined.push(message);
+                index += used;
+            }
+            None => {
+                combined.push(rest[0].clone());
+                index += 1;
+            }
+        }
+    }
+    combined
+}
+
+fn parameter_change_at(messages: &[MidiMessage]) -> Option<(MidiMessage, usize)> {
+    let controls: Vec<(u8, u8, u8)> = messages
+        .iter()
+        .take(6)
+        .map_while(as_control_change)
+        .collect();
+    let [(channel, select_msb, parameter_msb), (_, select_lsb, parameter_lsb), (_, CC_DATA_ENTRY_MSB, value_msb), (_, CC_DATA_ENTRY_LSB, value_lsb), ..] =
+        controls[..]
+    else {
+        return None;
+    };
+    if controls[..4].iter().any(|control| control.0 != channel) {
+        return None;
+    }
+    let parameter = fourteen_bit(parameter_msb, parameter_lsb);
+    let value = fourteen_bit(value_msb, value_lsb);
+    let message = match (select_msb, select_lsb) {
+        (CC_RPN_MSB, CC_RPN_LSB) => MidiMessage::Rpn {
+            channel,
+            parameter,
+            value,
+        },
+        (CC_NRPN_MSB, CC_NRPN_LSB) => MidiMessage::Nrpn {
+            channel,
+            parameter,
+            value,
+        },
+        _ => return None,
+    };
+    let deselect = [
+        (channel, select_msb, NULL_PARAMETER),
+        (channel, select_lsb, NULL_PARAMETER),
+    ];
+    let used = if controls.get(4..6) == Some(&deselect[..]) {
+        6
+    } else {
+        4
+    };
+    Some((message, used))
+}
+
+fn controller_pair_at(messages: &[MidiMessage]) -> Option<(MidiMessage, usize)> {
+    let coarse = as_control_change(messages.first()?)?;
+    let fine = as_control_change(messages.get(1)?)?;
+    let (channel, controller, value_msb) = coarse;
+    let paired =
+        controller < CC_LSB_OFFSET && fine.0 == channel && fine.1 == controller + CC_LSB_OFFSET;
+    paired.then(|| {
+        let message = MidiMessage::ControlChange14 {
+            channel,
+            controller,
+            value: fourteen_bit(value_msb, fine.2),
+        };
+        (message, 2)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mtc::FrameRate;
+
+    fn round_trip(message: MidiMessage) {
+        let bytes = message.to_bytes();
+        assert_eq!(
+            MidiMessage::decode(&bytes),
+            Ok(vec![message.clone()]),
+            "{:?} encoded as {:02X?}",
+            message,
+            bytes
+        );
+    }
+
+    #[test]
+    fn test_channel_messages_encode_with_channel_nibble() {
+        let note_on = MidiMessage::NoteOn {
+            channel: 1,
+            note: 60,
+            velocity: 100,
+            duration_ticks: 48,
+        };
+        assert_eq!(note_on.to_bytes(), vec![0x91, 60, 100]);
+        assert_eq!(
+            MidiMessage::NoteOff {
+                channel: 2,
+                note: 60
+            }
+            .to_bytes(),
+            vec![0x82, 60, 0]
+        );
+        assert_eq!(
+            MidiMessage::AllNotesOff { channel: 3 }.to_bytes(),
+            vec![0xB3, 123, 0]
+        );
+        assert_eq!(
+            MidiMessage::AllSoundOff { channel: 3 }.to_bytes(),
+            vec![0xB3, 120, 0]
+        );
+    }
+
+    #[test]
+    fn test_realtime_messages_encode_as_single_status_bytes() {
+        assert_eq!(MidiMessage::TimingClock.to_bytes(), vec![0xF8]);
+        assert_eq!(MidiMessage::Start.to_bytes(), vec![0xFA]);
+        assert_eq!(MidiMessage::Continue.to_bytes(), vec![0xFB]);
+        assert_eq!(MidiMessage::Stop.to_bytes(), vec![0xFC]);
+        assert_eq!(MidiMessage::ActiveSensing.to_bytes(), vec![0xFE]);
+        assert_eq!(MidiMessage::Reset.to_bytes(), vec![0xFF]);
+    }
+
+    #[test]
+    fn test_song_position_pointer_encodes_lsb_first() {
+        assert_eq!(
+            MidiMessage::SongPositionPointer(0x3FFF).to_bytes(),
+            vec![0xF2, 0x7F, 0x7F]
+        );
+        assert_eq!(
+            MidiMessage::SongPositionPointer(200).to_bytes(),
+            vec![0xF2, 0x48, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_voice_messages_encode() {
+        let cc = MidiMessage::ControlChange {
+            channel: 4,
+            controller: 74,
+            value: 90,
+        };
+        assert_eq!(cc.to_bytes(), vec![0xB4, 74, 90]);
+        let program = MidiMessage::ProgramChange {
+            channel: 9,
+            program: 12,
+        };
+        assert_eq!(program.to_bytes(), vec![0xC9, 12]);
+        let bend = MidiMessage::PitchBend {
+            channel: 0,
+            value: PITCH_BEND_CENTER,
+        };
+        assert_eq!(bend.to_bytes(), vec![0xE0, 0x00, 0x40]);
+        let pressure = MidiMessage::ChannelAftertouch {
+            channel: 1,
+            pressure: 64,
+        };
+        assert_eq!(pressure.to_bytes(), vec![0xD1, 64]);
+        let poly = MidiMessage::PolyAftertouch {
+            channel: 2,
+            note: 60,
+            pressure: 30,
+        };
+        assert_eq!(poly.to_bytes(), vec![0xA2, 60, 30]);
+        assert_eq!(
+            MidiMessage::SysEx(vec![0x7D, 0x01]).to_bytes(),
+            vec![0xF0, 0x7D, 0x01, 0xF7]
+        );
+    }
+
+    #[test]
+    fn test_multi_part_messages_encode_as_control_changes() {
+        let filter = MidiMessage::ControlChange14 {
+            channel: 0,
+            controller: 1,
+            value: 0x2001,
+        };
+        assert_eq!(filter.to_bytes(), vec![0xB0, 1, 0x40, 0xB0, 33, 0x01]);
+
+        // Pitch bend range of 12 semitones, then deselect
+        let range = MidiMessage::Rpn {
+            channel: 0,
+            parameter: 0,
+            value: 12 << 7,
+        };
+        assert_eq!(
+            range.to_bytes(),
+            vec![
+                0xB0, 101, 0, 0xB0, 100, 0, 0xB0, 6, 12, 0xB0, 38, 0, 0xB0, 101, 127, 0xB0, 100,
+                127
+            ]
+        );
+        let nrpn = MidiMessage::Nrpn {
+            channel: 5,
+            parameter: 0x0102,
+            value: 0x0304,
+        };
+        assert_eq!(&nrpn.to_bytes()[..6], &[0xB5, 99, 0x02, 0xB5, 98, 0x02]);
+    }
+
+    #[test]
+    fn test_every_message_round_trips() {
+        let timecode = crate::mtc::Timecode::from_seconds(3_723.5, FrameRate::Fps25);
+        let messages = vec![
+            MidiMessage::NoteOn {
+                channel: 15,
+                note: 127,
+                velocity: 1,
+                duration_ticks: 0,
+            },
+            MidiMessage::NoteOff {
+                channel: 0,
+                note: 0,
+            },
+            MidiMessage::AllNotesOff { channel: 7 },
+            MidiMessage::AllSoundOff { channel: 8 },
+            MidiMessage::ControlChange {
+                channel: 3,
+                controller: 74,
+                value: 127,
+            },
+            MidiMessage::ControlChange14 {
+                channel: 2,
+                controller: 7,
+                value: 0x3FFF,
+            },
+            MidiMessage::Rpn {
+                channel: 1,
+                parameter: 0x0001,
+                value: 0x2000,
+            },
+            MidiMessage::Nrpn {
+                channel: 9,
+                parameter: 0x1234,
+                value: 0x0ABC,
+            },
+            MidiMessage::ProgramChange {
+                channel: 0,
+                program: 99,
+            },
+            MidiMessage::PitchBend {
+                channel: 4,
+                value: 0x1ABC,
+            },
+            MidiMessage::ChannelAftertouch {
+                channel: 5,
+                pressure: 0,
+            },
+            MidiMessage::PolyAftertouch {
+                channel: 6,
+                note: 64,
+                pressure: 100,
+            },
+            MidiMessage::SysEx(vec![0x7D, 0x10, 0x7F, 0x00]),
+            MidiMessage::SongPositionPointer(0x1234),
+            MidiMessage::SongSelect(12),
+            MidiMessage::TuneRequest,
+            MidiMessage::TimingClock,
+            MidiMessage::Start,
+            MidiMessage::Continue,
+            MidiMessage::Stop,
+            MidiMessage::ActiveSensing,
+            MidiMessage::Reset,
+            MidiMessage::MtcQuarterFrame(0x35),
+            MidiMessage::MtcFullFrame(timecode),
+        ];
+        for message in messages.iter().cloned() {
+            round_trip(message);
+        }
+
+        // And all of them back to back
+        let bytes: Vec<u8> = messages.iter().flat_map(MidiMessage::to_bytes).collect();
+        assert_eq!(MidiMessage::decode(&bytes), Ok(messages));
+    }
+
+    #[test]
+    fn test_plain_control_changes_are_not_combined() {
+        // Controller 39 is the fine half of 7, but only straight after it
+        let bytes = [0xB0, 7, 100, 0xB1, 39, 5, 0xB0, 39, 5];
+        let decoded = MidiMessage::decode(&bytes).unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert!(decoded
+            .iter()
+            .all(|message| matches!(message, MidiMessage::ControlChange { .. })));
+
+        // A parameter change without the deselect still decodes
+        let rpn = MidiMessage::Rpn {
+            channel: 0,
+            parameter: 2,
+            value: 64 << 7,
+        };
+        let bytes = rpn.to_bytes();
+        assert_eq!(MidiMessage::decode(&bytes[..12]), Ok(vec![rpn]));
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_input() {
+        assert_eq!(
+            MidiMessage::decode(&[0x40, 0x40]),
+            Err(DecodeError::MissingStatus(0x40))
+        );
+        assert_eq!(
+            MidiMessage::decode(&[0x90, 60]),
+            Err(DecodeError::Truncated(0x90))
+        );
+        assert_eq!(
+            MidiMessage::decode(&[0x90, 60, 0xF8]),
+            Err(DecodeError::Truncated(0x90))
+        );
+        assert_eq!(
+            MidiMessage::decode(&[0xF0, 0x01, 0x02]),
+            Err(DecodeError::Truncated(0xF0))
+        );
+        assert_eq!(
+            MidiMessage::decode(&[0xF4]),
+            Err(DecodeError::UnsupportedStatus(0xF4))
+        );
+        assert_eq!(MidiMessage::decode(&[]), Ok(Vec::new()));
+    }
+}
@@ -1,33 +1,188 @@
-use log::{debug, error, info};
+use crate::active_notes::ActiveNotes;
+use crate::error::{MidiError, Recovery, RecoveryPolicy};
+use crate::latency::{LatencyCompensator, PortLatency};
+use crate::output_worker::{
+    OutputStats, OutputWorker, OverflowPolicy, PortWriter, QueueError, DEFAULT_QUEUE_CAPACITY,
+};
+use crate::reconnect::{ConnectionState, DeviceStatus, ReconnectPolicy, Reconnector};
+use crate::routing::{RoutingTable, PRIMARY_PORT};
+use crate::scheduler::{EventHandle, EventQueue};
+use crate::thru::ThruTable;
+use log::{debug, error, info, trace, warn};
 use midir::{MidiOutput as MidirOutput, MidiOutputConnection as MidirOutputConnection};
-use std::collections::HashMap;
-use std::error::Error;
-
-pub enum MidiMessage {
-    NoteOn {
-        channel: u8,
-        note: u8,
-        velocity: u8,
-        duration_ticks: u64,
-    },
-    NoteOff {
-        channel: u8,
-        note: u8,
-    },
-    AllNotesOff {
-        channel: u8,
-    },
-}
+use std::time::{Duration, Instant};
+
+pub use crate::midi_message::MidiMessage;
 
 pub trait MidiOutput {
-    fn send(&mut self, message: MidiMessage) -> Result<(), Box<dyn Error>>;
+    fn send(&mut self, message: MidiMessage) -> Result<(), MidiError>;
     fn process_tick_events(&mut self, current_tick: u64, new_events: Vec<MidiMessage>);
 }
 
+// Index of the primary port; named and clock-only ports follow it
+const PRIMARY_INDEX: usize = 0;
+// Device name a capturing port answers to when looking up its latency
+const CAPTURE_PORT_NAME: &str = "capture";
+const MIDI_CHANNELS: u8 = 16;
+
+// Where a port's messages end up
+enum Sink {
+    Closed,
+    // Written by a worker thread so a stuck device cannot stall the engine
+    Device(OutputWorker),
+    // Collected instead of sent, for offline rendering
+    Capture(Vec<MidiMessage>),
+}
+
+/// How one output port is doing, for status displays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortStatus {
+    pub name: String,
+    // Device port it is connected to, if any
+    pub device: Option<String>,
+    pub clock_only: bool,
+    // Device connection of a port that writes to hardware
+    pub connection: Option<ConnectionState>,
+    pub stats: OutputStats,
+    // Messages waiting for the port's worker
+    pub queued: usize,
+    pub last_error: Option<String>,
+}
+
+struct OutputPort {
+    // Name routes refer to the port by
+    name: String,
+    // Device name it was opened with; empty while closed
+    device: String,
+    sink: Sink,
+    // Clock-only ports get clock and transport but no notes
+    clock_only: bool,
+    // Counts for capturing and closed sinks, and for workers since closed
+    stats: OutputStats,
+    last_error: Option<String>,
+}
+
+impl OutputPort {
+    fn closed(name: &str, clock_only: bool) -> Self {
+        OutputPort {
+            name: name.to_string(),
+            device: String::new(),
+            sink: Sink::Closed,
+            clock_only,
+            stats: OutputStats::default(),
+            last_error: None,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        !matches!(self.sink, Sink::Closed)
+    }
+
+    fn open(&mut self, device: String, sink: Sink) {
+        self.device = device;
+        self.sink = sink;
+        self.last_error = None;
+    }
+
+    // Keeps the closing worker's counts; it finishes what is queued alone
+    fn close(&mut self) {
+        if let Sink::Device(worker) = &self.sink {
+            self.stats += worker.stats();
+            self.last_error = worker.last_error().or(self.last_error.take());
+        }
+        self.device.clear();
+        self.sink = Sink::Closed;
+    }
+
+    fn record_error(&mut self, e: &MidiError) {
+        self.stats.errors += 1;
+        self.last_error = Some(e.to_string());
+    }
+
+    // Hands a message to the port's worker, or captures it, keeping count
+    // of what went out and what failed
+    fn write(&mut self, message: &MidiMessage) -> Result<(), MidiError> {
+        let result = match &mut self.sink {
+            Sink::Closed => Err(MidiError::PortClosed {
+                port: self.name.clone(),
+            }),
+            // The worker counts its own sends, errors and drops
+            Sink::Device(worker) => {
+                return worker.send(message.to_bytes()).map_err(|e| match e {
+                    QueueError::Full => MidiError::BufferOverrun { dropped_events: 1 },
+                    QueueError::Closed => MidiError::PortClosed {
+                        port: self.name.clone(),
+                    },
+                })
+            }
+            Sink::Capture(captured) => {
+                captured.push(message.clone());
+                Ok(())
+            }
+        };
+        match &result {
+            Ok(()) => self.stats.sent += 1,
+            Err(e) => self.record_error(e),
+        }
+        result
+    }
+
+    fn status(&self) -> PortStatus {
+        let mut stats = self.stats;
+        let mut last_error = self.last_error.clone();
+        let mut queued = 0;
+        let mut connection = None;
+        let device = match &self.sink {
+            Sink::Closed => None,
+            Sink::Capture(_) => Some(self.device.clone()),
+            Sink::Device(worker) => {
+                stats += worker.stats();
+                last_error = worker.last_error().or(last_error);
+                queued = worker.queued();
+                connection = worker.connection();
+                match &connection {
+                    Some(ConnectionState::Connected(port)) => Some(port.clone()),
+                    _ => None,
+                }
+            }
+        };
+        PortStatus {
+            name: self.name.clone(),
+            device,
+            clock_only: self.clock_only,
+            connection,
+            stats,
+            queued,
+            last_error,
+        }
+    }
+}
+
 pub struct MidiOutputManager {
-    connection: Option<MidirOutputConnection>,
-    // New field: a mapping from target tick to scheduled MIDI messages.
-    scheduled_notes: HashMap<u64, Vec<MidiMessage>>,
+    // The primary port first, then named and clock-only ports. Ports are
+    // only ever added, so an index names the same port for good.
+    ports: Vec<OutputPort>,
+    // Which ports each track and channel is sent to
+    routing: RoutingTable,
+    // Which ports the messages from each input are played through to
+    thru: ThruTable,
+    // Messages waiting for their tick, mostly the NoteOffs of playing
+    // notes, with the track they came from
+    scheduled_notes: EventQueue<(Option<u16>, MidiMessage)>,
+    // When set, timing clock and transport messages are transmitted
+    clock_master: bool,
+    // Queue size, overflow, reconnect and recovery policies for ports
+    // opened from now on
+    queue_capacity: usize,
+    overflow: OverflowPolicy,
+    reconnect: ReconnectPolicy,
+    recovery: RecoveryPolicy,
+    // Per-port latencies and the lookahead queue that lines the ports up
+    latencies: Vec<PortLatency>,
+    compensator: LatencyCompensator,
+    bpm: f64,
+    // Notes started on each port and not yet ended
+    active_notes: ActiveNotes,
 }
 
 impl Default for MidiOutputManager {
@@ -39,76 +194,475 @@ impl Default for MidiOutputManager {
 impl MidiOutputManager {
     pub fn new() -> Self {
         MidiOutputManager {
-            connection: None,
-            scheduled_notes: HashMap::new(),
+            ports: vec![OutputPort::closed(PRIMARY_PORT, false)],
+            routing: RoutingTable::default(),
+            thru: ThruTable::default(),
+            scheduled_notes: EventQueue::new(),
+            clock_master: false,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            overflow: OverflowPolicy::default(),
+            reconnect: ReconnectPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            latencies: Vec::new(),
+            compensator: LatencyCompensator::new(),
+            bpm: 0.0,
+            active_notes: ActiveNotes::new(),
         }
     }
 
-    pub fn connect_to_first_available(&mut self) -> Result<(), Box<dyn Error>> {
-        let midi_out = MidirOutput::new("phasorsyncrs-output")?;
+    /// An output whose primary port records every message it is asked to
+    /// send.
+    pub fn capturing() -> Self {
+        let mut manager = Self::new();
+        manager.ports[PRIMARY_INDEX].open(CAPTURE_PORT_NAME.to_string(), Sink::Capture(Vec::new()));
+        manager
+    }
+
+    /// Adds a named port that records what is routed to it.
+    pub fn add_capture_port(&mut self, name: &str) {
+        let index = self.port_slot(name, false);
+        self.ports[index].open(CAPTURE_PORT_NAME.to_string(), Sink::Capture(Vec::new()));
+        self.update_latencies();
+    }
+
+    /// Drains the messages recorded by a capturing primary port.
+    pub fn take_captured(&mut self) -> Vec<MidiMessage> {
+        self.take_captured_from(PRIMARY_PORT)
+    }
 
-        // Get available output ports
-        let out_ports = midi_out.ports();
-        if out_ports.is_empty() {
-            return Err("No MIDI output ports available".into());
+    /// Drains the messages recorded by the capturing port `name`.
+    pub fn take_captured_from(&mut self, name: &str) -> Vec<MidiMessage> {
+        match self
+            .port_index(name)
+            .map(|index| &mut self.ports[index].sink)
+        {
+            Some(Sink::Capture(captured)) => std::mem::take(captured),
+            _ => Vec::new(),
         }
+    }
 
-        // Just use the first available port for simplicity
-        let port = &out_ports[0];
-        let port_name = midi_out.port_name(port)?;
+    pub fn set_clock_master(&mut self, enabled: bool) {
+        self.clock_master = enabled;
+    }
 
-        info!("Connecting to MIDI output port: {}", port_name);
-        let connection = midi_out.connect(port, "phasorsyncrs-output-conn")?;
-        self.connection = Some(connection);
-        Ok(())
+    pub fn is_clock_master(&self) -> bool {
+        self.clock_master
     }
 
-    pub fn connect_to_device(&mut self, device_name: &str) -> Result<(), Box<dyn Error>> {
-        let midi_out = MidirOutput::new("phasorsyncrs-output")?;
+    /// True if at least one output port is connected to a device or
+    /// capturing.
+    pub fn is_connected(&self) -> bool {
+        self.ports.iter().any(|port| match &port.sink {
+            Sink::Closed => false,
+            Sink::Capture(_) => true,
+            Sink::Device(worker) => worker
+                .connection()
+                .is_some_and(|state| state.is_connected()),
+        })
+    }
+
+    /// True if at least one output port is connected or still looking for
+    /// its device.
+    pub fn is_active(&self) -> bool {
+        self.ports.iter().any(|port| match &port.sink {
+            Sink::Device(worker) => {
+                !matches!(worker.connection(), Some(ConnectionState::GaveUp { .. }))
+            }
+            sink => !matches!(sink, Sink::Closed),
+        })
+    }
 
-        let out_ports = midi_out.ports();
-        let available_ports: Vec<String> = out_ports
+    /// The connection of every port that writes to a device.
+    pub fn device_statuses(&self) -> Vec<DeviceStatus> {
+        self.ports
             .iter()
-            .filter_map(|p| midi_out.port_name(p).ok())
+            .filter_map(|port| match &port.sink {
+                Sink::Device(worker) => Some(DeviceStatus {
+                    role: port.name.clone(),
+                    device: port.device.clone(),
+                    input: false,
+                    state: worker.connection()?,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Connection state and traffic of every port, the primary first.
+    pub fn port_statuses(&self) -> Vec<PortStatus> {
+        self.ports.iter().map(OutputPort::status).collect()
+    }
+
+    /// Message counts summed over every port.
+    pub fn stats(&self) -> OutputStats {
+        let mut total = OutputStats::default();
+        for status in self.port_statuses() {
+            total += status.stats;
+        }
+        total
+    }
+
+    /// Sets how many messages each port queues for its worker and what
+    /// happens when the queue is full. Applies to ports opened afterwards.
+    pub fn set_output_queue(&mut self, capacity: usize, overflow: OverflowPolicy) {
+        info!(
+            "MIDI output queue: {} messages per port, {} when full",
+            capacity, overflow
+        );
+        self.queue_capacity = capacity;
+        self.overflow = overflow;
+    }
+
+    /// Sets how ports look for their device again after it goes away.
+    /// Applies to ports opened afterwards.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect = policy;
+    }
+
+    /// Sets whether ports whose device is missing when they are opened keep
+    /// looking for it, or stay closed. Applies to ports opened afterwards.
+    pub fn set_recovery_policy(&mut self, policy: RecoveryPolicy) {
+        self.recovery = policy;
+    }
+
+    /// Waits up to `timeout` for every port's worker to write what it has
+    /// queued. Returns false if a port did not catch up in time.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        self.ports.iter().all(|port| match &port.sink {
+            Sink::Device(worker) => {
+                worker.flush(deadline.saturating_duration_since(Instant::now()))
+            }
+            _ => true,
+        })
+    }
+
+    pub fn connect_to_first_available(&mut self) -> Result<(), MidiError> {
+        let midi_out = MidirOutput::new("phasorsyncrs-output")
+            .map_err(|e| MidiError::Backend(e.to_string()))?;
+
+        // Just use the first available port for simplicity
+        let port_name = midi_out
+            .ports()
+            .first()
+            .and_then(|port| midi_out.port_name(port).ok())
+            .ok_or_else(|| MidiError::PortNotFound {
+                device: String::new(),
+                available: Vec::new(),
+            })?;
+        self.open_port(PRIMARY_INDEX, &port_name)
+    }
+
+    pub fn connect_to_device(&mut self, device_name: &str) -> Result<(), MidiError> {
+        self.connect_port(PRIMARY_PORT, device_name)
+    }
+
+    /// Connects the named port `name` to the first device port containing
+    /// `device_name`, adding the port if it is new. A port that fails to
+    /// connect is still added, closed, so its error shows in its status.
+    pub fn connect_port(&mut self, name: &str, device_name: &str) -> Result<(), MidiError> {
+        let index = self.port_slot(name, false);
+        self.open_port(index, device_name)
+    }
+
+    /// Connects an additional port that follows our clock but receives no notes.
+    pub fn add_clock_output(&mut self, device_name: &str) -> Result<(), MidiError> {
+        let index = self.port_slot(device_name, true);
+        self.open_port(index, device_name)
+    }
+
+    /// Closes the port `name`; messages routed to it fail until it is
+    /// connected again.
+    pub fn disconnect_port(&mut self, name: &str) {
+        if let Some(index) = self.port_index(name) {
+            info!("Disconnecting MIDI output '{}'", name);
+            self.ports[index].close();
+        }
+    }
+
+    // Hands the port to a worker that keeps looking for the device if it
+    // is missing now, unless the recovery or reconnect policy rules that out
+    fn open_port(&mut self, index: usize, device_name: &str) -> Result<(), MidiError> {
+        let mut writer = DeviceWriter::new(device_name, self.reconnect);
+        let result = writer.connect(Instant::now());
+        let port = &mut self.ports[index];
+        if let Some(ConnectionState::Connected(port_name)) = writer.connection() {
+            info!("MIDI output '{}' connected to {}", port.name, port_name);
+        }
+        let retry = match &result {
+            Ok(()) => true,
+            Err(e) => self.recovery.recover(e) == Recovery::Retry,
+        };
+        if retry && !matches!(writer.connection(), Some(ConnectionState::GaveUp { .. })) {
+            let worker =
+                OutputWorker::spawn(&port.name, self.queue_capacity, self.overflow, writer);
+            port.close();
+            port.open(device_name.to_string(), Sink::Device(worker));
+            self.update_latencies();
+        }
+        if let Err(e) = &result {
+            self.ports[index].record_error(e);
+        }
+        result
+    }
+
+    fn port_index(&self, name: &str) -> Option<usize> {
+        self.ports.iter().position(|port| port.name == name)
+    }
+
+    // The index of the port `name`, adding it closed if it is new
+    fn port_slot(&mut self, name: &str, clock_only: bool) -> usize {
+        self.port_index(name).unwrap_or_else(|| {
+            self.ports.push(OutputPort::closed(name, clock_only));
+            self.ports.len() - 1
+        })
+    }
+
+    /// Sets which ports each track and channel is sent to.
+    pub fn set_routing(&mut self, routing: RoutingTable) {
+        for route in routing.routes() {
+            match self.port_index(&route.port) {
+                Some(_) => info!("Routing {:?} to '{}'", route.source, route.port),
+                None => warn!(
+                    "Route for {:?} names unknown output '{}'; its messages are dropped",
+                    route.source, route.port
+                ),
+            }
+        }
+        self.routing = routing;
+    }
+
+    pub fn set_thru(&mut self, thru: ThruTable) {
+        for route in thru.routes() {
+            match self.port_index(&route.port) {
+                Some(_) => info!("Playing '{}' through to '{}'", route.input, route.port),
+                None => warn!(
+                    "Thru route from '{}' names unknown output '{}'; its messages are dropped",
+                    route.input, route.port
+                ),
+            }
+        }
+        self.thru = thru;
+    }
+
+    /// Plays a message from `input` through to the ports its thru routes
+    /// pick, merged with the generated messages. It goes out at once,
+    /// ahead of latency compensation, as it is being played live.
+    pub fn forward(&mut self, input: &str, message: &MidiMessage) {
+        let forwarded: Vec<(usize, MidiMessage)> = self
+            .thru
+            .resolve(input, message)
+            .into_iter()
+            .filter_map(|(name, message)| Some((self.port_index(name)?, message)))
             .collect();
+        for (port, message) in forwarded {
+            if self.ports[port].is_open() {
+                self.transmit_to(port, message);
+            } else {
+                trace!(
+                    "Dropping thru message for closed output '{}'",
+                    self.ports[port].name
+                );
+            }
+        }
+    }
 
-        info!("Available MIDI output ports: {:?}", available_ports);
+    /// Sets how late each port's device sounds, so the others can be held
+    /// back to match it.
+    pub fn set_port_latencies(&mut self, latencies: Vec<PortLatency>) {
+        for entry in &latencies {
+            info!("Output latency for '{}': {}", entry.port, entry.latency);
+        }
+        self.latencies = latencies;
+        self.update_latencies();
+    }
 
-        let port = out_ports
+    /// Tempo used to turn millisecond latencies into ticks.
+    pub fn set_bpm(&mut self, bpm: f64) {
+        if bpm != self.bpm {
+            self.bpm = bpm;
+            self.update_latencies();
+        }
+    }
+
+    // Port indices line up with the compensator's; closed ports have none
+    fn update_latencies(&mut self) {
+        let ticks: Vec<i64> = self
+            .ports
             .iter()
-            .find(|p| {
-                midi_out
-                    .port_name(p)
-                    .unwrap_or_default()
-                    .contains(device_name)
+            .map(|port| match port.device.as_str() {
+                "" => 0,
+                device => PortLatency::lookup(&self.latencies, device).to_ticks(self.bpm),
             })
-            .ok_or_else(|| {
-                error!("MIDI output device '{}' not found", device_name);
-                info!("Available devices: {:?}", available_ports);
-                "MIDI output device not found"
-            })?;
+            .collect();
+        self.compensator.set_latencies(&ticks);
+    }
+
+    /// Sends a clock or transport message to every open port when acting
+    /// as clock master; does nothing otherwise.
+    pub fn send_clock(&mut self, message: MidiMessage) {
+        if !self.clock_master {
+            return;
+        }
+        for port in 0..self.ports.len() {
+            if !self.ports[port].is_open() {
+                continue;
+            }
+            if let Some(message) = self.compensator.schedule(port, message.clone()) {
+                self.transmit_to(port, message);
+            }
+        }
+    }
 
-        let port_name = midi_out.port_name(port)?;
-        info!("Connecting to MIDI output port: {}", port_name);
+    // The ports and messages the routing picks for `message`. Routes to
+    // unknown ports were warned about when set and go nowhere.
+    fn route(&self, track: Option<u16>, message: &MidiMessage) -> Vec<(usize, MidiMessage)> {
+        self.routing
+            .resolve(track, message)
+            .into_iter()
+            .filter_map(|(name, message)| Some((self.port_index(name)?, message)))
+            .collect()
+    }
 
-        let connection = midi_out.connect(port, "phasorsyncrs-output-conn")?;
-        self.connection = Some(connection);
-        Ok(())
+    // Sends on every port the routing picks, once latency compensation
+    // allows. Fails if any of those ports is closed.
+    fn send_routed(&mut self, track: Option<u16>, message: MidiMessage) -> Result<(), MidiError> {
+        let mut result = Ok(());
+        for (port, message) in self.route(track, &message) {
+            if !self.ports[port].is_open() {
+                result = Err(MidiError::PortClosed {
+                    port: self.ports[port].name.clone(),
+                });
+                continue;
+            }
+            if let Some(message) = self.compensator.schedule(port, message) {
+                self.transmit_to(port, message);
+            }
+        }
+        result
+    }
+
+    // Writes a message to a port straight away, logging failures
+    fn transmit_to(&mut self, port: usize, message: MidiMessage) {
+        let output = &mut self.ports[port];
+        log_message(&output.name, &message);
+        match output.write(&message) {
+            Ok(()) => self.active_notes.observe(port, &message),
+            Err(e) => error!("Failed to send MIDI message to '{}': {}", output.name, e),
+        }
+    }
+
+    // Send whatever the lookahead queue has released on this tick
+    fn process_delayed_events(&mut self) {
+        for (port, message) in self.compensator.advance() {
+            self.transmit_to(port, message);
+        }
     }
 
-    // Process any scheduled events for the current tick
+    /// Queues `message` to go out on `tick`.
+    pub fn schedule(&mut self, tick: u64, message: MidiMessage) -> EventHandle {
+        self.scheduled_notes.schedule(tick, (None, message))
+    }
+
+    /// Drops a scheduled message; returns false if it already went out.
+    pub fn cancel(&mut self, handle: EventHandle) -> bool {
+        self.scheduled_notes.cancel(handle).is_some()
+    }
+
+    /// The tick a scheduled message is due on, if it is still pending.
+    pub fn scheduled_tick(&self, handle: EventHandle) -> Option<u64> {
+        self.scheduled_notes.due_tick(handle)
+    }
+
+    pub fn scheduled_count(&self) -> usize {
+        self.scheduled_notes.len()
+    }
+
+    /// Number of notes started and not yet ended, across every port.
+    pub fn sounding_notes(&self) -> usize {
+        self.active_notes.len()
+    }
+
+    /// Ends every sounding note now, e.g. when the transport stops and the
+    /// ticks their NoteOffs were waiting for will not come. Notes still
+    /// held back by latency compensation are dropped unheard.
+    pub fn release_notes(&mut self) {
+        for (port, message) in self.compensator.drain_all() {
+            if !message.is_note() {
+                self.transmit_to(port, message);
+            }
+        }
+        let scheduled = self.scheduled_notes.drain_all();
+        self.send_scheduled(
+            scheduled
+                .into_iter()
+                .filter(|(_, m)| !m.is_note())
+                .collect(),
+        );
+
+        let released = self.active_notes.release();
+        if !released.is_empty() {
+            info!("Releasing {} sounding notes", released.len());
+        }
+        for (port, note_off) in released {
+            self.transmit_to(port, note_off);
+        }
+    }
+
+    /// MIDI panic: releases every note, then sends All Notes Off and All
+    /// Sound Off on all 16 channels of every open port that takes notes.
+    pub fn panic(&mut self) {
+        self.release_notes();
+        for port in 0..self.ports.len() {
+            if !self.ports[port].is_open() || self.ports[port].clock_only {
+                continue;
+            }
+            info!("Sending MIDI panic to '{}'", self.ports[port].name);
+            for channel in 0..MIDI_CHANNELS {
+                self.transmit_to(port, MidiMessage::AllNotesOff { channel });
+                self.transmit_to(port, MidiMessage::AllSoundOff { channel });
+            }
+        }
+    }
+
+    /// Moves scheduled messages by `offset` ticks to follow a locate, so
+    /// notes still end after the length they were given.
+    pub fn shift_scheduled(&mut self, offset: i64) {
+        self.scheduled_notes.shift(offset);
+    }
+
+    // Process every scheduled event due by the current tick
     fn process_scheduled_events(&mut self, current_tick: u64) {
-        if let Some(scheduled_events) = self.scheduled_notes.remove(&current_tick) {
-            for event in scheduled_events {
-                if let Err(e) = self.send(event) {
-                    error!("Failed to send scheduled MIDI event: {}", e);
-                }
+        let events = self.scheduled_notes.drain_until(current_tick);
+        self.send_scheduled(events);
+    }
+
+    fn send_scheduled(&mut self, events: Vec<(Option<u16>, MidiMessage)>) {
+        for (track, event) in events {
+            if let Err(e) = self.send_routed(track, event) {
+                error!("Failed to send scheduled MIDI event: {}", e);
             }
         }
     }
 
+    /// Sends the events a musical graph track produced on this tick, so
+    /// the track's routes apply. Call after `process_tick_events`.
+    pub fn process_track_events(
+        &mut self,
+        current_tick: u64,
+        track: u16,
+        events: Vec<MidiMessage>,
+    ) {
+        self.process_new_events(current_tick, Some(track), events);
+    }
+
     // Process new events and schedule any necessary Note Off events
-    fn process_new_events(&mut self, current_tick: u64, new_events: Vec<MidiMessage>) {
+    fn process_new_events(
+        &mut self,
+        current_tick: u64,
+        track: Option<u16>,
+        new_events: Vec<MidiMessage>,
+    ) {
         for event in new_events {
             match event {
                 MidiMessage::NoteOn {
@@ -117,25 +671,27 @@ impl MidiOutputManager {
                     velocity,
                     duration_ticks,
                 } => {
-                    // Send NoteOn immediately
-                    if let Err(e) = self.send(MidiMessage::NoteOn {
-                        channel,
-                        note,
-                        velocity,
-                        duration_ticks: 0, // Not needed when sending
-                    }) {
+                    // Send NoteOn, or queue it behind the slowest port
+                    if let Err(e) = self.send_routed(
+                        track,
+                        MidiMessage::NoteOn {
+                            channel,
+                            note,
+                            velocity,
+                            duration_ticks: 0, // Not needed when sending
+                        },
+                    ) {
                         error!("Failed to send NoteOn: {}", e);
                     }
 
-                    // Schedule the corresponding NoteOff
-                    let target_tick = current_tick + duration_ticks;
-                    self.scheduled_notes
-                        .entry(target_tick)
-                        .or_default()
-                        .push(MidiMessage::NoteOff { channel, note });
+                    // Schedule the corresponding NoteOff, routed the same way
+                    self.scheduled_notes.schedule(
+                        current_tick + duration_ticks,
+                        (track, MidiMessage::NoteOff { channel, note }),
+                    );
                 }
                 _ => {
-                    if let Err(e) = self.send(event) {
+                    if let Err(e) = self.send_routed(track, event) {
                         error!("Failed to send MIDI event: {}", e);
                     }
                 }
@@ -144,47 +700,471 @@ impl MidiOutputManager {
     }
 }
 
-impl MidiOutput for MidiOutputManager {
-    fn send(&mut self, message: MidiMessage) -> Result<(), Box<dyn Error>> {
-        let conn = self
-            .connection
-            .as_mut()
-            .ok_or("MIDI output not connected")?;
-
-        match message {
-            MidiMessage::NoteOn {
-                channel,
-                note,
-                velocity,
-                duration_ticks: _, // Ignore duration_ticks when sending
-            } => {
-                let msg = [0x90 | (channel & 0x0F), note, velocity];
-                debug!(
-                    "Sending MIDI Note On: ch={}, note={}, vel={}",
-                    channel, note, velocity
-                );
-                conn.send(&msg)?;
+fn log_message(port: &str, message: &MidiMessage) {
+    match message {
+        MidiMessage::NoteOn {
+            channel,
+            note,
+            velocity,
+            duration_ticks: _, // Ignore duration_ticks when sending
+        } => debug!(
+            "Sending MIDI Note On to '{}': ch={}, note={}, vel={}",
+            port, channel, note, velocity
+        ),
+        MidiMessage::NoteOff { channel, note } => {
+            debug!(
+                "Sending MIDI Note Off to '{}': ch={}, note={}",
+                port, channel, note
+            )
+        }
+        MidiMessage::AllNotesOff { channel } => {
+            debug!("Sending All Notes Off to '{}': ch={}", port, channel)
+        }
+        MidiMessage::AllSoundOff { channel } => {
+            debug!("Sending All Sound Off to '{}': ch={}", port, channel)
+        }
+        MidiMessage::MtcFullFrame(timecode) => {
+            debug!("Sending MTC full frame {} to '{}'", timecode, port)
+        }
+        other => trace!("Sending MIDI message to '{}': {:?}", port, other),
+    }
+}
+
+// Writes to the first device port whose name contains `device_name`, and
+// finds it again after it is unplugged
+struct DeviceWriter {
+    device_name: String,
+    connection: Option<MidirOutputConnection>,
+    reconnector: Reconnector,
+    // When the connected port was last seen in the port list
+    last_check: Instant,
+}
+
+impl DeviceWriter {
+    fn new(device_name: &str, policy: ReconnectPolicy) -> Self {
+        let now = Instant::now();
+        DeviceWriter {
+            device_name: device_name.to_string(),
+            connection: None,
+            reconnector: Reconnector::new(policy, now),
+            last_check: now,
+        }
+    }
+
+    fn connect(&mut self, now: Instant) -> Result<(), MidiError> {
+        match open_output_port(&self.device_name) {
+            Ok((port_name, connection)) => {
+                self.connection = Some(connection);
+                self.reconnector.connected(port_name);
+                self.last_check = now;
+                Ok(())
+            }
+            Err(e) => {
+                self.reconnector.failed(now);
+                Err(e)
             }
-            MidiMessage::NoteOff { channel, note } => {
-                let msg = [0x80 | (channel & 0x0F), note, 0];
-                debug!("Sending MIDI Note Off: ch={}, note={}", channel, note);
-                conn.send(&msg)?;
+        }
+    }
+
+    fn lose(&mut self, now: Instant) {
+        warn!("MIDI output device '{}' disconnected", self.device_name);
+        self.connection = None;
+        self.reconnector.lost(now);
+    }
+
+    // Unplugged devices drop out of the port list; send errors alone are
+    // not always raised for them
+    fn still_present(&self) -> bool {
+        let ConnectionState::Connected(port_name) = self.reconnector.state() else {
+            return false;
+        };
+        match MidirOutput::new("phasorsyncrs-scan") {
+            Ok(midi_out) => midi_out
+                .ports()
+                .iter()
+                .any(|port| midi_out.port_name(port).ok().as_ref() == Some(port_name)),
+            // Can't tell, so keep the connection
+            Err(_) => true,
+        }
+    }
+}
+
+impl PortWriter for DeviceWriter {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let Some(connection) = self.connection.as_mut() else {
+            return Err(MidiError::PortDisconnected {
+                device: self.device_name.clone(),
             }
-            MidiMessage::AllNotesOff { channel } => {
-                let msg = [0xB0 | (channel & 0x0F), 123, 0];
-                debug!("Sending All Notes Off: ch={}", channel);
-                conn.send(&msg)?;
+            .to_string());
+        };
+        connection.send(bytes).map_err(|e| {
+            self.lose(Instant::now());
+            e.to_string()
+        })
+    }
+
+    fn maintain(&mut self, now: Instant) {
+        let interval = self.reconnector.policy().interval;
+        if self.connection.is_some() {
+            if now.duration_since(self.last_check) >= interval {
+                self.last_check = now;
+                if !self.still_present() {
+                    self.lose(now);
+                }
+            }
+        } else if self.reconnector.due(now) {
+            match self.connect(now) {
+                Ok(()) => info!("MIDI output device '{}' reconnected", self.device_name),
+                Err(e) => debug!("MIDI output device '{}': {}", self.device_name, e),
             }
         }
-        Ok(())
+    }
+
+    fn connection(&self) -> Option<ConnectionState> {
+        Some(self.reconnector.state().clone())
+    }
+}
+
+fn open_output_port(device_name: &str) -> Result<(String, MidirOutputConnection), MidiError> {
+    let midi_out =
+        MidirOutput::new("phasorsyncrs-output").map_err(|e| MidiError::Backend(e.to_string()))?;
+
+    let out_ports = midi_out.ports();
+    let available_ports: Vec<String> = out_ports
+        .iter()
+        .filter_map(|p| midi_out.port_name(p).ok())
+        .collect();
+
+    debug!("Available MIDI output ports: {:?}", available_ports);
+
+    let port = out_ports
+        .iter()
+        .find(|p| {
+            midi_out
+                .port_name(p)
+                .unwrap_or_default()
+                .contains(device_name)
+        })
+        .ok_or_else(|| MidiError::PortNotFound {
+            device: device_name.to_string(),
+            available: available_ports.clone(),
+        })?;
+
+    let connect_error = |reason: String| MidiError::Connect {
+        device: device_name.to_string(),
+        reason,
+    };
+    let port_name = midi_out
+        .port_name(port)
+        .map_err(|e| connect_error(e.to_string()))?;
+    info!("Connecting to MIDI output port: {}", port_name);
+
+    let connection = midi_out
+        .connect(port, "phasorsyncrs-output-conn")
+        .map_err(|e| connect_error(e.to_string()))?;
+    Ok((port_name, connection))
+}
+
+impl MidiOutput for MidiOutputManager {
+    /// Sends on the ports the routing picks once their latency
+    /// compensation allows.
+    fn send(&mut self, message: MidiMessage) -> Result<(), MidiError> {
+        self.send_routed(None, message)
     }
 
     // Process MIDI events for the current tick
     fn process_tick_events(&mut self, current_tick: u64, new_events: Vec<MidiMessage>) {
-        // First, process any scheduled events for the current tick
+        // Messages held back for latency compensation go out first
+        self.process_delayed_events();
+
+        // Then any scheduled events for the current tick
         self.process_scheduled_events(current_tick);
 
         // Then process any new events
-        self.process_new_events(current_tick, new_events);
+        self.process_new_events(current_tick, None, new_events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_clock_is_noop_unless_master() {
+        // No connection and not master: nothing is attempted, nothing panics
+        let mut manager = MidiOutputManager::new();
+        assert!(!manager.is_connected());
+        manager.send_clock(MidiMessage::TimingClock);
+        assert!(!manager.is_clock_master());
+
+        manager.set_clock_master(true);
+        manager.send_clock(MidiMessage::TimingClock);
+        assert!(manager.is_clock_master());
+    }
+
+    #[test]
+    fn test_notes_wait_out_the_port_latency() {
+        use crate::latency::{Latency, PortLatency};
+
+        // A port that plays early is held back by its negative latency
+        let mut manager = MidiOutputManager::capturing();
+        manager.set_port_latencies(vec![PortLatency {
+            port: "capture".to_string(),
+            latency: Latency::Ticks(-2),
+        }]);
+        let note_on = MidiMessage::NoteOn {
+            channel: 0,
+            note: 60,
+            velocity: 100,
+            duration_ticks: 4,
+        };
+
+        manager.process_tick_events(1, vec![note_on]);
+        manager.process_tick_events(2, Vec::new());
+        assert!(manager.take_captured().is_empty());
+
+        manager.process_tick_events(3, Vec::new());
+        assert!(matches!(
+            manager.take_captured().as_slice(),
+            [MidiMessage::NoteOn { note: 60, .. }]
+        ));
+
+        // The note off is due on tick 5 and held back just as long
+        for tick in 4..=6 {
+            manager.process_tick_events(tick, Vec::new());
+        }
+        assert!(manager.take_captured().is_empty());
+        manager.process_tick_events(7, Vec::new());
+        assert_eq!(
+            manager.take_captured(),
+            vec![MidiMessage::NoteOff {
+                channel: 0,
+                note: 60
+            }]
+        );
+    }
+
+    #[test]
+    fn test_a_tempo_change_keeps_note_offs_after_their_note_ons() {
+        use crate::latency::{Latency, PortLatency};
+
+        // 12 ms is 23 ticks at 120 BPM but only 12 at 60 BPM
+        let mut manager = MidiOutputManager::capturing();
+        manager.set_port_latencies(vec![PortLatency {
+            port: "capture".to_string(),
+            latency: Latency::Millis(-12.0),
+        }]);
+        manager.set_bpm(120.0);
+        manager.process_tick_events(
+            0,
+            vec![MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                duration_ticks: 2,
+            }],
+        );
+        manager.process_tick_events(1, Vec::new());
+        manager.set_bpm(60.0);
+        for tick in 2..40 {
+            manager.process_tick_events(tick, Vec::new());
+        }
+
+        assert!(matches!(
+            manager.take_captured().as_slice(),
+            [
+                MidiMessage::NoteOn { note: 60, .. },
+                MidiMessage::NoteOff { note: 60, .. },
+            ]
+        ));
+        assert_eq!(manager.sounding_notes(), 0);
+    }
+
+    #[test]
+    fn test_panic_ends_sounding_notes_and_drops_unheard_ones() {
+        use crate::latency::{Latency, PortLatency};
+
+        let mut manager = MidiOutputManager::capturing();
+        let note_on = |note| MidiMessage::NoteOn {
+            channel: 2,
+            note,
+            velocity: 100,
+            duration_ticks: 960,
+        };
+        manager.process_tick_events(0, vec![note_on(60)]);
+        assert_eq!(manager.sounding_notes(), 1);
+
+        // A note still held back by latency compensation was never heard
+        manager.set_port_latencies(vec![PortLatency {
+            port: "capture".to_string(),
+            latency: Latency::Ticks(-10),
+        }]);
+        manager.process_tick_events(1, vec![note_on(64)]);
+        manager.take_captured();
+
+        manager.panic();
+        let captured = manager.take_captured();
+        assert_eq!(
+            captured[0],
+            MidiMessage::NoteOff {
+                channel: 2,
+                note: 60
+            }
+        );
+        assert_eq!(captured.len(), 1 + 2 * 16);
+        assert!(captured.contains(&MidiMessage::AllSoundOff { channel: 15 }));
+        assert_eq!(manager.sounding_notes(), 0);
+        assert_eq!(manager.scheduled_count(), 0);
+
+        // Nothing is left to go out later
+        for tick in 2..20 {
+            manager.process_tick_events(tick, Vec::new());
+        }
+        assert!(manager.take_captured().is_empty());
+    }
+
+    #[test]
+    fn test_track_routes_send_notes_to_their_port_and_channel() {
+        use crate::routing::{Route, RoutingTable};
+
+        let mut manager = MidiOutputManager::capturing();
+        manager.add_capture_port("bass");
+        manager.set_routing(RoutingTable::new(vec![
+            Route::parse("track:1=bass:3").unwrap()
+        ]));
+        let note_on = MidiMessage::NoteOn {
+            channel: 0,
+            note: 36,
+            velocity: 100,
+            duration_ticks: 2,
+        };
+
+        manager.process_tick_events(0, Vec::new());
+        manager.process_track_events(0, 1, vec![note_on.clone()]);
+        manager.process_tick_events(0, vec![note_on]);
+        for tick in 1..=2 {
+            manager.process_tick_events(tick, Vec::new());
+        }
+
+        // The NoteOff follows its NoteOn to the routed port and channel
+        let bass = manager.take_captured_from("bass");
+        assert!(matches!(
+            bass.as_slice(),
+            [
+                MidiMessage::NoteOn {
+                    channel: 2,
+                    note: 36,
+                    ..
+                },
+                MidiMessage::NoteOff {
+                    channel: 2,
+                    note: 36
+                }
+            ]
+        ));
+        assert_eq!(manager.take_captured().len(), 2);
+
+        let statuses = manager.port_statuses();
+        assert_eq!(statuses[1].name, "bass");
+        assert_eq!(statuses[1].stats.sent, 2);
+        assert_eq!(statuses[1].stats.errors, 0);
+    }
+
+    #[test]
+    fn test_thru_merges_played_notes_with_generated_ones() {
+        use crate::thru::{ThruRoute, ThruTable};
+
+        let mut manager = MidiOutputManager::capturing();
+        manager.add_capture_port("bass");
+        manager.set_clock_master(true);
+        manager.set_thru(ThruTable::new(vec![
+            ThruRoute::parse("keys=bass:2,notes=0-59").unwrap(),
+            ThruRoute::parse("clock=main,type=clock").unwrap(),
+        ]));
+        let played = |note| MidiMessage::NoteOn {
+            channel: 0,
+            note,
+            velocity: 90,
+            duration_ticks: 0,
+        };
+
+        manager.process_tick_events(
+            0,
+            vec![MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100,
+                duration_ticks: 1,
+            }],
+        );
+        manager.forward("keys", &played(36));
+        manager.forward("keys", &played(72));
+        manager.forward("clock", &MidiMessage::TimingClock);
+
+        assert_eq!(
+            manager.take_captured_from("bass"),
+            vec![MidiMessage::NoteOn {
+                channel: 1,
+                note: 36,
+                velocity: 90,
+                duration_ticks: 0,
+            }]
+        );
+        assert!(matches!(
+            manager.take_captured().as_slice(),
+            [
+                MidiMessage::NoteOn { note: 60, .. },
+                MidiMessage::TimingClock
+            ]
+        ));
+        // Played notes are released with the generated ones
+        assert_eq!(manager.sounding_notes(), 2);
+    }
+
+    #[test]
+    fn test_failed_port_reports_its_error_without_closing_the_others() {
+        let mut manager = MidiOutputManager::capturing();
+        assert!(manager
+            .connect_port("lead", "No Such Device Attached")
+            .is_err());
+
+        let statuses = manager.port_statuses();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[1].device, None);
+        assert_eq!(statuses[1].stats.errors, 1);
+        assert!(statuses[1].last_error.is_some());
+        assert_eq!(
+            statuses[1].connection,
+            Some(ConnectionState::Disconnected { attempts: 1 })
+        );
+        assert!(manager.is_connected());
+
+        manager.disconnect_port("main");
+        assert!(!manager.is_connected());
+        assert!(manager.send(MidiMessage::Start).is_err());
+    }
+
+    #[test]
+    fn test_failed_port_stays_closed_when_degrading() {
+        let mut manager = MidiOutputManager::capturing();
+        manager.set_recovery_policy(RecoveryPolicy::Degrade);
+        let result = manager.connect_port("lead", "No Such Device Attached");
+        assert!(matches!(
+            result,
+            Err(MidiError::PortNotFound { .. } | MidiError::Backend(_))
+        ));
+
+        let statuses = manager.port_statuses();
+        assert_eq!(statuses[1].connection, None);
+        assert_eq!(statuses[1].stats.errors, 1);
+
+        manager.disconnect_port("main");
+        assert!(!manager.is_active());
+        assert_eq!(
+            manager.send(MidiMessage::Start),
+            Err(MidiError::PortClosed {
+                port: "main".to_string()
+            })
+        );
     }
 }
@@ -0,0 +1,411 @@
+// mtc.rs
+
+use std::fmt;
+
+// A quarter-frame sequence describes the frame at which its first piece was
+// sent, and takes two frames to transmit
+const QUARTER_FRAME_LATENCY_FRAMES: u64 = 2;
+
+/// MIDI Time Code frame rates, in the order of their two-bit rate code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameRate {
+    Fps24,
+    Fps25,
+    /// 29.97 fps drop-frame
+    Fps2997Drop,
+    #[default]
+    Fps30,
+}
+
+impl FrameRate {
+    /// Parses the rates accepted on the command line: 24, 25, 29.97df, 30.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "24" => Some(FrameRate::Fps24),
+            "25" => Some(FrameRate::Fps25),
+            "29.97df" => Some(FrameRate::Fps2997Drop),
+            "30" => Some(FrameRate::Fps30),
+            _ => None,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code & 0x03 {
+            0 => FrameRate::Fps24,
+            1 => FrameRate::Fps25,
+            2 => FrameRate::Fps2997Drop,
+            _ => FrameRate::Fps30,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            FrameRate::Fps24 => 0,
+            FrameRate::Fps25 => 1,
+            FrameRate::Fps2997Drop => 2,
+            FrameRate::Fps30 => 3,
+        }
+    }
+
+    /// Frames counted per timecode second.
+    fn nominal_fps(self) -> u64 {
+        match self {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps2997Drop | FrameRate::Fps30 => 30,
+        }
+    }
+
+    /// Frames per second of real time.
+    pub fn real_fps(self) -> f64 {
+        match self {
+            FrameRate::Fps2997Drop => 30_000.0 / 1_001.0,
+            rate => rate.nominal_fps() as f64,
+        }
+    }
+
+    fn frames_per_day(self) -> u64 {
+        match self {
+            // 24 hours of 10 minute blocks, each dropping 18 labels
+            FrameRate::Fps2997Drop => 144 * 17_982,
+            rate => rate.nominal_fps() * 86_400,
+        }
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FrameRate::Fps24 => "24",
+            FrameRate::Fps25 => "25",
+            FrameRate::Fps2997Drop => "29.97df",
+            FrameRate::Fps30 => "30",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A timecode address. Drop-frame labels skip frames 0 and 1 at the start
+/// of every minute except each tenth, so the count keeps pace with 29.97 fps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: FrameRate,
+}
+
+impl Timecode {
+    /// Timecode of the `frame`th frame since midnight.
+    pub fn from_frame_count(frame: u64, rate: FrameRate) -> Self {
+        let mut label = frame % rate.frames_per_day();
+        if rate == FrameRate::Fps2997Drop {
+            let blocks = label / 17_982;
+            let rest = label % 17_982;
+            let dropped_minutes = if rest < 2 { 0 } else { (rest - 2) / 1_798 };
+            label += 18 * blocks + 2 * dropped_minutes;
+        }
+
+        let fps = rate.nominal_fps();
+        Timecode {
+            hours: (label / (fps * 3_600) % 24) as u8,
+            minutes: (label / (fps * 60) % 60) as u8,
+            seconds: (label / fps % 60) as u8,
+            frames: (label % fps) as u8,
+            rate,
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        let fps = self.rate.nominal_fps();
+        let minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let label = (minutes * 60 + self.seconds as u64) * fps + self.frames as u64;
+        match self.rate {
+            FrameRate::Fps2997Drop => label - 2 * (minutes - minutes / 10),
+            _ => label,
+        }
+    }
+
+    pub fn from_seconds(seconds: f64, rate: FrameRate) -> Self {
+        let frame = (seconds.max(0.0) * rate.real_fps() + 1e-6).floor() as u64;
+        Self::from_frame_count(frame, rate)
+    }
+
+    pub fn to_seconds(&self) -> f64 {
+        self.frame_count() as f64 / self.rate.real_fps()
+    }
+
+    fn add_frames(&self, frames: u64) -> Self {
+        Self::from_frame_count(self.frame_count() + frames, self.rate)
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.rate == FrameRate::Fps2997Drop {
+            ';'
+        } else {
+            ':'
+        };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours, self.minutes, self.seconds, separator, self.frames
+        )
+    }
+}
+
+/// Data byte of quarter-frame `piece` (0-7) describing `timecode`.
+pub fn quarter_frame_data(timecode: &Timecode, piece: u8) -> u8 {
+    let value = match piece & 0x07 {
+        0 => timecode.frames & 0x0F,
+        1 => (timecode.frames >> 4) & 0x01,
+        2 => timecode.seconds & 0x0F,
+        3 => (timecode.seconds >> 4) & 0x03,
+        4 => timecode.minutes & 0x0F,
+        5 => (timecode.minutes >> 4) & 0x03,
+        6 => timecode.hours & 0x0F,
+        _ => ((timecode.hours >> 4) & 0x01) | (timecode.rate.code() << 1),
+    };
+    ((piece & 0x07) << 4) | value
+}
+
+/// Encodes a full-frame SysEx message, used to locate followers.
+pub fn full_frame(timecode: &Timecode) -> Vec<u8> {
+    vec![
+        0xF0,
+        0x7F,
+        0x7F, // all devices
+        0x01,
+        0x01,
+        (timecode.rate.code() << 5) | (timecode.hours & 0x1F),
+        timecode.minutes,
+        timecode.seconds,
+        timecode.frames,
+        0xF7,
+    ]
+}
+
+/// Decodes a full-frame SysEx message, or `None` for any other SysEx.
+pub fn decode_full_frame(message: &[u8]) -> Option<Timecode> {
+    match *message {
+        [0xF0, 0x7F, _, 0x01, 0x01, hours, minutes, seconds, frames, 0xF7] => Some(Timecode {
+            hours: hours & 0x1F,
+            minutes: minutes & 0x3F,
+            seconds: seconds & 0x3F,
+            frames: frames & 0x1F,
+            rate: FrameRate::from_code(hours >> 5),
+        }),
+        _ => None,
+    }
+}
+
+/// Assembles timecode from the eight pieces of a quarter-frame sequence.
+/// Sequences must arrive in order; anything else resets the decoder until
+/// the next piece 0.
+#[derive(Debug, Default)]
+pub struct QuarterFrameDecoder {
+    pieces: [u8; 8],
+    next_piece: u8,
+}
+
+impl QuarterFrameDecoder {
+    /// Feeds the data byte of a 0xF1 message. Returns the current timecode
+    /// once a full sequence has been received.
+    pub fn push(&mut self, data: u8) -> Option<Timecode> {
+        let piece = (data >> 4) & 0x07;
+        if piece != self.next_piece {
+            self.next_piece = 0;
+            if piece != 0 {
+                return None;
+            }
+        }
+
+        self.pieces[piece as usize] = data & 0x0F;
+        self.next_piece = (piece + 1) % 8;
+        (piece == 7).then(|| self.assemble())
+    }
+
+    fn assemble(&self) -> Timecode {
+        let p = &self.pieces;
+        let timecode = Timecode {
+            frames: p[0] | ((p[1] & 0x01) << 4),
+            seconds: p[2] | ((p[3] & 0x03) << 4),
+            minutes: p[4] | ((p[5] & 0x03) << 4),
+            hours: p[6] | ((p[7] & 0x01) << 4),
+            rate: FrameRate::from_code(p[7] >> 1),
+        };
+        timecode.add_frames(QUARTER_FRAME_LATENCY_FRAMES)
+    }
+}
+
+/// Produces MTC from transport time. The caller advances it by the real
+/// time each tick represents and sends whatever quarter frames fall due.
+#[derive(Debug)]
+pub struct MtcGenerator {
+    rate: FrameRate,
+    seconds: f64,
+    next_quarter_frame: u64,
+}
+
+impl MtcGenerator {
+    pub fn new(rate: FrameRate) -> Self {
+        MtcGenerator {
+            rate,
+            seconds: 0.0,
+            next_quarter_frame: 0,
+        }
+    }
+
+    pub fn timecode(&self) -> Timecode {
+        Timecode::from_seconds(self.seconds, self.rate)
+    }
+
+    /// Jumps to `seconds` of transport time and returns the timecode to
+    /// announce in a full-frame message. Quarter frames resume at the next
+    /// even frame so every sequence starts with piece 0.
+    pub fn locate(&mut self, seconds: f64) -> Timecode {
+        self.seconds = seconds.max(0.0);
+        let frame = (self.seconds * self.rate.real_fps() - 1e-6).ceil().max(0.0) as u64;
+        self.next_quarter_frame = (frame + frame % 2) * 4;
+        self.timecode()
+    }
+
+    /// Moves transport time on by `seconds`, returning the data bytes of
+    /// the quarter frames now due.
+    pub fn advance(&mut self, seconds: f64) -> Vec<u8> {
+        self.seconds += seconds;
+        let quarter_frames_per_second = 4.0 * self.rate.real_fps();
+
+        let mut due = Vec::new();
+        while self.next_quarter_frame as f64 / quarter_frames_per_second <= self.seconds + 1e-9 {
+            let quarter_frame = self.next_quarter_frame;
+            let sequence_frame = quarter_frame / 8 * 2;
+            let timecode = Timecode::from_frame_count(sequence_frame, self.rate);
+            due.push(quarter_frame_data(&timecode, (quarter_frame % 8) as u8));
+            self.next_quarter_frame += 1;
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tc(rate: FrameRate, [hours, minutes, seconds, frames]: [u8; 4]) -> Timecode {
+        Timecode {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            rate,
+        }
+    }
+
+    #[test]
+    fn test_parse_frame_rates() {
+        assert_eq!(FrameRate::parse("24"), Some(FrameRate::Fps24));
+        assert_eq!(FrameRate::parse("29.97df"), Some(FrameRate::Fps2997Drop));
+        assert_eq!(FrameRate::parse("29.97"), None);
+        assert_eq!(FrameRate::Fps2997Drop.to_string(), "29.97df");
+    }
+
+    #[test]
+    fn test_non_drop_frame_count_round_trip() {
+        let timecode = tc(FrameRate::Fps25, [1, 2, 3, 4]);
+        assert_eq!(timecode.frame_count(), ((62 * 60) + 3) * 25 + 4);
+        assert_eq!(
+            Timecode::from_frame_count(timecode.frame_count(), FrameRate::Fps25),
+            timecode
+        );
+    }
+
+    #[test]
+    fn test_drop_frame_skips_labels_at_minute_boundaries() {
+        let rate = FrameRate::Fps2997Drop;
+        let last_of_minute = tc(rate, [0, 0, 59, 29]);
+        let next = Timecode::from_frame_count(last_of_minute.frame_count() + 1, rate);
+        assert_eq!(next, tc(rate, [0, 1, 0, 2]));
+        assert_eq!(next.to_string(), "00:01:00;02");
+
+        // Every tenth minute keeps frames 0 and 1
+        let before_ten = tc(rate, [0, 9, 59, 29]);
+        let at_ten = Timecode::from_frame_count(before_ten.frame_count() + 1, rate);
+        assert_eq!(at_ten, tc(rate, [0, 10, 0, 0]));
+
+        // An hour of drop-frame is an hour of real time to within a frame
+        assert!((tc(rate, [1, 0, 0, 0]).to_seconds() - 3_600.0).abs() < 1.0 / 29.97);
+    }
+
+    #[test]
+    fn test_quarter_frames_round_trip_through_decoder() {
+        let timecode = tc(FrameRate::Fps30, [23, 59, 58, 20]);
+        let mut decoder = QuarterFrameDecoder::default();
+
+        let decoded: Vec<Timecode> = (0..8)
+            .filter_map(|piece| decoder.push(quarter_frame_data(&timecode, piece)))
+            .collect();
+
+        // The decoded time accounts for the two frames the sequence took
+        assert_eq!(decoded, vec![tc(FrameRate::Fps30, [23, 59, 58, 22])]);
+    }
+
+    #[test]
+    fn test_decoder_waits_for_piece_zero_after_a_gap() {
+        let timecode = tc(FrameRate::Fps24, [0, 0, 1, 0]);
+        let mut decoder = QuarterFrameDecoder::default();
+
+        for piece in [3, 4, 5, 6, 7] {
+            assert_eq!(decoder.push(quarter_frame_data(&timecode, piece)), None);
+        }
+        let mut result = None;
+        for piece in 0..8 {
+            result = decoder.push(quarter_frame_data(&timecode, piece));
+        }
+        assert_eq!(result, Some(tc(FrameRate::Fps24, [0, 0, 1, 2])));
+    }
+
+    #[test]
+    fn test_full_frame_round_trip() {
+        let timecode = tc(FrameRate::Fps2997Drop, [10, 20, 30, 12]);
+        let message = full_frame(&timecode);
+        assert_eq!(message[5], (2 << 5) | 10);
+        assert_eq!(decode_full_frame(&message), Some(timecode));
+        assert_eq!(
+            decode_full_frame(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_generator_emits_four_quarter_frames_per_frame() {
+        let mut generator = MtcGenerator::new(FrameRate::Fps25);
+        assert_eq!(generator.locate(0.0), tc(FrameRate::Fps25, [0, 0, 0, 0]));
+
+        // Two frames' worth of time is one full sequence, pieces 0 to 7
+        let mut due = generator.advance(0.0);
+        due.extend(generator.advance(2.0 / 25.0 - 1e-6));
+        let pieces: Vec<u8> = due.iter().map(|data| data >> 4).collect();
+        assert_eq!(pieces, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_generator_output_decodes_to_transport_time() {
+        let mut generator = MtcGenerator::new(FrameRate::Fps30);
+        generator.locate(61.5);
+
+        let mut decoder = QuarterFrameDecoder::default();
+        let mut decoded = None;
+        for _ in 0..30 {
+            for data in generator.advance(1.0 / 120.0) {
+                decoded = decoder.push(data).or(decoded);
+            }
+        }
+
+        let decoded = decoded.unwrap();
+        assert!((decoded.to_seconds() - generator.timecode().to_seconds()).abs() <= 2.0 / 30.0);
+        assert_eq!(decoded.minutes, 1);
+    }
+}
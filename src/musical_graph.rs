@@ -1,17 +1,27 @@
 use crate::state;
 use log::debug;
+use std::cell::Cell;
 
 // Musical graph constants
-const TICKS_PER_BEAT: u64 = 24;
-const BEATS_PER_BAR: u64 = 4;
 const TRIGGER_EVERY_N_BARS: u64 = 1;
 
-// Static variable to track the musical tick count
-static mut MUSICAL_TICK_COUNT: u64 = 0;
+/// Track the graph's notes are routed as. The graph has a single track.
+pub const MIDDLE_C_TRACK: u16 = 1;
 
-/// Processes a tick event by checking the current bar and beat.
-/// If the current bar is nonzero, is a multiple of 8, and the current beat is 0,
-/// then log that a Middle C event is triggered.
+// Musical tick count, kept per thread so each engine (and each test or
+// offline render) counts independently
+thread_local! {
+    static MUSICAL_TICK_COUNT: Cell<u64> = const { Cell::new(0) };
+}
+
+/// How many ticks the musical graph has processed on this thread.
+pub fn musical_tick_count() -> u64 {
+    MUSICAL_TICK_COUNT.with(Cell::get)
+}
+
+/// Processes a tick event by working out the musical bar and beat from the
+/// shared meter. On the first tick of each bar, log that a Middle C event is
+/// triggered.
 ///
 /// Returns true if a Middle C note was triggered, false otherwise.
 pub fn process_tick(shared_state: &mut state::SharedState) -> bool {
@@ -22,33 +32,28 @@ pub fn process_tick(shared_state: &mut state::SharedState) -> bool {
 
     let mut middle_c_triggered = false;
 
-    // Safely increment our own tick counter
-    unsafe {
-        MUSICAL_TICK_COUNT += 1;
-
-        // Calculate musical bar and beat
-        let beat = (MUSICAL_TICK_COUNT / TICKS_PER_BEAT) % BEATS_PER_BAR;
-        let bar = (MUSICAL_TICK_COUNT / (TICKS_PER_BEAT * BEATS_PER_BAR)) + 1; // 1-indexed
-
-        // Add info logging every 24 ticks (once per beat)
-        if MUSICAL_TICK_COUNT.is_multiple_of(TICKS_PER_BEAT) {
-            let tick_count = MUSICAL_TICK_COUNT; // Copy to local variable
-            debug!(
-                "Musical graph tick count: {}, bar: {}, beat: {}",
-                tick_count, bar, beat
-            );
-        }
+    // Increment our own tick counter
+    let tick_count = musical_tick_count() + 1;
+    MUSICAL_TICK_COUNT.with(|count| count.set(tick_count));
 
-        // Check if we're at the start of a bar (beat 0) and at a multiple of 8 bars
-        // Only trigger on the first tick of the beat (when MUSICAL_TICK_COUNT is divisible by TICKS_PER_BEAT)
-        if beat == 0
-            && bar > 0
-            && TRIGGER_EVERY_N_BARS > 0
-            && MUSICAL_TICK_COUNT.is_multiple_of(TICKS_PER_BEAT)
-        {
-            debug!("Middle C triggered at musical bar: {}, beat: {}", bar, beat);
-            middle_c_triggered = true;
-        }
+    // Calculate musical bar and beat (1-indexed) in the current meter
+    let position = shared_state.meter.position(tick_count);
+
+    // Add info logging once per beat
+    if position.tick_in_beat == 0 {
+        debug!(
+            "Musical graph tick count: {}, bar: {}, beat: {}",
+            tick_count, position.bar, position.beat
+        );
+    }
+
+    // Only trigger on the first tick of the bar
+    if position.is_downbeat() && TRIGGER_EVERY_N_BARS > 0 {
+        debug!(
+            "Middle C triggered at musical bar: {}, beat: {}",
+            position.bar, position.beat
+        );
+        middle_c_triggered = true;
     }
 
     middle_c_triggered
@@ -56,14 +61,19 @@ pub fn process_tick(shared_state: &mut state::SharedState) -> bool {
 
 /// Reset the musical tick count, should be called when transport is stopped
 pub fn reset_musical_tick_count() {
-    unsafe {
-        MUSICAL_TICK_COUNT = 0;
-    }
+    MUSICAL_TICK_COUNT.with(|count| count.set(0));
+}
+
+/// Move the musical tick count to match a transport locate
+pub fn set_musical_tick_count(tick: u64) {
+    MUSICAL_TICK_COUNT.with(|count| count.set(tick));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::TICKS_PER_BEAT;
+    use crate::meter::{MeterMap, TimeSignature};
     use crate::state::SharedState;
     use crate::state::TransportState;
 
@@ -75,16 +85,15 @@ mod tests {
             current_beat: 0,
             current_bar: 8, // 8 is a multiple of 8 and > 0.
             bpm: 120,
-            recording: false,
-            recording_target: None,
             transport_state: TransportState::Stopped,
+            ..SharedState::new(120)
         };
 
         // Call process_tick; this should not trigger Middle C because transport is stopped
         let triggered = process_tick(&mut state);
 
         // Verify Middle C was not triggered because transport is stopped
-        assert_eq!(triggered, false);
+        assert!(!triggered);
         assert_eq!(state.current_bar, 8);
         assert_eq!(state.current_beat, 0);
     }
@@ -100,31 +109,28 @@ mod tests {
             current_beat: 0,
             current_bar: 0,
             bpm: 120,
-            recording: false,
-            recording_target: None,
             transport_state: TransportState::Playing,
+            ..SharedState::new(120)
         };
 
         let mut trigger_count = 0;
 
-        // Simulate ticks for 8 bars (8 bars * 4 beats * 24 ticks = 768 ticks)
+        // Simulate ticks for 8 bars
         // This should trigger Middle C on every bar
-        for _ in 0..768 {
+        for _ in 0..(8 * TimeSignature::default().ticks_per_bar()) {
             let triggered = process_tick(&mut state);
             if triggered {
                 trigger_count += 1;
 
-                // Verify it only triggers at the expected position (beat 0, first tick)
-                unsafe {
-                    let beat = (MUSICAL_TICK_COUNT / TICKS_PER_BEAT) % BEATS_PER_BAR;
-
-                    assert_eq!(beat, 0, "Middle C should only trigger on beat 0");
-                    assert_eq!(
-                        MUSICAL_TICK_COUNT % TICKS_PER_BEAT,
-                        0,
-                        "Middle C should only trigger on the first tick of the beat"
-                    );
-                }
+                // Verify it only triggers at the expected position (beat 1, first tick)
+                let beat = state.meter.position(musical_tick_count()).beat;
+
+                assert_eq!(beat, 1, "Middle C should only trigger on beat 1");
+                assert_eq!(
+                    musical_tick_count() % TICKS_PER_BEAT,
+                    0,
+                    "Middle C should only trigger on the first tick of the beat"
+                );
             }
         }
 
@@ -134,4 +140,27 @@ mod tests {
             "Middle C should be triggered on every bar"
         );
     }
+
+    #[test]
+    fn test_middle_c_follows_odd_meter() {
+        reset_musical_tick_count();
+
+        let mut state = SharedState {
+            transport_state: TransportState::Playing,
+            meter: MeterMap::new(TimeSignature::new(7, 8).unwrap()),
+            ..SharedState::new(120)
+        };
+
+        // Four bars of 7/8 are 14 quarter notes, not 16
+        let bar = state.meter.position(0).signature.ticks_per_bar();
+        let mut triggers = Vec::new();
+        for _ in 0..(4 * bar) {
+            if process_tick(&mut state) {
+                triggers.push(musical_tick_count());
+            }
+        }
+
+        assert_eq!(triggers, vec![bar, 2 * bar, 3 * bar, 4 * bar]);
+        assert_eq!(bar, TICKS_PER_BEAT * 7 / 2);
+    }
 }
@@ -0,0 +1,432 @@
+// output_worker.rs
+
+use crate::reconnect::ConnectionState;
+use log::{error, info, warn};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Messages queued per port before the overflow policy applies.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+// Warn about the first message a full queue drops and every this many after
+const DROP_WARNING_EVERY: u64 = 100;
+// A message that waited longer than this for its port counts as late
+const LATE_AFTER: Duration = Duration::from_millis(5);
+// Longest an idle worker goes without maintaining its port
+const MAINTAIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Writes a port's messages on its worker thread.
+pub trait PortWriter: Send + 'static {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String>;
+
+    /// Called between writes at least every `MAINTAIN_INTERVAL`, e.g. to
+    /// notice an unplugged device or find it again.
+    fn maintain(&mut self, _now: Instant) {}
+
+    /// Where the writer's device connection stands, if it has one.
+    fn connection(&self) -> Option<ConnectionState> {
+        None
+    }
+}
+
+impl<F> PortWriter for F
+where
+    F: FnMut(&[u8]) -> Result<(), String> + Send + 'static,
+{
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self(bytes)
+    }
+}
+
+/// What happens to a message sent while its port's queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Make room by dropping the message that has waited longest
+    DropOldest,
+    /// Drop the message being sent
+    #[default]
+    DropNewest,
+    /// Wait for room, stalling the engine like a direct send would
+    Block,
+}
+
+impl OverflowPolicy {
+    /// Parses `drop-oldest`, `drop-newest` or `block`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "drop-oldest" => Some(OverflowPolicy::DropOldest),
+            "drop-newest" => Some(OverflowPolicy::DropNewest),
+            "block" => Some(OverflowPolicy::Block),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OverflowPolicy::DropOldest => "drop-oldest",
+            OverflowPolicy::DropNewest => "drop-newest",
+            OverflowPolicy::Block => "block",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Message counts for one port, or summed over several.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputStats {
+    pub sent: u64,
+    pub errors: u64,
+    // Lost to a full queue
+    pub dropped: u64,
+    // Sent, but after waiting longer than `LATE_AFTER`
+    pub late: u64,
+}
+
+impl std::ops::AddAssign for OutputStats {
+    fn add_assign(&mut self, other: Self) {
+        self.sent += other.sent;
+        self.errors += other.errors;
+        self.dropped += other.dropped;
+        self.late += other.late;
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QueueError {
+    /// The queue was full and the message was dropped
+    Full,
+    /// The worker has stopped
+    Closed,
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::Full => write!(f, "output queue full, message dropped"),
+            QueueError::Closed => write!(f, "output worker stopped"),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+struct Queued {
+    bytes: Vec<u8>,
+    queued_at: Instant,
+}
+
+struct QueueState {
+    queue: VecDeque<Queued>,
+    // Set while the worker is writing a message it has taken off the queue
+    writing: bool,
+    closed: bool,
+    stats: OutputStats,
+    last_error: Option<String>,
+    connection: Option<ConnectionState>,
+}
+
+struct Shared {
+    port: String,
+    state: Mutex<QueueState>,
+    // Signalled whenever the queue, `writing` or `closed` changes
+    changed: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+/// Writes one port's messages on a thread of its own, so a slow or stuck
+/// device holds up only its own queue and never the engine.
+///
+/// The engine hands over encoded messages through a bounded queue; when it
+/// is full the overflow policy decides what gives. Dropping the worker lets
+/// it finish what is queued and then closes the port.
+pub struct OutputWorker {
+    shared: Arc<Shared>,
+}
+
+impl OutputWorker {
+    /// Starts a worker for the port `name` that writes with `writer`.
+    pub fn spawn<W: PortWriter>(
+        name: &str,
+        capacity: usize,
+        policy: OverflowPolicy,
+        writer: W,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(QueueState {
+                queue: VecDeque::with_capacity(capacity),
+                writing: false,
+                closed: false,
+                stats: OutputStats::default(),
+                last_error: None,
+                connection: writer.connection(),
+            }),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+            port: name.to_string(),
+        });
+        // Not joined: a stuck device would hang whoever waited for it
+        let worker_shared = Arc::clone(&shared);
+        thread::Builder::new()
+            .name(format!("midi-out {}", name))
+            .spawn(move || run_worker(&worker_shared, writer))
+            .expect("Failed to spawn MIDI output thread");
+        OutputWorker { shared }
+    }
+
+    /// Queues a message for the port, applying the overflow policy if the
+    /// queue is full.
+    pub fn send(&self, bytes: Vec<u8>) -> Result<(), QueueError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if self.shared.policy == OverflowPolicy::Block {
+            state = self
+                .shared
+                .changed
+                .wait_while(state, |s| {
+                    !s.closed && s.queue.len() >= self.shared.capacity
+                })
+                .unwrap();
+        }
+        if state.closed {
+            return Err(QueueError::Closed);
+        }
+        if state.queue.len() >= self.shared.capacity {
+            state.stats.dropped += 1;
+            if state.stats.dropped % DROP_WARNING_EVERY == 1 {
+                warn!(
+                    "MIDI output '{}' queue full, {} messages dropped so far",
+                    self.shared.port, state.stats.dropped
+                );
+            }
+            match self.shared.policy {
+                OverflowPolicy::DropNewest | OverflowPolicy::Block => return Err(QueueError::Full),
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                }
+            }
+        }
+        state.queue.push_back(Queued {
+            bytes,
+            queued_at: Instant::now(),
+        });
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for everything queued to be written. Returns
+    /// false if the port did not catch up in time.
+    pub fn flush(&self, timeout: Duration) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        let (state, _) = self
+            .shared
+            .changed
+            .wait_timeout_while(state, timeout, |s| {
+                !s.closed && (s.writing || !s.queue.is_empty())
+            })
+            .unwrap();
+        !state.writing && state.queue.is_empty()
+    }
+
+    pub fn stats(&self) -> OutputStats {
+        self.shared.state.lock().unwrap().stats
+    }
+
+    /// The most recent write error, if the port has had one.
+    pub fn last_error(&self) -> Option<String> {
+        self.shared.state.lock().unwrap().last_error.clone()
+    }
+
+    /// Where the port's device connection stands, if it has one.
+    pub fn connection(&self) -> Option<ConnectionState> {
+        self.shared.state.lock().unwrap().connection.clone()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.shared.state.lock().unwrap().queue.len()
+    }
+}
+
+impl Drop for OutputWorker {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+fn run_worker<W: PortWriter>(shared: &Shared, mut writer: W) {
+    loop {
+        let (mut state, _) = shared
+            .changed
+            .wait_timeout_while(shared.state.lock().unwrap(), MAINTAIN_INTERVAL, |s| {
+                !s.closed && s.queue.is_empty()
+            })
+            .unwrap();
+        let message = state.queue.pop_front();
+        if message.is_none() && state.closed {
+            break;
+        }
+        state.writing = message.is_some();
+        drop(state);
+        shared.changed.notify_all();
+
+        writer.maintain(Instant::now());
+        let written = message.map(|message| {
+            let waited = message.queued_at.elapsed();
+            (waited, writer.write(&message.bytes))
+        });
+
+        let mut state = shared.state.lock().unwrap();
+        state.writing = false;
+        state.connection = writer.connection();
+        if let Some((waited, result)) = written {
+            record_write(shared, &mut state, waited, result);
+        }
+        drop(state);
+        shared.changed.notify_all();
+    }
+    info!("MIDI output worker for '{}' stopped", shared.port);
+}
+
+fn record_write(
+    shared: &Shared,
+    state: &mut QueueState,
+    waited: Duration,
+    result: Result<(), String>,
+) {
+    match result {
+        Ok(()) => {
+            state.stats.sent += 1;
+            if waited > LATE_AFTER {
+                state.stats.late += 1;
+            }
+        }
+        Err(e) => {
+            if state.last_error.as_deref() != Some(e.as_str()) {
+                error!("Failed to send MIDI message to '{}': {}", shared.port, e);
+            }
+            state.stats.errors += 1;
+            state.last_error = Some(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    type Writer = Box<dyn FnMut(&[u8]) -> Result<(), String> + Send>;
+
+    // A port that writes into a channel, held up until `release` is sent
+    // to. Writing 0xFF fails as if the device had gone.
+    fn stalled_port() -> (Writer, mpsc::Sender<()>, mpsc::Receiver<Vec<u8>>) {
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (written_tx, written_rx) = mpsc::channel();
+        let write = move |bytes: &[u8]| {
+            release_rx.recv().map_err(|e| e.to_string())?;
+            if bytes == [0xFF] {
+                return Err("device gone".to_string());
+            }
+            written_tx.send(bytes.to_vec()).map_err(|e| e.to_string())
+        };
+        (Box::new(write), release_tx, written_rx)
+    }
+
+    // Lets the worker take the first message, so it sits in `write`
+    fn wait_for_writer(worker: &OutputWorker) {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while worker.queued() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_policy_parses() {
+        assert_eq!(
+            OverflowPolicy::parse("drop-oldest"),
+            Some(OverflowPolicy::DropOldest)
+        );
+        assert_eq!(OverflowPolicy::parse("Block"), Some(OverflowPolicy::Block));
+        assert_eq!(OverflowPolicy::parse("drop"), None);
+        assert_eq!(OverflowPolicy::default().to_string(), "drop-newest");
+    }
+
+    #[test]
+    fn test_drop_newest_keeps_what_was_queued() {
+        let (write, release, written) = stalled_port();
+        let worker = OutputWorker::spawn("test", 2, OverflowPolicy::DropNewest, write);
+        worker.send(vec![1]).unwrap();
+        wait_for_writer(&worker);
+
+        worker.send(vec![2]).unwrap();
+        worker.send(vec![3]).unwrap();
+        assert_eq!(worker.send(vec![4]), Err(QueueError::Full));
+
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        assert!(worker.flush(Duration::from_secs(1)));
+        assert_eq!(written.try_iter().collect::<Vec<_>>(), [[1], [2], [3]]);
+        let stats = worker.stats();
+        assert_eq!((stats.sent, stats.dropped), (3, 1));
+    }
+
+    #[test]
+    fn test_drop_oldest_makes_room() {
+        let (write, release, written) = stalled_port();
+        let worker = OutputWorker::spawn("test", 2, OverflowPolicy::DropOldest, write);
+        worker.send(vec![1]).unwrap();
+        wait_for_writer(&worker);
+
+        for byte in 2..=4 {
+            worker.send(vec![byte]).unwrap();
+        }
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        assert!(worker.flush(Duration::from_secs(1)));
+        assert_eq!(written.try_iter().collect::<Vec<_>>(), [[1], [3], [4]]);
+        assert_eq!(worker.stats().dropped, 1);
+    }
+
+    #[test]
+    fn test_stuck_port_neither_blocks_sender_nor_flush() {
+        let (write, _release, _written) = stalled_port();
+        let worker = OutputWorker::spawn("test", 1, OverflowPolicy::DropNewest, write);
+        worker.send(vec![1]).unwrap();
+        wait_for_writer(&worker);
+        worker.send(vec![2]).unwrap();
+
+        let start = Instant::now();
+        assert_eq!(worker.send(vec![3]), Err(QueueError::Full));
+        assert!(!worker.flush(Duration::from_millis(20)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_late_messages_and_errors_are_counted() {
+        let (write, release, _written) = stalled_port();
+        let worker = OutputWorker::spawn("test", 8, OverflowPolicy::Block, write);
+        worker.send(vec![0xFF]).unwrap();
+        wait_for_writer(&worker);
+
+        // Both wait out the failing write ahead of them
+        worker.send(vec![0x90]).unwrap();
+        worker.send(vec![0x80]).unwrap();
+        thread::sleep(LATE_AFTER * 2);
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        assert!(worker.flush(Duration::from_secs(1)));
+
+        let stats = worker.stats();
+        assert_eq!((stats.sent, stats.late, stats.errors), (2, 2, 1));
+        assert_eq!(worker.last_error().as_deref(), Some("device gone"));
+    }
+}
@@ -0,0 +1,194 @@
+// reconnect.rs
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How often to look for a device that went away or was never found, and
+/// for how long.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    pub interval: Duration,
+    // Attempts to connect before giving up; None keeps trying
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            interval: Duration::from_secs(2),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Where a device's connection stands.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    /// Connected to the named port
+    Connected(String),
+    /// Not connected; another attempt follows after the interval
+    Disconnected { attempts: u32 },
+    /// Not connected, and no more attempts will be made
+    GaveUp { attempts: u32 },
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, ConnectionState::Connected(_))
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Connected(port) => write!(f, "connected to {}", port),
+            ConnectionState::Disconnected { attempts: 0 } => write!(f, "disconnected"),
+            ConnectionState::Disconnected { attempts } => {
+                write!(f, "reconnecting ({} failed attempts)", attempts)
+            }
+            ConnectionState::GaveUp { attempts } => {
+                write!(f, "gave up after {} attempts", attempts)
+            }
+        }
+    }
+}
+
+/// The connection state of one device, as shown in the TUI and `/status`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceStatus {
+    // What the device is used for: "clock", "timecode" or an output's name
+    pub role: String,
+    // Name the device is found by; any port containing it matches
+    pub device: String,
+    pub input: bool,
+    pub state: ConnectionState,
+}
+
+/// Paces the attempts to connect to a device.
+///
+/// The first attempt is due at once. After a failure, or after the device
+/// goes away, the next waits out the policy's interval.
+#[derive(Debug)]
+pub struct Reconnector {
+    policy: ReconnectPolicy,
+    attempts: u32,
+    next_attempt: Instant,
+    state: ConnectionState,
+}
+
+impl Reconnector {
+    pub fn new(policy: ReconnectPolicy, now: Instant) -> Self {
+        Reconnector {
+            policy,
+            attempts: 0,
+            next_attempt: now,
+            state: ConnectionState::Disconnected { attempts: 0 },
+        }
+    }
+
+    pub fn policy(&self) -> ReconnectPolicy {
+        self.policy
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// True when another attempt to connect should be made now.
+    pub fn due(&self, now: Instant) -> bool {
+        matches!(self.state, ConnectionState::Disconnected { .. }) && now >= self.next_attempt
+    }
+
+    pub fn connected(&mut self, port: String) {
+        self.attempts = 0;
+        self.state = ConnectionState::Connected(port);
+    }
+
+    /// Records a failed attempt, giving up once the policy's attempts are
+    /// used.
+    pub fn failed(&mut self, now: Instant) {
+        self.attempts += 1;
+        self.wait_or_give_up(now);
+    }
+
+    /// The connected device went away; look for it again after the
+    /// interval.
+    pub fn lost(&mut self, now: Instant) {
+        self.attempts = 0;
+        self.wait_or_give_up(now);
+    }
+
+    fn wait_or_give_up(&mut self, now: Instant) {
+        let attempts = self.attempts;
+        self.next_attempt = now + self.policy.interval;
+        self.state = match self.policy.max_attempts {
+            Some(max) if attempts >= max => ConnectionState::GaveUp { attempts },
+            _ => ConnectionState::Disconnected { attempts },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(2);
+
+    fn policy(max_attempts: Option<u32>) -> ReconnectPolicy {
+        ReconnectPolicy {
+            interval: INTERVAL,
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn test_attempts_wait_out_the_interval() {
+        let start = Instant::now();
+        let mut reconnector = Reconnector::new(policy(None), start);
+        assert!(reconnector.due(start));
+
+        reconnector.failed(start);
+        assert!(!reconnector.due(start + INTERVAL / 2));
+        assert!(reconnector.due(start + INTERVAL));
+        assert_eq!(
+            reconnector.state(),
+            &ConnectionState::Disconnected { attempts: 1 }
+        );
+
+        reconnector.connected("OXI ONE MIDI 1".to_string());
+        assert!(!reconnector.due(start + INTERVAL * 10));
+        assert!(reconnector.state().is_connected());
+
+        // A lost device starts the count again
+        let unplugged = start + INTERVAL * 10;
+        reconnector.lost(unplugged);
+        assert!(!reconnector.due(unplugged));
+        assert!(reconnector.due(unplugged + INTERVAL));
+        assert_eq!(reconnector.state().to_string(), "disconnected");
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let start = Instant::now();
+        let mut reconnector = Reconnector::new(policy(Some(2)), start);
+        reconnector.failed(start);
+        reconnector.failed(start + INTERVAL);
+        assert_eq!(
+            reconnector.state(),
+            &ConnectionState::GaveUp { attempts: 2 }
+        );
+        assert!(!reconnector.due(start + INTERVAL * 100));
+    }
+
+    #[test]
+    fn test_no_attempts_means_never_reconnect() {
+        let start = Instant::now();
+        let mut reconnector = Reconnector::new(policy(Some(0)), start);
+        reconnector.connected("TR-8".to_string());
+        reconnector.lost(start);
+        assert_eq!(
+            reconnector.state(),
+            &ConnectionState::GaveUp { attempts: 0 }
+        );
+    }
+}
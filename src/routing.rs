@@ -0,0 +1,259 @@
+// routing.rs
+
+use crate::midi_output::MidiMessage;
+use std::fmt;
+
+/// Name of the output opened with `--midi-output`, or the first available
+/// port. Unrouted messages go here.
+pub const PRIMARY_PORT: &str = "main";
+
+/// A named output destination, e.g. `bass=Minilogue`. Routes refer to the
+/// port by its name; it connects to the first device port whose name
+/// contains `device`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputPortSpec {
+    pub name: String,
+    pub device: String,
+}
+
+impl OutputPortSpec {
+    /// Parses `NAME=DEVICE`.
+    pub fn parse(spec: &str) -> Result<Self, RoutingError> {
+        let invalid = || RoutingError::Port(spec.to_string());
+        let (name, device) = spec.split_once('=').ok_or_else(invalid)?;
+        let (name, device) = (name.trim(), device.trim());
+        if name.is_empty() || device.is_empty() || name.contains(':') {
+            return Err(invalid());
+        }
+        Ok(OutputPortSpec {
+            name: name.to_string(),
+            device: device.to_string(),
+        })
+    }
+}
+
+/// What a route picks up: every message of a musical graph track, or every
+/// message on a MIDI channel. Tracks count from 1; channels are 0-15.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteSource {
+    Track(u16),
+    Channel(u8),
+}
+
+/// Sends a track or channel to a named port, optionally moving it to
+/// another channel there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub source: RouteSource,
+    pub port: String,
+    pub channel: Option<u8>,
+}
+
+impl Route {
+    /// Parses `track:N=PORT[:CHANNEL]` or `ch:N=PORT[:CHANNEL]`, with
+    /// channels written 1-16 as on the front panel of a synth.
+    pub fn parse(spec: &str) -> Result<Self, RoutingError> {
+        let invalid = || RoutingError::Route(spec.to_string());
+        let (source, destination) = spec.split_once('=').ok_or_else(invalid)?;
+        let source = match source.trim().split_once(':').ok_or_else(invalid)? {
+            ("track", track) => {
+                RouteSource::Track(track.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?)
+            }
+            ("ch" | "channel", channel) => {
+                RouteSource::Channel(parse_channel(channel).ok_or_else(invalid)?)
+            }
+            _ => return Err(invalid()),
+        };
+        let (port, channel) = match destination.trim().split_once(':') {
+            Some((port, channel)) => (port, Some(parse_channel(channel).ok_or_else(invalid)?)),
+            None => (destination.trim(), None),
+        };
+        if port.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Route {
+            source,
+            port: port.to_string(),
+            channel,
+        })
+    }
+
+    // The message as it arrives at the route's port
+    fn apply(&self, message: &MidiMessage) -> MidiMessage {
+        let mut message = message.clone();
+        if let Some(channel) = self.channel {
+            message.set_channel(channel);
+        }
+        message
+    }
+}
+
+// A 1-16 channel number as the 0-15 MIDI uses on the wire
+fn parse_channel(channel: &str) -> Option<u8> {
+    let channel: u8 = channel.trim().parse().ok()?;
+    (1..=16).contains(&channel).then(|| channel - 1)
+}
+
+/// Decides which ports each outgoing message goes to.
+///
+/// Routes for a message's track win over routes for its channel, and every
+/// matching route of the winning kind gets a copy, so one part can be
+/// layered across several synths. Messages no route picks up, and those
+/// without a channel such as timecode, go to the primary port unchanged.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub fn new(routes: Vec<Route>) -> Self {
+        RoutingTable { routes }
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// The port names and messages to send for `message` from `track`.
+    pub fn resolve(&self, track: Option<u16>, message: &MidiMessage) -> Vec<(&str, MidiMessage)> {
+        let Some(channel) = message.channel() else {
+            return vec![(PRIMARY_PORT, message.clone())];
+        };
+        let by_track: Vec<&Route> = self
+            .routes
+            .iter()
+            .filter(|route| track.is_some_and(|t| route.source == RouteSource::Track(t)))
+            .collect();
+        let matching = if by_track.is_empty() {
+            self.routes
+                .iter()
+                .filter(|route| route.source == RouteSource::Channel(channel))
+                .collect()
+        } else {
+            by_track
+        };
+        if matching.is_empty() {
+            return vec![(PRIMARY_PORT, message.clone())];
+        }
+        matching
+            .into_iter()
+            .map(|route| (route.port.as_str(), route.apply(message)))
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RoutingError {
+    Port(String),
+    Route(String),
+    Input(String),
+    Thru(String),
+}
+
+impl fmt::Display for RoutingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoutingError::Port(spec) => {
+                write!(f, "invalid output '{}' (expected NAME=DEVICE)", spec)
+            }
+            RoutingError::Route(spec) => write!(
+                f,
+                "invalid route '{}' (expected track:N=PORT[:CHANNEL] or ch:N=PORT[:CHANNEL])",
+                spec
+            ),
+            RoutingError::Input(spec) => write!(
+                f,
+                "invalid input '{}' (expected NAME=DEVICE; clock and timecode are taken)",
+                spec
+            ),
+            RoutingError::Thru(spec) => write!(
+                f,
+                "invalid thru route '{}' (expected INPUT=PORT[:CHANNEL][,ch=N[-M]][,type=KIND[+KIND]][,notes=LOW-HIGH])",
+                spec
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RoutingError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(channel: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel,
+            note: 60,
+            velocity: 100,
+            duration_ticks: 0,
+        }
+    }
+
+    #[test]
+    fn test_parses_ports_and_routes() {
+        assert_eq!(
+            OutputPortSpec::parse("bass = Minilogue xd"),
+            Ok(OutputPortSpec {
+                name: "bass".to_string(),
+                device: "Minilogue xd".to_string(),
+            })
+        );
+        assert!(OutputPortSpec::parse("Minilogue").is_err());
+        assert!(OutputPortSpec::parse("a:b=Minilogue").is_err());
+
+        assert_eq!(
+            Route::parse("track:2=bass:3"),
+            Ok(Route {
+                source: RouteSource::Track(2),
+                port: "bass".to_string(),
+                channel: Some(2),
+            })
+        );
+        assert_eq!(
+            Route::parse("ch:10=drums"),
+            Ok(Route {
+                source: RouteSource::Channel(9),
+                port: "drums".to_string(),
+                channel: None,
+            })
+        );
+        assert!(Route::parse("track:0=bass").is_err());
+        assert!(Route::parse("ch:17=bass").is_err());
+        assert!(Route::parse("bus:1=bass").is_err());
+        assert!(Route::parse("ch:1=bass:0").is_err());
+        assert!(Route::parse("ch:1=").is_err());
+    }
+
+    #[test]
+    fn test_track_routes_win_over_channel_routes() {
+        let table = RoutingTable::new(vec![
+            Route::parse("ch:2=keys").unwrap(),
+            Route::parse("track:1=bass:5").unwrap(),
+            Route::parse("track:1=lead").unwrap(),
+        ]);
+
+        assert_eq!(
+            table.resolve(Some(1), &note_on(1)),
+            vec![("bass", note_on(4)), ("lead", note_on(1))]
+        );
+        assert_eq!(
+            table.resolve(Some(2), &note_on(1)),
+            vec![("keys", note_on(1))]
+        );
+        assert_eq!(table.resolve(None, &note_on(1)), vec![("keys", note_on(1))]);
+    }
+
+    #[test]
+    fn test_unrouted_messages_go_to_the_primary_port() {
+        let table = RoutingTable::new(vec![Route::parse("track:1=bass").unwrap()]);
+        assert_eq!(
+            table.resolve(None, &note_on(0)),
+            vec![(PRIMARY_PORT, note_on(0))]
+        );
+        assert_eq!(
+            table.resolve(Some(1), &MidiMessage::MtcQuarterFrame(0x21)),
+            vec![(PRIMARY_PORT, MidiMessage::MtcQuarterFrame(0x21))]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+// scheduler.rs
+
+use std::collections::{BTreeMap, HashMap};
+
+/// Identifies a scheduled event so it can be cancelled before it is due.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EventHandle(u64);
+
+/// Events ordered by the tick they are due on.
+///
+/// Events due on the same tick come out in the order they were scheduled.
+/// Draining takes everything due up to a tick, so an event whose exact tick
+/// was skipped still goes out on the next drain.
+#[derive(Debug)]
+pub struct EventQueue<T> {
+    // Keyed by (tick, sequence) so equal ticks keep insertion order
+    events: BTreeMap<(u64, u64), T>,
+    // Due tick of each pending sequence number, for cancelling
+    due: HashMap<u64, u64>,
+    next_sequence: u64,
+}
+
+impl<T> Default for EventQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> EventQueue<T> {
+    pub fn new() -> Self {
+        EventQueue {
+            events: BTreeMap::new(),
+            due: HashMap::new(),
+            next_sequence: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn schedule(&mut self, tick: u64, event: T) -> EventHandle {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.events.insert((tick, sequence), event);
+        self.due.insert(sequence, tick);
+        EventHandle(sequence)
+    }
+
+    /// Removes a pending event, returning it if it had not gone out yet.
+    pub fn cancel(&mut self, handle: EventHandle) -> Option<T> {
+        let tick = self.due.remove(&handle.0)?;
+        self.events.remove(&(tick, handle.0))
+    }
+
+    /// The tick a pending event is due on.
+    pub fn due_tick(&self, handle: EventHandle) -> Option<u64> {
+        self.due.get(&handle.0).copied()
+    }
+
+    /// The tick the earliest pending event is due on.
+    pub fn next_tick(&self) -> Option<u64> {
+        self.events.keys().next().map(|(tick, _)| *tick)
+    }
+
+    /// Removes and returns every event due on or before `tick`, in order.
+    pub fn drain_until(&mut self, tick: u64) -> Vec<T> {
+        let later = match tick.checked_add(1) {
+            Some(after) => self.events.split_off(&(after, 0)),
+            None => BTreeMap::new(),
+        };
+        let due = std::mem::replace(&mut self.events, later);
+        due.into_iter()
+            .map(|((_, sequence), event)| {
+                self.due.remove(&sequence);
+                event
+            })
+            .collect()
+    }
+
+    /// Removes and returns every pending event, in order.
+    pub fn drain_all(&mut self) -> Vec<T> {
+        self.drain_until(u64::MAX)
+    }
+
+    /// Moves every pending event by `offset` ticks, e.g. to follow a
+    /// locate. Events pushed before tick 0 fall due on tick 0.
+    pub fn shift(&mut self, offset: i64) {
+        let events = std::mem::take(&mut self.events);
+        self.events = events
+            .into_iter()
+            .map(|((tick, sequence), event)| {
+                let tick = tick.saturating_add_signed(offset);
+                self.due.insert(sequence, tick);
+                ((tick, sequence), event)
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain_returns_everything_due_in_order() {
+        let mut queue = EventQueue::new();
+        queue.schedule(10, "c");
+        queue.schedule(4, "a");
+        queue.schedule(10, "d");
+        queue.schedule(7, "b");
+        queue.schedule(11, "e");
+
+        // Ticks 4 and 7 were never drained exactly but still go out
+        assert_eq!(queue.drain_until(10), vec!["a", "b", "c", "d"]);
+        assert_eq!(queue.next_tick(), Some(11));
+        assert!(queue.drain_until(10).is_empty());
+        assert_eq!(queue.drain_all(), vec!["e"]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_removes_only_that_event() {
+        let mut queue = EventQueue::new();
+        let first = queue.schedule(5, 1);
+        let second = queue.schedule(5, 2);
+
+        assert_eq!(queue.cancel(first), Some(1));
+        assert_eq!(queue.cancel(first), None);
+        assert_eq!(queue.due_tick(second), Some(5));
+        assert_eq!(queue.drain_until(5), vec![2]);
+
+        // A handle for an event that already went out cancels nothing
+        assert_eq!(queue.cancel(second), None);
+    }
+
+    #[test]
+    fn test_shift_moves_events_and_their_handles() {
+        let mut queue = EventQueue::new();
+        let late = queue.schedule(100, "late");
+        queue.schedule(20, "early");
+
+        queue.shift(-50);
+        assert_eq!(queue.due_tick(late), Some(50));
+        assert_eq!(queue.next_tick(), Some(0));
+        assert_eq!(queue.drain_until(0), vec!["early"]);
+
+        queue.shift(10);
+        assert_eq!(queue.cancel(late), Some("late"));
+        assert_eq!(queue.len(), 0);
+    }
+}
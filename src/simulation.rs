@@ -0,0 +1,488 @@
+// simulation.rs
+
+use crate::clock::{ClockSource, ClockThreads, RunFlag, TempoHandle, TickDeadlines};
+use crate::config::TICKS_PER_CLOCK;
+use crate::error::MidiError;
+use crate::event_loop::{EngineMessage, EventLoop, TransportAction};
+use crate::meter::MeterMap;
+use crate::midi_output::{MidiMessage, MidiOutputManager};
+use crate::state::SharedState;
+use crate::tempo_map::TempoMap;
+use log::{error, info};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A time base that only moves when told to. Clones share the same clock,
+/// so a test and the simulated clock it drives always agree on "now".
+#[derive(Clone)]
+pub struct VirtualTime {
+    origin: Instant,
+    elapsed_ns: Arc<AtomicU64>,
+}
+
+impl Default for VirtualTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualTime {
+    pub fn new() -> Self {
+        VirtualTime {
+            origin: Instant::now(),
+            elapsed_ns: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The current virtual instant, usable wherever the engine expects an
+    /// `Instant`.
+    pub fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_ns.load(Ordering::Relaxed))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.elapsed_ns
+            .fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Moves time forward to `instant`. Time never runs backwards, so an
+    /// instant in the past leaves it unchanged.
+    pub fn advance_to(&self, instant: Instant) {
+        let target_ns = instant.saturating_duration_since(self.origin).as_nanos() as u64;
+        self.elapsed_ns.fetch_max(target_ns, Ordering::Relaxed);
+    }
+}
+
+/// How a simulated clock advances once started.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimulationMode {
+    /// Only ticks when `step` is called.
+    Stepped,
+    /// Emits the given number of ticks as fast as the engine accepts them.
+    FreeRunning { ticks: u64 },
+}
+
+/// A clock source on virtual time. Each MIDI clock tick jumps the time
+/// base to the tick's deadline and is sent as `TickAt`, so the engine sees
+/// perfectly regular timestamps no matter how fast the simulation runs.
+pub struct SimulatedClock {
+    ticker: Arc<Mutex<SimulatedTicker>>,
+    mode: SimulationMode,
+    threads: ClockThreads,
+}
+
+struct SimulatedTicker {
+    deadlines: TickDeadlines,
+    time: VirtualTime,
+    tempo: TempoHandle,
+    tick_tx: Sender<EngineMessage>,
+    tick_count: u64,
+}
+
+impl SimulatedTicker {
+    /// Advances time to the next deadline and emits a tick. Returns false
+    /// once the engine has gone away.
+    fn step(&mut self) -> bool {
+        let bpm = self.tempo.bpm();
+        if bpm != self.deadlines.bpm() {
+            self.deadlines.set_bpm(self.tick_count, bpm);
+        }
+
+        self.tick_count += 1;
+        let deadline = self.deadlines.deadline(self.tick_count);
+        self.time.advance_to(deadline);
+
+        if let Err(e) = self.tick_tx.send(EngineMessage::TickAt(deadline)) {
+            error!("Simulated clock lost the engine channel: {}", e);
+            return false;
+        }
+        true
+    }
+
+    /// Steps up to `ticks` times, returning how many ticks were delivered.
+    fn run(&mut self, ticks: u64) -> u64 {
+        (0..ticks).take_while(|_| self.step()).count() as u64
+    }
+}
+
+/// Free-running thread body; takes the lock per tick so a stop is noticed
+/// between any two ticks.
+fn run_until_stopped(ticker: &Mutex<SimulatedTicker>, ticks: u64, running: &RunFlag) {
+    for _ in 0..ticks {
+        if !running.is_running() || !ticker.lock().unwrap().step() {
+            return;
+        }
+    }
+}
+
+impl SimulatedClock {
+    pub fn new(
+        time: VirtualTime,
+        tempo: TempoHandle,
+        tick_tx: Sender<EngineMessage>,
+        mode: SimulationMode,
+    ) -> Self {
+        let ticker = SimulatedTicker {
+            deadlines: TickDeadlines::new(time.now(), tempo.bpm()),
+            time,
+            tempo,
+            tick_tx,
+            tick_count: 0,
+        };
+        SimulatedClock {
+            ticker: Arc::new(Mutex::new(ticker)),
+            mode,
+            threads: ClockThreads::default(),
+        }
+    }
+
+    /// Emits the next tick. Returns false once the engine has gone away.
+    pub fn step(&self) -> bool {
+        self.ticker.lock().unwrap().step()
+    }
+
+    /// Emits up to `ticks` ticks, returning how many were delivered.
+    pub fn step_ticks(&self, ticks: u64) -> u64 {
+        self.ticker.lock().unwrap().run(ticks)
+    }
+
+    pub fn tick_count(&self) -> u64 {
+        self.ticker.lock().unwrap().tick_count
+    }
+
+    /// When the next tick will be stamped, at the current tempo.
+    pub fn next_tick_at(&self) -> Instant {
+        let ticker = self.ticker.lock().unwrap();
+        ticker.deadlines.deadline(ticker.tick_count + 1)
+    }
+}
+
+impl ClockSource for SimulatedClock {
+    fn start(&self) -> Result<(), MidiError> {
+        let Some(running) = self.threads.begin() else {
+            return Ok(());
+        };
+        match self.mode {
+            SimulationMode::Stepped => info!("Simulated clock ready, stepping manually"),
+            SimulationMode::FreeRunning { ticks } => {
+                info!("Simulated clock running {} ticks", ticks);
+                let ticker = Arc::clone(&self.ticker);
+                self.threads
+                    .spawn(move || run_until_stopped(&ticker, ticks, &running));
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&self) {
+        self.threads.stop();
+    }
+}
+
+/// A MIDI message produced by an offline render, with the tick it was sent
+/// on and its time from the start of the song.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderedEvent {
+    pub tick: u64,
+    pub time: Duration,
+    pub message: MidiMessage,
+}
+
+/// Plays `bars` bars of the song through the engine on virtual time and
+/// returns every MIDI message it sent. Nothing touches a device or sleeps,
+/// so whole songs render in milliseconds.
+pub fn render_offline(
+    bpm: f64,
+    tempo_map: TempoMap,
+    meter: MeterMap,
+    bars: u32,
+) -> Vec<RenderedEvent> {
+    let pulses = meter.bar_start(bars + 1) / TICKS_PER_CLOCK;
+    let shared_state = Arc::new(Mutex::new(SharedState::new(bpm as u32)));
+    {
+        let mut state = shared_state.lock().unwrap();
+        state.tempo_map = tempo_map;
+        state.meter = meter;
+    }
+
+    let (engine_tx, engine_rx) = mpsc::channel();
+    let tempo = TempoHandle::new(bpm);
+    let time = VirtualTime::new();
+    let clock = SimulatedClock::new(
+        time.clone(),
+        tempo.clone(),
+        engine_tx.clone(),
+        SimulationMode::Stepped,
+    );
+
+    let mut engine = EventLoop::new(
+        Arc::clone(&shared_state),
+        engine_rx,
+        Some(MidiOutputManager::capturing()),
+    );
+    engine.set_tempo_handle(tempo);
+    engine.set_recording_enabled(false);
+    engine.handle_message(EngineMessage::TransportCommand(TransportAction::Start));
+
+    let mut rendered = Vec::new();
+    for pulse in 0..pulses {
+        // The engine's ticks between pulses play at their own times
+        let next_pulse = clock.next_tick_at();
+        while let Some(deadline) = engine
+            .next_interpolated_tick()
+            .filter(|deadline| pulse > 0 && *deadline < next_pulse)
+        {
+            time.advance_to(deadline);
+            engine.handle_interpolated_ticks(deadline);
+            capture(&mut engine, &shared_state, &time, &mut rendered);
+        }
+
+        clock.step();
+        engine.handle_pending();
+        capture(&mut engine, &shared_state, &time, &mut rendered);
+    }
+    rendered
+}
+
+/// Moves what the engine sent since the last call into `rendered`.
+fn capture(
+    engine: &mut EventLoop,
+    shared_state: &Arc<Mutex<SharedState>>,
+    time: &VirtualTime,
+    rendered: &mut Vec<RenderedEvent>,
+) {
+    let tick = shared_state.lock().unwrap().get_tick_count();
+    rendered.extend(
+        engine
+            .take_captured_midi()
+            .into_iter()
+            .map(|message| RenderedEvent {
+                tick,
+                time: time.elapsed(),
+                message,
+            }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meter::TimeSignature;
+
+    fn note_ons(events: &[RenderedEvent]) -> Vec<&RenderedEvent> {
+        events
+            .iter()
+            .filter(|event| matches!(event.message, MidiMessage::NoteOn { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn test_virtual_time_only_moves_when_advanced() {
+        let time = VirtualTime::new();
+        let start = time.now();
+        assert_eq!(time.now(), start);
+
+        time.advance(Duration::from_millis(250));
+        assert_eq!(time.now(), start + Duration::from_millis(250));
+
+        // Clones share the same clock, and it never runs backwards
+        let other = time.clone();
+        other.advance_to(start + Duration::from_secs(1));
+        other.advance_to(start);
+        assert_eq!(time.elapsed(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_stepped_clock_stamps_exact_deadlines() {
+        let (tx, rx) = mpsc::channel();
+        let time = VirtualTime::new();
+        let start = time.now();
+        let clock = SimulatedClock::new(
+            time.clone(),
+            TempoHandle::new(120.0),
+            tx,
+            SimulationMode::Stepped,
+        );
+
+        assert_eq!(clock.step_ticks(24), 24);
+        let stamps: Vec<Instant> = rx
+            .try_iter()
+            .map(|message| match message {
+                EngineMessage::TickAt(at) => at,
+                other => panic!("unexpected message {:?}", other),
+            })
+            .collect();
+
+        // 24 ticks at 120 BPM is exactly one half-second beat
+        assert_eq!(stamps.len(), 24);
+        assert_eq!(stamps[23], start + Duration::from_millis(500));
+        assert_eq!(time.now(), stamps[23]);
+    }
+
+    #[test]
+    fn test_stepped_clock_follows_tempo_changes() {
+        let (tx, _rx) = mpsc::channel();
+        let time = VirtualTime::new();
+        let tempo = TempoHandle::new(120.0);
+        let clock = SimulatedClock::new(time.clone(), tempo.clone(), tx, SimulationMode::Stepped);
+
+        clock.step_ticks(24);
+        tempo.set_bpm(60.0);
+        clock.step_ticks(24);
+
+        // Half a second for the first beat, a full second for the next
+        assert_eq!(time.elapsed(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn test_free_running_clock_outpaces_realtime() {
+        let (tx, rx) = mpsc::channel();
+        let time = VirtualTime::new();
+        let clock = SimulatedClock::new(
+            time.clone(),
+            TempoHandle::new(120.0),
+            tx,
+            SimulationMode::FreeRunning { ticks: 960 },
+        );
+
+        let started = Instant::now();
+        clock.start().unwrap();
+        drop(clock);
+        assert_eq!(rx.iter().count(), 960);
+
+        // Ten bars of music, far quicker than the 20 seconds they represent
+        assert_eq!(time.elapsed(), Duration::from_secs(20));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_engine_measures_simulated_tempo_exactly() {
+        let shared_state = Arc::new(Mutex::new(SharedState::new(120)));
+        shared_state.lock().unwrap().transport_state = crate::state::TransportState::Playing;
+        let (tx, rx) = mpsc::channel();
+        let clock = SimulatedClock::new(
+            VirtualTime::new(),
+            TempoHandle::new(90.0),
+            tx,
+            SimulationMode::Stepped,
+        );
+        let mut engine = EventLoop::new(Arc::clone(&shared_state), rx, None);
+
+        clock.step_ticks(96);
+        engine.handle_pending();
+
+        let state = shared_state.lock().unwrap();
+        assert_eq!(state.get_tick_count(), 96 * TICKS_PER_CLOCK);
+        assert_eq!(state.get_bpm(), 90);
+    }
+
+    #[test]
+    fn test_engine_interpolates_ticks_between_pulses() {
+        let shared_state = Arc::new(Mutex::new(SharedState::new(120)));
+        shared_state.lock().unwrap().transport_state = crate::state::TransportState::Playing;
+        let (tx, rx) = mpsc::channel();
+        let time = VirtualTime::new();
+        // 125 BPM gives a 20 ms pulse interval
+        let clock = SimulatedClock::new(
+            time.clone(),
+            TempoHandle::new(125.0),
+            tx,
+            SimulationMode::Stepped,
+        );
+        let mut engine = EventLoop::new(Arc::clone(&shared_state), rx, None);
+
+        clock.step_ticks(2);
+        engine.handle_pending();
+        assert_eq!(
+            engine.next_interpolated_tick(),
+            Some(time.now() + Duration::from_millis(20) / TICKS_PER_CLOCK as u32)
+        );
+
+        // Half way to the next pulse, half of the ticks in between have played
+        time.advance(Duration::from_millis(10));
+        engine.handle_interpolated_ticks(time.now());
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            2 * TICKS_PER_CLOCK + TICKS_PER_CLOCK / 2
+        );
+
+        // and the next pulse lands back on the pulse grid
+        clock.step();
+        engine.handle_pending();
+        assert_eq!(
+            shared_state.lock().unwrap().get_tick_count(),
+            3 * TICKS_PER_CLOCK
+        );
+    }
+
+    #[test]
+    fn test_render_offline_places_notes_on_the_timeline() {
+        let events = render_offline(120.0, TempoMap::default(), MeterMap::default(), 8);
+        let notes = note_ons(&events);
+        assert!(!notes.is_empty());
+
+        // Each 120 BPM bar lasts exactly two seconds
+        let ticks_per_bar = TimeSignature::default().ticks_per_bar();
+        for note in notes {
+            assert_eq!(note.tick % ticks_per_bar, 0);
+            assert_eq!(
+                note.time,
+                Duration::from_secs(2 * note.tick / ticks_per_bar)
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_offline_is_repeatable_and_follows_tempo_map() {
+        let steady = render_offline(120.0, TempoMap::default(), MeterMap::default(), 8);
+        assert_eq!(
+            steady,
+            render_offline(120.0, TempoMap::default(), MeterMap::default(), 8)
+        );
+
+        // Halving the tempo from bar 2 leaves the notes on the same ticks but
+        // stretches every bar after the first to four seconds
+        let slowed = render_offline(
+            120.0,
+            TempoMap::parse("1:120,2:60").unwrap(),
+            MeterMap::default(),
+            8,
+        );
+        let ticks_per_bar = TimeSignature::default().ticks_per_bar();
+        let steady_notes = note_ons(&steady);
+        let slowed_notes = note_ons(&slowed);
+        assert_eq!(steady_notes.len(), slowed_notes.len());
+        for (steady, slowed) in steady_notes.iter().zip(&slowed_notes) {
+            assert_eq!(steady.tick, slowed.tick);
+            let bar = slowed.tick / ticks_per_bar;
+            assert_eq!(slowed.time, Duration::from_secs(2 + 4 * (bar - 1)));
+        }
+    }
+
+    #[test]
+    fn test_render_offline_follows_the_meter() {
+        // 6/8 at 120 quarter notes a minute is a second and a half a bar
+        let events = render_offline(
+            120.0,
+            TempoMap::default(),
+            MeterMap::parse("6/8").unwrap(),
+            4,
+        );
+        let times: Vec<Duration> = note_ons(&events).iter().map(|note| note.time).collect();
+        assert_eq!(
+            times,
+            vec![
+                Duration::from_millis(1500),
+                Duration::from_millis(3000),
+                Duration::from_millis(4500),
+                Duration::from_millis(6000),
+            ]
+        );
+    }
+}
@@ -1,15 +1,36 @@
 // state.rs
 
-use crate::config::{BEATS_PER_BAR, TICKS_PER_BEAT};
+use crate::clock::JitterStats;
+use crate::meter::{MeterMap, TimeSignature};
+use crate::midi_input::InputActivity;
+use crate::mtc::Timecode;
+use crate::output_worker::OutputStats;
+use crate::reconnect::DeviceStatus;
+use crate::swing::Swing;
+use crate::tempo_map::{MusicalPosition, TempoMap};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TransportState {
     Stopped,
     Playing,
+    Paused,
+}
+
+/// Where the engine's ticks are currently coming from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockStatus {
+    Internal,
+    External,
+    /// External clock lost; running on at the last measured tempo
+    Flywheel,
+    /// Following a Link session on the network
+    Link,
 }
 
 pub struct SharedState {
     pub bpm: u32,
+    pub precise_bpm: f64,
+    pub phase_error_ms: f64,
     pub tick_count: u64,
     pub current_beat: u32,
     pub current_bar: u32,
@@ -18,18 +39,48 @@ pub struct SharedState {
 
     // Add this
     pub transport_state: TransportState,
+    pub tempo_map: TempoMap,
+    pub clock_status: ClockStatus,
+    // Input device the external clock is bound to, if one is
+    pub clock_device: Option<String>,
+    // Last MIDI Time Code read or generated
+    pub timecode: Option<Timecode>,
+    // Groove applied between the clock and the musical graph
+    pub swing: Swing,
+    // Time signatures by bar; bar and beat are derived from this
+    pub meter: MeterMap,
+    // Messages sent, dropped and late across the MIDI output ports
+    pub midi_output: OutputStats,
+    // Connection state of every MIDI device in use, inputs first
+    pub devices: Vec<DeviceStatus>,
+    // Notes, controllers and other messages received on the MIDI inputs
+    pub midi_input: InputActivity,
+    // Tick lateness of the internal clock, while it is driving
+    pub jitter: Option<JitterStats>,
 }
 
 impl SharedState {
     pub fn new(_bpm: u32) -> Self {
         SharedState {
             bpm: 0, // Always initialize at 0, will be updated by measurements
+            precise_bpm: 0.0,
+            phase_error_ms: 0.0,
             tick_count: 0,
             current_beat: 0,
             current_bar: 0,
             recording: false,
             recording_target: None,
             transport_state: TransportState::Stopped,
+            tempo_map: TempoMap::default(),
+            clock_status: ClockStatus::Internal,
+            clock_device: None,
+            timecode: None,
+            swing: Swing::default(),
+            meter: MeterMap::default(),
+            midi_output: OutputStats::default(),
+            devices: Vec::new(),
+            midi_input: InputActivity::default(),
+            jitter: None,
         }
     }
 
@@ -40,19 +91,25 @@ impl SharedState {
         }
 
         self.tick_count += 1;
+        self.update_position();
+    }
 
-        // Calculate the tick position within the current beat.
-        let _tick_in_beat = self.tick_count % TICKS_PER_BEAT;
-
-        // Calculate the current beat (0-indexed) within a bar.
-        let beat_number = (self.tick_count / TICKS_PER_BEAT) % BEATS_PER_BAR;
-
-        // Calculate the current bar.
-        let bar_number = self.tick_count / (TICKS_PER_BEAT * BEATS_PER_BAR);
+    /// Moves the transport to an absolute tick; the next tick played is the
+    /// one following `tick`. Locating to zero is the same as a rewind.
+    pub fn locate(&mut self, tick: u64) {
+        self.tick_count = tick;
+        if tick == 0 {
+            self.current_beat = 0;
+            self.current_bar = 0;
+        } else {
+            self.update_position();
+        }
+    }
 
-        // Update the shared state with values for display.
-        self.current_beat = (beat_number + 1) as u32;
-        self.current_bar = (bar_number + 1) as u32;
+    fn update_position(&mut self) {
+        let position = self.meter.position(self.tick_count);
+        self.current_beat = position.beat;
+        self.current_bar = position.bar;
     }
 
     pub fn get_bpm(&self) -> u32 {
@@ -71,11 +128,45 @@ impl SharedState {
     pub fn get_current_bar(&self) -> u32 {
         self.current_bar
     }
+
+    /// The time signature of the bar the transport is in.
+    pub fn time_signature(&self) -> TimeSignature {
+        self.meter.signature_at_bar(self.current_bar)
+    }
+
+    /// Records a device's connection state, replacing what was known about
+    /// the device with the same role and direction.
+    pub fn update_device(&mut self, status: DeviceStatus) {
+        let known = self
+            .devices
+            .iter_mut()
+            .find(|device| device.input == status.input && device.role == status.role);
+        match known {
+            Some(device) if *device == status => {}
+            Some(device) => *device = status,
+            None => {
+                self.devices.push(status);
+                self.devices.sort_by_key(|device| !device.input);
+            }
+        }
+    }
+
+    /// Current bar and fractional beat, used to look up the tempo map.
+    pub fn position(&self) -> MusicalPosition {
+        let position = self.meter.position(self.tick_count);
+        let ticks_per_beat = position.signature.ticks_per_beat();
+        MusicalPosition {
+            bar: self.current_bar,
+            beat: self.current_beat as f64 + position.tick_in_beat as f64 / ticks_per_beat as f64,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::TICKS_PER_BEAT;
+    use crate::reconnect::ConnectionState;
 
     #[test]
     fn test_shared_state_initializes_with_zero_bpm() {
@@ -89,4 +180,82 @@ mod tests {
             "BPM should initialize to 0 regardless of config"
         );
     }
+
+    #[test]
+    fn test_locate_recomputes_bar_and_beat() {
+        let mut state = SharedState::new(120);
+        state.locate(TICKS_PER_BEAT * 4 * 2 + TICKS_PER_BEAT);
+        assert_eq!(state.get_current_bar(), 3);
+        assert_eq!(state.get_current_beat(), 2);
+
+        state.locate(0);
+        assert_eq!(state.get_tick_count(), 0);
+        assert_eq!(state.get_current_bar(), 0);
+        assert_eq!(state.get_current_beat(), 0);
+    }
+
+    #[test]
+    fn test_position_includes_fraction_of_beat() {
+        let mut state = SharedState::new(120);
+        state.transport_state = TransportState::Playing;
+        for _ in 0..(TICKS_PER_BEAT * 4 + TICKS_PER_BEAT / 2) {
+            state.tick_update();
+        }
+
+        let position = state.position();
+        assert_eq!(position.bar, 2);
+        assert_eq!(position.beat, 1.5);
+    }
+
+    #[test]
+    fn test_bar_and_beat_follow_the_time_signature() {
+        let mut state = SharedState::new(120);
+        state.meter = MeterMap::parse("1:4/4,2:7/8").unwrap();
+        let eighth = TICKS_PER_BEAT / 2;
+
+        // Bar 2 starts after one bar of 4/4; beat 5 of 7/8 is four eighths in
+        state.locate(TICKS_PER_BEAT * 4 + eighth * 4 + eighth / 2);
+        assert_eq!(state.get_current_bar(), 2);
+        assert_eq!(state.get_current_beat(), 5);
+        assert_eq!(state.time_signature().to_string(), "7/8");
+        assert_eq!(state.position().beat, 5.5);
+
+        // 7/8 bars are seven eighths long
+        state.locate(TICKS_PER_BEAT * 4 + eighth * 7);
+        assert_eq!(state.get_current_bar(), 3);
+        assert_eq!(state.get_current_beat(), 1);
+    }
+
+    #[test]
+    fn test_devices_are_updated_in_place_inputs_first() {
+        let device = |role: &str, input, state| DeviceStatus {
+            role: role.to_string(),
+            device: "OXI".to_string(),
+            input,
+            state,
+        };
+        let mut state = SharedState::new(120);
+        state.update_device(device(
+            "main",
+            false,
+            ConnectionState::Disconnected { attempts: 0 },
+        ));
+        state.update_device(device(
+            "clock",
+            true,
+            ConnectionState::Connected("OXI 1".into()),
+        ));
+        state.update_device(device(
+            "main",
+            false,
+            ConnectionState::GaveUp { attempts: 3 },
+        ));
+
+        assert_eq!(state.devices.len(), 2);
+        assert_eq!(state.devices[0].role, "clock");
+        assert_eq!(
+            state.devices[1].state,
+            ConnectionState::GaveUp { attempts: 3 }
+        );
+    }
 }
@@ -0,0 +1,217 @@
+// swing.rs
+
+use crate::config::TICKS_PER_BEAT;
+use std::fmt;
+
+pub const MIN_SWING_PERCENT: f64 = 50.0;
+pub const MAX_SWING_PERCENT: f64 = 75.0;
+
+/// The subdivision whose off-beats are delayed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwingGrid {
+    Eighth,
+    #[default]
+    Sixteenth,
+}
+
+impl SwingGrid {
+    /// Parses `8`/`8th` or `16`/`16th`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "8" | "8th" => Some(SwingGrid::Eighth),
+            "16" | "16th" => Some(SwingGrid::Sixteenth),
+            _ => None,
+        }
+    }
+
+    pub fn toggled(self) -> Self {
+        match self {
+            SwingGrid::Eighth => SwingGrid::Sixteenth,
+            SwingGrid::Sixteenth => SwingGrid::Eighth,
+        }
+    }
+
+    /// Length of one subdivision in ticks.
+    fn ticks(self) -> u64 {
+        match self {
+            SwingGrid::Eighth => TICKS_PER_BEAT / 2,
+            SwingGrid::Sixteenth => TICKS_PER_BEAT / 4,
+        }
+    }
+}
+
+impl fmt::Display for SwingGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwingGrid::Eighth => write!(f, "8th"),
+            SwingGrid::Sixteenth => write!(f, "16th"),
+        }
+    }
+}
+
+/// MPC-style swing: the percentage of each pair of subdivisions given to
+/// the first one. 50% is straight, 66% is close to a triplet feel.
+///
+/// Swing is a warp between clock ticks and musical ticks. The on-beat half
+/// of each pair is stretched and the off-beat half squeezed, so the
+/// musical graph sees its off-beats late while every pair still takes the
+/// same number of clock ticks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Swing {
+    percent: f64,
+    grid: SwingGrid,
+}
+
+impl Default for Swing {
+    fn default() -> Self {
+        Swing {
+            percent: MIN_SWING_PERCENT,
+            grid: SwingGrid::default(),
+        }
+    }
+}
+
+impl Swing {
+    pub fn new(percent: f64, grid: SwingGrid) -> Self {
+        Swing {
+            percent: clamp_percent(percent),
+            grid,
+        }
+    }
+
+    pub fn percent(&self) -> f64 {
+        self.percent
+    }
+
+    pub fn grid(&self) -> SwingGrid {
+        self.grid
+    }
+
+    pub fn with_percent(self, percent: f64) -> Self {
+        Swing::new(percent, self.grid)
+    }
+
+    pub fn with_grid(self, grid: SwingGrid) -> Self {
+        Swing { grid, ..self }
+    }
+
+    /// How many musical ticks have passed by clock tick `tick`.
+    pub fn musical_ticks_at(&self, tick: u64) -> u64 {
+        let subdivision = self.grid.ticks();
+        let pair = 2 * subdivision;
+        let base = tick - tick % pair;
+        let offset = (tick % pair) as f64;
+
+        // Clock ticks into the pair at which the off-beat now falls
+        let swing_point = pair as f64 * self.percent / 100.0;
+        let musical_offset = if offset < swing_point {
+            offset * subdivision as f64 / swing_point
+        } else {
+            subdivision as f64
+                + (offset - swing_point) * subdivision as f64 / (pair as f64 - swing_point)
+        };
+        // The epsilon keeps exact boundaries from rounding down a tick
+        base + (musical_offset + 1e-9).floor() as u64
+    }
+
+    /// How many times the musical graph should run on clock tick `tick`:
+    /// none while an off-beat is held back, two as the pair catches up.
+    pub fn musical_ticks_due(&self, tick: u64) -> u64 {
+        self.musical_ticks_at(tick) - self.musical_ticks_at(tick.saturating_sub(1))
+    }
+}
+
+impl fmt::Display for Swing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.0}% {}", self.percent, self.grid)
+    }
+}
+
+fn clamp_percent(percent: f64) -> f64 {
+    if percent.is_finite() {
+        percent.clamp(MIN_SWING_PERCENT, MAX_SWING_PERCENT)
+    } else {
+        MIN_SWING_PERCENT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The clock tick on which the musical graph reaches `musical_tick`.
+    fn clock_tick_of(swing: &Swing, musical_tick: u64) -> u64 {
+        (1..)
+            .find(|&tick| swing.musical_ticks_at(tick) >= musical_tick)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_straight_swing_is_the_identity() {
+        let swing = Swing::default();
+        for tick in 0..200 {
+            assert_eq!(swing.musical_ticks_at(tick), tick);
+            assert_eq!(swing.musical_ticks_due(tick + 1), 1);
+        }
+    }
+
+    #[test]
+    fn test_off_beat_eighths_are_delayed() {
+        // At 75% the off-beat 8th moves from half way to three quarters
+        // through the beat
+        let beat = TICKS_PER_BEAT;
+        let swing = Swing::new(75.0, SwingGrid::Eighth);
+        assert_eq!(clock_tick_of(&swing, beat / 2), beat * 3 / 4);
+        assert_eq!(clock_tick_of(&swing, beat * 3 / 2), beat * 7 / 4);
+
+        // 66% lands on the first tick at or after 66% of the beat
+        let swing = Swing::new(66.0, SwingGrid::Eighth);
+        let expected = (beat as f64 * 0.66).ceil() as u64;
+        assert_eq!(clock_tick_of(&swing, beat / 2), expected);
+    }
+
+    #[test]
+    fn test_off_beat_sixteenths_are_delayed() {
+        let beat = TICKS_PER_BEAT;
+        let swing = Swing::new(75.0, SwingGrid::Sixteenth);
+        assert_eq!(clock_tick_of(&swing, beat / 4), beat * 3 / 8);
+        assert_eq!(clock_tick_of(&swing, beat * 3 / 4), beat * 7 / 8);
+    }
+
+    #[test]
+    fn test_downbeats_and_tick_totals_are_untouched() {
+        let swing = Swing::new(62.0, SwingGrid::Eighth);
+        for beat in 0..8 {
+            assert_eq!(
+                swing.musical_ticks_at(beat * TICKS_PER_BEAT),
+                beat * TICKS_PER_BEAT
+            );
+        }
+
+        // The graph runs exactly once per clock tick on average, never more
+        // than twice on a single tick
+        let bar = 4 * TICKS_PER_BEAT;
+        let total: u64 = (1..=bar).map(|tick| swing.musical_ticks_due(tick)).sum();
+        assert_eq!(total, bar);
+        assert!((1..=bar).all(|tick| swing.musical_ticks_due(tick) <= 2));
+    }
+
+    #[test]
+    fn test_percent_is_clamped_to_mpc_range() {
+        assert_eq!(Swing::new(40.0, SwingGrid::Eighth).percent(), 50.0);
+        assert_eq!(Swing::new(90.0, SwingGrid::Eighth).percent(), 75.0);
+        assert_eq!(Swing::new(f64::NAN, SwingGrid::Eighth).percent(), 50.0);
+        assert_eq!(
+            Swing::new(58.0, SwingGrid::Sixteenth).to_string(),
+            "58% 16th"
+        );
+    }
+
+    #[test]
+    fn test_grid_parse_and_toggle() {
+        assert_eq!(SwingGrid::parse("8"), Some(SwingGrid::Eighth));
+        assert_eq!(SwingGrid::parse("16th"), Some(SwingGrid::Sixteenth));
+        assert_eq!(SwingGrid::parse("32"), None);
+        assert_eq!(SwingGrid::Eighth.toggled(), SwingGrid::Sixteenth);
+    }
+}
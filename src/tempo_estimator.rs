@@ -0,0 +1,257 @@
+// tempo_estimator.rs
+
+use crate::config::MIDI_CLOCK_PPQN;
+use log::{debug, trace};
+use std::time::{Duration, Instant};
+
+// Loop gains for the phase (alpha) and period (beta) corrections. Beta is
+// chosen close to alpha^2 / (2 - alpha) for a critically damped response.
+const DEFAULT_PHASE_GAIN: f64 = 0.1;
+const DEFAULT_PERIOD_GAIN: f64 = 0.005;
+
+// A tick interval this many times longer or shorter than the tracked period
+// means the clock stopped or jumped, so the loop re-locks rather than
+// slewing towards it.
+const RELOCK_RATIO: f64 = 4.0;
+
+/// Selects how the engine derives BPM from incoming ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TempoEstimatorKind {
+    /// Average of the last bar of tick intervals, rounded to whole BPM
+    #[default]
+    MovingAverage,
+    /// Phase-locked loop tracking tick period and phase
+    PhaseLocked,
+}
+
+/// Alpha-beta phase-locked loop over tick arrival times. It predicts when
+/// the next tick is due, corrects that prediction by a fraction of each
+/// observed phase error, and slowly adapts the period to follow tempo.
+pub struct PllEstimator {
+    phase_gain: f64,
+    period_gain: f64,
+    last_tick: Option<Instant>,
+    period_ns: Option<f64>,
+    predicted_ns: f64,
+    phase_error_ns: f64,
+}
+
+impl Default for PllEstimator {
+    fn default() -> Self {
+        Self::new(DEFAULT_PHASE_GAIN, DEFAULT_PERIOD_GAIN)
+    }
+}
+
+impl PllEstimator {
+    pub fn new(phase_gain: f64, period_gain: f64) -> Self {
+        PllEstimator {
+            phase_gain,
+            period_gain,
+            last_tick: None,
+            period_ns: None,
+            predicted_ns: 0.0,
+            phase_error_ns: 0.0,
+        }
+    }
+
+    /// Feeds the arrival time of a tick into the loop.
+    pub fn observe(&mut self, now: Instant) {
+        let Some(last_tick) = self.last_tick else {
+            self.last_tick = Some(now);
+            return;
+        };
+        // Times are tracked as nanoseconds since the previous tick so the
+        // arithmetic can be signed.
+        let observed_ns = now.duration_since(last_tick).as_nanos() as f64;
+
+        match self.period_ns {
+            Some(period_ns) => self.correct(observed_ns, period_ns),
+            None => self.lock(observed_ns),
+        }
+        self.last_tick = Some(now);
+    }
+
+    fn lock(&mut self, interval_ns: f64) {
+        if interval_ns <= 0.0 {
+            return;
+        }
+        debug!("PLL locked with tick period {:.0} ns", interval_ns);
+        self.period_ns = Some(interval_ns);
+        self.predicted_ns = interval_ns;
+        self.phase_error_ns = 0.0;
+    }
+
+    fn correct(&mut self, observed_ns: f64, period_ns: f64) {
+        let ratio = observed_ns / period_ns;
+        if !(1.0 / RELOCK_RATIO..=RELOCK_RATIO).contains(&ratio) {
+            debug!("PLL saw a {:.0} ns tick interval, re-locking", observed_ns);
+            self.lock(observed_ns);
+            return;
+        }
+
+        let residual_ns = observed_ns - self.predicted_ns;
+        let period_ns = period_ns + self.period_gain * residual_ns;
+        let corrected_ns = self.predicted_ns + self.phase_gain * residual_ns;
+        self.period_ns = Some(period_ns);
+        self.phase_error_ns = residual_ns;
+        // Next prediction relative to the tick just observed
+        self.predicted_ns = corrected_ns - observed_ns + period_ns;
+        trace!(
+            "PLL residual {:.0} ns, period {:.0} ns",
+            residual_ns,
+            period_ns
+        );
+    }
+
+    /// Fractional tempo estimate, once at least two ticks have been seen.
+    pub fn bpm(&self) -> Option<f64> {
+        self.period_ns
+            .map(|period_ns| 60_000_000_000.0 / (period_ns * MIDI_CLOCK_PPQN as f64))
+    }
+
+    /// When the loop expects the next tick to arrive.
+    pub fn predicted_next_tick(&self) -> Option<Instant> {
+        let last_tick = self.last_tick?;
+        self.period_ns?;
+        Some(last_tick + Duration::from_nanos(self.predicted_ns.max(0.0).round() as u64))
+    }
+
+    /// Signed difference in milliseconds between the last tick and its
+    /// prediction; positive means the tick arrived late.
+    pub fn phase_error_ms(&self) -> f64 {
+        self.phase_error_ns / 1_000_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic jitter source so the tests are repeatable.
+    struct Lcg(u64);
+
+    impl Lcg {
+        /// Uniform value in [-1, 1)
+        fn next_unit(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 11) as f64 / (1u64 << 53) as f64) * 2.0 - 1.0
+        }
+    }
+
+    fn tick_period(bpm: f64) -> Duration {
+        Duration::from_nanos((60_000_000_000.0 / (bpm * MIDI_CLOCK_PPQN as f64)) as u64)
+    }
+
+    /// Tick source on an ideal timeline with up to ±1 ms of jitter.
+    struct JitteredTicks {
+        ideal: Instant,
+        jitter: Lcg,
+    }
+
+    impl JitteredTicks {
+        fn new(seed: u64) -> Self {
+            JitteredTicks {
+                ideal: Instant::now(),
+                jitter: Lcg(seed),
+            }
+        }
+
+        /// Feeds `ticks` jittered ticks at `bpm`, continuing the timeline.
+        fn feed(&mut self, pll: &mut PllEstimator, bpm: f64, ticks: u32) {
+            let period = tick_period(bpm);
+            for _ in 0..ticks {
+                self.ideal += period;
+                let offset_us = (self.jitter.next_unit() * 1_000.0) as i64;
+                let arrival = if offset_us >= 0 {
+                    self.ideal + Duration::from_micros(offset_us as u64)
+                } else {
+                    self.ideal - Duration::from_micros(offset_us.unsigned_abs())
+                };
+                pll.observe(arrival);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_estimate_until_two_ticks() {
+        let mut pll = PllEstimator::default();
+        assert_eq!(pll.bpm(), None);
+        pll.observe(Instant::now());
+        assert_eq!(pll.bpm(), None);
+        assert_eq!(pll.predicted_next_tick(), None);
+    }
+
+    #[test]
+    fn test_steady_ticks_give_exact_fractional_bpm() {
+        let mut pll = PllEstimator::default();
+        let start = Instant::now();
+        let period = tick_period(123.4);
+        for n in 0..48 {
+            pll.observe(start + period * n);
+        }
+
+        assert!((pll.bpm().unwrap() - 123.4).abs() < 0.001);
+        assert!(pll.phase_error_ms().abs() < 0.001);
+    }
+
+    #[test]
+    fn test_jittered_ticks_settle_close_to_true_tempo() {
+        let mut pll = PllEstimator::default();
+        JitteredTicks::new(42).feed(&mut pll, 120.0, 24 * 32);
+
+        // ±1 ms of jitter on a 20.8 ms tick is ±5%, yet the estimate holds
+        // within a tenth of a BPM
+        let bpm = pll.bpm().unwrap();
+        assert!((bpm - 120.0).abs() < 0.1, "estimated {bpm}");
+        assert!(pll.phase_error_ms().abs() <= 2.5);
+    }
+
+    #[test]
+    fn test_follows_tempo_change() {
+        let mut pll = PllEstimator::default();
+        let mut ticks = JitteredTicks::new(7);
+        ticks.feed(&mut pll, 120.0, 24 * 16);
+        ticks.feed(&mut pll, 126.0, 24 * 16);
+
+        let bpm = pll.bpm().unwrap();
+        assert!((bpm - 126.0).abs() < 0.2, "estimated {bpm}");
+    }
+
+    #[test]
+    fn test_predicts_next_tick() {
+        let mut pll = PllEstimator::default();
+        let start = Instant::now();
+        let period = tick_period(120.0);
+        for n in 0..24 {
+            pll.observe(start + period * n);
+        }
+
+        let predicted = pll.predicted_next_tick().unwrap();
+        let expected = start + period * 24;
+        let error = if predicted > expected {
+            predicted - expected
+        } else {
+            expected - predicted
+        };
+        assert!(error < Duration::from_micros(10));
+    }
+
+    #[test]
+    fn test_relocks_after_clock_gap() {
+        let mut pll = PllEstimator::default();
+        let mut ticks = JitteredTicks::new(1);
+        ticks.feed(&mut pll, 120.0, 48);
+
+        // Two seconds of silence, then the clock comes back at a new tempo
+        let restart = ticks.ideal + Duration::from_secs(2);
+        pll.observe(restart);
+        let period = tick_period(100.0);
+        for n in 1..=4 {
+            pll.observe(restart + period * n);
+        }
+        assert!((pll.bpm().unwrap() - 100.0).abs() < 0.5);
+    }
+}
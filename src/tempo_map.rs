@@ -0,0 +1,254 @@
+// tempo_map.rs
+
+use std::fmt;
+
+/// A position in the song as bar and (fractional) beat, both 1-indexed to
+/// match what `SharedState` displays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MusicalPosition {
+    pub bar: u32,
+    pub beat: f64,
+}
+
+impl MusicalPosition {
+    fn is_before(&self, other: &MusicalPosition) -> bool {
+        self.bar < other.bar || (self.bar == other.bar && self.beat < other.beat)
+    }
+
+    /// Bars elapsed since `start`, with beats contributing fractional bars.
+    fn bars_since(&self, start: &MusicalPosition, beats_per_bar: f64) -> f64 {
+        (self.bar as f64 - start.bar as f64) + (self.beat - start.beat) / beats_per_bar
+    }
+}
+
+/// How a tempo change reaches its target from the tempo before it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TempoRamp {
+    Jump,
+    Linear { bars: u32 },
+    Exponential { bars: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TempoChange {
+    pub at: MusicalPosition,
+    pub bpm: f64,
+    pub ramp: TempoRamp,
+}
+
+/// Bar-indexed tempo changes consulted by the engine on every tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TempoMap {
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    pub fn new(mut changes: Vec<TempoChange>) -> Self {
+        changes.sort_by(|a, b| {
+            a.at.bar
+                .cmp(&b.at.bar)
+                .then(a.at.beat.total_cmp(&b.at.beat))
+        });
+        TempoMap { changes }
+    }
+
+    /// Parses a comma separated list of `BAR[.BEAT]:BPM[:linear|exp:BARS]`
+    /// entries, e.g. `1:120,17:140:linear:4,33.3:90`.
+    pub fn parse(spec: &str) -> Result<Self, TempoMapError> {
+        let changes = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(parse_change)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(changes))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+
+    /// Tempo the map prescribes at `position`, or `None` before the first change.
+    pub fn bpm_at(&self, position: MusicalPosition, beats_per_bar: u32) -> Option<f64> {
+        let mut bpm = None;
+        for change in &self.changes {
+            if position.is_before(&change.at) {
+                break;
+            }
+            let from = bpm.unwrap_or(change.bpm);
+            let progress = ramp_progress(change, &position, beats_per_bar as f64);
+            bpm = Some(interpolate(change.ramp, from, change.bpm, progress));
+        }
+        bpm
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .changes
+            .iter()
+            .map(|change| {
+                let (ramp, bars) = match change.ramp {
+                    TempoRamp::Jump => ("jump", 0),
+                    TempoRamp::Linear { bars } => ("linear", bars),
+                    TempoRamp::Exponential { bars } => ("exp", bars),
+                };
+                format!(
+                    "{{\"bar\":{},\"beat\":{},\"bpm\":{},\"ramp\":\"{}\",\"ramp_bars\":{}}}",
+                    change.at.bar, change.at.beat, change.bpm, ramp, bars
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+}
+
+fn ramp_progress(change: &TempoChange, position: &MusicalPosition, beats_per_bar: f64) -> f64 {
+    let bars = match change.ramp {
+        TempoRamp::Jump => return 1.0,
+        TempoRamp::Linear { bars } | TempoRamp::Exponential { bars } => bars,
+    };
+    if bars == 0 {
+        return 1.0;
+    }
+    (position.bars_since(&change.at, beats_per_bar) / bars as f64).clamp(0.0, 1.0)
+}
+
+fn interpolate(ramp: TempoRamp, from: f64, to: f64, progress: f64) -> f64 {
+    match ramp {
+        TempoRamp::Jump => to,
+        TempoRamp::Linear { .. } => from + (to - from) * progress,
+        TempoRamp::Exponential { .. } => from * (to / from).powf(progress),
+    }
+}
+
+fn parse_change(entry: &str) -> Result<TempoChange, TempoMapError> {
+    let invalid = || TempoMapError(entry.to_string());
+    let fields: Vec<&str> = entry.split(':').collect();
+
+    let at = parse_position(fields.first().copied().unwrap_or("")).ok_or_else(invalid)?;
+    let bpm = fields
+        .get(1)
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|bpm| bpm.is_finite() && *bpm > 0.0)
+        .ok_or_else(invalid)?;
+
+    let ramp = match (fields.get(2).copied(), fields.get(3)) {
+        (None, None) | (Some("jump"), None) => TempoRamp::Jump,
+        (Some("linear"), Some(bars)) => TempoRamp::Linear {
+            bars: bars.parse().map_err(|_| invalid())?,
+        },
+        (Some("exp"), Some(bars)) => TempoRamp::Exponential {
+            bars: bars.parse().map_err(|_| invalid())?,
+        },
+        _ => return Err(invalid()),
+    };
+    if fields.len() > 4 {
+        return Err(invalid());
+    }
+
+    Ok(TempoChange { at, bpm, ramp })
+}
+
+fn parse_position(field: &str) -> Option<MusicalPosition> {
+    let (bar, beat) = match field.split_once('.') {
+        Some((bar, beat)) => (bar, beat.parse::<u32>().ok()?),
+        None => (field, 1),
+    };
+    let bar = bar.parse::<u32>().ok()?;
+    if bar == 0 || beat == 0 {
+        return None;
+    }
+    Some(MusicalPosition {
+        bar,
+        beat: beat as f64,
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TempoMapError(String);
+
+impl fmt::Display for TempoMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid tempo map entry '{}' (expected BAR[.BEAT]:BPM[:linear|exp:BARS])",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for TempoMapError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(bar: u32, beat: f64) -> MusicalPosition {
+        MusicalPosition { bar, beat }
+    }
+
+    #[test]
+    fn test_parse_sorts_entries_and_reads_ramps() {
+        let map = TempoMap::parse("17:140:linear:4, 1:120, 33.3:90:exp:2").unwrap();
+        let changes = map.changes();
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].at, at(1, 1.0));
+        assert_eq!(changes[1].ramp, TempoRamp::Linear { bars: 4 });
+        assert_eq!(changes[2].at, at(33, 3.0));
+        assert_eq!(changes[2].ramp, TempoRamp::Exponential { bars: 2 });
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_entries() {
+        assert!(TempoMap::parse("0:120").is_err());
+        assert!(TempoMap::parse("1:fast").is_err());
+        assert!(TempoMap::parse("1:120:linear").is_err());
+        assert!(TempoMap::parse("1:120:swing:2").is_err());
+    }
+
+    #[test]
+    fn test_no_tempo_before_first_change() {
+        let map = TempoMap::parse("5:100").unwrap();
+        assert_eq!(map.bpm_at(at(4, 4.5), 4), None);
+        assert_eq!(map.bpm_at(at(5, 1.0), 4), Some(100.0));
+    }
+
+    #[test]
+    fn test_jump_applies_immediately() {
+        let map = TempoMap::parse("1:120,9:90").unwrap();
+        assert_eq!(map.bpm_at(at(8, 4.9), 4), Some(120.0));
+        assert_eq!(map.bpm_at(at(9, 1.0), 4), Some(90.0));
+    }
+
+    #[test]
+    fn test_linear_ramp_interpolates_over_bars() {
+        let map = TempoMap::parse("1:100,5:140:linear:4").unwrap();
+
+        assert_eq!(map.bpm_at(at(5, 1.0), 4), Some(100.0));
+        assert_eq!(map.bpm_at(at(7, 1.0), 4), Some(120.0));
+        assert_eq!(map.bpm_at(at(7, 3.0), 4), Some(125.0));
+        assert_eq!(map.bpm_at(at(12, 1.0), 4), Some(140.0));
+    }
+
+    #[test]
+    fn test_exponential_ramp_is_geometric() {
+        let map = TempoMap::parse("1:100,3:400:exp:2").unwrap();
+        let halfway = map.bpm_at(at(4, 1.0), 4).unwrap();
+        assert!((halfway - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_to_json_lists_changes() {
+        let map = TempoMap::parse("1:120,9:140:linear:4").unwrap();
+        assert_eq!(
+            map.to_json(),
+            "[{\"bar\":1,\"beat\":1,\"bpm\":120,\"ramp\":\"jump\",\"ramp_bars\":0},\
+             {\"bar\":9,\"beat\":1,\"bpm\":140,\"ramp\":\"linear\",\"ramp_bars\":4}]"
+        );
+    }
+}
@@ -0,0 +1,325 @@
+// thru.rs
+
+use crate::midi_input::MessageKind;
+use crate::midi_message::MidiMessage;
+use crate::routing::RoutingError;
+use std::ops::RangeInclusive;
+
+/// Name thru routes use for the input bound as the external clock.
+pub const CLOCK_INPUT: &str = "clock";
+/// Name thru routes use for the input MIDI Time Code is read from.
+pub const TIMECODE_INPUT: &str = "timecode";
+
+/// A named input, e.g. `keys=KeyStep`, played through to the outputs. It
+/// connects to the first device port whose name contains `device`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputPortSpec {
+    pub name: String,
+    pub device: String,
+}
+
+impl InputPortSpec {
+    /// Parses `NAME=DEVICE`. The clock and timecode inputs already have
+    /// their names.
+    pub fn parse(spec: &str) -> Result<Self, RoutingError> {
+        let invalid = || RoutingError::Input(spec.to_string());
+        let (name, device) = spec.split_once('=').ok_or_else(invalid)?;
+        let (name, device) = (name.trim(), device.trim());
+        if name.is_empty()
+            || device.is_empty()
+            || name.contains(':')
+            || [CLOCK_INPUT, TIMECODE_INPUT].contains(&name)
+        {
+            return Err(invalid());
+        }
+        Ok(InputPortSpec {
+            name: name.to_string(),
+            device: device.to_string(),
+        })
+    }
+}
+
+/// Which of an input's messages a thru route passes on.
+///
+/// With no kinds given only channel messages pass, so clock, transport,
+/// timecode, active sensing, tune request and reset, and SysEx are each
+/// forwarded only when asked for. The channel
+/// and note limits leave messages without a channel or note alone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThruFilter {
+    pub kinds: Vec<MessageKind>,
+    // Source channels, 0-15
+    pub channels: Option<RangeInclusive<u8>>,
+    pub notes: Option<RangeInclusive<u8>>,
+}
+
+impl ThruFilter {
+    pub fn passes(&self, message: &MidiMessage) -> bool {
+        let kind = MessageKind::of(message);
+        let kind_passes = if self.kinds.is_empty() {
+            kind.is_channel_voice()
+        } else {
+            self.kinds.contains(&kind)
+        };
+        let within = |range: &Option<RangeInclusive<u8>>, value: Option<u8>| {
+            range
+                .as_ref()
+                .zip(value)
+                .is_none_or(|(range, value)| range.contains(&value))
+        };
+        kind_passes
+            && within(&self.channels, message.channel())
+            && within(&self.notes, message.note())
+    }
+
+    // One `ch=`, `type=` or `notes=` entry of a thru route
+    fn parse_entry(&mut self, entry: &str) -> Option<()> {
+        let (key, value) = entry.split_once('=')?;
+        match key.trim() {
+            "ch" | "channel" => {
+                let (low, high) = parse_range(value, 1, 16)?;
+                self.channels = Some(low - 1..=high - 1);
+            }
+            "type" => {
+                for kind in value.split('+') {
+                    self.kinds.push(MessageKind::parse(kind)?);
+                }
+            }
+            "notes" => {
+                let (low, high) = parse_range(value, 0, 127)?;
+                self.notes = Some(low..=high);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+// `N` or `LOW-HIGH`, within `min..=max`
+fn parse_range(value: &str, min: u8, max: u8) -> Option<(u8, u8)> {
+    let (low, high) = value.split_once('-').unwrap_or((value, value));
+    let (low, high): (u8, u8) = (low.trim().parse().ok()?, high.trim().parse().ok()?);
+    (min <= low && low <= high && high <= max).then_some((low, high))
+}
+
+/// Passes messages from a named input on to a named output, filtered and
+/// optionally moved to another channel there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThruRoute {
+    pub input: String,
+    pub port: String,
+    pub channel: Option<u8>,
+    pub filter: ThruFilter,
+}
+
+impl ThruRoute {
+    /// Parses `INPUT=PORT[:CHANNEL]` followed by any of `,ch=N[-M]`,
+    /// `,type=KIND[+KIND...]` and `,notes=LOW-HIGH`, with channels written
+    /// 1-16, e.g. `keys=bass:2,type=note+bend,notes=24-59`.
+    pub fn parse(spec: &str) -> Result<Self, RoutingError> {
+        let invalid = || RoutingError::Thru(spec.to_string());
+        let mut entries = spec.split(',');
+        let (input, destination) = entries
+            .next()
+            .and_then(|route| route.split_once('='))
+            .ok_or_else(invalid)?;
+        let (port, channel) = match destination.trim().split_once(':') {
+            Some((port, channel)) => {
+                let (channel, _) = parse_range(channel, 1, 16)
+                    .filter(|(low, high)| low == high)
+                    .ok_or_else(invalid)?;
+                (port.trim(), Some(channel - 1))
+            }
+            None => (destination.trim(), None),
+        };
+        let input = input.trim();
+        if input.is_empty() || port.is_empty() {
+            return Err(invalid());
+        }
+        let mut filter = ThruFilter::default();
+        for entry in entries {
+            filter.parse_entry(entry).ok_or_else(invalid)?;
+        }
+        Ok(ThruRoute {
+            input: input.to_string(),
+            port: port.to_string(),
+            channel,
+            filter,
+        })
+    }
+}
+
+/// Decides which output ports each input message is played through to.
+///
+/// Every route from the message's input whose filter passes it gets a
+/// copy, so one keyboard can be split or layered across several synths.
+/// Messages no route picks up go nowhere.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ThruTable {
+    routes: Vec<ThruRoute>,
+}
+
+impl ThruTable {
+    pub fn new(routes: Vec<ThruRoute>) -> Self {
+        ThruTable { routes }
+    }
+
+    pub fn routes(&self) -> &[ThruRoute] {
+        &self.routes
+    }
+
+    /// The port names and messages to send for `message` from `input`.
+    pub fn resolve(&self, input: &str, message: &MidiMessage) -> Vec<(&str, MidiMessage)> {
+        self.routes
+            .iter()
+            .filter(|route| route.input == input && route.filter.passes(message))
+            .map(|route| {
+                let mut message = message.clone();
+                if let Some(channel) = route.channel {
+                    message.set_channel(channel);
+                }
+                (route.port.as_str(), message)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(channel: u8, note: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel,
+            note,
+            velocity: 100,
+            duration_ticks: 0,
+        }
+    }
+
+    #[test]
+    fn test_parses_inputs_and_thru_routes() {
+        assert_eq!(
+            InputPortSpec::parse("keys = KeyStep"),
+            Ok(InputPortSpec {
+                name: "keys".to_string(),
+                device: "KeyStep".to_string(),
+            })
+        );
+        assert!(InputPortSpec::parse("clock=KeyStep").is_err());
+        assert!(InputPortSpec::parse("KeyStep").is_err());
+
+        assert_eq!(
+            ThruRoute::parse("keys=bass:2,ch=1-4,type=note+bend,notes=24-59"),
+            Ok(ThruRoute {
+                input: "keys".to_string(),
+                port: "bass".to_string(),
+                channel: Some(1),
+                filter: ThruFilter {
+                    kinds: vec![MessageKind::Note, MessageKind::PitchBend],
+                    channels: Some(0..=3),
+                    notes: Some(24..=59),
+                },
+            })
+        );
+        assert_eq!(
+            ThruRoute::parse("clock=main,type=clock").map(|route| route.filter.kinds),
+            Ok(vec![MessageKind::Clock])
+        );
+        for spec in [
+            "keys",
+            "keys=",
+            "keys=bass:17",
+            "keys=bass:1-2",
+            "keys=bass,ch=0",
+            "keys=bass,type=noise",
+            "keys=bass,notes=60-40",
+            "keys=bass,velocity=1",
+        ] {
+            assert!(ThruRoute::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_a_keyboard_splits_across_ports() {
+        let table = ThruTable::new(vec![
+            ThruRoute::parse("keys=bass:2,notes=0-59").unwrap(),
+            ThruRoute::parse("keys=lead,notes=60-127").unwrap(),
+            ThruRoute::parse("pads=drums:10").unwrap(),
+        ]);
+        assert_eq!(
+            table.resolve("keys", &note_on(0, 40)),
+            vec![("bass", note_on(1, 40))]
+        );
+        assert_eq!(
+            table.resolve("keys", &note_on(0, 72)),
+            vec![("lead", note_on(0, 72))]
+        );
+
+        // Controllers have no note, so both halves of the split get them
+        let sustain = MidiMessage::ControlChange {
+            channel: 0,
+            controller: 64,
+            value: 127,
+        };
+        assert_eq!(
+            table
+                .resolve("keys", &sustain)
+                .iter()
+                .map(|(port, message)| (*port, message.channel()))
+                .collect::<Vec<_>>(),
+            vec![("bass", Some(1)), ("lead", Some(0))]
+        );
+        assert!(table.resolve("other", &note_on(0, 40)).is_empty());
+    }
+
+    #[test]
+    fn test_clock_and_notes_are_forwarded_independently() {
+        let table = ThruTable::new(vec![
+            ThruRoute::parse("clock=drums,type=clock+transport").unwrap(),
+            ThruRoute::parse("clock=keys,ch=2").unwrap(),
+        ]);
+        assert_eq!(
+            table.resolve("clock", &MidiMessage::TimingClock),
+            vec![("drums", MidiMessage::TimingClock)]
+        );
+        assert_eq!(
+            table.resolve("clock", &MidiMessage::Start),
+            vec![("drums", MidiMessage::Start)]
+        );
+        assert_eq!(
+            table.resolve("clock", &note_on(1, 60)),
+            vec![("keys", note_on(1, 60))]
+        );
+        assert!(table.resolve("clock", &note_on(0, 60)).is_empty());
+        assert!(table
+            .resolve("clock", &MidiMessage::SysEx(vec![0x7D]))
+            .is_empty());
+        assert!(table
+            .resolve("clock", &MidiMessage::MtcQuarterFrame(0x10))
+            .is_empty());
+        assert!(table.resolve("clock", &MidiMessage::Reset).is_empty());
+    }
+
+    #[test]
+    fn test_system_messages_are_forwarded_when_asked_for() {
+        let table = ThruTable::new(vec![
+            ThruRoute::parse("keys=synth,type=sensing+system").unwrap(),
+            ThruRoute::parse("keys=drums,type=transport").unwrap(),
+        ]);
+        for message in [
+            MidiMessage::ActiveSensing,
+            MidiMessage::TuneRequest,
+            MidiMessage::Reset,
+        ] {
+            assert_eq!(
+                table.resolve("keys", &message),
+                vec![("synth", message.clone())]
+            );
+        }
+        assert_eq!(
+            table.resolve("keys", &MidiMessage::SongSelect(3)),
+            vec![("drums", MidiMessage::SongSelect(3))]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -15,38 +17,69 @@ use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::{error::Error, io, time::Duration};
 
+use crate::clock::JitterStats;
+use crate::clock_control::{ClockController, ClockSelection};
+use crate::error::MidiError;
 use crate::event_loop::{EngineMessage, TransportAction};
+use crate::external_clock;
+use crate::meter::TimeSignature;
+use crate::reconnect::{ConnectionState, DeviceStatus};
 use crate::state;
 
+const TEMPO_NUDGE_BPM: f64 = 1.0;
+const SWING_NUDGE_PERCENT: f64 = 1.0;
+// Meters the M key steps through, as (numerator, denominator)
+const METER_CYCLE: [(u32, u32); 5] = [(4, 4), (3, 4), (5, 4), (6, 8), (7, 8)];
+
 // Key mapping function moved from input.rs
 fn map_key_event(key: KeyEvent) -> Option<EngineMessage> {
     match key.code {
         KeyCode::Char(' ') => None, // We'll handle space key specially
+        KeyCode::Char('+') | KeyCode::Char('=') => Some(EngineMessage::NudgeTempo(TEMPO_NUDGE_BPM)),
+        KeyCode::Char('-') => Some(EngineMessage::NudgeTempo(-TEMPO_NUDGE_BPM)),
+        KeyCode::Char(']') => Some(EngineMessage::NudgeSwing(SWING_NUDGE_PERCENT)),
+        KeyCode::Char('[') => Some(EngineMessage::NudgeSwing(-SWING_NUDGE_PERCENT)),
+        KeyCode::Char('p') => Some(EngineMessage::TransportCommand(TransportAction::Pause)),
+        KeyCode::Char('c') => Some(EngineMessage::TransportCommand(TransportAction::Continue)),
+        KeyCode::Char('!') => Some(EngineMessage::Panic),
         _ => None,
     }
 }
 
-fn check_for_quit_key(key_event: &crossterm::event::KeyEvent) -> Result<(), Box<dyn Error>> {
-    if let KeyCode::Char(c) = key_event.code {
-        if c == 'q' || c == 'Q' {
-            log::info!("Quit key pressed. Exiting event loop.");
-            // Clean up the terminal before exiting
-            disable_raw_mode()?;
-            let mut stdout = io::stdout();
-            execute!(stdout, LeaveAlternateScreen, DisableMouseCapture)?;
-            log::info!("Terminal cleaned up, exiting TUI event loop");
-            std::process::exit(0); // Exit the program directly
-        }
+// Raw mode turns Ctrl-C into a key press rather than SIGINT, so it quits
+// like Q does
+fn is_quit_key(key_event: &crossterm::event::KeyEvent) -> bool {
+    match key_event.code {
+        KeyCode::Char('q') | KeyCode::Char('Q') => true,
+        KeyCode::Char('c') => key_event.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
     }
-    Ok(())
 }
+
+// Hands a message to the engine; once it has stopped the TUI has nothing
+// left to control
+fn send_to_engine(
+    message_tx: &Sender<EngineMessage>,
+    message: EngineMessage,
+) -> Result<(), MidiError> {
+    message_tx
+        .send(message)
+        .map_err(|_| MidiError::EngineStopped)
+}
+
 fn handle_key_event(
     key_event: crossterm::event::KeyEvent,
     message_tx: &Sender<EngineMessage>,
     shared_state: &Arc<Mutex<state::SharedState>>,
+    clocks: &ClockController,
 ) -> Result<(), Box<dyn Error>> {
     log::info!("Key event received: {:?}", key_event);
-    check_for_quit_key(&key_event)?;
+
+    // Clock switches happen here rather than in the engine, which keeps
+    // handling ticks while the old source shuts down
+    if switch_clock_for_key(key_event.code, clocks) {
+        return Ok(());
+    }
 
     // Special handling for space key to toggle transport
     if let KeyCode::Char(' ') = key_event.code {
@@ -60,24 +93,97 @@ fn handle_key_event(
         let command = match current_state {
             state::TransportState::Playing => TransportAction::Stop,
             state::TransportState::Stopped => TransportAction::Start,
+            state::TransportState::Paused => TransportAction::Continue,
         };
 
         log::info!("Space pressed - sending transport command: {:?}", command);
-        message_tx
-            .send(EngineMessage::TransportCommand(command))
-            .unwrap();
+        send_to_engine(message_tx, EngineMessage::TransportCommand(command))?;
+        return Ok(());
+    }
+
+    // Switching the swing grid keeps the current amount
+    if let KeyCode::Char('g') = key_event.code {
+        let swing = shared_state.lock().unwrap().swing;
+        let swing = swing.with_grid(swing.grid().toggled());
+        log::info!("G pressed - swinging {}", swing);
+        send_to_engine(message_tx, EngineMessage::SetSwing(swing))?;
+        return Ok(());
+    }
+
+    // The meter change lands on the next bar, so step on from what is
+    // already queued there rather than from the bar playing now
+    if let KeyCode::Char('m') = key_event.code {
+        let signature = {
+            let state = shared_state.lock().unwrap();
+            state.meter.signature_at_bar(state.get_current_bar() + 1)
+        };
+        let signature = next_time_signature(signature);
+        log::info!("M pressed - switching to {} at the next bar", signature);
+        send_to_engine(message_tx, EngineMessage::SetTimeSignature(signature))?;
         return Ok(());
     }
 
     // For all other keys, use the mapper
     if let Some(message) = map_key_event(key_event) {
         log::info!("Sending message to event loop: {:?}", message);
-        message_tx.send(message).unwrap();
+        send_to_engine(message_tx, message)?;
     }
 
     Ok(())
 }
 
+/// K cycles the clock source and D binds the next MIDI input device.
+/// Returns false for any other key.
+fn switch_clock_for_key(code: KeyCode, clocks: &ClockController) -> bool {
+    let selection = match code {
+        KeyCode::Char('k') => next_clock_source(&clocks.selection(), clocks.last_device()),
+        KeyCode::Char('d') => {
+            let devices = external_clock::input_device_names();
+            let Some(device) = next_device(&devices, clocks.last_device().as_deref()) else {
+                log::warn!("D pressed - no MIDI input devices to bind");
+                return true;
+            };
+            ClockSelection::External(device)
+        }
+        _ => return false,
+    };
+    log::info!("Switching clock to {}", selection);
+    if let Err(e) = clocks.switch(selection) {
+        log::warn!("Clock switch failed: {}", e);
+    }
+    true
+}
+
+/// Internal, then the last bound device if there is one, then Link.
+fn next_clock_source(current: &ClockSelection, last_device: Option<String>) -> ClockSelection {
+    match (current, last_device) {
+        (ClockSelection::Internal, Some(device)) => ClockSelection::External(device),
+        (ClockSelection::Internal, None) | (ClockSelection::External(_), _) => ClockSelection::Link,
+        (ClockSelection::Link, _) => ClockSelection::Internal,
+    }
+}
+
+/// The device after `current` in `devices`, wrapping round.
+fn next_device(devices: &[String], current: Option<&str>) -> Option<String> {
+    let next = current
+        .and_then(|current| devices.iter().position(|device| device == current))
+        .map_or(0, |index| index + 1);
+    devices.get(next % devices.len().max(1)).cloned()
+}
+
+/// The meter after `current` in the M key cycle. Meters outside the cycle
+/// go back to its start.
+fn next_time_signature(current: TimeSignature) -> TimeSignature {
+    let position = METER_CYCLE
+        .iter()
+        .position(|&(numerator, denominator)| {
+            current.numerator == numerator && current.denominator == denominator
+        })
+        .map_or(0, |index| index + 1);
+    let (numerator, denominator) = METER_CYCLE[position % METER_CYCLE.len()];
+    TimeSignature::new(numerator, denominator).unwrap_or_default()
+}
+
 fn render_ui<B: ratatui::backend::Backend>(
     f: &mut ratatui::Frame<B>,
     shared_state: &Arc<Mutex<state::SharedState>>,
@@ -128,38 +234,18 @@ fn build_transport_lines(state: &state::SharedState) -> Vec<Spans<'static>> {
         .as_deref()
         .unwrap_or("wav_files/take_%Y%m%d_%H%M%S_pair1.wav");
 
-    vec![
+    let mut lines = vec![
         Spans::from(vec![
             Span::raw("Transport: "),
             Span::styled(
                 format!("{:?}", state.transport_state),
                 Style::default().fg(Color::Cyan),
             ),
+            Span::raw("    Clock: "),
+            clock_status_span(state.clock_status, state.clock_device.as_deref()),
         ]),
-        Spans::from(vec![
-            Span::raw("BPM: "),
-            Span::styled(
-                state.get_bpm().to_string(),
-                Style::default().fg(Color::Green),
-            ),
-            Span::raw("    Tick: "),
-            Span::styled(
-                state.get_tick_count().to_string(),
-                Style::default().fg(Color::Green),
-            ),
-        ]),
-        Spans::from(vec![
-            Span::raw("Bar: "),
-            Span::styled(
-                state.get_current_bar().to_string(),
-                Style::default().fg(Color::Yellow),
-            ),
-            Span::raw("    Beat: "),
-            Span::styled(
-                state.get_current_beat().to_string(),
-                Style::default().fg(Color::Yellow),
-            ),
-        ]),
+        tempo_line(state),
+        position_line(state),
         Spans::from(vec![
             recording_indicator,
             Span::raw("  "),
@@ -168,13 +254,138 @@ fn build_transport_lines(state: &state::SharedState) -> Vec<Spans<'static>> {
                 Style::default().fg(Color::DarkGray),
             ),
         ]),
-    ]
+    ];
+    lines.extend(state.jitter.map(jitter_line));
+    lines.extend(state.devices.iter().map(device_line));
+    if let Some(last) = &state.midi_input.last {
+        lines.push(Spans::from(vec![
+            Span::raw(format!(
+                "MIDI in: {} messages, last ",
+                state.midi_input.events
+            )),
+            Span::styled(last.to_string(), Style::default().fg(Color::Cyan)),
+        ]));
+    }
+    lines
+}
+
+fn jitter_line(stats: JitterStats) -> Spans<'static> {
+    Spans::from(vec![
+        Span::raw("Jitter: "),
+        Span::styled(
+            format!(
+                "mean {} µs, max {} µs",
+                stats.mean.as_micros(),
+                stats.max.as_micros()
+            ),
+            Style::default().fg(Color::Cyan),
+        ),
+        Span::raw(format!(" over {} ticks", stats.ticks)),
+    ])
+}
+
+fn device_line(device: &DeviceStatus) -> Spans<'static> {
+    let color = match device.state {
+        ConnectionState::Connected(_) => Color::Green,
+        ConnectionState::Disconnected { .. } => Color::Yellow,
+        ConnectionState::GaveUp { .. } => Color::Red,
+    };
+    Spans::from(vec![
+        Span::raw(format!(
+            "MIDI {} {} ({}): ",
+            if device.input { "in " } else { "out" },
+            device.role,
+            device.device
+        )),
+        Span::styled(device.state.to_string(), Style::default().fg(color)),
+    ])
+}
+
+fn position_line(state: &state::SharedState) -> Spans<'static> {
+    Spans::from(vec![
+        Span::raw("Bar: "),
+        Span::styled(
+            state.get_current_bar().to_string(),
+            Style::default().fg(Color::Yellow),
+        ),
+        Span::raw("    Beat: "),
+        Span::styled(
+            state.get_current_beat().to_string(),
+            Style::default().fg(Color::Yellow),
+        ),
+        Span::raw("    Meter: "),
+        Span::styled(
+            state.time_signature().to_string(),
+            Style::default().fg(Color::Yellow),
+        ),
+        Span::raw("    TC: "),
+        Span::styled(
+            state
+                .timecode
+                .map_or_else(|| "--:--:--:--".to_string(), |tc| tc.to_string()),
+            Style::default().fg(Color::Yellow),
+        ),
+    ])
+}
+
+fn clock_status_span(status: state::ClockStatus, device: Option<&str>) -> Span<'static> {
+    let color = match status {
+        state::ClockStatus::Flywheel => Color::Red,
+        state::ClockStatus::Internal | state::ClockStatus::External | state::ClockStatus::Link => {
+            Color::Cyan
+        }
+    };
+    let label = match device {
+        Some(device) => format!("{:?} ({})", status, device),
+        None => format!("{:?}", status),
+    };
+    Span::styled(label, Style::default().fg(color))
+}
+
+fn tempo_line(state: &state::SharedState) -> Spans<'static> {
+    Spans::from(vec![
+        Span::raw("BPM: "),
+        Span::styled(
+            format!("{:.2}", state.precise_bpm),
+            Style::default().fg(Color::Green),
+        ),
+        Span::raw("    Phase: "),
+        Span::styled(
+            format!("{:+.2} ms", state.phase_error_ms),
+            Style::default().fg(Color::Green),
+        ),
+        Span::raw("    Tick: "),
+        Span::styled(
+            state.get_tick_count().to_string(),
+            Style::default().fg(Color::Green),
+        ),
+        Span::raw("    Swing: "),
+        Span::styled(state.swing.to_string(), Style::default().fg(Color::Green)),
+    ])
 }
 
 fn controls_paragraph() -> Paragraph<'static> {
     Paragraph::new(Spans::from(vec![
         Span::styled("SPACE", Style::default().fg(Color::Yellow)),
         Span::raw(": Start/Stop   "),
+        Span::styled("P", Style::default().fg(Color::Yellow)),
+        Span::raw(": Pause   "),
+        Span::styled("C", Style::default().fg(Color::Yellow)),
+        Span::raw(": Continue   "),
+        Span::styled("+/-", Style::default().fg(Color::Yellow)),
+        Span::raw(": Tempo   "),
+        Span::styled("[/]", Style::default().fg(Color::Yellow)),
+        Span::raw(": Swing   "),
+        Span::styled("G", Style::default().fg(Color::Yellow)),
+        Span::raw(": Swing grid   "),
+        Span::styled("M", Style::default().fg(Color::Yellow)),
+        Span::raw(": Meter   "),
+        Span::styled("K", Style::default().fg(Color::Yellow)),
+        Span::raw(": Clock   "),
+        Span::styled("D", Style::default().fg(Color::Yellow)),
+        Span::raw(": Clock device   "),
+        Span::styled("!", Style::default().fg(Color::Yellow)),
+        Span::raw(": Panic   "),
         Span::styled("Q", Style::default().fg(Color::Yellow)),
         Span::raw(": Quit"),
     ]))
@@ -182,9 +393,13 @@ fn controls_paragraph() -> Paragraph<'static> {
     .block(Block::default().borders(Borders::ALL).title("Controls"))
 }
 
+/// Runs the TUI until the quit key is pressed, which asks the engine to
+/// shut down first, or until the engine stops. The terminal is restored
+/// either way.
 pub fn run_tui_event_loop(
     shared_state: Arc<Mutex<state::SharedState>>,
     message_tx: Sender<EngineMessage>,
+    clocks: ClockController,
 ) -> Result<(), Box<dyn Error>> {
     log::info!("Starting TUI event loop");
     // Setup terminal
@@ -194,21 +409,48 @@ pub fn run_tui_event_loop(
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Main event loop
+    let result = run_until_quit(&mut terminal, &shared_state, &message_tx, &clocks);
+
+    disable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, LeaveAlternateScreen, DisableMouseCapture)?;
+    log::info!("Terminal cleaned up, exiting TUI event loop");
+    result
+}
+
+fn run_until_quit<B: ratatui::backend::Backend>(
+    terminal: &mut Terminal<B>,
+    shared_state: &Arc<Mutex<state::SharedState>>,
+    message_tx: &Sender<EngineMessage>,
+    clocks: &ClockController,
+) -> Result<(), Box<dyn Error>> {
     loop {
         // Repaint the UI on every iteration
-        terminal.draw(|f| render_ui(f, &shared_state))?;
+        terminal.draw(|f| render_ui(f, shared_state))?;
         log::debug!("Screen repainted");
 
         // Poll for an event with a timeout
         if event::poll(Duration::from_millis(100))? {
             if let Event::Key(key_event) = event::read()? {
-                handle_key_event(key_event, &message_tx, &shared_state)?;
+                if is_quit_key(&key_event) {
+                    log::info!("Quit key pressed. Exiting event loop.");
+                    return Ok(());
+                }
+                handle_key_event(key_event, message_tx, shared_state, clocks)?;
             }
         }
     }
 }
 
+/// Puts the terminal back as it was if the TUI still has it, for exits
+/// that do not pass through the TUI's own cleanup.
+pub fn restore_terminal() {
+    if crossterm::terminal::is_raw_mode_enabled().unwrap_or(false) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture);
+    }
+}
+
 #[cfg(test)]
 pub fn run_hello_world_tui(
     shared_state: Arc<Mutex<state::SharedState>>,
@@ -255,6 +497,155 @@ mod tests {
         assert!(map_key_event(key_event).is_none());
     }
 
+    #[test]
+    fn test_plus_and_minus_nudge_tempo() {
+        let up = map_key_event(KeyEvent::from(KeyCode::Char('+')));
+        assert!(matches!(up, Some(EngineMessage::NudgeTempo(delta)) if delta > 0.0));
+
+        let down = map_key_event(KeyEvent::from(KeyCode::Char('-')));
+        assert!(matches!(down, Some(EngineMessage::NudgeTempo(delta)) if delta < 0.0));
+    }
+
+    #[test]
+    fn test_pause_and_continue_keys() {
+        let pause = map_key_event(KeyEvent::from(KeyCode::Char('p')));
+        assert!(matches!(
+            pause,
+            Some(EngineMessage::TransportCommand(TransportAction::Pause))
+        ));
+
+        let resume = map_key_event(KeyEvent::from(KeyCode::Char('c')));
+        assert!(matches!(
+            resume,
+            Some(EngineMessage::TransportCommand(TransportAction::Continue))
+        ));
+    }
+
+    #[test]
+    fn test_bang_sends_panic() {
+        let panic = map_key_event(KeyEvent::from(KeyCode::Char('!')));
+        assert!(matches!(panic, Some(EngineMessage::Panic)));
+    }
+
+    #[test]
+    fn test_brackets_nudge_swing() {
+        let more = map_key_event(KeyEvent::from(KeyCode::Char(']')));
+        assert!(matches!(more, Some(EngineMessage::NudgeSwing(delta)) if delta > 0.0));
+
+        let less = map_key_event(KeyEvent::from(KeyCode::Char('[')));
+        assert!(matches!(less, Some(EngineMessage::NudgeSwing(delta)) if delta < 0.0));
+    }
+
+    #[test]
+    fn test_meter_key_cycles_from_the_next_bar() {
+        let shared_state = Arc::new(Mutex::new(SharedState::new(120)));
+        shared_state.lock().unwrap().meter = crate::meter::MeterMap::parse("1:4/4,2:6/8").unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clocks = stopped_clocks(tx.clone());
+
+        // At bar 0 the next bar is bar 1, already in 4/4
+        handle_key_event(
+            KeyEvent::from(KeyCode::Char('m')),
+            &tx,
+            &shared_state,
+            &clocks,
+        )
+        .unwrap();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(EngineMessage::SetTimeSignature(signature)) if signature.to_string() == "3/4"
+        ));
+
+        // In bar 1 the queued 6/8 moves on to 7/8, and 7/8 wraps round
+        shared_state.lock().unwrap().current_bar = 1;
+        handle_key_event(
+            KeyEvent::from(KeyCode::Char('m')),
+            &tx,
+            &shared_state,
+            &clocks,
+        )
+        .unwrap();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(EngineMessage::SetTimeSignature(signature)) if signature.to_string() == "7/8"
+        ));
+        let seven_eight = TimeSignature::new(7, 8).unwrap();
+        assert_eq!(next_time_signature(seven_eight), TimeSignature::default());
+    }
+
+    #[test]
+    fn test_keys_fail_once_the_engine_has_stopped() {
+        let shared_state = Arc::new(Mutex::new(SharedState::new(120)));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clocks = stopped_clocks(tx.clone());
+        drop(rx);
+
+        let result = handle_key_event(
+            KeyEvent::from(KeyCode::Char(' ')),
+            &tx,
+            &shared_state,
+            &clocks,
+        );
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<MidiError>(),
+            Some(&MidiError::EngineStopped)
+        );
+        assert!(is_quit_key(&KeyEvent::from(KeyCode::Char('q'))));
+        assert!(is_quit_key(&KeyEvent::new(
+            KeyCode::Char('c'),
+            KeyModifiers::CONTROL
+        )));
+        assert!(!is_quit_key(&KeyEvent::from(KeyCode::Char('c'))));
+    }
+
+    /// A controller that has not started, so switches only change what
+    /// would run.
+    fn stopped_clocks(engine_tx: Sender<EngineMessage>) -> ClockController {
+        let tick_tx = engine_tx.clone();
+        ClockController::new(engine_tx, ClockSelection::Internal, move |_| {
+            Box::new(crate::simulation::SimulatedClock::new(
+                crate::simulation::VirtualTime::new(),
+                crate::clock::TempoHandle::new(120.0),
+                tick_tx.clone(),
+                crate::simulation::SimulationMode::Stepped,
+            ))
+        })
+    }
+
+    #[test]
+    fn test_clock_key_cycles_sources() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let clocks = stopped_clocks(tx);
+
+        // With no device ever bound, external is skipped
+        assert!(switch_clock_for_key(KeyCode::Char('k'), &clocks));
+        assert_eq!(clocks.selection(), ClockSelection::Link);
+        assert!(switch_clock_for_key(KeyCode::Char('k'), &clocks));
+        assert_eq!(clocks.selection(), ClockSelection::Internal);
+        assert!(!switch_clock_for_key(KeyCode::Char('x'), &clocks));
+
+        let device = || Some("OXI ONE".to_string());
+        assert_eq!(
+            next_clock_source(&ClockSelection::Internal, device()),
+            ClockSelection::External("OXI ONE".to_string())
+        );
+        assert_eq!(
+            next_clock_source(&ClockSelection::External("OXI ONE".to_string()), device()),
+            ClockSelection::Link
+        );
+    }
+
+    #[test]
+    fn test_next_device_wraps_round() {
+        let devices = vec!["A".to_string(), "B".to_string()];
+        assert_eq!(next_device(&devices, None).as_deref(), Some("A"));
+        assert_eq!(next_device(&devices, Some("A")).as_deref(), Some("B"));
+        assert_eq!(next_device(&devices, Some("B")).as_deref(), Some("A"));
+        assert_eq!(next_device(&devices, Some("gone")).as_deref(), Some("A"));
+        assert_eq!(next_device(&[], None), None);
+    }
+
     #[test]
     fn test_other_key_returns_none() {
         let key_event = KeyEvent::from(KeyCode::Char('x'));
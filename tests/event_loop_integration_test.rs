@@ -1,6 +1,9 @@
 extern crate phasorsyncrs;
 
+use phasorsyncrs::clock::{ClockSource, TempoHandle};
+use phasorsyncrs::config::TICKS_PER_CLOCK;
 use phasorsyncrs::event_loop::{EngineMessage, EventLoop};
+use phasorsyncrs::simulation::{SimulatedClock, SimulationMode, VirtualTime};
 use phasorsyncrs::state::{SharedState, TransportState};
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
@@ -44,8 +47,8 @@ fn integration_test_event_loop_two_ticks() {
     let state = shared_state.lock().unwrap();
     assert_eq!(
         state.get_tick_count(),
-        2,
-        "Tick count should be 2 after 2 ticks"
+        2 * TICKS_PER_CLOCK,
+        "Tick count should be two pulses of engine ticks"
     );
     // Verify that BPM has been recalculated and is greater than 0.
     assert!(
@@ -53,3 +56,36 @@ fn integration_test_event_loop_two_ticks() {
         "BPM should be recalculated and greater than 0"
     );
 }
+
+#[test]
+fn integration_test_event_loop_on_simulated_clock() {
+    let shared_state = Arc::new(Mutex::new(SharedState::new(120)));
+    {
+        let mut state = shared_state.lock().unwrap();
+        state.transport_state = TransportState::Playing;
+    }
+
+    let (engine_tx, engine_rx) = mpsc::channel();
+    let event_loop = EventLoop::new(Arc::clone(&shared_state), engine_rx, None);
+    let handle = thread::spawn(move || {
+        let mut event_loop = event_loop;
+        event_loop.run();
+    });
+
+    // Sixteen bars at 140 BPM, delivered as fast as the engine takes them.
+    let time = VirtualTime::new();
+    let clock = SimulatedClock::new(
+        time.clone(),
+        TempoHandle::new(140.0),
+        engine_tx,
+        SimulationMode::FreeRunning { ticks: 1536 },
+    );
+    clock.start().unwrap();
+    drop(clock);
+    handle.join().expect("Event loop thread panicked");
+
+    let state = shared_state.lock().unwrap();
+    assert_eq!(state.get_tick_count(), 1536 * TICKS_PER_CLOCK);
+    assert_eq!(state.get_bpm(), 140);
+    assert_eq!(time.elapsed().as_millis(), 27_428);
+}